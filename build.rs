@@ -1,3 +1,50 @@
+use std::collections::hash_map::DefaultHasher;
+use std::env;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+
+/// Functions that are exported for C++ plugins (see `plugin_compat.rs`). Keep this in sync with
+/// `flutter-windows-shim`.
+const PLUGIN_EXPORTS: &[&str] = &[
+    "FlutterDesktopMessengerSend",
+    "FlutterDesktopMessengerSendWithReply",
+    "FlutterDesktopMessengerSendResponse",
+    "FlutterDesktopMessengerSetCallback",
+    "FlutterDesktopMessengerAddRef",
+    "FlutterDesktopMessengerRelease",
+    "FlutterDesktopMessengerIsAvailable",
+    "FlutterDesktopMessengerLock",
+    "FlutterDesktopMessengerUnlock",
+    "FlutterDesktopPluginRegistrarGetMessenger",
+    "FlutterDesktopRegistrarGetTextureRegistrar",
+    "FlutterDesktopPluginRegistrarSetDestructionHandler",
+    "FlutterDesktopPluginRegistrarGetView",
+    "FlutterDesktopPluginRegistrarGetViewById",
+    "FlutterDesktopPluginRegistrarRegisterTopLevelWindowProcDelegate",
+    "FlutterDesktopPluginRegistrarUnregisterTopLevelWindowProcDelegate",
+    "FlutterDesktopViewGetHWND",
+    "FlutterDesktopViewGetGraphicsAdapter",
+    "FlutterDesktopTextureRegistrarRegisterExternalTexture",
+    "FlutterDesktopTextureRegistrarUnregisterExternalTexture",
+    "FlutterDesktopTextureRegistrarMarkExternalTextureFrameAvailable",
+];
+
+/// Functions that are exported for Dart to call through `dart:ffi` (see `dart_ffi.rs`).
+const DART_FFI_EXPORTS: &[&str] = &[
+    "fluyt_get_window_handle",
+    "fluyt_get_dpi",
+    "fluyt_get_monitor_info",
+    "fluyt_clipboard_has_text",
+    "fluyt_clipboard_get_text",
+    "fluyt_free_string",
+];
+
+/// The archive that `embed-assets` bundles the assets into, read by `src/bundle.rs`. Each file is
+/// stored as its path relative to the assets directory (with `/` separators) and contents, each
+/// prefixed with its length as a little-endian u64.
+const ASSETS_ARCHIVE: &str = "flutter_assets.bin";
+
 fn main() {
     let build = dunce::canonicalize("build").unwrap();
     let angle_lib = build.join("angle-win64/lib");
@@ -5,4 +52,69 @@ fn main() {
     println!("cargo:rustc-link-search=native={}", angle_lib.display());
     println!("cargo:rustc-link-lib=dylib=libEGL.dll");
     println!("cargo:rustc-link-lib=dylib=libGLESv2.dll");
+
+    for name in PLUGIN_EXPORTS.iter().chain(DART_FFI_EXPORTS) {
+        println!("cargo:rustc-link-arg-bins=/EXPORT:{name}");
+    }
+
+    let out_dir = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    let mut hasher = DefaultHasher::new();
+
+    if env::var_os("CARGO_FEATURE_EMBED_ICU_DATA").is_some() {
+        println!("cargo:rerun-if-env-changed=FLUYT_ICU_DATA");
+        let path = env::var_os("FLUYT_ICU_DATA").map_or_else(|| "icudtl.dat".into(), PathBuf::from);
+        println!("cargo:rerun-if-changed={}", path.display());
+
+        let data =
+            fs::read(&path).unwrap_or_else(|e| panic!("failed to read {}: {e}", path.display()));
+        data.hash(&mut hasher);
+        fs::write(out_dir.join("icudtl.dat"), data).unwrap();
+    }
+
+    if env::var_os("CARGO_FEATURE_EMBED_ASSETS").is_some() {
+        println!("cargo:rerun-if-env-changed=FLUYT_ASSETS_DIR");
+        let dir = env::var_os("FLUYT_ASSETS_DIR")
+            .map_or_else(|| "example/build/flutter_assets".into(), PathBuf::from);
+        println!("cargo:rerun-if-changed={}", dir.display());
+
+        let mut files = vec![];
+        list_files(&dir, &mut files);
+        files.sort();
+
+        let mut archive = vec![];
+        for path in files {
+            let name = path
+                .strip_prefix(&dir)
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .replace('\\', "/");
+            let data = fs::read(&path).unwrap();
+
+            for bytes in [name.as_bytes(), &data] {
+                archive.extend_from_slice(&(bytes.len() as u64).to_le_bytes());
+                archive.extend_from_slice(bytes);
+            }
+        }
+
+        archive.hash(&mut hasher);
+        fs::write(out_dir.join(ASSETS_ARCHIVE), archive).unwrap();
+    }
+
+    // Identifies the embedded files, so that they are only extracted again when they change.
+    println!("cargo:rustc-env=FLUYT_BUNDLE_HASH={:016x}", hasher.finish());
+}
+
+fn list_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    let entries =
+        fs::read_dir(dir).unwrap_or_else(|e| panic!("failed to read {}: {e}", dir.display()));
+
+    for entry in entries {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            list_files(&path, files);
+        } else {
+            files.push(path);
+        }
+    }
 }
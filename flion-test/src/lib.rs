@@ -0,0 +1,327 @@
+//! A harness for golden image tests of the embedder.
+//!
+//! [`Harness`] runs an app in an off-screen window, with frames driven by a virtual clock so that
+//! animations are deterministic. Screenshots are compared with golden images, which are
+//! (re)written instead when `FLION_UPDATE_GOLDENS` is set.
+//!
+//! ```no_run
+//! # fn main() -> color_eyre::Result<()> {
+//! let mut harness = flion_test::Harness::new(flion_test::HarnessConfig::new(
+//!     "build/flutter_assets",
+//!     "icudtl.dat",
+//! ))?;
+//!
+//! harness.pump_frames(10)?;
+//! harness.match_golden("goldens/home.png", 0.001)?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::env;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use color_eyre::eyre::{self, bail};
+use flion::{FlionEngine, Frame};
+use windows::core::{w, HSTRING};
+use windows::Win32::Foundation::HWND;
+use windows::Win32::System::Com::{CoInitializeEx, COINIT_APARTMENTTHREADED};
+use windows::Win32::System::LibraryLoader::GetModuleHandleW;
+use windows::Win32::UI::WindowsAndMessaging::{
+    CreateWindowExW, DefWindowProcW, DestroyWindow, DispatchMessageW, PeekMessageW, RegisterClassW,
+    ShowWindow, TranslateMessage, MSG, PM_REMOVE, SW_SHOWNOACTIVATE, WNDCLASSW, WS_EX_NOACTIVATE,
+    WS_EX_NOREDIRECTIONBITMAP, WS_EX_TOOLWINDOW, WS_POPUP,
+};
+
+/// How long to wait for the engine to request a frame before assuming that it is idle.
+const FRAME_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long to keep running tasks after the last frame, so that it can be rasterized and
+/// presented before a screenshot is taken.
+const SETTLE_TIME: Duration = Duration::from_millis(100);
+
+/// Channels are compared with this tolerance, which allows for small differences between GPUs.
+const CHANNEL_TOLERANCE: u8 = 2;
+
+pub struct HarnessConfig {
+    pub assets_path: PathBuf,
+    pub icu_data_path: PathBuf,
+    pub initial_route: Option<String>,
+    /// Size of the view in physical pixels.
+    pub width: i32,
+    pub height: i32,
+    /// How far the virtual clock advances for each frame.
+    pub frame_interval: Duration,
+}
+
+impl HarnessConfig {
+    pub fn new(
+        assets_path: impl Into<PathBuf>,
+        icu_data_path: impl Into<PathBuf>,
+    ) -> HarnessConfig {
+        HarnessConfig {
+            assets_path: assets_path.into(),
+            icu_data_path: icu_data_path.into(),
+            initial_route: None,
+            width: 800,
+            height: 600,
+            frame_interval: Duration::from_nanos(1_000_000_000 / 60),
+        }
+    }
+}
+
+/// Runs an engine in an off-screen window. The harness must be used on the thread that created it.
+pub struct Harness {
+    engine: Option<FlionEngine>,
+    hwnd: HWND,
+    frame_interval: Duration,
+}
+
+impl Harness {
+    pub fn new(config: HarnessConfig) -> eyre::Result<Harness> {
+        // Fails harmlessly if the thread has already been initialized.
+        let _ = unsafe { CoInitializeEx(None, COINIT_APARTMENTTHREADED) };
+
+        let hwnd = create_window(config.width, config.height)?;
+
+        let mut engine = FlionEngine::new(
+            &config.assets_path,
+            &config.icu_data_path,
+            config.initial_route.as_deref(),
+        )?;
+
+        engine.use_virtual_clock();
+
+        // Constructed before attaching so that the window is destroyed if attaching fails.
+        let mut harness = Harness {
+            engine: None,
+            hwnd,
+            frame_interval: config.frame_interval,
+        };
+
+        engine.attach_hwnd(hwnd)?;
+        harness.engine = Some(engine);
+
+        Ok(harness)
+    }
+
+    pub fn engine(&self) -> &FlionEngine {
+        self.engine.as_ref().unwrap()
+    }
+
+    /// Produces up to `count` frames, stopping early if the engine stops requesting them (e.g.
+    /// because there are no more animations). Returns the number of frames that were produced.
+    pub fn pump_frames(&mut self, count: usize) -> eyre::Result<usize> {
+        let mut frames = 0;
+
+        while frames < count {
+            if !self.run_until(FRAME_TIMEOUT, |engine| engine.has_pending_frame())? {
+                break;
+            }
+
+            if self.engine().advance_frame(self.frame_interval)? {
+                frames += 1;
+            }
+        }
+
+        self.run_until(SETTLE_TIME, |_| Ok(false))?;
+
+        Ok(frames)
+    }
+
+    pub fn screenshot(&mut self) -> eyre::Result<Frame> {
+        self.engine().capture_frame()
+    }
+
+    /// Compares a screenshot with the golden image at `path`. The comparison fails if more than
+    /// `threshold` (between 0 and 1) of the pixels differ, in which case the screenshot is written
+    /// next to the golden image with an `.actual.png` extension.
+    ///
+    /// The golden image is written instead if `FLION_UPDATE_GOLDENS` is set.
+    pub fn match_golden(&mut self, path: impl AsRef<Path>, threshold: f64) -> eyre::Result<()> {
+        let path = path.as_ref();
+        let actual = self.screenshot()?;
+
+        if env::var_os("FLION_UPDATE_GOLDENS").is_some() {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::write(path, actual.encode_png()?)?;
+            return Ok(());
+        }
+
+        let expected = Frame::load(path)?;
+        let difference = compare(&actual, &expected)?;
+
+        if difference > threshold {
+            let actual_path = path.with_extension("actual.png");
+            std::fs::write(&actual_path, actual.encode_png()?)?;
+            bail!(
+                "{:.3}% of pixels differ from {} (see {})",
+                difference * 100.0,
+                path.display(),
+                actual_path.display()
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Runs the window's message loop (and so the engine's platform tasks) until `condition`
+    /// returns true, or `timeout` has elapsed. Returns whether the condition was met.
+    fn run_until(
+        &self,
+        timeout: Duration,
+        mut condition: impl FnMut(&FlionEngine) -> eyre::Result<bool>,
+    ) -> eyre::Result<bool> {
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            let mut msg = MSG::default();
+            while unsafe { PeekMessageW(&mut msg, None, 0, 0, PM_REMOVE) }.as_bool() {
+                unsafe {
+                    TranslateMessage(&msg);
+                    DispatchMessageW(&msg);
+                }
+            }
+
+            if condition(self.engine())? {
+                return Ok(true);
+            }
+
+            if Instant::now() >= deadline {
+                return Ok(false);
+            }
+
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
+
+impl Drop for Harness {
+    fn drop(&mut self) {
+        // The engine has to be shut down before its window is destroyed.
+        drop(self.engine.take());
+        let _ = unsafe { DestroyWindow(self.hwnd) };
+    }
+}
+
+/// Returns the fraction of pixels that differ by more than a small tolerance in any channel.
+pub fn compare(actual: &Frame, expected: &Frame) -> eyre::Result<f64> {
+    if (actual.width, actual.height) != (expected.width, expected.height) {
+        bail!(
+            "expected a {}x{} image, but got {}x{}",
+            expected.width,
+            expected.height,
+            actual.width,
+            actual.height
+        );
+    }
+
+    let total = (actual.width * actual.height) as usize;
+    if total == 0 {
+        return Ok(0.0);
+    }
+
+    let differing = actual
+        .pixels
+        .chunks_exact(4)
+        .zip(expected.pixels.chunks_exact(4))
+        .filter(|(a, b)| {
+            a.iter()
+                .zip(b.iter())
+                .any(|(a, b)| a.abs_diff(*b) > CHANNEL_TOLERANCE)
+        })
+        .count();
+
+    Ok(differing as f64 / total as f64)
+}
+
+/// Creates a window that isn't shown on screen or in the taskbar, but is still composited so that
+/// its contents can be captured.
+fn create_window(width: i32, height: i32) -> eyre::Result<HWND> {
+    unsafe {
+        let instance = GetModuleHandleW(None)?;
+        let class_name = w!("flion_test");
+
+        // Registration fails if another harness has already registered the class.
+        RegisterClassW(&WNDCLASSW {
+            lpfnWndProc: Some(DefWindowProcW),
+            hInstance: instance.into(),
+            lpszClassName: class_name,
+            ..Default::default()
+        });
+
+        let hwnd = CreateWindowExW(
+            WS_EX_TOOLWINDOW | WS_EX_NOACTIVATE | WS_EX_NOREDIRECTIONBITMAP,
+            class_name,
+            &HSTRING::from("flion-test"),
+            WS_POPUP,
+            -(width + 10_000),
+            -(height + 10_000),
+            width,
+            height,
+            None,
+            None,
+            instance,
+            None,
+        );
+
+        if hwnd.0 == 0 {
+            bail!(
+                "failed to create window: {}",
+                windows::core::Error::from_win32()
+            );
+        }
+
+        ShowWindow(hwnd, SW_SHOWNOACTIVATE);
+
+        Ok(hwnd)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solid(width: u32, height: u32, pixel: [u8; 4]) -> Frame {
+        Frame {
+            width,
+            height,
+            pixels: pixel.repeat((width * height) as usize),
+        }
+    }
+
+    #[test]
+    fn identical_images_match() {
+        let frame = solid(4, 4, [10, 20, 30, 255]);
+        assert_eq!(compare(&frame, &frame).unwrap(), 0.0);
+    }
+
+    #[test]
+    fn differences_within_tolerance_are_ignored() {
+        let actual = solid(4, 4, [10, 20, 30, 255]);
+        let expected = solid(4, 4, [12, 18, 30, 253]);
+        assert_eq!(compare(&actual, &expected).unwrap(), 0.0);
+    }
+
+    #[test]
+    fn differences_over_tolerance_are_counted() {
+        let actual = solid(4, 4, [10, 20, 30, 255]);
+        let mut expected = solid(4, 4, [10, 20, 30, 255]);
+        // Change one channel of a quarter of the pixels.
+        for pixel in expected.pixels.chunks_exact_mut(4).take(4) {
+            pixel[0] += CHANNEL_TOLERANCE + 1;
+        }
+
+        assert_eq!(compare(&actual, &expected).unwrap(), 0.25);
+    }
+
+    #[test]
+    fn size_mismatch_is_an_error() {
+        let actual = solid(4, 4, [0, 0, 0, 255]);
+        let expected = solid(4, 2, [0, 0, 0, 255]);
+        assert!(compare(&actual, &expected).is_err());
+    }
+}
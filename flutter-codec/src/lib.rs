@@ -80,6 +80,8 @@ pub enum EncodableValue<'a> {
     I64(i64),
     F64(Float64),
     Str(&'a str),
+    U8List(&'a [u8]),
+    List(Vec<EncodableValue<'a>>),
     Map(BTreeMap<EncodableValue<'a>, EncodableValue<'a>>),
 }
 
@@ -108,6 +110,27 @@ impl<'a> EncodableValue<'a> {
         }
     }
 
+    /// Returns the value as an integer, regardless of whether it was encoded as 32 or 64 bits.
+    /// The standard codec uses the smallest encoding that fits, so this should be used for ints
+    /// sent from Dart.
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            Self::I32(v) => Some(*v as i64),
+            Self::I64(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    /// Returns the value as a float, converting from integer values if necessary.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Self::I32(v) => Some(*v as f64),
+            Self::I64(v) => Some(*v as f64),
+            Self::F64(v) => Some(v.0),
+            _ => None,
+        }
+    }
+
     pub fn as_string(&self) -> Option<&'a str> {
         if let Self::Str(v) = self {
             Some(v)
@@ -116,6 +139,22 @@ impl<'a> EncodableValue<'a> {
         }
     }
 
+    pub fn as_u8_list(&self) -> Option<&'a [u8]> {
+        if let Self::U8List(v) = self {
+            Some(v)
+        } else {
+            None
+        }
+    }
+
+    pub fn as_list(&self) -> Option<&[EncodableValue<'a>]> {
+        if let Self::List(v) = self {
+            Some(v)
+        } else {
+            None
+        }
+    }
+
     pub fn as_map(&self) -> Option<&BTreeMap<EncodableValue<'a>, EncodableValue<'a>>> {
         if let Self::Map(v) = self {
             Some(v)
@@ -123,6 +162,11 @@ impl<'a> EncodableValue<'a> {
             None
         }
     }
+
+    /// Looks up a string key if this value is a map.
+    pub fn get(&self, key: &'a str) -> Option<&EncodableValue<'a>> {
+        self.as_map()?.get(&EncodableValue::Str(key))
+    }
 }
 
 fn read_size(cursor: &mut ReadCursor) -> io::Result<u32> {
@@ -147,10 +191,21 @@ fn write_size(w: &mut impl io::Write, value: u32) -> io::Result<()> {
 }
 
 fn read_string<'a>(cursor: &mut ReadCursor<'a>) -> io::Result<&'a str> {
+    let buf = read_bytes(cursor)?;
+    std::str::from_utf8(buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn read_bytes<'a>(cursor: &mut ReadCursor<'a>) -> io::Result<&'a [u8]> {
     let size = read_size(cursor)?;
     let buf = &cursor.get_ref()[cursor.position() as usize..][..size as usize];
     cursor.set_position(cursor.position() + size as u64);
-    std::str::from_utf8(buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    Ok(buf)
+}
+
+fn write_bytes(w: &mut WriteCursor, value: &[u8]) -> io::Result<()> {
+    write_size(w, value.len() as u32)?;
+    w.write_all(value)?;
+    Ok(())
 }
 
 fn write_string(w: &mut WriteCursor, value: &str) -> io::Result<()> {
@@ -159,6 +214,23 @@ fn write_string(w: &mut WriteCursor, value: &str) -> io::Result<()> {
     Ok(())
 }
 
+fn read_list<'a>(cursor: &mut ReadCursor<'a>) -> io::Result<Vec<EncodableValue<'a>>> {
+    let size = read_size(cursor)?;
+    let mut list = Vec::with_capacity(size as usize);
+    for _ in 0..size {
+        list.push(read_value(cursor)?);
+    }
+    Ok(list)
+}
+
+fn write_list(w: &mut WriteCursor, value: &[EncodableValue]) -> io::Result<()> {
+    write_size(w, value.len() as u32)?;
+    for v in value {
+        write_value(w, v)?;
+    }
+    Ok(())
+}
+
 fn read_map<'a>(
     cursor: &mut ReadCursor<'a>,
 ) -> io::Result<BTreeMap<EncodableValue<'a>, EncodableValue<'a>>> {
@@ -193,15 +265,18 @@ pub fn read_value<'a>(cursor: &mut ReadCursor<'a>) -> io::Result<EncodableValue<
         EncodedType::Int32 => Ok(EncodableValue::I32(cursor.read_i32::<NativeEndian>()?)),
         EncodedType::Int64 => Ok(EncodableValue::I64(cursor.read_i64::<NativeEndian>()?)),
         EncodedType::LargeInt => todo!(),
-        EncodedType::Float64 => Ok(EncodableValue::F64(Float64(
-            cursor.read_f64::<NativeEndian>()?,
-        ))),
+        EncodedType::Float64 => {
+            skip_to_alignment(cursor, 8);
+            Ok(EncodableValue::F64(Float64(
+                cursor.read_f64::<NativeEndian>()?,
+            )))
+        }
         EncodedType::String => Ok(EncodableValue::Str(read_string(cursor)?)),
-        EncodedType::UInt8List => todo!(),
+        EncodedType::UInt8List => Ok(EncodableValue::U8List(read_bytes(cursor)?)),
         EncodedType::Int32List => todo!(),
         EncodedType::Int64List => todo!(),
         EncodedType::Float64List => todo!(),
-        EncodedType::List => todo!(),
+        EncodedType::List => Ok(EncodableValue::List(read_list(cursor)?)),
         EncodedType::Map => Ok(EncodableValue::Map(read_map(cursor)?)),
         EncodedType::Float32List => todo!(),
     }
@@ -236,6 +311,14 @@ pub fn write_value(w: &mut WriteCursor, value: &EncodableValue) -> io::Result<()
             w.write_u8(EncodedType::String as u8)?;
             write_string(w, v)?;
         }
+        EncodableValue::U8List(v) => {
+            w.write_u8(EncodedType::UInt8List as u8)?;
+            write_bytes(w, v)?;
+        }
+        EncodableValue::List(v) => {
+            w.write_u8(EncodedType::List as u8)?;
+            write_list(w, v)?;
+        }
         EncodableValue::Map(v) => {
             w.write_u8(EncodedType::Map as u8)?;
             write_map(w, v)?;
@@ -256,3 +339,10 @@ fn align_to(w: &mut WriteCursor, align: usize) -> io::Result<()> {
     }
     Ok(())
 }
+
+fn skip_to_alignment(cursor: &mut ReadCursor, align: u64) {
+    let m = cursor.position() % align;
+    if m != 0 {
+        cursor.set_position(cursor.position() + align - m);
+    }
+}
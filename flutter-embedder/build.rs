@@ -6,15 +6,28 @@ fn main() {
     let embedder = build.join("windows-x64-embedder");
     let embedder_header = embedder.join("flutter_embedder.h");
 
+    // The engine is loaded at runtime and its functions are resolved through the proc table (see
+    // `src/loader.rs`), so only the types are generated, and nothing is linked.
     bindgen::builder()
         .header(embedder_header.to_str().unwrap())
         .parse_callbacks(Box::new(CargoCallbacks))
         .derive_default(true)
+        .blocklist_function(".*")
         .generate()
         .unwrap()
         .write_to_file("src/bindings.rs")
         .unwrap();
 
-    println!("cargo:rustc-link-search=native={}", embedder.display());
-    println!("cargo:rustc-link-lib=dylib=flutter_engine.dll");
+    // The commit of the engine that the header came from, written by `run.ps1`, so that a
+    // mismatched engine can be reported at runtime.
+    println!("cargo:rerun-if-env-changed=FLUTTER_ENGINE_COMMIT");
+    let commit = std::env::var("FLUTTER_ENGINE_COMMIT").ok().or_else(|| {
+        let version_file = embedder.join("engine.version");
+        println!("cargo:rerun-if-changed={}", version_file.display());
+        std::fs::read_to_string(version_file).ok()
+    });
+
+    if let Some(commit) = commit {
+        println!("cargo:rustc-env=FLUTTER_ENGINE_COMMIT={}", commit.trim());
+    }
 }
@@ -2,5 +2,7 @@
 #[allow(non_camel_case_types)]
 #[allow(non_snake_case)]
 mod bindings;
+mod loader;
 
 pub use bindings::*;
+pub use loader::*;
@@ -0,0 +1,203 @@
+//! Loads the engine from a DLL at runtime, so that the engine can be switched without rebuilding.
+//!
+//! The engine's functions are exposed as statics with the same names as the C functions, which
+//! can be called like them once [`load`] has succeeded.
+
+#![allow(non_upper_case_globals)]
+
+use std::ffi::{c_char, c_void, OsStr};
+use std::fmt;
+use std::ops::Deref;
+use std::os::windows::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use crate::bindings::*;
+
+#[link(name = "kernel32")]
+extern "system" {
+    fn LoadLibraryW(name: *const u16) -> *mut c_void;
+    fn GetProcAddress(module: *mut c_void, name: *const c_char) -> *mut c_void;
+    fn GetLastError() -> u32;
+}
+
+type GetProcAddressesFn = unsafe extern "C" fn(*mut FlutterEngineProcTable) -> FlutterEngineResult;
+
+struct Engine {
+    path: PathBuf,
+    procs: FlutterEngineProcTable,
+}
+
+static ENGINE: OnceLock<Engine> = OnceLock::new();
+
+/// The commit of the engine that the bindings were generated for, if it is known.
+pub const ENGINE_COMMIT: Option<&str> = option_env!("FLUTTER_ENGINE_COMMIT");
+
+#[derive(Debug)]
+pub enum LoadError {
+    /// `LoadLibraryW` failed with the given error code.
+    Library(PathBuf, u32),
+    /// The DLL doesn't export `FlutterEngineGetProcAddresses`, so it isn't an embedder engine.
+    MissingProcTable(PathBuf),
+    /// `FlutterEngineGetProcAddresses` failed.
+    ProcTable(PathBuf, FlutterEngineResult),
+    /// The engine doesn't provide these functions, which means that it is older than the bindings.
+    MissingProcs(PathBuf, Vec<&'static str>),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Library(path, code) => {
+                write!(f, "failed to load {} (error {code})", path.display())
+            }
+            LoadError::MissingProcTable(path) => {
+                write!(f, "{} is not a flutter embedder engine", path.display())
+            }
+            LoadError::ProcTable(path, result) => write!(
+                f,
+                "failed to get proc addresses from {} (result {result})",
+                path.display()
+            ),
+            LoadError::MissingProcs(path, names) => write!(
+                f,
+                "{} is older than the engine that the bindings were generated for, and is missing {}",
+                path.display(),
+                names.join(", ")
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+/// Loads the engine from `path`, if it hasn't already been loaded. The engine can only be loaded
+/// once per process, since it is never unloaded.
+pub fn load(path: &Path) -> Result<(), LoadError> {
+    if ENGINE.get().is_some() {
+        return Ok(());
+    }
+
+    let engine = unsafe { load_engine(path)? };
+
+    // Loses to another thread loading it at the same time, which is harmless since the library is
+    // just referenced once more.
+    let _ = ENGINE.set(engine);
+
+    Ok(())
+}
+
+/// The path that the engine was loaded from.
+pub fn loaded_path() -> Option<&'static Path> {
+    ENGINE.get().map(|engine| engine.path.as_path())
+}
+
+unsafe fn load_engine(path: &Path) -> Result<Engine, LoadError> {
+    let name = wide(path.as_os_str());
+    let module = LoadLibraryW(name.as_ptr());
+    if module.is_null() {
+        return Err(LoadError::Library(path.to_owned(), GetLastError()));
+    }
+
+    let get_proc_addresses = GetProcAddress(module, c"FlutterEngineGetProcAddresses".as_ptr());
+    if get_proc_addresses.is_null() {
+        return Err(LoadError::MissingProcTable(path.to_owned()));
+    }
+
+    let get_proc_addresses: GetProcAddressesFn = std::mem::transmute(get_proc_addresses);
+
+    let mut procs = FlutterEngineProcTable {
+        struct_size: std::mem::size_of::<FlutterEngineProcTable>(),
+        ..Default::default()
+    };
+
+    let result = get_proc_addresses(&mut procs);
+    if result != FlutterEngineResult_kSuccess {
+        return Err(LoadError::ProcTable(path.to_owned(), result));
+    }
+
+    // Older engines leave the entries that they don't know about null.
+    let missing = missing_procs(&procs);
+    if !missing.is_empty() {
+        return Err(LoadError::MissingProcs(path.to_owned(), missing));
+    }
+
+    Ok(Engine {
+        path: path.to_owned(),
+        procs,
+    })
+}
+
+fn wide(s: &OsStr) -> Vec<u16> {
+    s.encode_wide().chain([0]).collect()
+}
+
+/// An engine function, which derefs to the function pointer (so that it can be called directly).
+///
+/// Panics if the engine hasn't been loaded, or doesn't provide the function.
+pub struct EngineProc<T: 'static> {
+    name: &'static str,
+    get: fn(&'static Engine) -> &'static T,
+}
+
+impl<F: 'static> Deref for EngineProc<Option<F>> {
+    type Target = F;
+
+    fn deref(&self) -> &F {
+        let engine = ENGINE
+            .get()
+            .expect("the flutter engine has not been loaded");
+        (self.get)(engine)
+            .as_ref()
+            .unwrap_or_else(|| panic!("the flutter engine does not provide {}", self.name))
+    }
+}
+
+macro_rules! engine_procs {
+    ($($name:ident: $ty:ident = $field:ident;)*) => {
+        $(
+            pub static $name: EngineProc<$ty> = EngineProc {
+                name: stringify!($name),
+                get: |engine| &engine.procs.$field,
+            };
+        )*
+
+        fn missing_procs(procs: &FlutterEngineProcTable) -> Vec<&'static str> {
+            let mut missing = vec![];
+            $(
+                if procs.$field.is_none() {
+                    missing.push(stringify!($name));
+                }
+            )*
+            missing
+        }
+    };
+}
+
+engine_procs! {
+    FlutterEngineInitialize: FlutterEngineInitializeFnPtr = Initialize;
+    FlutterEngineRunInitialized: FlutterEngineRunInitializedFnPtr = RunInitialized;
+    FlutterEngineShutdown: FlutterEngineShutdownFnPtr = Shutdown;
+    FlutterEngineSendWindowMetricsEvent: FlutterEngineSendWindowMetricsEventFnPtr = SendWindowMetricsEvent;
+    FlutterEngineSendPointerEvent: FlutterEngineSendPointerEventFnPtr = SendPointerEvent;
+    FlutterEngineSendKeyEvent: FlutterEngineSendKeyEventFnPtr = SendKeyEvent;
+    FlutterEngineSendPlatformMessage: FlutterEngineSendPlatformMessageFnPtr = SendPlatformMessage;
+    FlutterPlatformMessageCreateResponseHandle: FlutterEnginePlatformMessageCreateResponseHandleFnPtr = PlatformMessageCreateResponseHandle;
+    FlutterPlatformMessageReleaseResponseHandle: FlutterEnginePlatformMessageReleaseResponseHandleFnPtr = PlatformMessageReleaseResponseHandle;
+    FlutterEngineSendPlatformMessageResponse: FlutterEngineSendPlatformMessageResponseFnPtr = SendPlatformMessageResponse;
+    FlutterEngineRegisterExternalTexture: FlutterEngineRegisterExternalTextureFnPtr = RegisterExternalTexture;
+    FlutterEngineUnregisterExternalTexture: FlutterEngineUnregisterExternalTextureFnPtr = UnregisterExternalTexture;
+    FlutterEngineMarkExternalTextureFrameAvailable: FlutterEngineMarkExternalTextureFrameAvailableFnPtr = MarkExternalTextureFrameAvailable;
+    FlutterEngineUpdateSemanticsEnabled: FlutterEngineUpdateSemanticsEnabledFnPtr = UpdateSemanticsEnabled;
+    FlutterEngineUpdateAccessibilityFeatures: FlutterEngineUpdateAccessibilityFeaturesFnPtr = UpdateAccessibilityFeatures;
+    FlutterEngineDispatchSemanticsAction: FlutterEngineDispatchSemanticsActionFnPtr = DispatchSemanticsAction;
+    FlutterEngineOnVsync: FlutterEngineOnVsyncFnPtr = OnVsync;
+    FlutterEngineTraceEventDurationBegin: FlutterEngineTraceEventDurationBeginFnPtr = TraceEventDurationBegin;
+    FlutterEngineTraceEventDurationEnd: FlutterEngineTraceEventDurationEndFnPtr = TraceEventDurationEnd;
+    FlutterEngineTraceEventInstant: FlutterEngineTraceEventInstantFnPtr = TraceEventInstant;
+    FlutterEngineGetCurrentTime: FlutterEngineGetCurrentTimeFnPtr = GetCurrentTime;
+    FlutterEngineRunTask: FlutterEngineRunTaskFnPtr = RunTask;
+    FlutterEngineUpdateLocales: FlutterEngineUpdateLocalesFnPtr = UpdateLocales;
+    FlutterEngineNotifyDisplayUpdate: FlutterEngineNotifyDisplayUpdateFnPtr = NotifyDisplayUpdate;
+    FlutterEnginePostDartObject: FlutterEnginePostDartObjectFnPtr = PostDartObject;
+}
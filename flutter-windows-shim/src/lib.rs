@@ -0,0 +1,107 @@
+//! A stand-in for `flutter_windows.dll`, which C++ plugins are linked against. Each function is
+//! forwarded to the function with the same name exported by the executable that loaded the
+//! plugin, so this needs to be placed next to the plugin DLLs.
+//!
+//! Handles and callbacks are passed through untouched, so they are all declared as pointers.
+
+#![allow(non_snake_case, clippy::missing_safety_doc)]
+
+use std::ffi::c_void;
+use std::sync::OnceLock;
+
+use windows::core::PCSTR;
+use windows::Win32::System::LibraryLoader::{GetModuleHandleW, GetProcAddress};
+
+type Ptr = *mut c_void;
+
+/// Looks up `name` in the executable. Plugins can be loaded by an executable that is older than
+/// the shim, so a missing function is logged rather than taking down the process.
+fn resolve(name: &'static str) -> Option<usize> {
+    let display_name = name.trim_end_matches('\0');
+    unsafe {
+        let exe = match GetModuleHandleW(None) {
+            Ok(exe) => exe,
+            Err(e) => {
+                eprintln!(
+                    "flutter_windows shim: failed to get executable module for {display_name}: {e}"
+                );
+                return None;
+            }
+        };
+
+        let f = GetProcAddress(exe, PCSTR(name.as_ptr()));
+        if f.is_none() {
+            eprintln!("flutter_windows shim: {display_name} is not exported by the executable");
+        }
+
+        f.map(|f| f as usize)
+    }
+}
+
+/// What a forwarded function returns when the executable doesn't export it, which is what the
+/// real embedder returns on failure.
+trait Unresolved {
+    fn unresolved() -> Self;
+}
+
+impl Unresolved for () {
+    fn unresolved() {}
+}
+
+impl Unresolved for bool {
+    fn unresolved() -> bool {
+        false
+    }
+}
+
+impl Unresolved for Ptr {
+    fn unresolved() -> Ptr {
+        std::ptr::null_mut()
+    }
+}
+
+impl Unresolved for i64 {
+    fn unresolved() -> i64 {
+        -1
+    }
+}
+
+macro_rules! forward {
+    ($(fn $name:ident($($arg:ident: $ty:ty),*) $(-> $ret:ty)?;)*) => {
+        $(
+            #[no_mangle]
+            pub unsafe extern "C" fn $name($($arg: $ty),*) $(-> $ret)? {
+                static FUNCTION: OnceLock<Option<usize>> = OnceLock::new();
+                let Some(f) = *FUNCTION.get_or_init(|| resolve(concat!(stringify!($name), "\0"))) else {
+                    return Unresolved::unresolved();
+                };
+                let f = std::mem::transmute::<usize, unsafe extern "C" fn($($ty),*) $(-> $ret)?>(f);
+                f($($arg),*)
+            }
+        )*
+    };
+}
+
+forward! {
+    fn FlutterDesktopMessengerSend(messenger: Ptr, channel: Ptr, message: Ptr, message_size: usize) -> bool;
+    fn FlutterDesktopMessengerSendWithReply(messenger: Ptr, channel: Ptr, message: Ptr, message_size: usize, reply: Ptr, user_data: Ptr) -> bool;
+    fn FlutterDesktopMessengerSendResponse(messenger: Ptr, handle: Ptr, data: Ptr, data_length: usize);
+    fn FlutterDesktopMessengerSetCallback(messenger: Ptr, channel: Ptr, callback: Ptr, user_data: Ptr);
+    fn FlutterDesktopMessengerAddRef(messenger: Ptr) -> Ptr;
+    fn FlutterDesktopMessengerRelease(messenger: Ptr);
+    fn FlutterDesktopMessengerIsAvailable(messenger: Ptr) -> bool;
+    fn FlutterDesktopMessengerLock(messenger: Ptr) -> Ptr;
+    fn FlutterDesktopMessengerUnlock(messenger: Ptr);
+    fn FlutterDesktopPluginRegistrarGetMessenger(registrar: Ptr) -> Ptr;
+    fn FlutterDesktopRegistrarGetTextureRegistrar(registrar: Ptr) -> Ptr;
+    fn FlutterDesktopPluginRegistrarSetDestructionHandler(registrar: Ptr, callback: Ptr);
+    fn FlutterDesktopPluginRegistrarGetView(registrar: Ptr) -> Ptr;
+    fn FlutterDesktopPluginRegistrarGetViewById(registrar: Ptr, view_id: i64) -> Ptr;
+    fn FlutterDesktopPluginRegistrarRegisterTopLevelWindowProcDelegate(registrar: Ptr, delegate: Ptr, user_data: Ptr);
+    fn FlutterDesktopPluginRegistrarUnregisterTopLevelWindowProcDelegate(registrar: Ptr, delegate: Ptr);
+    fn FlutterDesktopViewGetHWND(view: Ptr) -> Ptr;
+    fn FlutterDesktopViewGetGraphicsAdapter(view: Ptr) -> Ptr;
+    fn FlutterDesktopTextureRegistrarRegisterExternalTexture(texture_registrar: Ptr, info: Ptr) -> i64;
+    fn FlutterDesktopTextureRegistrarUnregisterExternalTexture(texture_registrar: Ptr, texture_id: i64, callback: Ptr, user_data: Ptr);
+    fn FlutterDesktopTextureRegistrarMarkExternalTextureFrameAvailable(texture_registrar: Ptr, texture_id: i64) -> bool;
+}
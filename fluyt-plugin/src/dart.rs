@@ -0,0 +1,366 @@
+//! Messaging with Dart isolates through ports, which avoids encoding messages for a platform
+//! channel and copying them through the platform thread.
+//!
+//! Values are posted to a Dart `SendPort` by its `nativePort`. To receive from Dart, a plugin opens
+//! a [`NativePort`] and gives Dart a `SendPort` for it with [`NativePort::send_to`]:
+//!
+//! ```dart
+//! final receivePort = ReceivePort();
+//! await channel.invokeMethod('connect', receivePort.sendPort.nativePort);
+//! final sendPort = await receivePort.first as SendPort;
+//! sendPort.send(Uint8List(1024));
+//! ```
+//!
+//! Native ports need the Dart VM's API, so the app has to pass `NativeApi.initializeApiDLData` to
+//! `flion/native_ports` first (see the embedder's `native_port` module).
+
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::{ptr, slice};
+
+use crate::RawDestroyCallback;
+
+pub type RawNativePortCallback =
+    unsafe extern "C" fn(user_data: *mut c_void, port: i64, message: *mut RawDartCObject);
+
+/// The layout of `FlutterEngineDartObject` in `flutter_embedder.h`.
+#[repr(C)]
+pub struct RawDartObject {
+    pub type_: u32,
+    pub value: RawDartObjectValue,
+}
+
+#[repr(C)]
+pub union RawDartObjectValue {
+    pub bool_value: bool,
+    pub int32_value: i32,
+    pub int64_value: i64,
+    pub double_value: f64,
+    pub string_value: *const c_char,
+    pub buffer_value: *const RawDartBuffer,
+}
+
+/// The layout of `FlutterEngineDartBuffer` in `flutter_embedder.h`.
+#[repr(C)]
+pub struct RawDartBuffer {
+    pub struct_size: usize,
+    pub user_data: *mut c_void,
+    pub buffer: *mut u8,
+    pub buffer_size: usize,
+    pub buffer_collect_callback: Option<unsafe extern "C" fn(user_data: *mut c_void)>,
+}
+
+pub const DART_OBJECT_TYPE_NULL: u32 = 0;
+pub const DART_OBJECT_TYPE_BOOL: u32 = 1;
+pub const DART_OBJECT_TYPE_INT32: u32 = 2;
+pub const DART_OBJECT_TYPE_INT64: u32 = 3;
+pub const DART_OBJECT_TYPE_DOUBLE: u32 = 4;
+pub const DART_OBJECT_TYPE_STRING: u32 = 5;
+pub const DART_OBJECT_TYPE_BUFFER: u32 = 6;
+
+/// The layout of `Dart_CObject` in `dart_native_api.h`, restricted to the types that are decoded
+/// by [`DartValue`].
+#[repr(C)]
+pub struct RawDartCObject {
+    pub type_: u32,
+    pub value: RawDartCObjectValue,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub union RawDartCObjectValue {
+    pub as_bool: bool,
+    pub as_int32: i32,
+    pub as_int64: i64,
+    pub as_double: f64,
+    pub as_string: *const c_char,
+    pub as_send_port: RawDartSendPort,
+    pub as_array: RawDartArray,
+    pub as_typed_data: RawDartTypedData,
+    /// The largest member, `as_external_typed_data`, which isn't decoded.
+    _size: [usize; 5],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct RawDartSendPort {
+    pub id: i64,
+    pub origin_id: i64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct RawDartArray {
+    pub length: isize,
+    pub values: *mut *mut RawDartCObject,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct RawDartTypedData {
+    pub type_: u32,
+    pub length: isize,
+    pub values: *const u8,
+}
+
+pub const DART_C_OBJECT_NULL: u32 = 0;
+pub const DART_C_OBJECT_BOOL: u32 = 1;
+pub const DART_C_OBJECT_INT32: u32 = 2;
+pub const DART_C_OBJECT_INT64: u32 = 3;
+pub const DART_C_OBJECT_DOUBLE: u32 = 4;
+pub const DART_C_OBJECT_STRING: u32 = 5;
+pub const DART_C_OBJECT_ARRAY: u32 = 6;
+pub const DART_C_OBJECT_TYPED_DATA: u32 = 7;
+pub const DART_C_OBJECT_EXTERNAL_TYPED_DATA: u32 = 8;
+pub const DART_C_OBJECT_SEND_PORT: u32 = 9;
+pub const DART_C_OBJECT_UNMODIFIABLE_EXTERNAL_TYPED_DATA: u32 = 13;
+
+/// `Dart_TypedData_kUint8` in `dart_api.h`. This is the type of a `Uint8List`.
+const DART_TYPED_DATA_UINT8: u32 = 2;
+
+/// A value to post to a Dart port, which is received as the equivalent Dart value (buffers as a
+/// `Uint8List`).
+pub enum DartObject<'a> {
+    Null,
+    Bool(bool),
+    I32(i32),
+    I64(i64),
+    F64(f64),
+    Str(&'a CStr),
+    /// Copied by the engine.
+    Buffer(&'a [u8]),
+    /// Handed to Dart without copying, and freed once it has been garbage collected.
+    OwnedBuffer(Vec<u8>),
+}
+
+/// A message received on a [`NativePort`], which borrows from the VM's copy of the message.
+#[derive(Debug)]
+pub enum DartValue<'a> {
+    Null,
+    Bool(bool),
+    I32(i32),
+    I64(i64),
+    F64(f64),
+    Str(&'a CStr),
+    List(Vec<DartValue<'a>>),
+    /// A `Uint8List`. Other typed data is [`DartValue::Unsupported`].
+    Bytes(&'a [u8]),
+    /// A `SendPort`, by its native port.
+    SendPort(i64),
+    /// The `Dart_CObject_Type` of a value that isn't decoded.
+    Unsupported(u32),
+}
+
+impl<'a> DartValue<'a> {
+    /// # Safety
+    ///
+    /// `object` must be a valid `Dart_CObject` that outlives `'a`.
+    pub unsafe fn from_raw(object: &'a RawDartCObject) -> DartValue<'a> {
+        let value = &object.value;
+        match object.type_ {
+            DART_C_OBJECT_NULL => DartValue::Null,
+            DART_C_OBJECT_BOOL => DartValue::Bool(value.as_bool),
+            DART_C_OBJECT_INT32 => DartValue::I32(value.as_int32),
+            DART_C_OBJECT_INT64 => DartValue::I64(value.as_int64),
+            DART_C_OBJECT_DOUBLE => DartValue::F64(value.as_double),
+            DART_C_OBJECT_STRING => DartValue::Str(CStr::from_ptr(value.as_string)),
+            DART_C_OBJECT_ARRAY => {
+                let array = value.as_array;
+                let values = if array.values.is_null() {
+                    &[]
+                } else {
+                    slice::from_raw_parts(array.values, array.length as usize)
+                };
+                DartValue::List(
+                    values
+                        .iter()
+                        .map(|value| DartValue::from_raw(&**value))
+                        .collect(),
+                )
+            }
+            // External typed data starts with the same members.
+            DART_C_OBJECT_TYPED_DATA
+            | DART_C_OBJECT_EXTERNAL_TYPED_DATA
+            | DART_C_OBJECT_UNMODIFIABLE_EXTERNAL_TYPED_DATA
+                if value.as_typed_data.type_ == DART_TYPED_DATA_UINT8 =>
+            {
+                let data = value.as_typed_data;
+                DartValue::Bytes(if data.values.is_null() {
+                    &[]
+                } else {
+                    slice::from_raw_parts(data.values, data.length as usize)
+                })
+            }
+            DART_C_OBJECT_SEND_PORT => DartValue::SendPort(value.as_send_port.id),
+            type_ => DartValue::Unsupported(type_),
+        }
+    }
+}
+
+pub(crate) type PostDartObject =
+    unsafe extern "C" fn(*const c_void, i64, *const RawDartObject) -> bool;
+pub(crate) type OpenNativePort = unsafe extern "C" fn(
+    *const c_void,
+    *const c_char,
+    RawNativePortCallback,
+    *mut c_void,
+    Option<RawDestroyCallback>,
+) -> i64;
+pub(crate) type CloseNativePort = unsafe extern "C" fn(*const c_void, i64) -> bool;
+pub(crate) type PostCObject = unsafe extern "C" fn(*const c_void, i64, *mut RawDartCObject) -> bool;
+
+/// Posts to Dart ports and opens native ports. This can be used from any thread.
+#[derive(Clone, Copy)]
+pub struct DartPorts {
+    pub(crate) context: *const c_void,
+    pub(crate) post_dart_object: PostDartObject,
+    pub(crate) open_native_port: OpenNativePort,
+    pub(crate) close_native_port: CloseNativePort,
+    pub(crate) post_c_object: PostCObject,
+}
+
+unsafe impl Send for DartPorts {}
+unsafe impl Sync for DartPorts {}
+
+impl DartPorts {
+    /// Posts `object` to the Dart `SendPort` whose `nativePort` is `port`. Returns `false` if it
+    /// couldn't be posted, e.g. because the port has been closed.
+    pub fn post(&self, port: i64, object: DartObject) -> bool {
+        unsafe extern "C" fn free_buffer(user_data: *mut c_void) {
+            drop(Box::from_raw(user_data.cast::<Vec<u8>>()));
+        }
+
+        let mut buffer = RawDartBuffer {
+            struct_size: std::mem::size_of::<RawDartBuffer>(),
+            user_data: ptr::null_mut(),
+            buffer: ptr::null_mut(),
+            buffer_size: 0,
+            buffer_collect_callback: None,
+        };
+
+        let (type_, value) = match object {
+            DartObject::Null => (DART_OBJECT_TYPE_NULL, RawDartObjectValue { int64_value: 0 }),
+            DartObject::Bool(value) => (
+                DART_OBJECT_TYPE_BOOL,
+                RawDartObjectValue { bool_value: value },
+            ),
+            DartObject::I32(value) => (
+                DART_OBJECT_TYPE_INT32,
+                RawDartObjectValue { int32_value: value },
+            ),
+            DartObject::I64(value) => (
+                DART_OBJECT_TYPE_INT64,
+                RawDartObjectValue { int64_value: value },
+            ),
+            DartObject::F64(value) => (
+                DART_OBJECT_TYPE_DOUBLE,
+                RawDartObjectValue {
+                    double_value: value,
+                },
+            ),
+            DartObject::Str(value) => (
+                DART_OBJECT_TYPE_STRING,
+                RawDartObjectValue {
+                    string_value: value.as_ptr(),
+                },
+            ),
+            DartObject::Buffer(bytes) => {
+                buffer.buffer = bytes.as_ptr().cast_mut();
+                buffer.buffer_size = bytes.len();
+                (
+                    DART_OBJECT_TYPE_BUFFER,
+                    RawDartObjectValue {
+                        buffer_value: &buffer,
+                    },
+                )
+            }
+            DartObject::OwnedBuffer(bytes) => {
+                let bytes = Box::leak(Box::new(bytes));
+                buffer.buffer = bytes.as_mut_ptr();
+                buffer.buffer_size = bytes.len();
+                buffer.user_data = (bytes as *mut Vec<u8>).cast();
+                buffer.buffer_collect_callback = Some(free_buffer);
+                (
+                    DART_OBJECT_TYPE_BUFFER,
+                    RawDartObjectValue {
+                        buffer_value: &buffer,
+                    },
+                )
+            }
+        };
+
+        let object = RawDartObject { type_, value };
+        unsafe { (self.post_dart_object)(self.context, port, &object) }
+    }
+
+    /// Opens a native port that calls `handler` with each message sent to it. Messages are handled
+    /// one at a time, on one of the Dart VM's threads.
+    ///
+    /// Returns `None` if Dart hasn't initialized the API yet, or the port couldn't be created.
+    pub fn open<F>(&self, name: &str, handler: F) -> Option<NativePort>
+    where
+        F: Fn(DartValue) + Send + Sync + 'static,
+    {
+        unsafe extern "C" fn callback<F: Fn(DartValue)>(
+            user_data: *mut c_void,
+            _port: i64,
+            message: *mut RawDartCObject,
+        ) {
+            if let Some(message) = message.as_ref() {
+                (*user_data.cast::<F>())(DartValue::from_raw(message));
+            }
+        }
+
+        unsafe extern "C" fn destroy<F>(user_data: *mut c_void) {
+            drop(Box::from_raw(user_data.cast::<F>()));
+        }
+
+        let name = CString::new(name).expect("port name contains a nul byte");
+        let user_data = Box::into_raw(Box::new(handler));
+
+        let id = unsafe {
+            (self.open_native_port)(
+                self.context,
+                name.as_ptr(),
+                callback::<F>,
+                user_data.cast(),
+                Some(destroy::<F>),
+            )
+        };
+
+        (id != 0).then_some(NativePort { id, ports: *self })
+    }
+}
+
+/// A port that Dart can send messages to, which is closed when this is dropped.
+pub struct NativePort {
+    id: i64,
+    ports: DartPorts,
+}
+
+impl NativePort {
+    pub fn id(&self) -> i64 {
+        self.id
+    }
+
+    /// Posts a `SendPort` for this port to the Dart port `port`, which is how Dart gets hold of
+    /// it.
+    pub fn send_to(&self, port: i64) -> bool {
+        let mut object = RawDartCObject {
+            type_: DART_C_OBJECT_SEND_PORT,
+            value: RawDartCObjectValue {
+                as_send_port: RawDartSendPort {
+                    id: self.id,
+                    origin_id: 0,
+                },
+            },
+        };
+
+        unsafe { (self.ports.post_c_object)(self.ports.context, port, &mut object) }
+    }
+}
+
+impl Drop for NativePort {
+    fn drop(&mut self) {
+        unsafe { (self.ports.close_native_port)(self.ports.context, self.id) };
+    }
+}
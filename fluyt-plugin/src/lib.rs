@@ -0,0 +1,335 @@
+//! The API for plugins that are loaded by fluyt at startup.
+//!
+//! A plugin is a `cdylib` that exports its registration function with [`export_plugin!`]:
+//!
+//! ```ignore
+//! fn register(registrar: &fluyt_plugin::Registrar) {
+//!     registrar.set_message_handler("my_plugin", |message, response| {
+//!         response.send(message);
+//!     });
+//! }
+//!
+//! fluyt_plugin::export_plugin!(register);
+//! ```
+//!
+//! Only C ABI types cross the library boundary, so plugins don't need to be built with the same
+//! compiler version as the embedder.
+
+use std::ffi::{c_char, c_void, CString};
+
+mod dart;
+
+pub use dart::*;
+
+/// Incremented whenever [`RawRegistrar`] changes incompatibly.
+pub const ABI_VERSION: u32 = 3;
+
+/// The name of the function exported by [`export_plugin!`].
+pub const ENTRY_POINT: &str = "fluyt_plugin_register";
+
+pub type RawEntryPoint = unsafe extern "C" fn(*const RawRegistrar) -> bool;
+
+pub type RawMessageCallback = unsafe extern "C" fn(
+    user_data: *mut c_void,
+    message: *const u8,
+    message_size: usize,
+    response: *mut RawResponseHandle,
+);
+
+type SendResponse = unsafe extern "C" fn(*mut RawResponseHandle, *const u8, usize);
+
+pub type RawDestroyCallback = unsafe extern "C" fn(user_data: *mut c_void);
+
+/// A pending response to a message, which must be passed to `send_response` exactly once.
+#[repr(C)]
+pub struct RawResponseHandle {
+    _private: [u8; 0],
+}
+
+/// The registrar passed to a plugin's entry point, which is only valid during the call.
+#[repr(C)]
+pub struct RawRegistrar {
+    pub abi_version: u32,
+    /// Passed to the functions below. This remains valid after registration, for as long as the
+    /// process is running.
+    pub context: *const c_void,
+    /// The top level window.
+    pub window_handle: isize,
+    /// Sets the handler for a channel, or removes it if `callback` is null. `destroy` is called
+    /// with `user_data` when the handler is replaced or removed.
+    pub set_message_handler: unsafe extern "C" fn(
+        context: *const c_void,
+        channel: *const c_char,
+        callback: Option<RawMessageCallback>,
+        user_data: *mut c_void,
+        destroy: Option<RawDestroyCallback>,
+    ),
+    /// Sends a message to the framework. This can be called from any thread.
+    pub send_message: unsafe extern "C" fn(
+        context: *const c_void,
+        channel: *const c_char,
+        message: *const u8,
+        message_size: usize,
+    ) -> bool,
+    pub send_response:
+        unsafe extern "C" fn(response: *mut RawResponseHandle, data: *const u8, data_size: usize),
+    /// Returns a new reference to the `ID3D11Device` that the engine renders with, which the
+    /// caller must release. This can be called from any thread.
+    pub get_d3d11_device: unsafe extern "C" fn(context: *const c_void) -> *mut c_void,
+    /// Returns the ANGLE `EGLDisplay` and the `EGLConfig` of the engine's contexts. This can be
+    /// called from any thread.
+    pub get_egl_display:
+        unsafe extern "C" fn(context: *const c_void, config: *mut *mut c_void) -> *mut c_void,
+    /// Creates an `EGLContext` that shares objects with the engine's contexts, or returns null on
+    /// failure. The caller must destroy it with `eglDestroyContext`. This can be called from any
+    /// thread.
+    pub create_egl_share_context: unsafe extern "C" fn(context: *const c_void) -> *mut c_void,
+    /// Posts an object to a Dart port, returning `false` on failure. This can be called from any
+    /// thread.
+    pub post_dart_object: unsafe extern "C" fn(
+        context: *const c_void,
+        port: i64,
+        object: *const RawDartObject,
+    ) -> bool,
+    /// Opens a native port that calls `callback` with each message sent to it, or returns 0 on
+    /// failure. `destroy` is called with `user_data` once the port is closed, or immediately on
+    /// failure. This can be called from any thread.
+    pub open_native_port: unsafe extern "C" fn(
+        context: *const c_void,
+        name: *const c_char,
+        callback: RawNativePortCallback,
+        user_data: *mut c_void,
+        destroy: Option<RawDestroyCallback>,
+    ) -> i64,
+    pub close_native_port: unsafe extern "C" fn(context: *const c_void, port: i64) -> bool,
+    /// Posts a `Dart_CObject` to a Dart port, returning `false` on failure. This can be called from
+    /// any thread.
+    pub post_c_object: unsafe extern "C" fn(
+        context: *const c_void,
+        port: i64,
+        object: *mut RawDartCObject,
+    ) -> bool,
+}
+
+pub struct Registrar<'a> {
+    raw: &'a RawRegistrar,
+}
+
+impl<'a> Registrar<'a> {
+    /// Returns `None` if the registrar was created by an incompatible embedder.
+    ///
+    /// # Safety
+    ///
+    /// `raw` must be the registrar passed to the plugin's entry point.
+    pub unsafe fn from_raw(raw: *const RawRegistrar) -> Option<Registrar<'a>> {
+        let raw = raw.as_ref()?;
+        if raw.abi_version != ABI_VERSION {
+            return None;
+        }
+        Some(Registrar { raw })
+    }
+
+    /// The `HWND` of the top level window.
+    pub fn window_handle(&self) -> isize {
+        self.raw.window_handle
+    }
+
+    pub fn messenger(&self) -> Messenger {
+        Messenger {
+            context: self.raw.context,
+            send_message: self.raw.send_message,
+        }
+    }
+
+    pub fn gpu(&self) -> Gpu {
+        Gpu {
+            context: self.raw.context,
+            get_d3d11_device: self.raw.get_d3d11_device,
+            get_egl_display: self.raw.get_egl_display,
+            create_egl_share_context: self.raw.create_egl_share_context,
+        }
+    }
+
+    pub fn dart_ports(&self) -> DartPorts {
+        DartPorts {
+            context: self.raw.context,
+            post_dart_object: self.raw.post_dart_object,
+            open_native_port: self.raw.open_native_port,
+            close_native_port: self.raw.close_native_port,
+            post_c_object: self.raw.post_c_object,
+        }
+    }
+
+    /// Sets the handler for messages on `channel`. Handlers are called on the platform thread.
+    pub fn set_message_handler<F>(&self, channel: &str, handler: F)
+    where
+        F: Fn(&[u8], Response) + 'static,
+    {
+        struct Handler<F> {
+            handler: F,
+            send_response: SendResponse,
+        }
+
+        unsafe extern "C" fn callback<F: Fn(&[u8], Response)>(
+            user_data: *mut c_void,
+            message: *const u8,
+            message_size: usize,
+            response: *mut RawResponseHandle,
+        ) {
+            let handler = &*user_data.cast::<Handler<F>>();
+            let message = if message.is_null() {
+                &[]
+            } else {
+                std::slice::from_raw_parts(message, message_size)
+            };
+            (handler.handler)(
+                message,
+                Response {
+                    handle: response,
+                    send_response: handler.send_response,
+                },
+            );
+        }
+
+        unsafe extern "C" fn destroy<F>(user_data: *mut c_void) {
+            drop(Box::from_raw(user_data.cast::<Handler<F>>()));
+        }
+
+        let channel = CString::new(channel).expect("channel name contains a nul byte");
+        let user_data = Box::into_raw(Box::new(Handler {
+            handler,
+            send_response: self.raw.send_response,
+        }));
+
+        unsafe {
+            (self.raw.set_message_handler)(
+                self.raw.context,
+                channel.as_ptr(),
+                Some(callback::<F>),
+                user_data.cast(),
+                Some(destroy::<F>),
+            );
+        }
+    }
+
+    pub fn remove_message_handler(&self, channel: &str) {
+        let channel = CString::new(channel).expect("channel name contains a nul byte");
+        unsafe {
+            (self.raw.set_message_handler)(
+                self.raw.context,
+                channel.as_ptr(),
+                None,
+                std::ptr::null_mut(),
+                None,
+            );
+        }
+    }
+}
+
+/// Sends messages to the framework. This can be used from any thread.
+#[derive(Clone, Copy)]
+pub struct Messenger {
+    context: *const c_void,
+    send_message: unsafe extern "C" fn(*const c_void, *const c_char, *const u8, usize) -> bool,
+}
+
+unsafe impl Send for Messenger {}
+unsafe impl Sync for Messenger {}
+
+impl Messenger {
+    /// Returns `false` if the message couldn't be sent, e.g. because the engine isn't running.
+    pub fn send(&self, channel: &str, message: &[u8]) -> bool {
+        let Ok(channel) = CString::new(channel) else {
+            return false;
+        };
+        unsafe {
+            (self.send_message)(
+                self.context,
+                channel.as_ptr(),
+                message.as_ptr(),
+                message.len(),
+            )
+        }
+    }
+}
+
+/// Gives access to the GPU resources that the engine renders with, so that plugins can create
+/// resources (e.g. textures) that the engine can use. This can be used from any thread.
+///
+/// The device and display are replaced if the device is lost, after which resources created from
+/// the old ones can't be used. Plugins should query them again rather than keeping them.
+#[derive(Clone, Copy)]
+pub struct Gpu {
+    context: *const c_void,
+    get_d3d11_device: unsafe extern "C" fn(*const c_void) -> *mut c_void,
+    get_egl_display: unsafe extern "C" fn(*const c_void, *mut *mut c_void) -> *mut c_void,
+    create_egl_share_context: unsafe extern "C" fn(*const c_void) -> *mut c_void,
+}
+
+unsafe impl Send for Gpu {}
+unsafe impl Sync for Gpu {}
+
+impl Gpu {
+    /// Returns a new reference to the `ID3D11Device`, which the caller owns (e.g. with
+    /// `ID3D11Device::from_raw` in the `windows` crate).
+    pub fn d3d11_device(&self) -> *mut c_void {
+        unsafe { (self.get_d3d11_device)(self.context) }
+    }
+
+    /// Returns the `EGLDisplay` and the `EGLConfig` of the engine's contexts.
+    pub fn egl_display(&self) -> (*mut c_void, *mut c_void) {
+        let mut config = std::ptr::null_mut();
+        let display = unsafe { (self.get_egl_display)(self.context, &mut config) };
+        (display, config)
+    }
+
+    /// Creates an `EGLContext` that shares objects with the engine's contexts, which the caller
+    /// must destroy. Returns `None` on failure.
+    pub fn create_egl_share_context(&self) -> Option<*mut c_void> {
+        let context = unsafe { (self.create_egl_share_context)(self.context) };
+        (!context.is_null()).then_some(context)
+    }
+}
+
+/// The response to a message. If this is dropped without being sent, an empty response is sent,
+/// which the framework treats as the method not being implemented.
+pub struct Response {
+    handle: *mut RawResponseHandle,
+    send_response: SendResponse,
+}
+
+impl Response {
+    pub fn send(self, data: &[u8]) {
+        unsafe { (self.send_response)(self.handle, data.as_ptr(), data.len()) };
+        std::mem::forget(self);
+    }
+}
+
+impl Drop for Response {
+    fn drop(&mut self) {
+        unsafe { (self.send_response)(self.handle, std::ptr::null(), 0) };
+    }
+}
+
+/// Exports `$register` (a `fn(&Registrar)`) as the plugin's entry point. If it panics, the plugin
+/// fails to load.
+#[macro_export]
+macro_rules! export_plugin {
+    ($register:path) => {
+        #[no_mangle]
+        pub unsafe extern "C" fn fluyt_plugin_register(raw: *const $crate::RawRegistrar) -> bool {
+            // Unwinding into the embedder is undefined behaviour, so a panic fails registration
+            // instead.
+            let register =
+                std::panic::AssertUnwindSafe(|| match $crate::Registrar::from_raw(raw) {
+                    Some(registrar) => {
+                        $register(&registrar);
+                        true
+                    }
+                    None => false,
+                });
+
+            std::panic::catch_unwind(register).unwrap_or(false)
+        }
+    };
+}
@@ -0,0 +1,165 @@
+// C API for embedding a Flutter view in an existing Win32 application, provided by flion.dll.
+//
+// All functions must be called on the thread that owns the window the engine is attached to. The
+// engine's tasks are run by that thread's message loop.
+
+#ifndef FLION_H_
+#define FLION_H_
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <windows.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct FlionEngine FlionEngine;
+typedef struct FlionResponseHandle FlionResponseHandle;
+
+typedef enum {
+  // Frames start at each display refresh.
+  kFlionFramePacingVsync = 0,
+  // Frames start at display refreshes, at most max_frame_rate times a second.
+  kFlionFramePacingCapped = 1,
+  // Frames start as soon as the engine asks for them, for benchmarking.
+  kFlionFramePacingUncapped = 2,
+} FlionFramePacing;
+
+// The pixel format that layers are rendered in.
+typedef enum {
+  kFlionSurfaceFormatBgra8 = 0,
+  kFlionSurfaceFormatRgba8 = 1,
+} FlionSurfaceFormat;
+
+typedef struct {
+  // Must be set to sizeof(FlionEngineConfig).
+  size_t struct_size;
+  // Path to the flutter_assets directory.
+  const char* assets_path;
+  // Path to icudtl.dat.
+  const char* icu_data_path;
+  // May be NULL.
+  const char* initial_route;
+  FlionFramePacing frame_pacing;
+  // Only used with kFlionFramePacingCapped.
+  uint32_t max_frame_rate;
+  FlionSurfaceFormat surface_format;
+  // Samples per pixel for multisample antialiasing, limited to what the GPU supports. 0 or 1
+  // disables multisampling.
+  uint32_t msaa_samples;
+  // Path to flutter_engine.dll. If NULL, it is found as described in flion_engine_create.
+  const char* engine_path;
+  // Where the engine caches compiled shaders across runs. If NULL, nothing is cached.
+  const char* persistent_cache_path;
+  // Read from the cache without adding to it.
+  bool persistent_cache_read_only;
+  // Extra command line switches for the engine, e.g. "--trace-startup". May be NULL if
+  // engine_switch_count is 0.
+  const char* const* engine_switches;
+  size_t engine_switch_count;
+  // Flags for the Dart VM, e.g. "--enable-asserts". Release builds of the engine only accept a few
+  // of them. The engine splits the flags on commas, so attaching the engine fails if a flag
+  // contains one. May be NULL if dart_flag_count is 0.
+  const char* const* dart_flags;
+  size_t dart_flag_count;
+} FlionEngineConfig;
+
+typedef enum {
+  kFlionPointerDown = 0,
+  kFlionPointerUp = 1,
+  kFlionPointerMove = 2,
+  kFlionPointerHover = 3,
+  kFlionPointerAdd = 4,
+  kFlionPointerRemove = 5,
+  // Ends the current gesture without an up event, e.g. when the host loses focus.
+  kFlionPointerCancel = 6,
+} FlionPointerPhase;
+
+// Mouse buttons that are pressed, which can be combined.
+typedef enum {
+  kFlionPointerButtonPrimary = 1 << 0,
+  kFlionPointerButtonSecondary = 1 << 1,
+  kFlionPointerButtonMiddle = 1 << 2,
+  kFlionPointerButtonBack = 1 << 3,
+  kFlionPointerButtonForward = 1 << 4,
+} FlionPointerButtons;
+
+// Called with messages sent by the framework. Every message must be responded to with
+// flion_engine_send_response.
+typedef void (*FlionMessageCallback)(const uint8_t* message,
+                                     size_t message_size,
+                                     FlionResponseHandle* response,
+                                     void* user_data);
+
+// Called with a captured frame, as straight alpha RGBA rows from top to bottom. The pixels are
+// only valid for the duration of the call.
+typedef void (*FlionFrameCallback)(const uint8_t* pixels,
+                                   uint32_t width,
+                                   uint32_t height,
+                                   void* user_data);
+
+// Creates an engine, which isn't run until it is attached to a window. Returns NULL on failure.
+//
+// The engine DLL is loaded by the first engine that is created, from engine_path if it is set,
+// or else the path in the FLUYT_ENGINE environment variable, failing if that path doesn't exist.
+// Otherwise it is loaded from flutter_engine.dll next to the executable, then
+// %LOCALAPPDATA%\fluyt\engine\flutter_engine.dll.
+FlionEngine* flion_engine_create(const FlionEngineConfig* config);
+
+// Launches the engine, rendering into the client area of hwnd. The window must outlive the
+// engine.
+bool flion_engine_attach_hwnd(FlionEngine* engine, HWND hwnd);
+
+// Launches the engine, rendering into a new child of visual (an
+// ABI::Windows::UI::Composition::IContainerVisual*) instead of the window's client area. The host's
+// compositor commits the engine's changes. hwnd is still used to run the engine's tasks.
+bool flion_engine_attach_visual(FlionEngine* engine, HWND hwnd, void* visual);
+
+// Sends a mouse event at a position in physical pixels relative to the window. buttons is a
+// combination of FlionPointerButtons, pressed at the time of the event. Changes to the pressed
+// buttons while another is held down are sent as moves.
+bool flion_engine_send_pointer(FlionEngine* engine,
+                               FlionPointerPhase phase,
+                               double x,
+                               double y,
+                               int64_t buttons);
+
+// Sends a platform message to the framework.
+bool flion_engine_send_message(FlionEngine* engine,
+                               const char* channel,
+                               const uint8_t* message,
+                               size_t message_size);
+
+// Sets the callback for messages on a channel, or removes it if callback is NULL.
+bool flion_engine_set_message_handler(FlionEngine* engine,
+                                      const char* channel,
+                                      FlionMessageCallback callback,
+                                      void* user_data);
+
+// Responds to a message. A NULL data responds that the message wasn't handled.
+void flion_engine_send_response(FlionResponseHandle* response,
+                                const uint8_t* data,
+                                size_t data_size);
+
+// Captures the latest composited frame, calling callback before returning.
+bool flion_engine_capture_frame(FlionEngine* engine,
+                                FlionFrameCallback callback,
+                                void* user_data);
+
+// Inserts a visual (an ABI::Windows::UI::Composition::IVisual* created by the view's compositor)
+// below or above the Flutter content, positioned in physical pixels from the top left of the view.
+bool flion_engine_insert_visual(FlionEngine* engine, void* visual, bool above);
+
+// Removes a visual that was inserted with flion_engine_insert_visual.
+bool flion_engine_remove_visual(FlionEngine* engine, void* visual);
+
+// Shuts down the engine and frees it.
+void flion_engine_destroy(FlionEngine* engine);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif
+
+#endif  // FLION_H_
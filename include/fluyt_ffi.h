@@ -0,0 +1,52 @@
+// Functions exported by the fluyt executable for Dart to call through dart:ffi, e.g. with
+// DynamicLibrary.executable(). These can be called from the Dart UI thread.
+
+#ifndef FLUYT_FFI_H_
+#define FLUYT_FFI_H_
+
+#include <stdbool.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+// A rectangle in physical pixels, in virtual screen coordinates.
+typedef struct {
+  int32_t left;
+  int32_t top;
+  int32_t right;
+  int32_t bottom;
+} FluytRect;
+
+typedef struct {
+  FluytRect bounds;
+  // The bounds excluding the taskbar and docked windows.
+  FluytRect work_area;
+  uint32_t dpi;
+  bool is_primary;
+} FluytMonitorInfo;
+
+// Returns the HWND of the top level window, or 0 if it hasn't been created yet.
+intptr_t fluyt_get_window_handle(void);
+
+// Returns the window's DPI, where 96 is a scale factor of 1, or 0 if there is no window.
+uint32_t fluyt_get_dpi(void);
+
+// Fills in info for the monitor that the window is on. Returns false on failure, in which case
+// info is left unchanged.
+bool fluyt_get_monitor_info(FluytMonitorInfo* info);
+
+bool fluyt_clipboard_has_text(void);
+
+// Returns the text on the clipboard as UTF-8, or NULL if there is none. The string must be freed
+// with fluyt_free_string.
+char* fluyt_clipboard_get_text(void);
+
+void fluyt_free_string(char* string);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif
+
+#endif  // FLUYT_FFI_H_
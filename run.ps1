@@ -16,10 +16,19 @@ if (!(Test-Path ".\build\windows-x64-embedder")) {
     Expand-Archive ".\build\windows-x64-embedder.zip" -DestinationPath ".\build\windows-x64-embedder"
 }
 
+# Read by the bindings and at startup to check that the engine matches the header. Only written
+# when it changes, since the bindings are regenerated when it does.
+$engine_version = ".\build\windows-x64-embedder\engine.version"
+if (!(Test-Path $engine_version) -or (Get-Content $engine_version) -ne $flutter_engine_commit) {
+    Set-Content $engine_version $flutter_engine_commit -NoNewline
+}
+
 if (!(Test-Path ".\target\debug\flutter_engine.dll")) {
     Copy-Item ".\build\windows-x64-embedder\flutter_engine.dll" ".\target\debug\flutter_engine.dll"
 }
 
+Copy-Item $engine_version ".\target\debug\flutter_engine.version"
+
 $angle_version = "2023-04-01-23-12"
 $extract_angle = $false
 
@@ -0,0 +1,92 @@
+use std::cell::Cell;
+use std::rc::Rc;
+
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::engine::FlutterEngine;
+use crate::error;
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ExitType {
+    /// The app exits without asking the framework.
+    Required,
+    /// The framework is asked first, and can cancel the exit (e.g. to save changes).
+    Cancelable,
+}
+
+/// Implements the framework's cancellable exit protocol, where closing the window (or a call to
+/// `System.exitApplication`) first asks the framework with `System.requestAppExit`.
+#[derive(Clone, Default)]
+pub struct AppExit {
+    /// Set once the framework has sent `System.initializationComplete`. Until then, there is
+    /// nothing listening for exit requests and the app exits straight away.
+    initialized: Rc<Cell<bool>>,
+    /// Whether the framework is currently being asked, so that closing the window again while
+    /// e.g. an "unsaved changes" dialog is open doesn't ask again.
+    pending: Rc<Cell<bool>>,
+}
+
+impl AppExit {
+    pub fn new() -> AppExit {
+        AppExit::default()
+    }
+
+    pub fn set_initialized(&self) {
+        self.initialized.set(true);
+    }
+
+    /// Calls `exit` if the app should exit, once the framework has responded to the request.
+    pub fn request(
+        &self,
+        engine: &FlutterEngine,
+        exit_type: ExitType,
+        exit: impl FnOnce() + 'static,
+    ) -> error::Result<()> {
+        if matches!(exit_type, ExitType::Required) || !self.initialized.get() {
+            exit();
+            return Ok(());
+        }
+
+        if self.pending.replace(true) {
+            return Ok(());
+        }
+
+        let message = json!({
+            "method": "System.requestAppExit",
+            "args": {
+                "type": "cancelable",
+            },
+        });
+
+        let pending = self.pending.clone();
+
+        engine.send_platform_message_with_reply(
+            c"flutter/platform",
+            &serde_json::to_vec(&message)?,
+            move |reply| {
+                pending.set(false);
+
+                #[derive(Deserialize)]
+                struct Response {
+                    response: String,
+                }
+
+                // An empty reply means that nothing handled the request.
+                let response = match serde_json::from_slice::<[Response; 1]>(reply) {
+                    Ok([response]) => response.response,
+                    Err(_) if reply.is_empty() => "exit".to_owned(),
+                    Err(e) => {
+                        tracing::error!("invalid exit response: {e}");
+                        "exit".to_owned()
+                    }
+                };
+
+                if response == "exit" {
+                    exit();
+                }
+            },
+        )
+    }
+}
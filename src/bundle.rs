@@ -0,0 +1,129 @@
+use std::ffi::CString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::{self, bail, OptionExt};
+
+use crate::engine;
+use crate::paths;
+
+#[cfg(feature = "embed-icu-data")]
+static ICU_DATA: Option<&[u8]> = Some(include_bytes!(concat!(env!("OUT_DIR"), "/icudtl.dat")));
+#[cfg(not(feature = "embed-icu-data"))]
+static ICU_DATA: Option<&[u8]> = None;
+
+/// The archive written by the build script, see `ASSETS_ARCHIVE` in `build.rs`.
+#[cfg(feature = "embed-assets")]
+static ASSETS: Option<&[u8]> = Some(include_bytes!(concat!(
+    env!("OUT_DIR"),
+    "/flutter_assets.bin"
+)));
+#[cfg(not(feature = "embed-assets"))]
+static ASSETS: Option<&[u8]> = None;
+
+/// The engine only reads ICU data and assets from disk, so files that are embedded in the
+/// executable are extracted to a directory that is named after their hash. They are only written
+/// again when the embedded files change.
+fn extract_dir() -> eyre::Result<PathBuf> {
+    Ok(paths::local_app_data_dir()?.join(concat!("bundle-", env!("FLUYT_BUNDLE_HASH"))))
+}
+
+pub fn assets_embedded() -> bool {
+    ASSETS.is_some()
+}
+
+/// The path to the ICU data, extracting it first if it is embedded.
+pub fn icu_data_path() -> eyre::Result<CString> {
+    let Some(data) = ICU_DATA else {
+        return Ok(engine::ICU_DATA_PATH.to_owned());
+    };
+
+    let path = extract_dir()?.join("icudtl.dat");
+    if !path.exists() {
+        tracing::info!(path = %path.display(), "extracting icu data");
+        write_atomically(&path, |temp_path| Ok(fs::write(temp_path, data)?))?;
+    }
+
+    path_to_cstring(&path)
+}
+
+/// The path to the assets directory, extracting it first if it is embedded.
+pub fn assets_path() -> eyre::Result<CString> {
+    let Some(archive) = ASSETS else {
+        return Ok(engine::ASSETS_PATH.to_owned());
+    };
+
+    let path = extract_dir()?.join("flutter_assets");
+    if !path.exists() {
+        tracing::info!(path = %path.display(), "extracting assets");
+        write_atomically(&path, |temp_path| extract_archive(archive, temp_path))?;
+    }
+
+    path_to_cstring(&path)
+}
+
+/// Writes to a temporary path that is then renamed, so that a partially written file or directory
+/// isn't used if the app exits in the middle of extracting it.
+///
+/// Several instances can be started at once on the first run, so each one writes to its own
+/// temporary path, and whichever finishes last finds that the path already exists.
+fn write_atomically(
+    path: &Path,
+    write: impl FnOnce(&Path) -> eyre::Result<()>,
+) -> eyre::Result<()> {
+    let temp_path = path.with_extension(format!("{}.tmp", std::process::id()));
+    if temp_path.is_dir() {
+        fs::remove_dir_all(&temp_path)?;
+    }
+
+    fs::create_dir_all(path.parent().ok_or_eyre("invalid path")?)?;
+    write(&temp_path)?;
+
+    if let Err(e) = fs::rename(&temp_path, path) {
+        if !path.exists() {
+            return Err(e.into());
+        }
+
+        tracing::info!(path = %path.display(), "already extracted by another instance");
+        let _ = if temp_path.is_dir() {
+            fs::remove_dir_all(&temp_path)
+        } else {
+            fs::remove_file(&temp_path)
+        };
+    }
+
+    Ok(())
+}
+
+fn extract_archive(mut archive: &[u8], dir: &Path) -> eyre::Result<()> {
+    fn take<'a>(archive: &mut &'a [u8]) -> eyre::Result<&'a [u8]> {
+        let Some((len, rest)) = archive.split_first_chunk::<8>() else {
+            bail!("truncated assets archive");
+        };
+
+        let len = u64::from_le_bytes(*len) as usize;
+        if rest.len() < len {
+            bail!("truncated assets archive");
+        }
+
+        let (bytes, rest) = rest.split_at(len);
+        *archive = rest;
+        Ok(bytes)
+    }
+
+    while !archive.is_empty() {
+        let name = std::str::from_utf8(take(&mut archive)?)?;
+        let data = take(&mut archive)?;
+
+        let path = dir.join(name);
+        fs::create_dir_all(path.parent().ok_or_eyre("invalid asset path")?)?;
+        fs::write(path, data)?;
+    }
+
+    Ok(())
+}
+
+fn path_to_cstring(path: &Path) -> eyre::Result<CString> {
+    let path = path.to_str().ok_or_eyre("path is not valid unicode")?;
+    Ok(CString::new(path)?)
+}
@@ -0,0 +1,123 @@
+use std::io::Cursor;
+use std::str::FromStr;
+use std::sync::OnceLock;
+use std::time::Instant;
+
+use flutter_codec::EncodableValue;
+
+static FILTER: OnceLock<ChannelFilter> = OnceLock::new();
+
+/// Selects which channels are logged, from a comma separated list of channel names. Names can end
+/// with `*` to match any channel with that prefix, and names prefixed with `-` exclude channels,
+/// e.g. `flion/*,-flion/video`. A list of only exclusions (or an empty list) logs every other
+/// channel.
+#[derive(Clone, Debug, Default)]
+pub struct ChannelFilter {
+    allow: Vec<String>,
+    deny: Vec<String>,
+}
+
+impl FromStr for ChannelFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut filter = ChannelFilter::default();
+
+        for pattern in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            match pattern.strip_prefix('-') {
+                Some("") => return Err(format!("expected a channel name after '-' in '{s}'")),
+                Some(pattern) => filter.deny.push(pattern.to_owned()),
+                None => filter.allow.push(pattern.to_owned()),
+            }
+        }
+
+        Ok(filter)
+    }
+}
+
+impl ChannelFilter {
+    fn matches(&self, channel: &str) -> bool {
+        let matches = |pattern: &String| match pattern.strip_suffix('*') {
+            Some(prefix) => channel.starts_with(prefix),
+            None => channel == pattern,
+        };
+
+        (self.allow.is_empty() || self.allow.iter().any(matches)) && !self.deny.iter().any(matches)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Direction {
+    /// Sent by the engine to a handler.
+    Incoming,
+    /// Sent to the engine.
+    Outgoing,
+}
+
+/// Starts logging platform messages on channels matching `filter`. Can only be enabled once.
+pub fn enable(filter: ChannelFilter) {
+    if FILTER.set(filter).is_err() {
+        tracing::warn!("channel logging is already enabled");
+    }
+}
+
+/// Logs a platform message if its channel is being logged. The returned value should be finished
+/// when the reply is received, to log the reply.
+pub fn message(direction: Direction, channel: &str, message: &[u8]) -> Option<PendingReply> {
+    if !FILTER.get()?.matches(channel) {
+        return None;
+    }
+
+    let method = decode_method(message);
+
+    tracing::info!(
+        ?direction,
+        channel,
+        method = method.as_deref(),
+        size = message.len(),
+        "platform message"
+    );
+
+    Some(PendingReply {
+        direction,
+        channel: channel.to_owned(),
+        method,
+        start: Instant::now(),
+    })
+}
+
+/// A logged message that is waiting for a reply.
+pub struct PendingReply {
+    direction: Direction,
+    channel: String,
+    method: Option<String>,
+    start: Instant,
+}
+
+impl PendingReply {
+    /// Logs the reply to the message, which is `None` if the channel (or method) isn't handled.
+    pub fn finish(self, reply: Option<&[u8]>) {
+        tracing::info!(
+            direction = ?self.direction,
+            channel = self.channel.as_str(),
+            method = self.method.as_deref(),
+            size = reply.map(|reply| reply.len()),
+            latency = ?self.start.elapsed(),
+            "platform message reply"
+        );
+    }
+}
+
+/// Returns the method name if the message is a method call, encoded with either the standard or
+/// the JSON method codec.
+fn decode_method(message: &[u8]) -> Option<String> {
+    if message.first() == Some(&b'{') {
+        let value = serde_json::from_slice::<serde_json::Value>(message).ok()?;
+        return value.get("method")?.as_str().map(str::to_owned);
+    }
+
+    match flutter_codec::read_value(&mut Cursor::new(message)) {
+        Ok(EncodableValue::Str(method)) => Some(method.to_owned()),
+        _ => None,
+    }
+}
@@ -0,0 +1,263 @@
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use clap::Parser;
+use windows::UI::Color;
+
+use crate::channel_log::ChannelFilter;
+use crate::compositor::{ResizeAnchor, SurfaceFormat};
+use crate::size_constraints::Size;
+use crate::system_keys::ShortcutPolicy;
+use crate::task_runner::ThreadPriority;
+use crate::window_effects::{Backdrop, BorderColor, CornerPreference};
+
+#[derive(Debug, Parser)]
+pub struct Args {
+    /// Limit hover pointer events to at most one per frame. Events sent while a button is pressed
+    /// are not affected.
+    #[arg(long)]
+    pub throttle_hover: bool,
+
+    /// The system backdrop material drawn behind the window.
+    #[arg(long, value_enum, default_value_t)]
+    pub backdrop: Backdrop,
+
+    /// How the corners of the window are rounded, on Windows 11.
+    #[arg(long, value_enum, default_value_t)]
+    pub corner_preference: CornerPreference,
+
+    /// The color of the window border on Windows 11, as `#RRGGBB`, `default` or `none`.
+    #[arg(long)]
+    pub border_color: Option<BorderColor>,
+
+    /// Hide the window's content from screenshots and screen sharing, from the start.
+    #[arg(long)]
+    pub content_protection: bool,
+
+    /// The minimum size of the window content, as `<width>x<height>` in logical pixels.
+    #[arg(long)]
+    pub min_size: Option<Size>,
+
+    /// The maximum size of the window content, as `<width>x<height>` in logical pixels.
+    #[arg(long)]
+    pub max_size: Option<Size>,
+
+    /// Lock the window content to this aspect ratio (width / height) while resizing.
+    #[arg(long)]
+    pub aspect_ratio: Option<f64>,
+
+    /// Keep the window hidden until Flutter has rendered its first frame.
+    #[arg(long)]
+    pub wait_for_first_frame: bool,
+
+    /// How long to wait for the first frame before showing the window anyway, in milliseconds.
+    #[arg(long, default_value_t = 5000)]
+    pub first_frame_timeout: u64,
+
+    /// A color to fill the window with until the first frame is rendered, as `#RRGGBB` or
+    /// `#AARRGGBB`.
+    #[arg(long)]
+    pub splash_color: Option<HexColor>,
+
+    /// An image to show centered in the window until the first frame is rendered.
+    #[arg(long)]
+    pub splash_image: Option<String>,
+
+    /// Save the window position and size on exit and restore it on the next launch.
+    #[arg(long)]
+    pub remember_window_placement: bool,
+
+    /// Disable the Dart VM service (debug and profile builds only).
+    #[arg(long)]
+    pub disable_vm_service: bool,
+
+    /// The host that the Dart VM service binds to.
+    #[arg(long)]
+    pub vm_service_host: Option<String>,
+
+    /// The port that the Dart VM service binds to. Uses a random port if not specified.
+    #[arg(long)]
+    pub vm_service_port: Option<u16>,
+
+    /// A switch to pass to the engine, e.g. `--trace-startup`. Can be given multiple times.
+    #[arg(long = "engine-switch", allow_hyphen_values = true)]
+    pub engine_switches: Vec<String>,
+
+    /// A flag to pass to the Dart VM, e.g. `--enable-asserts` or `--old_gen_heap_size=1024`. Can
+    /// be given multiple times. Release builds of the engine only accept a few flags. Flags can't
+    /// contain commas, since the engine splits them on commas.
+    #[arg(long = "dart-flag", allow_hyphen_values = true)]
+    pub dart_flags: Vec<String>,
+
+    /// Where the engine caches compiled shaders across runs, so that they are only compiled on the
+    /// first run. Defaults to `shader_cache` in the app's local app data directory.
+    #[arg(long, conflicts_with = "no_persistent_cache")]
+    pub persistent_cache_dir: Option<PathBuf>,
+
+    /// Don't cache compiled shaders across runs.
+    #[arg(long)]
+    pub no_persistent_cache: bool,
+
+    /// Read from the shader cache without adding to it, e.g. for a cache shipped with the app.
+    #[arg(long)]
+    pub persistent_cache_read_only: bool,
+
+    /// Watch the assets directory and hot reload when it changes. Requires the VM service.
+    #[arg(long)]
+    pub watch: bool,
+
+    /// Run Dart code on the platform thread, so that platform channel handlers are called
+    /// synchronously on the thread that owns the window.
+    #[arg(long)]
+    pub merged_platform_ui_thread: bool,
+
+    /// Render at most this many frames a second, e.g. to save power. Frames are still synchronized
+    /// to the display.
+    #[arg(long, conflicts_with = "no_vsync", value_parser = clap::value_parser!(u32).range(1..))]
+    pub max_fps: Option<u32>,
+
+    /// Render at most this many frames a second and turn off the backdrop while the system is on
+    /// battery or in Battery Saver.
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub power_saving_max_fps: Option<u32>,
+
+    /// Start frames as soon as the engine asks for them instead of waiting for the display, for
+    /// measuring the maximum frame rate.
+    #[arg(long)]
+    pub no_vsync: bool,
+
+    /// The pixel format that layers are rendered in.
+    #[arg(long, value_enum, default_value_t)]
+    pub surface_format: SurfaceFormat,
+
+    /// Render layers with multisample antialiasing, using this many samples per pixel. This is
+    /// limited to what the GPU supports.
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..=16))]
+    pub msaa_samples: u32,
+
+    /// Which corner of the window the last frame stays pinned to while the window is resized ahead
+    /// of the next frame. `auto` pins the corner opposite the edges being dragged.
+    #[arg(long, value_enum, default_value_t)]
+    pub resize_anchor: ResizeAnchor,
+
+    /// A color to fill the part of the window that the last frame doesn't cover while the window
+    /// is being resized, as `#RRGGBB` or `#AARRGGBB`.
+    #[arg(long)]
+    pub resize_letterbox_color: Option<HexColor>,
+
+    /// Relaunch the engine on a new D3D device when the GPU is reset or removed, instead of exiting
+    /// with an error. All Dart state is lost, since the engine's GPU resources can't be moved to
+    /// another device, so the app should check `flion/device_loss` to restore what it can.
+    #[arg(long)]
+    pub relaunch_on_device_loss: bool,
+
+    /// Priority of the engine's background worker threads.
+    #[arg(long, value_enum, default_value_t = ThreadPriority::BelowNormal)]
+    pub worker_thread_priority: ThreadPriority,
+
+    /// Priority of the engine's UI thread.
+    #[arg(long, value_enum, default_value_t = ThreadPriority::AboveNormal)]
+    pub ui_thread_priority: ThreadPriority,
+
+    /// Priority of the engine's raster thread.
+    #[arg(long, value_enum, default_value_t = ThreadPriority::AboveNormal)]
+    pub raster_thread_priority: ThreadPriority,
+
+    /// Register the raster thread with the multimedia class scheduler service.
+    #[arg(long)]
+    pub raster_mmcss: bool,
+
+    /// The route that the app should start on.
+    #[arg(long)]
+    pub route: Option<String>,
+
+    /// Registers the app as the handler for URIs with this scheme.
+    #[arg(long)]
+    pub protocol: Option<String>,
+
+    /// The engine DLL to load. Defaults to `$FLUYT_ENGINE`, then `flutter_engine.dll` next to the
+    /// executable, then `%LOCALAPPDATA%\fluyt\engine\flutter_engine.dll`.
+    #[arg(long)]
+    pub engine: Option<PathBuf>,
+
+    /// A plugin DLL to load, either a Rust plugin built with `fluyt-plugin` or a C++ Flutter plugin
+    /// built against the official Windows embedder. Can be given multiple times.
+    #[arg(long = "plugin")]
+    pub plugins: Vec<PathBuf>,
+
+    /// Run the app as an integration test. Results and screenshots reported by
+    /// `package:integration_test` are written to this directory, and the app exits once all tests
+    /// have finished, with a non-zero exit code if any failed. The VM service is always enabled, so
+    /// that `flutter_driver` can connect.
+    #[arg(long)]
+    pub integration_test_output: Option<PathBuf>,
+
+    /// Record the pointer and keyboard input sent to the engine to this file, for reproducing bugs
+    /// with `--replay-input`.
+    #[arg(long)]
+    pub record_input: Option<PathBuf>,
+
+    /// Replay input recorded with `--record-input`, with the same timing relative to launch. The
+    /// window should be the same size as when the input was recorded, since positions are not
+    /// scaled.
+    #[arg(long)]
+    pub replay_input: Option<PathBuf>,
+
+    /// Write the embedder's trace events (frame presentation, commits, vsync waits) to this file,
+    /// in the Chrome trace event format, for loading into chrome://tracing or Perfetto.
+    #[arg(long)]
+    pub trace_to_file: Option<PathBuf>,
+
+    /// Log every platform message (channel, method, size and reply latency), for debugging
+    /// plugins. Optionally takes a comma separated list of channels to log, where names ending
+    /// with `*` match a prefix and names prefixed with `-` are excluded, e.g.
+    /// `--log-channels=flion/*,-flion/video`.
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "")]
+    pub log_channels: Option<ChannelFilter>,
+
+    /// Whether Alt+F4 closes the window when the app doesn't handle it.
+    #[arg(long, value_enum, default_value_t)]
+    pub close_shortcut: ShortcutPolicy,
+
+    /// Whether Alt+Space opens the window menu when the app doesn't handle it.
+    #[arg(long, value_enum, default_value_t)]
+    pub window_menu_shortcut: ShortcutPolicy,
+
+    /// Whether F10, Alt and Alt+<letter> activate the menu bar when the app doesn't handle them.
+    #[arg(long, value_enum, default_value_t)]
+    pub menu_bar_shortcut: ShortcutPolicy,
+
+    /// Show the performance overlay on launch. It can also be toggled with Ctrl+Shift+P.
+    #[arg(long)]
+    pub perf_overlay: bool,
+
+    /// A deep link that the app was launched with.
+    pub uri: Option<String>,
+}
+
+/// A color parsed from `#RRGGBB` or `#AARRGGBB`.
+#[derive(Clone, Copy, Debug)]
+pub struct HexColor(pub Color);
+
+impl FromStr for HexColor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = s.trim_start_matches('#');
+        let value = u32::from_str_radix(hex, 16).map_err(|e| e.to_string())?;
+        let value = match hex.len() {
+            6 => 0xff000000 | value,
+            8 => value,
+            _ => return Err(format!("expected #RRGGBB or #AARRGGBB, found '{s}'")),
+        };
+
+        let [b, g, r, a] = value.to_le_bytes();
+
+        Ok(HexColor(Color {
+            A: a,
+            R: r,
+            G: g,
+            B: b,
+        }))
+    }
+}
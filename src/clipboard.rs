@@ -0,0 +1,433 @@
+use std::collections::BTreeMap;
+
+use color_eyre::eyre::{self, bail};
+use flutter_codec::EncodableValue;
+use windows::core::w;
+use windows::Win32::Foundation::{HANDLE, HGLOBAL, HWND};
+use windows::Win32::System::DataExchange::{
+    AddClipboardFormatListener, CloseClipboard, EmptyClipboard, GetClipboardData,
+    GetClipboardOwner, GetClipboardSequenceNumber, IsClipboardFormatAvailable, OpenClipboard,
+    RegisterClipboardFormatW, SetClipboardData,
+};
+use windows::Win32::System::Memory::{
+    GlobalAlloc, GlobalLock, GlobalSize, GlobalUnlock, GMEM_MOVEABLE,
+};
+use windows::Win32::System::Ole::{CF_DIB, CF_UNICODETEXT};
+
+use crate::event_channel::EventChannel;
+use crate::screenshot::Frame;
+use crate::standard_method_channel::{StandardMethodHandler, StandardMethodReply};
+
+const BI_RGB: u32 = 0;
+const BI_BITFIELDS: u32 = 3;
+const BITMAPINFOHEADER_SIZE: usize = 40;
+
+/// Closes the clipboard when dropped, so that it isn't left open if reading or writing fails.
+struct OpenedClipboard;
+
+impl OpenedClipboard {
+    fn open(hwnd: HWND) -> eyre::Result<OpenedClipboard> {
+        unsafe { OpenClipboard(hwnd)? };
+        Ok(OpenedClipboard)
+    }
+
+    fn read(&self, format: u32) -> eyre::Result<Option<Vec<u8>>> {
+        if !is_available(format) {
+            return Ok(None);
+        }
+
+        unsafe {
+            let memory = HGLOBAL(GetClipboardData(format)?.0 as _);
+            let data = GlobalLock(memory).cast::<u8>();
+            if data.is_null() {
+                return Ok(None);
+            }
+
+            let bytes = std::slice::from_raw_parts(data, GlobalSize(memory)).to_vec();
+
+            // This fails with no error once the memory is fully unlocked.
+            let _ = GlobalUnlock(memory);
+
+            Ok(Some(bytes))
+        }
+    }
+
+    fn write(&self, format: u32, bytes: &[u8]) -> eyre::Result<()> {
+        unsafe {
+            let memory = GlobalAlloc(GMEM_MOVEABLE, bytes.len())?;
+            let data = GlobalLock(memory).cast::<u8>();
+            std::ptr::copy_nonoverlapping(bytes.as_ptr(), data, bytes.len());
+            let _ = GlobalUnlock(memory);
+
+            // The clipboard owns the memory once this succeeds.
+            SetClipboardData(format, HANDLE(memory.0 as _))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for OpenedClipboard {
+    fn drop(&mut self) {
+        let _ = unsafe { CloseClipboard() };
+    }
+}
+
+/// The registered "HTML Format", which is HTML with a header giving the offsets of the copied
+/// fragment.
+fn html_format() -> u32 {
+    unsafe { RegisterClipboardFormatW(w!("HTML Format")) }
+}
+
+/// Browsers and most image editors put a png on the clipboard alongside the bitmap, which (unlike
+/// the bitmap) keeps transparency.
+fn png_format() -> u32 {
+    unsafe { RegisterClipboardFormatW(w!("PNG")) }
+}
+
+fn is_available(format: u32) -> bool {
+    unsafe { IsClipboardFormatAvailable(format).is_ok() }
+}
+
+pub fn has_text() -> bool {
+    is_available(CF_UNICODETEXT.0 as u32)
+}
+
+pub fn has_html() -> bool {
+    is_available(html_format())
+}
+
+pub fn has_image() -> bool {
+    is_available(png_format()) || is_available(CF_DIB.0 as u32)
+}
+
+/// The MIME types of the formats on the clipboard that can be read with `flion/clipboard`.
+fn available_formats() -> Vec<&'static str> {
+    [
+        ("text/plain", has_text()),
+        ("text/html", has_html()),
+        ("image/png", has_image()),
+    ]
+    .into_iter()
+    .filter(|(_, available)| *available)
+    .map(|(format, _)| format)
+    .collect()
+}
+
+pub fn get_text(hwnd: HWND) -> eyre::Result<Option<String>> {
+    let Some(bytes) = OpenedClipboard::open(hwnd)?.read(CF_UNICODETEXT.0 as u32)? else {
+        return Ok(None);
+    };
+
+    let text = bytes
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .take_while(|&c| c != 0)
+        .collect::<Vec<u16>>();
+
+    Ok(Some(String::from_utf16_lossy(&text)))
+}
+
+/// Returns the copied HTML fragment, without the surrounding document.
+pub fn get_html(hwnd: HWND) -> eyre::Result<Option<String>> {
+    let Some(bytes) = OpenedClipboard::open(hwnd)?.read(html_format())? else {
+        return Ok(None);
+    };
+
+    let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    let data = String::from_utf8_lossy(&bytes[..len]);
+
+    let offset = |name: &str| {
+        data.lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))
+            .and_then(|offset| offset.trim().parse::<usize>().ok())
+    };
+
+    let fragment = match (offset("StartFragment"), offset("EndFragment")) {
+        (Some(start), Some(end)) => bytes.get(start..end.min(len)),
+        _ => None,
+    };
+
+    Ok(fragment.map(|fragment| String::from_utf8_lossy(fragment).into_owned()))
+}
+
+/// Returns the copied image encoded as a png, converting it from a bitmap if there isn't a png on
+/// the clipboard.
+pub fn get_png(hwnd: HWND) -> eyre::Result<Option<Vec<u8>>> {
+    let clipboard = OpenedClipboard::open(hwnd)?;
+
+    if let Some(png) = clipboard.read(png_format())? {
+        return Ok(Some(png));
+    }
+
+    match clipboard.read(CF_DIB.0 as u32)? {
+        Some(dib) => Ok(Some(decode_dib(&dib)?.encode_png()?)),
+        None => Ok(None),
+    }
+}
+
+/// Data to write to the clipboard, which replaces its current contents. Each of the formats
+/// should be a representation of the same content.
+#[derive(Default)]
+pub struct ClipboardContents<'a> {
+    pub text: Option<&'a str>,
+    pub html: Option<&'a str>,
+    pub png: Option<&'a [u8]>,
+}
+
+pub fn set_text(hwnd: HWND, text: &str) -> eyre::Result<()> {
+    set(
+        hwnd,
+        &ClipboardContents {
+            text: Some(text),
+            ..Default::default()
+        },
+    )
+}
+
+pub fn set(hwnd: HWND, contents: &ClipboardContents) -> eyre::Result<()> {
+    // Decode the image first, so that the clipboard isn't left empty if it is invalid.
+    let dib = contents.png.map(Frame::decode).transpose()?.map(encode_dib);
+
+    let clipboard = OpenedClipboard::open(hwnd)?;
+
+    unsafe { EmptyClipboard()? };
+
+    if let Some(text) = contents.text {
+        let text = text
+            .encode_utf16()
+            .chain([0])
+            .flat_map(u16::to_le_bytes)
+            .collect::<Vec<u8>>();
+
+        clipboard.write(CF_UNICODETEXT.0 as u32, &text)?;
+    }
+
+    if let Some(html) = contents.html {
+        clipboard.write(html_format(), &encode_html(html))?;
+    }
+
+    if let (Some(png), Some(dib)) = (contents.png, dib) {
+        clipboard.write(png_format(), png)?;
+        clipboard.write(CF_DIB.0 as u32, &dib)?;
+    }
+
+    Ok(())
+}
+
+fn encode_html(fragment: &str) -> Vec<u8> {
+    const PREFIX: &str = "<html><body>\r\n<!--StartFragment-->";
+    const SUFFIX: &str = "<!--EndFragment-->\r\n</body></html>";
+
+    // The offsets are zero padded to a fixed width, so that the header's length doesn't depend on
+    // them.
+    let header =
+        |start_html: usize, end_html: usize, start_fragment: usize, end_fragment: usize| {
+            format!(
+                concat!(
+                    "Version:0.9\r\n",
+                    "StartHTML:{:010}\r\n",
+                    "EndHTML:{:010}\r\n",
+                    "StartFragment:{:010}\r\n",
+                    "EndFragment:{:010}\r\n",
+                ),
+                start_html, end_html, start_fragment, end_fragment,
+            )
+        };
+
+    let start_html = header(0, 0, 0, 0).len();
+    let start_fragment = start_html + PREFIX.len();
+    let end_fragment = start_fragment + fragment.len();
+    let end_html = end_fragment + SUFFIX.len();
+
+    let mut data = header(start_html, end_html, start_fragment, end_fragment);
+    data.push_str(PREFIX);
+    data.push_str(fragment);
+    data.push_str(SUFFIX);
+
+    let mut data = data.into_bytes();
+    data.push(0);
+    data
+}
+
+/// Converts a packed DIB (a `BITMAPINFOHEADER` followed by the pixels). Only uncompressed 24 and
+/// 32 bit bitmaps are supported, which is what apps put on the clipboard in practice.
+fn decode_dib(dib: &[u8]) -> eyre::Result<Frame> {
+    let u16_at = |i: usize| dib.get(i..i + 2).map(|b| u16::from_le_bytes([b[0], b[1]]));
+    let u32_at = |i: usize| {
+        dib.get(i..i + 4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    };
+
+    let (Some(header_size), Some(width), Some(height), Some(bit_count), Some(compression)) =
+        (u32_at(0), u32_at(4), u32_at(8), u16_at(14), u32_at(16))
+    else {
+        bail!("invalid bitmap header");
+    };
+
+    let width = width as i32;
+    let height = height as i32;
+    let top_down = height < 0;
+    let (width, height) = (width.unsigned_abs(), height.unsigned_abs());
+
+    let mut offset = header_size as usize;
+    match (compression, bit_count) {
+        (BI_RGB, 24 | 32) => {}
+        // The masks follow the header, unless they are part of a larger header. They are assumed
+        // to be the standard BGR ones.
+        (BI_BITFIELDS, 32) if offset == BITMAPINFOHEADER_SIZE => offset += 12,
+        (BI_BITFIELDS, 32) => {}
+        _ => bail!("unsupported bitmap format: {bit_count} bpp, compression {compression}"),
+    }
+
+    let bytes_per_pixel = bit_count as usize / 8;
+    let stride = (width as usize * bytes_per_pixel + 3) & !3;
+
+    let Some(data) = dib.get(offset..offset + stride * height as usize) else {
+        bail!("bitmap is truncated");
+    };
+
+    let mut pixels = Vec::with_capacity(width as usize * height as usize * 4);
+
+    for y in 0..height as usize {
+        let row = if top_down { y } else { height as usize - 1 - y };
+        let row = &data[row * stride..][..width as usize * bytes_per_pixel];
+
+        for pixel in row.chunks_exact(bytes_per_pixel) {
+            let alpha = if bytes_per_pixel == 4 { pixel[3] } else { 255 };
+            pixels.extend_from_slice(&[pixel[2], pixel[1], pixel[0], alpha]);
+        }
+    }
+
+    // The alpha channel of 32 bit bitmaps is often unused, and left as zero.
+    if bytes_per_pixel == 4 && pixels.chunks_exact(4).all(|pixel| pixel[3] == 0) {
+        pixels.chunks_exact_mut(4).for_each(|pixel| pixel[3] = 255);
+    }
+
+    Ok(Frame {
+        width,
+        height,
+        pixels,
+    })
+}
+
+/// Converts a frame to a bottom up, 32 bit packed DIB.
+fn encode_dib(frame: Frame) -> Vec<u8> {
+    let mut dib = Vec::with_capacity(BITMAPINFOHEADER_SIZE + frame.pixels.len());
+
+    dib.extend_from_slice(&(BITMAPINFOHEADER_SIZE as u32).to_le_bytes());
+    dib.extend_from_slice(&(frame.width as i32).to_le_bytes());
+    dib.extend_from_slice(&(frame.height as i32).to_le_bytes());
+    dib.extend_from_slice(&1u16.to_le_bytes()); // planes
+    dib.extend_from_slice(&32u16.to_le_bytes()); // bit count
+    dib.extend_from_slice(&BI_RGB.to_le_bytes());
+    dib.extend_from_slice(&(frame.pixels.len() as u32).to_le_bytes());
+    // The resolution and palette fields are unused.
+    dib.extend_from_slice(&[0; 16]);
+
+    for row in frame.pixels.chunks_exact(frame.width as usize * 4).rev() {
+        for pixel in row.chunks_exact(4) {
+            dib.extend_from_slice(&[pixel[2], pixel[1], pixel[0], pixel[3]]);
+        }
+    }
+
+    dib
+}
+
+/// Handles `flion/clipboard`, which extends the framework's plain text clipboard with HTML and
+/// images.
+pub struct ClipboardHandler {
+    hwnd: HWND,
+}
+
+impl ClipboardHandler {
+    pub fn new(hwnd: HWND) -> ClipboardHandler {
+        ClipboardHandler { hwnd }
+    }
+}
+
+impl StandardMethodHandler for ClipboardHandler {
+    fn handle(&self, method: &str, args: EncodableValue, reply: StandardMethodReply) {
+        match method {
+            "getFormats" => reply.success(&EncodableValue::List(
+                available_formats()
+                    .into_iter()
+                    .map(EncodableValue::Str)
+                    .collect(),
+            )),
+            "getText" => match get_text(self.hwnd) {
+                Ok(text) => reply.success(
+                    &text
+                        .as_deref()
+                        .map_or(EncodableValue::Null, EncodableValue::Str),
+                ),
+                Err(e) => reply.error("clipboard_error", Some(&format!("{e:?}"))),
+            },
+            "getHtml" => match get_html(self.hwnd) {
+                Ok(html) => reply.success(
+                    &html
+                        .as_deref()
+                        .map_or(EncodableValue::Null, EncodableValue::Str),
+                ),
+                Err(e) => reply.error("clipboard_error", Some(&format!("{e:?}"))),
+            },
+            "getImage" => match get_png(self.hwnd) {
+                Ok(png) => reply.success(
+                    &png.as_deref()
+                        .map_or(EncodableValue::Null, EncodableValue::U8List),
+                ),
+                Err(e) => reply.error("clipboard_error", Some(&format!("{e:?}"))),
+            },
+            "setData" => {
+                let contents = ClipboardContents {
+                    text: args.get("text").and_then(|v| v.as_string()),
+                    html: args.get("html").and_then(|v| v.as_string()),
+                    png: args.get("image").and_then(|v| v.as_u8_list()),
+                };
+
+                match set(self.hwnd, &contents) {
+                    Ok(()) => reply.success(&EncodableValue::Null),
+                    Err(e) => reply.error("clipboard_error", Some(&format!("{e:?}"))),
+                }
+            }
+            _ => {
+                tracing::warn!(method, "unimplemented");
+                reply.not_implemented();
+            }
+        }
+    }
+}
+
+/// Starts sending `WM_CLIPBOARDUPDATE` to the window whenever the clipboard changes. The listener is
+/// removed when the window is destroyed.
+pub fn listen_for_changes(hwnd: HWND) -> eyre::Result<()> {
+    unsafe { AddClipboardFormatListener(hwnd)? };
+    Ok(())
+}
+
+/// Handles `WM_CLIPBOARDUPDATE`, sending the new formats on `flion/clipboard/events`.
+pub fn handle_clipboard_update(hwnd: HWND, events: &EventChannel) -> eyre::Result<()> {
+    if !events.is_listening() {
+        return Ok(());
+    }
+
+    let formats = available_formats();
+    let sequence_number = unsafe { GetClipboardSequenceNumber() };
+    // Whether the change was made by this app, e.g. from `flion/clipboard`.
+    let is_local = unsafe { GetClipboardOwner() } == hwnd;
+
+    events.send(&EncodableValue::Map(BTreeMap::from_iter([
+        (
+            EncodableValue::Str("formats"),
+            EncodableValue::List(formats.into_iter().map(EncodableValue::Str).collect()),
+        ),
+        (
+            EncodableValue::Str("sequenceNumber"),
+            EncodableValue::I64(sequence_number.into()),
+        ),
+        (
+            EncodableValue::Str("isLocal"),
+            EncodableValue::Bool(is_local),
+        ),
+    ])))
+}
@@ -0,0 +1,52 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use windows::UI::Composition::Core::CompositorController;
+
+use crate::timeline;
+
+/// Coalesces commits of the window's visual tree.
+///
+/// Frames are committed as soon as they are presented. Other changes to the tree (e.g. the splash
+/// screen and the performance overlay) only request a commit, which is made once the event loop
+/// has run out of events, unless a frame has committed them first.
+pub struct CommitBatcher {
+    compositor_controller: CompositorController,
+    /// Set when changes have been made outside of a frame that haven't been committed yet.
+    pending: AtomicBool,
+}
+
+impl CommitBatcher {
+    pub fn new(compositor_controller: CompositorController) -> Arc<CommitBatcher> {
+        Arc::new(CommitBatcher {
+            compositor_controller,
+            pending: AtomicBool::new(false),
+        })
+    }
+
+    pub fn compositor_controller(&self) -> &CompositorController {
+        &self.compositor_controller
+    }
+
+    /// Commits a frame, along with any other changes that are waiting to be committed.
+    pub fn commit(&self) -> windows::core::Result<()> {
+        let _span = timeline::span(c"Commit");
+        self.pending.store(false, Ordering::Relaxed);
+        self.compositor_controller.Commit()
+    }
+
+    /// Requests a commit for changes that were made outside of a frame.
+    pub fn request_commit(&self) {
+        self.pending.store(true, Ordering::Relaxed);
+    }
+
+    /// Commits requested changes if they haven't been committed yet. Should be called once the
+    /// event loop has handled all pending events.
+    pub fn flush(&self) -> windows::core::Result<()> {
+        if !self.pending.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+
+        self.compositor_controller.Commit()
+    }
+}
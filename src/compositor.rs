@@ -1,66 +1,644 @@
 use std::ffi::c_void;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
 use std::{mem, ptr};
 
-use color_eyre::eyre;
+use clap::ValueEnum;
+use color_eyre::eyre::{self, bail, OptionExt};
 use flutter_embedder::{
     FlutterBackingStore, FlutterBackingStoreConfig,
     FlutterBackingStoreType_kFlutterBackingStoreTypeOpenGL, FlutterBackingStore__bindgen_ty_1,
     FlutterLayer, FlutterLayerContentType_kFlutterLayerContentTypeBackingStore,
-    FlutterOpenGLBackingStore, FlutterOpenGLBackingStore__bindgen_ty_1, FlutterOpenGLSurface,
-    FlutterOpenGLTargetType_kFlutterOpenGLTargetTypeSurface,
+    FlutterLayerContentType_kFlutterLayerContentTypePlatformView, FlutterOpenGLBackingStore,
+    FlutterOpenGLBackingStore__bindgen_ty_1, FlutterOpenGLFramebuffer, FlutterOpenGLSurface,
+    FlutterOpenGLTargetType_kFlutterOpenGLTargetTypeFramebuffer,
+    FlutterOpenGLTargetType_kFlutterOpenGLTargetTypeSurface, FlutterSize,
 };
 use khronos_egl::{self as egl};
 use windows::core::ComInterface;
-use windows::Foundation::Numerics::Vector2;
-use windows::Foundation::Size;
+use windows::Foundation::Numerics::{Matrix4x4, Vector2, Vector3};
+use windows::Foundation::{AsyncActionCompletedHandler, Size};
 use windows::Graphics::DirectX::{DirectXAlphaMode, DirectXPixelFormat};
-use windows::Win32::Foundation::POINT;
+use windows::Win32::Foundation::{POINT, WPARAM};
 use windows::Win32::Graphics::Direct3D11::{ID3D11Device, ID3D11Texture2D};
 use windows::Win32::Graphics::Dwm::DwmFlush;
 use windows::Win32::System::WinRT::Composition::{
-    ICompositionDrawingSurfaceInterop, ICompositorInterop,
+    ICompositionDrawingSurfaceInterop, ICompositionGraphicsDeviceInterop, ICompositorInterop,
 };
-use windows::UI::Composition::Core::CompositorController;
+use windows::Win32::UI::WindowsAndMessaging::{
+    WMSZ_BOTTOMLEFT, WMSZ_LEFT, WMSZ_TOP, WMSZ_TOPLEFT, WMSZ_TOPRIGHT,
+};
+use windows::UI::Color;
 use windows::UI::Composition::{
-    CompositionDrawingSurface, CompositionGraphicsDevice, ContainerVisual, SpriteVisual,
+    self as composition, CompositionDrawingSurface, CompositionGraphicsDevice,
+    CompositionSurfaceBrush, ContainerVisual, SpriteVisual, Visual,
 };
 
+use crate::commit_batcher::CommitBatcher;
 use crate::egl_manager::EglManager;
+use crate::error;
+use crate::flight_recorder::{self, EventKind};
+use crate::frame_stats::{self, FrameTimings};
+use crate::platform_views::PlatformViewRegistry;
 use crate::resize_controller::ResizeController;
+use crate::timeline;
+
+const GL_BGRA8_EXT: u32 = 0x93A1;
+
+/// The pixel format of the surfaces that layers are rendered into. These are the formats that the
+/// engine accepts for GL backing stores.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum SurfaceFormat {
+    #[default]
+    Bgra8,
+    Rgba8,
+}
+
+impl SurfaceFormat {
+    fn pixel_format(self) -> DirectXPixelFormat {
+        match self {
+            SurfaceFormat::Bgra8 => DirectXPixelFormat::B8G8R8A8UIntNormalized,
+            SurfaceFormat::Rgba8 => DirectXPixelFormat::R8G8B8A8UIntNormalized,
+        }
+    }
+
+    fn gl_format(self) -> u32 {
+        match self {
+            SurfaceFormat::Bgra8 => GL_BGRA8_EXT,
+            SurfaceFormat::Rgba8 => gl::RGBA8,
+        }
+    }
+}
 
 pub struct Compositor {
-    compositor_controller: CompositorController,
+    compositor: composition::Compositor,
+    /// Used to commit changes once a frame has been presented. This is `None` if the compositor is
+    /// owned by the host, in which case changes are committed by the host's compositor.
+    commits: Option<Arc<CommitBatcher>>,
     composition_device: CompositionGraphicsDevice,
     egl_manager: Arc<EglManager>,
     resize_controller: Arc<ResizeController>,
     root_visual: ContainerVisual,
-    layers: Vec<*const FlutterLayer>,
+    layers_visual: ContainerVisual,
+    native_visuals: NativeVisuals,
+    content_anchor: ContentAnchor,
+    layers: Vec<LayerKey>,
+    platform_views: Arc<PlatformViewRegistry>,
+    errors: Arc<ErrorReporter>,
+    first_frame_callback: Option<Box<dyn FnOnce() + Send>>,
+    surface_format: SurfaceFormat,
+    msaa_samples: u32,
+    /// Queried from ANGLE when the first multisampled backing store is created.
+    max_msaa_samples: Option<u32>,
+}
+
+/// Where a host's visual is drawn relative to the Flutter layers.
+#[allow(dead_code)] // Only used by the C API.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VisualPlacement {
+    BelowLayers,
+    AboveLayers,
+}
+
+/// Visuals owned by the host that are drawn below or above the Flutter layers, e.g. for native
+/// video or D3D content that doesn't need to be interleaved with Flutter content like a platform
+/// view. Visuals are positioned in physical pixels from the top left of the view, and each one is
+/// drawn above those that were inserted before it with the same placement.
+#[derive(Clone)]
+pub struct NativeVisuals {
+    below: ContainerVisual,
+    above: ContainerVisual,
+    commits: Option<Arc<CommitBatcher>>,
+}
+
+impl NativeVisuals {
+    #[allow(dead_code)] // Only used by the C API.
+    pub fn insert(&self, visual: &Visual, placement: VisualPlacement) -> eyre::Result<()> {
+        let container = match placement {
+            VisualPlacement::BelowLayers => &self.below,
+            VisualPlacement::AboveLayers => &self.above,
+        };
+
+        container.Children()?.InsertAtTop(visual)?;
+
+        self.commit()
+    }
+
+    /// Does nothing if the visual wasn't inserted.
+    #[allow(dead_code)] // Only used by the C API.
+    pub fn remove(&self, visual: &Visual) -> eyre::Result<()> {
+        let Ok(parent) = visual.Parent() else {
+            return Ok(());
+        };
+
+        if parent != self.below && parent != self.above {
+            return Ok(());
+        }
+
+        parent.Children()?.Remove(visual)?;
+
+        self.commit()
+    }
+
+    /// Positions the containers at the top of the root visual, which is flipped vertically (as GL
+    /// renders upside down).
+    fn place(&self, height: f32) -> eyre::Result<()> {
+        for container in [&self.below, &self.above] {
+            container.SetOffset(Vector3::new(0.0, height, 0.0))?;
+        }
+        Ok(())
+    }
+
+    /// Hosts change these rarely, and the event loop may be owned by the host, so changes are
+    /// committed straight away rather than waiting for the loop to run out of events.
+    fn commit(&self) -> eyre::Result<()> {
+        if let Some(commits) = &self.commits {
+            commits.commit()?;
+        }
+        Ok(())
+    }
+}
+
+/// Which corner of the view the last frame stays pinned to after the view has been resized, until
+/// a frame at the new size is presented.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum ResizeAnchor {
+    /// The corner opposite the edges that are being dragged, so that the content stays still on
+    /// screen when resizing from the left or top edges, and the top left otherwise.
+    #[default]
+    Auto,
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+/// Keeps the presented layers pinned to a corner of the view while it is resized ahead of them,
+/// optionally filling the uncovered area with a letterbox color.
+///
+/// Sizes are in physical pixels. Nothing is moved until [`ContentAnchor::handle_view_resized`] has
+/// been called, for hosts that size the view themselves.
+#[derive(Clone)]
+pub struct ContentAnchor {
+    layers_visual: ContainerVisual,
+    commits: Option<Arc<CommitBatcher>>,
+    state: Arc<Mutex<AnchorState>>,
+}
+
+#[derive(Default)]
+struct AnchorState {
+    anchor: ResizeAnchor,
+    letterbox: Option<SpriteVisual>,
+    view_size: Option<(f32, f32)>,
+    frame_size: Option<(f32, f32)>,
+    /// The `WMSZ_*` edge from the last `WM_SIZING`, kept until the frames have caught up with the
+    /// view after the move/size loop exits.
+    sizing_edge: Option<u32>,
+    in_size_move: bool,
+}
+
+impl ContentAnchor {
+    /// Handles `WM_SIZING`, which says which edge is being dragged.
+    pub fn handle_sizing(&self, wparam: WPARAM) {
+        let mut state = self.state.lock().unwrap();
+        state.sizing_edge = Some(wparam.0 as u32);
+        state.in_size_move = true;
+    }
+
+    /// Should be called on `WM_EXITSIZEMOVE`.
+    pub fn handle_exit_size_move(&self) {
+        self.state.lock().unwrap().in_size_move = false;
+    }
+
+    /// Should be called whenever the view is resized, before the engine is sent the new metrics.
+    pub fn handle_view_resized(&self, width: i32, height: i32) -> eyre::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.view_size = Some((width as f32, height as f32));
+        self.update(&mut state)?;
+
+        // The anchored content has moved, which has to be committed even if the engine doesn't
+        // present a frame at the new size before the event loop runs out of events.
+        if let Some(commits) = &self.commits {
+            commits.request_commit();
+        }
+
+        Ok(())
+    }
+
+    fn handle_frame_presented(&self, width: f32, height: f32) -> eyre::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.frame_size = Some((width, height));
+        self.update(&mut state)
+    }
+
+    fn update(&self, state: &mut AnchorState) -> eyre::Result<()> {
+        let (Some((view_width, view_height)), Some((frame_width, frame_height))) =
+            (state.view_size, state.frame_size)
+        else {
+            return Ok(());
+        };
+
+        let caught_up = (view_width, view_height) == (frame_width, frame_height);
+        if caught_up && !state.in_size_move {
+            state.sizing_edge = None;
+        }
+
+        let (right, bottom) = match state.anchor {
+            ResizeAnchor::Auto => match state.sizing_edge {
+                Some(WMSZ_LEFT | WMSZ_BOTTOMLEFT) => (true, false),
+                Some(WMSZ_TOP | WMSZ_TOPRIGHT) => (false, true),
+                Some(WMSZ_TOPLEFT) => (true, true),
+                _ => (false, false),
+            },
+            ResizeAnchor::TopLeft => (false, false),
+            ResizeAnchor::TopRight => (true, false),
+            ResizeAnchor::BottomLeft => (false, true),
+            ResizeAnchor::BottomRight => (true, true),
+        };
+
+        // The root visual is flipped vertically, so layers at the origin are pinned to the bottom
+        // of the view.
+        let x = if right { view_width - frame_width } else { 0.0 };
+        let y = if bottom {
+            0.0
+        } else {
+            view_height - frame_height
+        };
+
+        self.layers_visual.SetOffset(Vector3::new(x, y, 0.0))?;
+
+        if let Some(letterbox) = &state.letterbox {
+            letterbox.SetIsVisible(!caught_up)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Identifies the content of a presented layer, to detect when layers have changed.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum LayerKey {
+    BackingStore(*const CompositorFlutterLayer),
+    PlatformView(i64),
 }
 
 struct CompositorFlutterLayer {
     egl_manager: Arc<EglManager>,
+    composition_device: CompositionGraphicsDevice,
+    errors: Arc<ErrorReporter>,
     visual: SpriteVisual,
+    brush: CompositionSurfaceBrush,
     composition_surface: CompositionDrawingSurface,
+    size: FlutterSize,
+    format: SurfaceFormat,
     egl_surface: Option<egl::Surface>,
+    /// Set if the engine renders the layer with multisampling.
+    msaa: Option<MsaaFramebuffer>,
+}
+
+impl CompositorFlutterLayer {
+    fn make_current(&mut self) -> eyre::Result<()> {
+        let _span = timeline::span(c"MakeLayerCurrent");
+
+        if self.egl_surface.is_some() {
+            bail!("layer surface is already current");
+        }
+
+        let (texture, offset) = match self.begin_draw() {
+            Ok(res) => res,
+            Err(e) => {
+                // The surface may have been lost (e.g. because the device was reset), in which
+                // case drawing can continue on a new one.
+                tracing::warn!("failed to begin drawing, recreating surface: {e}");
+                self.recreate_surface()?;
+                self.begin_draw()?
+            }
+        };
+
+        let egl_surface = match self
+            .egl_manager
+            .create_surface_from_d3d11_texture(&texture, offset)
+        {
+            Ok(egl_surface) => egl_surface,
+            Err(e) => {
+                unsafe {
+                    self.composition_surface
+                        .cast::<ICompositionDrawingSurfaceInterop>()?
+                        .EndDraw()?
+                };
+                return Err(e);
+            }
+        };
+
+        self.egl_surface = Some(egl_surface);
+        self.egl_manager.make_surface_current(egl_surface)?;
+
+        Ok(())
+    }
+
+    fn begin_draw(&self) -> eyre::Result<(ID3D11Texture2D, (i32, i32))> {
+        let interop = self
+            .composition_surface
+            .cast::<ICompositionDrawingSurfaceInterop>()?;
+
+        let mut offset = POINT::default();
+        let texture: ID3D11Texture2D = unsafe { interop.BeginDraw(None, &mut offset)? };
+
+        Ok((texture, (offset.x, offset.y)))
+    }
+
+    fn recreate_surface(&mut self) -> eyre::Result<()> {
+        let composition_surface = self.composition_device.CreateDrawingSurface(
+            Size {
+                Width: self.size.width as f32,
+                Height: self.size.height as f32,
+            },
+            self.format.pixel_format(),
+            DirectXAlphaMode::Premultiplied,
+        )?;
+
+        self.brush.SetSurface(&composition_surface)?;
+        self.composition_surface = composition_surface;
+
+        Ok(())
+    }
+
+    /// Resolves the multisampled framebuffer that the engine rendered into onto the surface. The
+    /// context is left current without a surface, as the engine renders into framebuffers with
+    /// whatever surface is current.
+    fn resolve(&mut self) -> eyre::Result<()> {
+        let _span = timeline::span(c"ResolveLayer");
+
+        let Some(framebuffer) = self.msaa.as_ref().map(|msaa| msaa.framebuffer) else {
+            return Ok(());
+        };
+
+        self.make_current()?;
+
+        let width = self.size.width as i32;
+        let height = self.size.height as i32;
+
+        // The engine's GL state is restored afterwards, since it doesn't expect it to change
+        // between frames. The scissor test would otherwise apply to the blit.
+        unsafe {
+            let mut previous = 0;
+            gl::GetIntegerv(gl::FRAMEBUFFER_BINDING, &mut previous);
+            let scissor = gl::IsEnabled(gl::SCISSOR_TEST) == gl::TRUE;
+
+            gl::Disable(gl::SCISSOR_TEST);
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, framebuffer);
+            gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, 0);
+            gl::BlitFramebuffer(
+                0,
+                0,
+                width,
+                height,
+                0,
+                0,
+                width,
+                height,
+                gl::COLOR_BUFFER_BIT,
+                gl::NEAREST,
+            );
+
+            gl::BindFramebuffer(gl::FRAMEBUFFER, previous as u32);
+            if scissor {
+                gl::Enable(gl::SCISSOR_TEST);
+            }
+        }
+
+        self.egl_manager.make_context_current()?;
+
+        Ok(())
+    }
+}
+
+/// A multisampled framebuffer that the engine renders a layer into. Composition surfaces can't be
+/// multisampled, so it is resolved onto the layer's surface when the layer is presented.
+struct MsaaFramebuffer {
+    framebuffer: u32,
+    renderbuffer: u32,
+    /// Skia needs a stencil buffer for clips and some paths, which has to have the same number of
+    /// samples as the color buffer.
+    depth_stencil: u32,
+}
+
+impl MsaaFramebuffer {
+    /// This must be called on the raster thread.
+    fn new(
+        format: SurfaceFormat,
+        samples: u32,
+        size: FlutterSize,
+    ) -> eyre::Result<MsaaFramebuffer> {
+        let mut msaa = MsaaFramebuffer {
+            framebuffer: 0,
+            renderbuffer: 0,
+            depth_stencil: 0,
+        };
+
+        let status = unsafe {
+            let mut previous = 0;
+            gl::GetIntegerv(gl::FRAMEBUFFER_BINDING, &mut previous);
+
+            gl::GenRenderbuffers(1, &mut msaa.renderbuffer);
+            gl::BindRenderbuffer(gl::RENDERBUFFER, msaa.renderbuffer);
+            gl::RenderbufferStorageMultisample(
+                gl::RENDERBUFFER,
+                samples as i32,
+                format.gl_format(),
+                size.width as i32,
+                size.height as i32,
+            );
+
+            gl::GenRenderbuffers(1, &mut msaa.depth_stencil);
+            gl::BindRenderbuffer(gl::RENDERBUFFER, msaa.depth_stencil);
+            gl::RenderbufferStorageMultisample(
+                gl::RENDERBUFFER,
+                samples as i32,
+                gl::DEPTH24_STENCIL8,
+                size.width as i32,
+                size.height as i32,
+            );
+
+            gl::GenFramebuffers(1, &mut msaa.framebuffer);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, msaa.framebuffer);
+            gl::FramebufferRenderbuffer(
+                gl::FRAMEBUFFER,
+                gl::COLOR_ATTACHMENT0,
+                gl::RENDERBUFFER,
+                msaa.renderbuffer,
+            );
+            gl::FramebufferRenderbuffer(
+                gl::FRAMEBUFFER,
+                gl::DEPTH_STENCIL_ATTACHMENT,
+                gl::RENDERBUFFER,
+                msaa.depth_stencil,
+            );
+
+            let status = gl::CheckFramebufferStatus(gl::FRAMEBUFFER);
+
+            gl::BindRenderbuffer(gl::RENDERBUFFER, 0);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, previous as u32);
+
+            status
+        };
+
+        if status != gl::FRAMEBUFFER_COMPLETE {
+            bail!("multisampled framebuffer is incomplete: {status:#x}");
+        }
+
+        Ok(msaa)
+    }
+}
+
+impl Drop for MsaaFramebuffer {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteFramebuffers(1, &self.framebuffer);
+            gl::DeleteRenderbuffers(1, &self.renderbuffer);
+            gl::DeleteRenderbuffers(1, &self.depth_stencil);
+        }
+    }
+}
+
+/// Number of consecutive rendering failures after which the error handler is invoked.
+const MAX_CONSECUTIVE_FAILURES: u32 = 10;
+
+/// Tracks rendering failures so that persistent ones can be reported to the user, rather than
+/// leaving the window blank.
+struct ErrorReporter {
+    device: Mutex<ID3D11Device>,
+    consecutive_failures: AtomicU32,
+    handler: Mutex<Option<Box<dyn FnOnce(String) + Send>>>,
+    is_device_lost: AtomicBool,
+    device_lost_handler: Mutex<Option<Box<dyn Fn() + Send>>>,
+}
+
+impl ErrorReporter {
+    fn success(&self) {
+        self.consecutive_failures.store(0, Ordering::Relaxed);
+    }
+
+    fn failure(&self, error: &eyre::Report) {
+        flight_recorder::record(EventKind::Error, format!("{error:?}"));
+
+        // Failures are expected until the device has been recreated, and aren't counted.
+        if self.is_device_lost.load(Ordering::Relaxed) {
+            return;
+        }
+
+        if self.check_device_removed() {
+            return;
+        }
+
+        let failures = self.consecutive_failures.fetch_add(1, Ordering::Relaxed) + 1;
+        if failures < MAX_CONSECUTIVE_FAILURES {
+            return;
+        }
+
+        if let Some(handler) = self.handler.lock().unwrap().take() {
+            handler(format!("{error:?}"));
+        }
+    }
+
+    /// Invokes the device lost handler if the device has been removed, unless it already has been.
+    fn check_device_removed(&self) -> bool {
+        if self.is_device_lost.load(Ordering::Relaxed) {
+            return true;
+        }
+
+        let removed_reason = unsafe { self.device.lock().unwrap().GetDeviceRemovedReason() };
+        if let Err(reason) = removed_reason {
+            tracing::error!("d3d device lost: {reason}");
+            self.is_device_lost.store(true, Ordering::Relaxed);
+            if let Some(handler) = &*self.device_lost_handler.lock().unwrap() {
+                handler();
+            }
+            return true;
+        }
+
+        false
+    }
+}
+
+/// Checks whether the compositor's D3D device has been removed outside of rendering, e.g. after
+/// the system has resumed from sleep, when there may be no frames to notice it.
+#[derive(Clone)]
+pub struct DeviceLossCheck(Arc<ErrorReporter>);
+
+impl DeviceLossCheck {
+    /// Invokes the device lost handler if the device has been removed.
+    pub fn check(&self) {
+        self.0.check_device_removed();
+    }
 }
 
 impl Compositor {
     pub fn new(
         device: ID3D11Device,
-        compositor_controller: CompositorController,
+        commits: Arc<CommitBatcher>,
         egl_manager: Arc<EglManager>,
         resize_controller: Arc<ResizeController>,
         root_visual: ContainerVisual,
-    ) -> eyre::Result<Compositor> {
+        platform_views: Arc<PlatformViewRegistry>,
+    ) -> error::Result<Compositor> {
+        Compositor::create(
+            device,
+            commits.compositor_controller().Compositor()?,
+            Some(commits),
+            egl_manager,
+            resize_controller,
+            root_visual,
+            platform_views,
+        )
+    }
+
+    /// Creates a compositor that renders into a visual tree owned by the host, without a window
+    /// target or compositor controller of its own. `root_visual` must have been created by
+    /// `compositor`.
+    #[allow(dead_code)] // Only used by the C API.
+    pub fn with_external_visual(
+        device: ID3D11Device,
+        compositor: composition::Compositor,
+        egl_manager: Arc<EglManager>,
+        resize_controller: Arc<ResizeController>,
+        root_visual: ContainerVisual,
+        platform_views: Arc<PlatformViewRegistry>,
+    ) -> error::Result<Compositor> {
+        Compositor::create(
+            device,
+            compositor,
+            None,
+            egl_manager,
+            resize_controller,
+            root_visual,
+            platform_views,
+        )
+    }
+
+    fn create(
+        device: ID3D11Device,
+        compositor: composition::Compositor,
+        commits: Option<Arc<CommitBatcher>>,
+        egl_manager: Arc<EglManager>,
+        resize_controller: Arc<ResizeController>,
+        root_visual: ContainerVisual,
+        platform_views: Arc<PlatformViewRegistry>,
+    ) -> error::Result<Compositor> {
         let composition_device = unsafe {
-            compositor_controller
-                .Compositor()
-                .unwrap()
+            compositor
                 .cast::<ICompositorInterop>()?
                 .CreateGraphicsDevice(&device)?
         };
 
+        let errors = Arc::new(ErrorReporter {
+            device: Mutex::new(device),
+            consecutive_failures: AtomicU32::new(0),
+            handler: Mutex::new(None),
+            is_device_lost: AtomicBool::new(false),
+            device_lost_handler: Mutex::new(None),
+        });
+
         macro_rules! gl_load {
             ($($name:ident)*) => {
                 $(
@@ -69,142 +647,333 @@ impl Compositor {
             };
         }
 
+        // Flutter layers are kept in their own container so that other visuals (e.g. a splash
+        // screen) can be placed in the root visual without being affected by layer updates.
+        let layers_visual = compositor.CreateContainerVisual()?;
+
+        root_visual.Children()?.InsertAtBottom(&layers_visual)?;
+
+        // Visuals owned by the host are kept in containers either side of the layers, so they stay
+        // in place when layers are reordered. Their children render the right way up.
+        let native_visuals = NativeVisuals {
+            below: compositor.CreateContainerVisual()?,
+            above: compositor.CreateContainerVisual()?,
+            commits: commits.clone(),
+        };
+
+        for container in [&native_visuals.below, &native_visuals.above] {
+            container.SetTransformMatrix(Matrix4x4 {
+                M11: 1.0,
+                M22: -1.0,
+                M33: 1.0,
+                M44: 1.0,
+                ..Default::default()
+            })?;
+        }
+
+        let content_anchor = ContentAnchor {
+            layers_visual: layers_visual.clone(),
+            commits: commits.clone(),
+            state: Arc::new(Mutex::new(AnchorState::default())),
+        };
+
+        root_visual
+            .Children()?
+            .InsertBelow(&native_visuals.below, &layers_visual)?;
+        root_visual
+            .Children()?
+            .InsertAbove(&native_visuals.above, &layers_visual)?;
+
         gl_load!(
             GenTextures
             GenFramebuffers
+            GenRenderbuffers
             BindTexture
             BindFramebuffer
+            BindRenderbuffer
             TexParameteri
             FramebufferTexture2D
+            FramebufferRenderbuffer
+            CheckFramebufferStatus
             DeleteTextures
             DeleteFramebuffers
+            DeleteRenderbuffers
+            GetIntegerv
+            IsEnabled
+            Enable
+            Disable
         );
 
+        // Contexts are created for ES 2, which only has these through ANGLE's extensions.
+        gl::RenderbufferStorageMultisample::load_with(|_| {
+            egl_manager
+                .get_proc_address("glRenderbufferStorageMultisampleANGLE")
+                .unwrap_or(ptr::null_mut())
+        });
+        gl::BlitFramebuffer::load_with(|_| {
+            egl_manager
+                .get_proc_address("glBlitFramebufferANGLE")
+                .unwrap_or(ptr::null_mut())
+        });
+
         Ok(Compositor {
-            compositor_controller,
+            compositor,
+            commits,
             composition_device,
             egl_manager,
             resize_controller,
             root_visual,
+            layers_visual,
+            native_visuals,
+            content_anchor,
             layers: vec![],
+            platform_views,
+            errors,
+            first_frame_callback: None,
+            surface_format: SurfaceFormat::default(),
+            msaa_samples: 1,
+            max_msaa_samples: None,
         })
     }
 
+    /// Returns a handle for inserting the host's own visuals below or above the Flutter layers.
+    #[allow(dead_code)] // Only used by the C API.
+    pub fn native_visuals(&self) -> NativeVisuals {
+        self.native_visuals.clone()
+    }
+
+    /// Returns a handle for keeping the layers pinned to a corner of the view while it is resized.
+    pub fn content_anchor(&self) -> ContentAnchor {
+        self.content_anchor.clone()
+    }
+
+    pub fn set_resize_anchor(&mut self, anchor: ResizeAnchor) {
+        self.content_anchor.state.lock().unwrap().anchor = anchor;
+    }
+
+    /// Fills the area of the view that isn't covered by the last frame while it is being resized,
+    /// instead of showing whatever is behind the window.
+    pub fn set_resize_letterbox_color(&mut self, color: Color) -> eyre::Result<()> {
+        let letterbox = self.compositor.CreateSpriteVisual()?;
+        letterbox.SetRelativeSizeAdjustment(Vector2::new(1.0, 1.0))?;
+        letterbox.SetBrush(&self.compositor.CreateColorBrushWithColor(color)?)?;
+        letterbox.SetIsVisible(false)?;
+
+        self.root_visual.Children()?.InsertAtBottom(&letterbox)?;
+
+        let previous = self
+            .content_anchor
+            .state
+            .lock()
+            .unwrap()
+            .letterbox
+            .replace(letterbox);
+
+        if let Some(previous) = previous {
+            self.root_visual.Children()?.Remove(&previous)?;
+        }
+
+        Ok(())
+    }
+
+    /// Sets the pixel format of layers. This must be called before the engine is started.
+    pub fn set_surface_format(&mut self, format: SurfaceFormat) {
+        self.surface_format = format;
+    }
+
+    /// Sets the number of samples per pixel that layers are rendered with, which is limited to
+    /// what the GPU supports. Layers aren't multisampled if this is 1. This must be called before
+    /// the engine is started.
+    pub fn set_msaa_samples(&mut self, samples: u32) {
+        self.msaa_samples = samples.max(1);
+    }
+
+    /// The number of samples to render layers with. This must be called on the raster thread.
+    fn effective_msaa_samples(&mut self) -> u32 {
+        if self.msaa_samples <= 1 {
+            return 1;
+        }
+
+        let requested = self.msaa_samples;
+        let max = *self.max_msaa_samples.get_or_insert_with(|| {
+            // This fails (leaving max at 0) if ANGLE doesn't support multisampled renderbuffers.
+            let mut max = 0;
+            unsafe { gl::GetIntegerv(gl::MAX_SAMPLES, &mut max) };
+            let max = max.max(1) as u32;
+
+            if max < requested {
+                tracing::warn!(requested, max, "msaa sample count is not supported");
+            }
+
+            max
+        });
+
+        requested.min(max)
+    }
+
+    /// Sets a callback to be invoked (on the raster thread) once the first frame has been
+    /// presented.
+    pub fn set_first_frame_callback(&mut self, callback: impl FnOnce() + Send + 'static) {
+        self.first_frame_callback = Some(Box::new(callback));
+    }
+
+    /// Sets a callback to be invoked (on the raster thread) if rendering keeps failing, with a
+    /// description of the last error.
+    pub fn set_error_handler(&mut self, handler: impl FnOnce(String) + Send + 'static) {
+        *self.errors.handler.lock().unwrap() = Some(Box::new(handler));
+    }
+
+    /// Sets a callback to be invoked (on the raster thread) when the D3D device has been removed,
+    /// e.g. because of a driver update or GPU hang. The handler should recreate the device and pass
+    /// it to [`Compositor::set_device`] once the engine has been shut down.
+    pub fn set_device_lost_handler(&mut self, handler: impl Fn() + Send + 'static) {
+        *self.errors.device_lost_handler.lock().unwrap() = Some(Box::new(handler));
+    }
+
+    pub fn device_loss_check(&self) -> DeviceLossCheck {
+        DeviceLossCheck(self.errors.clone())
+    }
+
+    /// Switches rendering to a new D3D device.
+    pub fn set_device(&mut self, device: ID3D11Device) -> error::Result<()> {
+        unsafe {
+            self.composition_device
+                .cast::<ICompositionGraphicsDeviceInterop>()?
+                .SetRenderingDevice(&device)?;
+        }
+
+        *self.errors.device.lock().unwrap() = device;
+        self.errors.consecutive_failures.store(0, Ordering::Relaxed);
+        self.errors.is_device_lost.store(false, Ordering::Relaxed);
+
+        Ok(())
+    }
+
     pub fn create_backing_store(
         &mut self,
         config: &FlutterBackingStoreConfig,
         out: &mut FlutterBackingStore,
     ) -> eyre::Result<()> {
+        let _span = timeline::span(c"CreateBackingStore");
+
         let size = config.size;
 
-        let visual = self
-            .compositor_controller
-            .Compositor()?
-            .CreateSpriteVisual()?;
+        let visual = self.compositor.CreateSpriteVisual()?;
 
         visual.SetSize(Vector2::new(size.width as f32, size.height as f32))?;
 
-        let composition_surface = self
-            .composition_device
-            .CreateDrawingSurface(
-                Size {
-                    Width: size.width as f32,
-                    Height: size.height as f32,
-                },
-                DirectXPixelFormat::B8G8R8A8UIntNormalized,
-                DirectXAlphaMode::Premultiplied,
-            )
-            .unwrap();
+        let composition_surface = self.composition_device.CreateDrawingSurface(
+            Size {
+                Width: size.width as f32,
+                Height: size.height as f32,
+            },
+            self.surface_format.pixel_format(),
+            DirectXAlphaMode::Premultiplied,
+        )?;
+
+        let samples = self.effective_msaa_samples();
+        let msaa = if samples > 1 {
+            Some(MsaaFramebuffer::new(self.surface_format, samples, size)?)
+        } else {
+            None
+        };
 
         let surface_brush = self
-            .compositor_controller
-            .Compositor()?
+            .compositor
             .CreateSurfaceBrushWithSurface(&composition_surface)?;
 
         visual.SetBrush(&surface_brush)?;
 
         let compositor_layer = Box::leak(Box::new(CompositorFlutterLayer {
             egl_manager: self.egl_manager.clone(),
+            composition_device: self.composition_device.clone(),
+            errors: self.errors.clone(),
             visual,
+            brush: surface_brush,
             composition_surface,
+            size,
+            format: self.surface_format,
             egl_surface: None,
+            msaa,
         }));
 
         extern "C" fn make_surface_current(
             user_data: *mut c_void,
             gl_state_changed: *mut bool,
         ) -> bool {
-            let layer = unsafe {
-                user_data
-                    .cast::<CompositorFlutterLayer>()
-                    .as_mut()
-                    .expect("layer must not be null")
-            };
-
-            let composition_surface_interop = layer
-                .composition_surface
-                .cast::<ICompositionDrawingSurfaceInterop>()
-                .unwrap();
-
-            let mut update_offset = POINT::default();
-            let texture: ID3D11Texture2D = unsafe {
-                composition_surface_interop
-                    .BeginDraw(None, &mut update_offset)
-                    .unwrap()
+            let Some(layer) = (unsafe { user_data.cast::<CompositorFlutterLayer>().as_mut() })
+            else {
+                tracing::error!("layer is null");
+                return false;
             };
 
-            assert!(layer.egl_surface.is_none());
-
-            let egl_surface = layer.egl_surface.insert(
-                layer
-                    .egl_manager
-                    .create_surface_from_d3d11_texture(&texture, (update_offset.x, update_offset.y))
-                    .unwrap(),
-            );
-
-            layer
-                .egl_manager
-                .make_surface_current(*egl_surface)
-                .unwrap();
-
             unsafe {
                 *gl_state_changed = false;
             }
 
+            frame_stats::record_raster_start();
+
+            if let Err(e) = layer.make_current() {
+                tracing::error!("failed to make layer surface current: {e:?}");
+                layer.errors.failure(&e);
+                return false;
+            }
+
             true
         }
 
         extern "C" fn clear_current_surface(user_data: *mut c_void, _: *mut bool) -> bool {
-            let layer = unsafe {
-                user_data
-                    .cast::<CompositorFlutterLayer>()
-                    .as_mut()
-                    .expect("layer must not be null")
+            let Some(layer) = (unsafe { user_data.cast::<CompositorFlutterLayer>().as_mut() })
+            else {
+                tracing::error!("layer is null");
+                return false;
             };
 
-            layer.egl_manager.clear_current().unwrap();
+            if let Err(e) = layer.egl_manager.clear_current() {
+                tracing::error!("failed to clear current layer surface: {e:?}");
+                layer.errors.failure(&e);
+                return false;
+            }
 
             true
         }
 
-        out.type_ = FlutterBackingStoreType_kFlutterBackingStoreTypeOpenGL;
-        out.user_data = (compositor_layer as *mut CompositorFlutterLayer).cast();
-        out.__bindgen_anon_1 = FlutterBackingStore__bindgen_ty_1 {
-            open_gl: FlutterOpenGLBackingStore {
+        // The framebuffer is deleted when the backing store is collected.
+        extern "C" fn destroy_framebuffer(_: *mut c_void) {}
+
+        let user_data = (compositor_layer as *mut CompositorFlutterLayer).cast();
+
+        let open_gl = match &compositor_layer.msaa {
+            Some(msaa) => FlutterOpenGLBackingStore {
+                type_: FlutterOpenGLTargetType_kFlutterOpenGLTargetTypeFramebuffer,
+                __bindgen_anon_1: FlutterOpenGLBackingStore__bindgen_ty_1 {
+                    framebuffer: FlutterOpenGLFramebuffer {
+                        target: self.surface_format.gl_format(),
+                        name: msaa.framebuffer,
+                        user_data: ptr::null_mut(),
+                        destruction_callback: Some(destroy_framebuffer),
+                    },
+                },
+            },
+            None => FlutterOpenGLBackingStore {
                 type_: FlutterOpenGLTargetType_kFlutterOpenGLTargetTypeSurface,
                 __bindgen_anon_1: FlutterOpenGLBackingStore__bindgen_ty_1 {
                     surface: FlutterOpenGLSurface {
                         struct_size: mem::size_of::<FlutterOpenGLSurface>(),
-                        format: /* GL_BGRA8_EXT */ 0x93A1,
+                        format: self.surface_format.gl_format(),
                         make_current_callback: Some(make_surface_current),
                         clear_current_callback: Some(clear_current_surface),
                         destruction_callback: None,
-                        user_data: compositor_layer as *mut _ as _,
+                        user_data,
                     },
                 },
             },
         };
 
+        out.type_ = FlutterBackingStoreType_kFlutterBackingStoreTypeOpenGL;
+        out.user_data = user_data;
+        out.__bindgen_anon_1 = FlutterBackingStore__bindgen_ty_1 { open_gl };
+
         Ok(())
     }
 
@@ -224,73 +993,198 @@ impl Compositor {
         Ok(())
     }
 
+    /// Moves a platform view's visual to the position of its layer, in a frame of the given
+    /// height.
+    fn place_platform_view(
+        &self,
+        visual: &ContainerVisual,
+        layer: &FlutterLayer,
+        frame_height: f32,
+    ) -> eyre::Result<()> {
+        // The root visual is flipped vertically, as GL renders upside down. Platform views render
+        // the right way up, so they need to be flipped back and positioned from the bottom.
+        visual.SetSize(Vector2::new(
+            layer.size.width as f32,
+            layer.size.height as f32,
+        ))?;
+
+        visual.SetTransformMatrix(Matrix4x4 {
+            M11: 1.0,
+            M22: -1.0,
+            M33: 1.0,
+            M44: 1.0,
+            ..Default::default()
+        })?;
+
+        visual.SetOffset(Vector3::new(
+            layer.offset.x as f32,
+            frame_height - layer.offset.y as f32,
+            0.0,
+        ))?;
+
+        Ok(())
+    }
+
     pub fn present_layers(&mut self, layers: &[&FlutterLayer]) -> eyre::Result<()> {
-        // Composition layers need to be updated if flutter layers are added or removed.
-        let mut should_update_composition_layers = self.layers.len() != layers.len();
-
-        for (i, &layer) in layers.iter().enumerate() {
-            // Composition layers need to be updated if flutter layers have been reordered.
-            should_update_composition_layers =
-                should_update_composition_layers || self.layers[i] != layer;
-
-            // TODO: Support platform views
-            assert_eq!(
-                layer.type_,
-                FlutterLayerContentType_kFlutterLayerContentTypeBackingStore
-            );
+        let _span = timeline::span(c"PresentLayers");
 
-            let compositor_layer = unsafe {
-                (*layer.__bindgen_anon_1.backing_store)
-                    .user_data
-                    .cast::<CompositorFlutterLayer>()
-                    .as_mut()
-                    .unwrap()
-            };
+        let res = self.try_present_layers(layers);
+
+        match &res {
+            Ok(()) => self.errors.success(),
+            Err(e) => self.errors.failure(e),
+        }
+
+        res
+    }
+
+    fn try_present_layers(&mut self, layers: &[&FlutterLayer]) -> eyre::Result<()> {
+        let raster_end = frame_stats::now();
+
+        let mut keys = Vec::with_capacity(layers.len());
+        let mut visuals = Vec::with_capacity(layers.len());
+
+        // Backing stores are the size of the whole frame, which may not match the view yet if it
+        // has just been resized.
+        let frame_size = layers
+            .iter()
+            .find(|layer| {
+                layer.type_ == FlutterLayerContentType_kFlutterLayerContentTypeBackingStore
+            })
+            .map(|layer| (layer.size.width as f32, layer.size.height as f32));
+
+        let frame_height = match frame_size {
+            Some((_, height)) => height,
+            None => self.root_visual.Size()?.Y,
+        };
+
+        for &layer in layers {
+            if layer.type_ == FlutterLayerContentType_kFlutterLayerContentTypePlatformView {
+                let id = unsafe { (*layer.__bindgen_anon_1.platform_view).identifier };
+
+                let Some(visual) = self.platform_views.visual(id) else {
+                    // The view may have been disposed after the frame was produced.
+                    tracing::warn!(id, "unknown platform view");
+                    continue;
+                };
+
+                self.place_platform_view(&visual, layer, frame_height)?;
+
+                keys.push(LayerKey::PlatformView(id));
+                visuals.push(visual.cast::<Visual>()?);
+                continue;
+            }
+
+            if layer.type_ != FlutterLayerContentType_kFlutterLayerContentTypeBackingStore {
+                bail!("unsupported layer type: {}", layer.type_);
+            }
+
+            let compositor_layer = unsafe { layer_from_flutter(layer)? };
+
+            // Multisampled layers are rendered into their own framebuffer, rather than the surface.
+            compositor_layer.resolve()?;
 
             let composition_surface_interop = compositor_layer
                 .composition_surface
                 .cast::<ICompositionDrawingSurfaceInterop>()?;
 
+            // No fence is needed before ending the draw. ANGLE renders with the same D3D device
+            // that the composition device was created from, and the engine flushes its GL commands
+            // before presenting, so they are submitted to the device's immediate context before
+            // anything that composition does with the surface.
             if let Some(egl_surface) = compositor_layer.egl_surface.take() {
                 unsafe { composition_surface_interop.EndDraw()? };
                 compositor_layer.egl_manager.destroy_surface(egl_surface)?;
             }
+
+            keys.push(LayerKey::BackingStore(compositor_layer));
+            visuals.push(compositor_layer.visual.cast::<Visual>()?);
         }
 
-        // Flutter layers have changed. We need to re-insert all layer visuals into the root visual in
-        // the correct order.
-        if should_update_composition_layers {
-            self.root_visual.Children()?.RemoveAll()?;
-            self.layers.clear();
+        // Layers have been added, removed or reordered. We need to re-insert all layer visuals into
+        // the root visual in the correct order.
+        if keys != self.layers {
+            self.layers_visual.Children()?.RemoveAll()?;
 
-            for &layer in layers {
-                let compositor_layer = unsafe {
-                    (*layer.__bindgen_anon_1.backing_store)
-                        .user_data
-                        .cast::<CompositorFlutterLayer>()
-                        .as_mut()
-                        .unwrap()
-                };
+            for visual in &visuals {
+                self.layers_visual.Children()?.InsertAtTop(visual)?;
+            }
 
-                self.root_visual
-                    .Children()?
-                    .InsertAtTop(&compositor_layer.visual)?;
+            self.layers = keys;
+        }
 
-                self.layers.push(layer);
-            }
+        self.native_visuals.place(self.root_visual.Size()?.Y)?;
+
+        if let Some((width, height)) = frame_size {
+            self.content_anchor.handle_frame_presented(width, height)?;
         }
 
-        let commit_compositor = || self.compositor_controller.Commit().unwrap();
+        flight_recorder::record(EventKind::Present, format!("{} layers", layers.len()));
 
         if let Some(resize) = self.resize_controller.current_resize() {
+            timeline::instant(c"ResizeFrameGenerated");
             // Calling DwmFlush() seems to reduce glitches when resizing.
-            unsafe { DwmFlush()? };
-            commit_compositor();
+            {
+                let _span = timeline::span(c"DwmFlush");
+                unsafe { DwmFlush()? };
+            }
+            // The resize must be completed even if the commit fails, or the platform thread would
+            // be blocked forever.
+            let res = self.commit();
+            timeline::instant(c"ResizePresented");
             resize.complete();
+            res?;
         } else {
-            commit_compositor();
+            self.commit()?;
+        }
+
+        let timings = frame_stats::record_present(raster_end);
+        if frame_stats::has_listeners() {
+            self.report_frame_timings(timings);
+        }
+
+        if let Some(callback) = self.first_frame_callback.take() {
+            callback();
         }
 
         Ok(())
     }
+
+    /// Reports a frame's timings once DWM has finished processing its commit.
+    fn report_frame_timings(&self, mut timings: FrameTimings) {
+        let Some(commits) = &self.commits else {
+            return frame_stats::report(&timings);
+        };
+
+        let res = commits
+            .compositor_controller()
+            .EnsurePreviousCommitCompletedAsync()
+            .and_then(|action| {
+                action.SetCompleted(&AsyncActionCompletedHandler::new(move |_, _| {
+                    timings.dwm_confirmed = Some(frame_stats::now());
+                    frame_stats::report(&timings);
+                    Ok(())
+                }))
+            });
+
+        if let Err(e) = res {
+            tracing::warn!("failed to wait for commit to complete: {e}");
+            frame_stats::report(&timings);
+        }
+    }
+
+    fn commit(&self) -> eyre::Result<()> {
+        if let Some(commits) = &self.commits {
+            commits.commit()?;
+        }
+        Ok(())
+    }
+}
+
+unsafe fn layer_from_flutter(layer: &FlutterLayer) -> eyre::Result<&mut CompositorFlutterLayer> {
+    (*layer.__bindgen_anon_1.backing_store)
+        .user_data
+        .cast::<CompositorFlutterLayer>()
+        .as_mut()
+        .ok_or_eyre("layer user data is null")
 }
@@ -0,0 +1,157 @@
+use std::cell::Cell;
+use std::rc::Rc;
+
+use color_eyre::eyre;
+use flutter_codec::EncodableValue;
+use windows::Win32::Foundation::{HWND, POINT, RECT};
+use windows::Win32::Graphics::Gdi::ClientToScreen;
+use windows::Win32::UI::WindowsAndMessaging::{ClipCursor, GetClientRect};
+use winit::window::Window;
+
+use crate::standard_method_channel::{StandardMethodHandler, StandardMethodReply};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CursorGrabMode {
+    #[default]
+    None,
+    /// The cursor can't leave the window's client area.
+    Confined,
+    /// The cursor is hidden and held in the center of the window. Pointer events stop moving, so
+    /// motion should be read from `flion/raw_input` instead.
+    Locked,
+}
+
+/// Confines or locks the cursor to the window with `ClipCursor`.
+///
+/// The clip is shared by every app, so it is released while the window isn't focused and
+/// reapplied when it is focused again, moved or resized.
+#[derive(Clone)]
+pub struct CursorGrab {
+    hwnd: HWND,
+    window: Rc<Window>,
+    mode: Rc<Cell<CursorGrabMode>>,
+    focused: Rc<Cell<bool>>,
+    /// Whether the cursor is currently clipped by this window, so that only our own clip is
+    /// released.
+    clipped: Rc<Cell<bool>>,
+}
+
+impl CursorGrab {
+    pub fn new(hwnd: HWND, window: Rc<Window>) -> CursorGrab {
+        CursorGrab {
+            hwnd,
+            focused: Rc::new(Cell::new(window.has_focus())),
+            window,
+            mode: Rc::new(Cell::new(CursorGrabMode::None)),
+            clipped: Rc::new(Cell::new(false)),
+        }
+    }
+
+    pub fn mode(&self) -> CursorGrabMode {
+        self.mode.get()
+    }
+
+    pub fn set_mode(&self, mode: CursorGrabMode) -> eyre::Result<()> {
+        self.mode.set(mode);
+        self.window
+            .set_cursor_visible(mode != CursorGrabMode::Locked);
+        self.refresh()
+    }
+
+    pub fn handle_focus_changed(&self, focused: bool) -> eyre::Result<()> {
+        self.focused.set(focused);
+        self.refresh()
+    }
+
+    /// Reapplies the clip, which has to be done whenever the window moves or is resized.
+    pub fn refresh(&self) -> eyre::Result<()> {
+        let mode = self.mode.get();
+
+        if mode == CursorGrabMode::None || !self.focused.get() {
+            if self.clipped.replace(false) {
+                unsafe { ClipCursor(None)? };
+            }
+            return Ok(());
+        }
+
+        let mut rect = RECT::default();
+        let mut origin = POINT::default();
+
+        unsafe {
+            GetClientRect(self.hwnd, &mut rect)?;
+            ClientToScreen(self.hwnd, &mut origin);
+        }
+
+        let mut clip = RECT {
+            left: origin.x + rect.left,
+            top: origin.y + rect.top,
+            right: origin.x + rect.right,
+            bottom: origin.y + rect.bottom,
+        };
+
+        if mode == CursorGrabMode::Locked {
+            let x = (clip.left + clip.right) / 2;
+            let y = (clip.top + clip.bottom) / 2;
+            clip = RECT {
+                left: x,
+                top: y,
+                right: x + 1,
+                bottom: y + 1,
+            };
+        }
+
+        unsafe { ClipCursor(Some(&clip))? };
+        self.clipped.set(true);
+
+        Ok(())
+    }
+}
+
+/// Handles `flion/cursor_grab`.
+pub struct CursorGrabHandler {
+    grab: CursorGrab,
+}
+
+impl CursorGrabHandler {
+    pub fn new(grab: CursorGrab) -> CursorGrabHandler {
+        CursorGrabHandler { grab }
+    }
+}
+
+impl StandardMethodHandler for CursorGrabHandler {
+    fn handle(&self, method: &str, args: EncodableValue, reply: StandardMethodReply) {
+        match method {
+            "setMode" => {
+                let mode = match args.get("mode").and_then(|v| v.as_string()) {
+                    Some("none") => CursorGrabMode::None,
+                    Some("confined") => CursorGrabMode::Confined,
+                    Some("locked") => CursorGrabMode::Locked,
+                    _ => {
+                        return reply.error(
+                            "invalid_args",
+                            Some("expected mode to be one of none, confined or locked"),
+                        )
+                    }
+                };
+
+                match self.grab.set_mode(mode) {
+                    Ok(()) => reply.success(&EncodableValue::Null),
+                    Err(e) => reply.error("grab_failed", Some(&format!("{e:?}"))),
+                }
+            }
+            "getMode" => {
+                let mode = match self.grab.mode() {
+                    CursorGrabMode::None => "none",
+                    CursorGrabMode::Confined => "confined",
+                    CursorGrabMode::Locked => "locked",
+                };
+
+                reply.success(&EncodableValue::Str(mode));
+            }
+            _ => {
+                tracing::warn!(method, "unimplemented");
+                reply.not_implemented();
+            }
+        }
+    }
+}
@@ -0,0 +1,37 @@
+use color_eyre::eyre::{self, OptionExt};
+use windows::core::ComInterface;
+use windows::Win32::Graphics::Direct3D::D3D_DRIVER_TYPE_HARDWARE;
+use windows::Win32::Graphics::Direct3D10::ID3D10Multithread;
+use windows::Win32::Graphics::Direct3D11::{
+    D3D11CreateDevice, ID3D11Device, D3D11_CREATE_DEVICE_VIDEO_SUPPORT, D3D11_SDK_VERSION,
+};
+
+pub fn create_device() -> eyre::Result<ID3D11Device> {
+    let mut device = Default::default();
+
+    unsafe {
+        D3D11CreateDevice(
+            None,
+            D3D_DRIVER_TYPE_HARDWARE,
+            None,
+            // Required for hardware video decoding with Media Foundation.
+            D3D11_CREATE_DEVICE_VIDEO_SUPPORT,
+            None,
+            D3D11_SDK_VERSION,
+            Some(&mut device),
+            None,
+            None,
+        )?;
+    }
+
+    let device = device.ok_or_eyre("failed to create D3D11 device")?;
+
+    // The media engine uses the device from its own threads.
+    unsafe {
+        device
+            .cast::<ID3D10Multithread>()?
+            .SetMultithreadProtected(true)
+    };
+
+    Ok(device)
+}
@@ -0,0 +1,150 @@
+//! Functions that are exported from the executable for Dart to call through `dart:ffi`, for
+//! queries that are too frequent or latency sensitive to make over a platform channel:
+//!
+//! ```dart
+//! final exe = DynamicLibrary.executable();
+//! final getDpi = exe.lookupFunction<Uint32 Function(), int Function()>('fluyt_get_dpi');
+//! ```
+//!
+//! These are called on the Dart UI thread, so they only read state that is safe to access from
+//! any thread. The declarations are in `include/fluyt_ffi.h`. Keep the names in sync with
+//! `DART_FFI_EXPORTS` in `build.rs`.
+
+use std::ffi::{c_char, CString};
+use std::mem;
+use std::ptr;
+use std::sync::atomic::{AtomicIsize, Ordering};
+
+use windows::Win32::Foundation::{HWND, RECT};
+use windows::Win32::Graphics::Gdi::{
+    GetMonitorInfoW, MonitorFromWindow, MONITORINFO, MONITOR_DEFAULTTONEAREST,
+};
+use windows::Win32::UI::HiDpi::{GetDpiForMonitor, GetDpiForWindow, MDT_EFFECTIVE_DPI};
+use windows::Win32::UI::WindowsAndMessaging::MONITORINFOF_PRIMARY;
+
+use crate::clipboard;
+use crate::error_utils::ResultExt;
+
+/// The top level window, or 0 before it has been created.
+static WINDOW: AtomicIsize = AtomicIsize::new(0);
+
+pub fn set_window(hwnd: HWND) {
+    WINDOW.store(hwnd.0, Ordering::Release);
+}
+
+fn window() -> Option<HWND> {
+    match WINDOW.load(Ordering::Acquire) {
+        0 => None,
+        hwnd => Some(HWND(hwnd)),
+    }
+}
+
+/// A rectangle in physical pixels, in virtual screen coordinates.
+#[repr(C)]
+pub struct FluytRect {
+    pub left: i32,
+    pub top: i32,
+    pub right: i32,
+    pub bottom: i32,
+}
+
+impl From<RECT> for FluytRect {
+    fn from(rect: RECT) -> FluytRect {
+        FluytRect {
+            left: rect.left,
+            top: rect.top,
+            right: rect.right,
+            bottom: rect.bottom,
+        }
+    }
+}
+
+/// The monitor that the window is (mostly) on.
+#[repr(C)]
+pub struct FluytMonitorInfo {
+    pub bounds: FluytRect,
+    /// The bounds excluding the taskbar and docked windows.
+    pub work_area: FluytRect,
+    pub dpi: u32,
+    pub is_primary: bool,
+}
+
+/// Returns the `HWND` of the top level window, or 0 if it hasn't been created yet.
+#[no_mangle]
+extern "C" fn fluyt_get_window_handle() -> isize {
+    window().map_or(0, |hwnd| hwnd.0)
+}
+
+/// Returns the window's DPI, where 96 is a scale factor of 1, or 0 if there is no window.
+#[no_mangle]
+extern "C" fn fluyt_get_dpi() -> u32 {
+    window().map_or(0, |hwnd| unsafe { GetDpiForWindow(hwnd) })
+}
+
+/// Fills in `info` for the window's monitor. Returns `false` if there is no window or the query
+/// failed, in which case `info` is left unchanged.
+#[no_mangle]
+unsafe extern "C" fn fluyt_get_monitor_info(info: *mut FluytMonitorInfo) -> bool {
+    let (Some(hwnd), Some(info)) = (window(), info.as_mut()) else {
+        return false;
+    };
+
+    let monitor = MonitorFromWindow(hwnd, MONITOR_DEFAULTTONEAREST);
+
+    let mut monitor_info = MONITORINFO {
+        cbSize: mem::size_of::<MONITORINFO>() as u32,
+        ..Default::default()
+    };
+
+    if !GetMonitorInfoW(monitor, &mut monitor_info).as_bool() {
+        return false;
+    }
+
+    let (mut dpi_x, mut dpi_y) = (0, 0);
+    if GetDpiForMonitor(monitor, MDT_EFFECTIVE_DPI, &mut dpi_x, &mut dpi_y)
+        .trace_err()
+        .is_err()
+    {
+        return false;
+    }
+
+    *info = FluytMonitorInfo {
+        bounds: monitor_info.rcMonitor.into(),
+        work_area: monitor_info.rcWork.into(),
+        dpi: dpi_x,
+        is_primary: monitor_info.dwFlags & MONITORINFOF_PRIMARY != 0,
+    };
+
+    true
+}
+
+#[no_mangle]
+extern "C" fn fluyt_clipboard_has_text() -> bool {
+    clipboard::has_text()
+}
+
+/// Returns the text on the clipboard as UTF-8, which must be freed with [`fluyt_free_string`], or
+/// null if there is no text (or the clipboard couldn't be opened).
+#[no_mangle]
+extern "C" fn fluyt_clipboard_get_text() -> *mut c_char {
+    let Some(hwnd) = window() else {
+        return ptr::null_mut();
+    };
+
+    match clipboard::get_text(hwnd).trace_err() {
+        // Text with an embedded nul is cut off there, as it would be by C callers.
+        Ok(Some(text)) => {
+            let text = text.split('\0').next().unwrap_or_default();
+            CString::new(text).unwrap_or_default().into_raw()
+        }
+        Ok(None) | Err(_) => ptr::null_mut(),
+    }
+}
+
+/// Frees a string returned by one of these functions. Does nothing if `string` is null.
+#[no_mangle]
+unsafe extern "C" fn fluyt_free_string(string: *mut c_char) {
+    if !string.is_null() {
+        drop(CString::from_raw(string));
+    }
+}
@@ -0,0 +1,44 @@
+use std::ffi::{c_char, c_void, CStr};
+
+use tracing::Level;
+
+use crate::vm_service;
+
+/// The tag attached to messages printed by Dart code using `print`.
+pub const LOG_TAG: &CStr = c"flutter";
+
+/// Receives messages logged by Dart (through `print` or `dart:developer`'s `log`) and forwards them
+/// to tracing under the `dart` target.
+pub unsafe extern "C" fn log_message_callback(
+    tag: *const c_char,
+    message: *const c_char,
+    _user_data: *mut c_void,
+) {
+    let tag = CStr::from_ptr(tag).to_string_lossy();
+    let message = CStr::from_ptr(message).to_string_lossy();
+
+    match level(&message) {
+        Level::ERROR => tracing::error!(target: "dart", %tag, "{message}"),
+        Level::WARN => tracing::warn!(target: "dart", %tag, "{message}"),
+        _ => tracing::info!(target: "dart", %tag, "{message}"),
+    }
+
+    vm_service::capture_uri(&message);
+}
+
+/// Dart logs don't come with a level, so this guesses one from well known message formats.
+fn level(message: &str) -> Level {
+    let message = message.trim_start();
+
+    if message.starts_with("[ERROR")
+        || message.starts_with("[FATAL")
+        || message.contains("EXCEPTION CAUGHT BY")
+        || message.starts_with("Unhandled Exception:")
+    {
+        Level::ERROR
+    } else if message.starts_with("[WARNING") {
+        Level::WARN
+    } else {
+        Level::INFO
+    }
+}
@@ -0,0 +1,107 @@
+use std::ffi::c_void;
+
+use color_eyre::eyre::{self, OptionExt};
+use windows::core::{HSTRING, PCWSTR};
+use windows::Win32::Foundation::{LPARAM, WPARAM};
+use windows::Win32::System::DataExchange::COPYDATASTRUCT;
+use windows::Win32::UI::WindowsAndMessaging::{
+    FindWindowW, SendMessageW, SetForegroundWindow, WM_COPYDATA,
+};
+
+use crate::engine::FlutterEngine;
+use crate::navigation;
+use crate::registry;
+
+/// Class name of the main window, used to find an already running instance.
+pub const WINDOW_CLASS_NAME: &str = "fluyt";
+
+/// Identifies deep link payloads sent between instances with `WM_COPYDATA`.
+pub const COPYDATA_DEEP_LINK: usize = 0x666c7974;
+
+/// Registers the current executable as the handler for `scheme://` URIs for the current user.
+pub fn register_scheme(scheme: &str) -> eyre::Result<()> {
+    let exe = std::env::current_exe()?;
+    let exe = exe.to_str().ok_or_eyre("executable path is not valid unicode")?;
+
+    let key = format!("Software\\Classes\\{scheme}");
+
+    registry::set_string_value(&key, None, &format!("URL:{scheme}"))?;
+    registry::set_string_value(&key, Some("URL Protocol"), "")?;
+
+    // The launched instance needs the scheme to turn the uri into a route, and `--` stops a uri
+    // starting with `-` from being parsed as a flag.
+    registry::set_string_value(
+        &format!("{key}\\shell\\open\\command"),
+        None,
+        &format!("\"{exe}\" --protocol {scheme} -- \"%1\""),
+    )?;
+
+    Ok(())
+}
+
+/// Converts a `scheme://path?query` URI into a route (`/path?query`) that can be pushed to the
+/// framework. Returns `None` if the URI does not use `scheme`.
+pub fn route_from_uri(scheme: &str, uri: &str) -> Option<String> {
+    let (uri_scheme, rest) = uri.split_once(':')?;
+    if !uri_scheme.eq_ignore_ascii_case(scheme) {
+        return None;
+    }
+
+    let path = rest.trim_start_matches('/');
+    Some(format!("/{path}"))
+}
+
+/// Sends `uri` to an instance that is already running, if there is one, and brings it to the
+/// foreground. Returns `true` if the uri was forwarded.
+pub fn forward_to_running_instance(uri: &str) -> bool {
+    let hwnd = unsafe { FindWindowW(&HSTRING::from(WINDOW_CLASS_NAME), PCWSTR::null()) };
+    if hwnd.0 == 0 {
+        return false;
+    }
+
+    let data = COPYDATASTRUCT {
+        dwData: COPYDATA_DEEP_LINK,
+        cbData: uri.len() as u32,
+        lpData: uri.as_ptr() as *mut c_void,
+    };
+
+    unsafe {
+        SendMessageW(
+            hwnd,
+            WM_COPYDATA,
+            WPARAM(0),
+            LPARAM(&data as *const COPYDATASTRUCT as isize),
+        );
+        SetForegroundWindow(hwnd);
+    }
+
+    true
+}
+
+/// Handles a `WM_COPYDATA` message sent by [`forward_to_running_instance`]. Returns `false` if the
+/// message was not a deep link.
+pub unsafe fn handle_copy_data(engine: &FlutterEngine, scheme: &str, lparam: LPARAM) -> bool {
+    let Some(data) = (lparam.0 as *const COPYDATASTRUCT).as_ref() else {
+        return false;
+    };
+
+    if data.dwData != COPYDATA_DEEP_LINK || data.lpData.is_null() {
+        return false;
+    }
+
+    let bytes = std::slice::from_raw_parts(data.lpData as *const u8, data.cbData as usize);
+    let Ok(uri) = std::str::from_utf8(bytes) else {
+        tracing::error!("received invalid deep link");
+        return true;
+    };
+
+    tracing::info!(uri, "received deep link");
+
+    if let Some(route) = route_from_uri(scheme, uri) {
+        if let Err(e) = navigation::push_route_information(engine, &route) {
+            tracing::error!("failed to push route: {e}");
+        }
+    }
+
+    true
+}
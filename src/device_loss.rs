@@ -0,0 +1,43 @@
+//! Reports relaunches after the D3D device was lost (see `--relaunch-on-device-loss`), so that the
+//! app can restore what it can, since all Dart state is lost when the engine is relaunched:
+//!
+//! ```dart
+//! final relaunches = await const MethodChannel('flion/device_loss')
+//!     .invokeMethod<int>('getRelaunchCount');
+//! ```
+
+use std::cell::Cell;
+
+use flutter_codec::EncodableValue;
+
+use crate::standard_method_channel::{StandardMethodHandler, StandardMethodReply};
+
+#[derive(Default)]
+pub struct DeviceLossHandler {
+    relaunches: Cell<u32>,
+}
+
+impl DeviceLossHandler {
+    pub fn new() -> DeviceLossHandler {
+        DeviceLossHandler::default()
+    }
+
+    /// Records that the engine was relaunched on a new device.
+    pub fn record_relaunch(&self) {
+        self.relaunches.set(self.relaunches.get() + 1);
+    }
+}
+
+impl StandardMethodHandler for DeviceLossHandler {
+    fn handle(&self, method: &str, _args: EncodableValue, reply: StandardMethodReply) {
+        match method {
+            "getRelaunchCount" => {
+                reply.success(&EncodableValue::I64(self.relaunches.get() as i64));
+            }
+            _ => {
+                tracing::warn!(method, "unimplemented");
+                reply.not_implemented();
+            }
+        }
+    }
+}
@@ -0,0 +1,173 @@
+use std::cell::Cell;
+use std::mem;
+use std::sync::Arc;
+
+use windows::core::PCWSTR;
+use windows::Win32::Foundation::{BOOL, HWND, LPARAM, RECT};
+use windows::Win32::Graphics::Gdi::{
+    EnumDisplayMonitors, EnumDisplaySettingsW, GetMonitorInfoW, MonitorFromWindow, DEVMODEW,
+    ENUM_CURRENT_SETTINGS, HDC, HMONITOR, MONITORINFO, MONITORINFOEXW, MONITOR_DEFAULTTONEAREST,
+};
+use windows::Win32::UI::HiDpi::{GetDpiForMonitor, MDT_EFFECTIVE_DPI};
+use windows::Win32::UI::WindowsAndMessaging::MONITORINFOF_PRIMARY;
+
+use crate::engine::{Display, FlutterEngine};
+use crate::error;
+use crate::vsync_waiter::VsyncWaiter;
+
+struct Monitor {
+    handle: HMONITOR,
+    is_primary: bool,
+    display: Display,
+}
+
+/// Sends the connected displays to the engine, which uses their refresh rates for frame pacing.
+pub fn send_to_engine(engine: &FlutterEngine) -> error::Result<()> {
+    let displays = list_monitors()
+        .into_iter()
+        .map(|m| m.display)
+        .collect::<Vec<_>>();
+
+    engine.notify_display_update(&displays)?;
+
+    Ok(())
+}
+
+/// Keeps the vsync waiter up to date with the refresh rate of the display that the window is on,
+/// as displays are changed and the window is moved between them.
+pub struct DisplayTracker {
+    hwnd: HWND,
+    vsync_waiter: Arc<VsyncWaiter>,
+    /// The display that the window was last seen on.
+    current: Cell<Option<HMONITOR>>,
+}
+
+impl DisplayTracker {
+    pub fn new(hwnd: HWND, vsync_waiter: Arc<VsyncWaiter>) -> DisplayTracker {
+        DisplayTracker {
+            hwnd,
+            vsync_waiter,
+            current: Cell::new(None),
+        }
+    }
+
+    /// Should be called on startup and on `WM_DISPLAYCHANGE`, which is sent when a display's mode
+    /// (including its refresh rate) changes or a display is connected.
+    pub fn update(&self) {
+        self.update_window_display(&list_monitors());
+    }
+
+    /// Should be called when the window moves, since it may now be on a different display.
+    pub fn handle_window_moved(&self) {
+        let monitor = unsafe { MonitorFromWindow(self.hwnd, MONITOR_DEFAULTTONEAREST) };
+        if self.current.get() != Some(monitor) {
+            self.update_window_display(&list_monitors());
+        }
+    }
+
+    fn update_window_display(&self, monitors: &[Monitor]) {
+        let handle = unsafe { MonitorFromWindow(self.hwnd, MONITOR_DEFAULTTONEAREST) };
+        self.current.set(Some(handle));
+
+        let Some(monitor) = monitors.iter().find(|m| m.handle == handle) else {
+            self.vsync_waiter.set_refresh_rate(None);
+            return;
+        };
+
+        tracing::info!(
+            refresh_rate = monitor.display.refresh_rate,
+            is_primary = monitor.is_primary,
+            "window is on display"
+        );
+
+        // DWM's timing info is for the primary display, but follows it when its rate changes
+        // dynamically, so it is preferred there.
+        self.vsync_waiter.set_refresh_rate(if monitor.is_primary {
+            None
+        } else {
+            Some(monitor.display.refresh_rate)
+        });
+    }
+}
+
+fn list_monitors() -> Vec<Monitor> {
+    unsafe extern "system" fn callback(
+        handle: HMONITOR,
+        _: HDC,
+        _: *mut RECT,
+        monitors: LPARAM,
+    ) -> BOOL {
+        let monitors = &mut *(monitors.0 as *mut Vec<Monitor>);
+
+        if let Some(monitor) = monitor_info(handle) {
+            monitors.push(monitor);
+        }
+
+        true.into()
+    }
+
+    let mut monitors = Vec::new();
+
+    unsafe {
+        EnumDisplayMonitors(
+            None,
+            None,
+            Some(callback),
+            LPARAM(&mut monitors as *mut Vec<Monitor> as isize),
+        );
+    }
+
+    monitors
+}
+
+unsafe fn monitor_info(handle: HMONITOR) -> Option<Monitor> {
+    let mut info = MONITORINFOEXW {
+        monitorInfo: MONITORINFO {
+            cbSize: mem::size_of::<MONITORINFOEXW>() as u32,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    if !GetMonitorInfoW(handle, &mut info.monitorInfo).as_bool() {
+        return None;
+    }
+
+    let mut mode = DEVMODEW {
+        dmSize: mem::size_of::<DEVMODEW>() as u16,
+        ..Default::default()
+    };
+
+    if !EnumDisplaySettingsW(
+        PCWSTR(info.szDevice.as_ptr()),
+        ENUM_CURRENT_SETTINGS,
+        &mut mode,
+    )
+    .as_bool()
+    {
+        return None;
+    }
+
+    // 0 and 1 mean that the display uses its default rate, which isn't known.
+    if mode.dmDisplayFrequency <= 1 {
+        return None;
+    }
+
+    let mut dpi_x = 96;
+    let mut dpi_y = 96;
+    let _ = GetDpiForMonitor(handle, MDT_EFFECTIVE_DPI, &mut dpi_x, &mut dpi_y);
+
+    let rect = info.monitorInfo.rcMonitor;
+
+    Some(Monitor {
+        handle,
+        is_primary: info.monitorInfo.dwFlags & MONITORINFOF_PRIMARY != 0,
+        display: Display {
+            id: handle.0 as u64,
+            refresh_rate: mode.dmDisplayFrequency as f64,
+            width: (rect.right - rect.left) as usize,
+            height: (rect.bottom - rect.top) as usize,
+            device_pixel_ratio: dpi_x as f64 / 96.0,
+        },
+    })
+}
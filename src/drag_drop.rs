@@ -0,0 +1,507 @@
+use std::collections::BTreeMap;
+use std::mem::ManuallyDrop;
+use std::rc::Rc;
+
+use color_eyre::eyre::{self, bail};
+use flutter_codec::EncodableValue;
+use windows::core::{implement, w, Result as WinResult, HRESULT, HSTRING};
+use windows::Win32::Foundation::{
+    BOOL, COLORREF, DRAGDROP_S_CANCEL, DRAGDROP_S_DROP, DRAGDROP_S_USEDEFAULTCURSORS, HGLOBAL,
+    HWND, POINT, POINTL, SIZE, S_OK,
+};
+use windows::Win32::Graphics::Gdi::{
+    CreateDIBSection, DeleteObject, ScreenToClient, BITMAPINFO, BITMAPINFOHEADER, BI_RGB,
+    DIB_RGB_COLORS,
+};
+use windows::Win32::System::Com::{
+    CoCreateInstance, IDataObject, CLSCTX_INPROC_SERVER, DVASPECT_CONTENT, FORMATETC, STGMEDIUM,
+    STGMEDIUM_0, TYMED_HGLOBAL,
+};
+use windows::Win32::System::DataExchange::RegisterClipboardFormatW;
+use windows::Win32::System::Memory::{GlobalAlloc, GlobalLock, GlobalUnlock, GMEM_MOVEABLE};
+use windows::Win32::System::Ole::{
+    DoDragDrop, IDropSource, IDropSource_Impl, IDropTarget, IDropTarget_Impl, RegisterDragDrop,
+    ReleaseStgMedium, CF_HDROP, CF_UNICODETEXT, DROPEFFECT, DROPEFFECT_COPY, DROPEFFECT_LINK,
+    DROPEFFECT_MOVE, DROPEFFECT_NONE,
+};
+use windows::Win32::System::SystemServices::{
+    MK_LBUTTON, MK_MBUTTON, MK_RBUTTON, MK_XBUTTON1, MK_XBUTTON2, MODIFIERKEYS_FLAGS,
+};
+use windows::Win32::UI::HiDpi::GetDpiForWindow;
+use windows::Win32::UI::Input::KeyboardAndMouse::{
+    GetKeyState, VIRTUAL_KEY, VK_LBUTTON, VK_MBUTTON, VK_RBUTTON, VK_XBUTTON1, VK_XBUTTON2,
+};
+use windows::Win32::UI::Shell::{
+    CLSID_DragDropHelper, DragQueryFileW, IDragSourceHelper, SHCreateDataObject, HDROP, SHDRAGIMAGE,
+};
+
+use crate::error_utils::ResultExt;
+use crate::event_channel::EventChannel;
+use crate::screenshot::Frame;
+use crate::standard_method_channel::{StandardMethodHandler, StandardMethodReply};
+
+/// Registers a drop target on the window which forwards drag events to `events`.
+pub fn register(hwnd: HWND, events: Rc<EventChannel>) -> eyre::Result<()> {
+    let target: IDropTarget = DropTarget { hwnd, events }.into();
+    unsafe { RegisterDragDrop(hwnd, &target)? };
+    Ok(())
+}
+
+#[implement(IDropTarget)]
+struct DropTarget {
+    hwnd: HWND,
+    events: Rc<EventChannel>,
+}
+
+#[derive(Default)]
+struct DropData {
+    files: Vec<String>,
+    text: Option<String>,
+    url: Option<String>,
+}
+
+impl DropData {
+    fn read(data: &IDataObject) -> DropData {
+        DropData {
+            files: unsafe { read_files(data) }.unwrap_or_default(),
+            text: unsafe { read_string(data, CF_UNICODETEXT.0) },
+            url: unsafe {
+                read_string(
+                    data,
+                    RegisterClipboardFormatW(w!("UniformResourceLocatorW")) as u16,
+                )
+            },
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.files.is_empty() && self.text.is_none() && self.url.is_none()
+    }
+}
+
+impl DropTarget {
+    fn send_event(&self, event_type: &str, pt: Option<&POINTL>, data: Option<&DropData>) {
+        let mut event = BTreeMap::new();
+
+        event.insert(EncodableValue::Str("type"), EncodableValue::Str(event_type));
+
+        if let Some(pt) = pt {
+            let (x, y) = self.to_logical(pt);
+            event.insert(EncodableValue::Str("x"), EncodableValue::F64(x.into()));
+            event.insert(EncodableValue::Str("y"), EncodableValue::F64(y.into()));
+        }
+
+        if let Some(data) = data {
+            event.insert(
+                EncodableValue::Str("files"),
+                EncodableValue::List(
+                    data.files
+                        .iter()
+                        .map(|file| EncodableValue::Str(file))
+                        .collect(),
+                ),
+            );
+
+            if let Some(text) = &data.text {
+                event.insert(EncodableValue::Str("text"), EncodableValue::Str(text));
+            }
+
+            if let Some(url) = &data.url {
+                event.insert(EncodableValue::Str("url"), EncodableValue::Str(url));
+            }
+        }
+
+        let _ = self.events.send(&EncodableValue::Map(event)).trace_err();
+    }
+
+    fn to_logical(&self, pt: &POINTL) -> (f64, f64) {
+        let mut point = POINT { x: pt.x, y: pt.y };
+        unsafe { ScreenToClient(self.hwnd, &mut point) };
+        let scale_factor = unsafe { GetDpiForWindow(self.hwnd) } as f64 / 96.0;
+        (point.x as f64 / scale_factor, point.y as f64 / scale_factor)
+    }
+}
+
+impl IDropTarget_Impl for DropTarget {
+    fn DragEnter(
+        &self,
+        pdataobj: Option<&IDataObject>,
+        _grfkeystate: MODIFIERKEYS_FLAGS,
+        pt: &POINTL,
+        pdweffect: *mut DROPEFFECT,
+    ) -> WinResult<()> {
+        let data = pdataobj.map(DropData::read).unwrap_or_default();
+
+        let effect = if data.is_empty() || !self.events.is_listening() {
+            DROPEFFECT_NONE
+        } else {
+            DROPEFFECT_COPY
+        };
+
+        unsafe { *pdweffect = effect };
+
+        self.send_event("enter", Some(pt), Some(&data));
+
+        Ok(())
+    }
+
+    fn DragOver(
+        &self,
+        _grfkeystate: MODIFIERKEYS_FLAGS,
+        pt: &POINTL,
+        pdweffect: *mut DROPEFFECT,
+    ) -> WinResult<()> {
+        let effect = if self.events.is_listening() {
+            DROPEFFECT_COPY
+        } else {
+            DROPEFFECT_NONE
+        };
+
+        unsafe { *pdweffect = effect };
+
+        self.send_event("over", Some(pt), None);
+
+        Ok(())
+    }
+
+    fn DragLeave(&self) -> WinResult<()> {
+        self.send_event("leave", None, None);
+        Ok(())
+    }
+
+    fn Drop(
+        &self,
+        pdataobj: Option<&IDataObject>,
+        _grfkeystate: MODIFIERKEYS_FLAGS,
+        pt: &POINTL,
+        pdweffect: *mut DROPEFFECT,
+    ) -> WinResult<()> {
+        let data = pdataobj.map(DropData::read).unwrap_or_default();
+
+        unsafe { *pdweffect = DROPEFFECT_COPY };
+
+        self.send_event("drop", Some(pt), Some(&data));
+
+        Ok(())
+    }
+}
+
+fn hglobal_format(format: u16) -> FORMATETC {
+    FORMATETC {
+        cfFormat: format,
+        ptd: std::ptr::null_mut(),
+        dwAspect: DVASPECT_CONTENT.0,
+        lindex: -1,
+        tymed: TYMED_HGLOBAL.0 as u32,
+    }
+}
+
+unsafe fn read_files(data: &IDataObject) -> Option<Vec<String>> {
+    let mut medium = data.GetData(&hglobal_format(CF_HDROP.0)).ok()?;
+
+    let hdrop = HDROP(medium.u.hGlobal.0 as isize);
+    let count = DragQueryFileW(hdrop, u32::MAX, None);
+
+    let mut files = Vec::with_capacity(count as usize);
+    for i in 0..count {
+        let len = DragQueryFileW(hdrop, i, None) as usize;
+        let mut buf = vec![0u16; len + 1];
+        DragQueryFileW(hdrop, i, Some(&mut buf));
+        files.push(String::from_utf16_lossy(&buf[..len]));
+    }
+
+    ReleaseStgMedium(&mut medium);
+
+    Some(files)
+}
+
+unsafe fn read_string(data: &IDataObject, format: u16) -> Option<String> {
+    let mut medium = data.GetData(&hglobal_format(format)).ok()?;
+
+    let hglobal: HGLOBAL = medium.u.hGlobal;
+    let ptr = GlobalLock(hglobal) as *const u16;
+
+    let value = if ptr.is_null() {
+        None
+    } else {
+        let len = (0..).take_while(|&i| *ptr.add(i) != 0).count();
+        Some(String::from_utf16_lossy(std::slice::from_raw_parts(
+            ptr, len,
+        )))
+    };
+
+    let _ = GlobalUnlock(hglobal);
+    ReleaseStgMedium(&mut medium);
+
+    value
+}
+
+/// Handles `flion/dragdrop`, which is both the event channel for drops onto the window and the
+/// method channel for starting drags out of it.
+pub struct DragDropHandler {
+    events: Rc<EventChannel>,
+    on_drag_finished: Box<dyn Fn()>,
+}
+
+impl DragDropHandler {
+    /// `on_drag_finished` is called after a drag started from the app has finished, since the
+    /// mouse button was released inside the drag loop and the window never received it.
+    pub fn new(events: Rc<EventChannel>, on_drag_finished: impl Fn() + 'static) -> DragDropHandler {
+        DragDropHandler {
+            events,
+            on_drag_finished: Box::new(on_drag_finished),
+        }
+    }
+}
+
+impl StandardMethodHandler for DragDropHandler {
+    fn handle(&self, method: &str, args: EncodableValue, reply: StandardMethodReply) {
+        if method != "startDrag" {
+            return self.events.handle(method, args, reply);
+        }
+
+        let res = unsafe { start_drag(&args) };
+
+        (self.on_drag_finished)();
+
+        match res {
+            Ok(effect) => {
+                let effect = match effect {
+                    DROPEFFECT_COPY => "copy",
+                    DROPEFFECT_MOVE => "move",
+                    DROPEFFECT_LINK => "link",
+                    _ => "none",
+                };
+
+                reply.success(&EncodableValue::Str(effect));
+            }
+            Err(e) => reply.error("drag_failed", Some(&format!("{e:?}"))),
+        }
+    }
+}
+
+/// Runs an OLE drag with the files, text and custom formats in `args`, returning the effect chosen
+/// by the drop target. This doesn't return until the drag has finished.
+///
+/// The drag image is an optional png, drawn with the cursor at its `imageOffsetX` and
+/// `imageOffsetY` (in physical pixels).
+unsafe fn start_drag(args: &EncodableValue) -> eyre::Result<DROPEFFECT> {
+    let data: IDataObject = SHCreateDataObject(None, None, None)?;
+    let mut is_empty = true;
+
+    if let Some(files) = args.get("files").and_then(|v| v.as_list()) {
+        let files = files.iter().filter_map(|file| file.as_string());
+        set_data(&data, CF_HDROP.0, &encode_drop_files(files))?;
+        is_empty = false;
+    }
+
+    if let Some(text) = args.get("text").and_then(|v| v.as_string()) {
+        set_data(&data, CF_UNICODETEXT.0, &encode_utf16(text))?;
+        is_empty = false;
+    }
+
+    if let Some(formats) = args.get("formats").and_then(|v| v.as_map()) {
+        for (name, value) in formats {
+            let (Some(name), Some(value)) = (name.as_string(), value.as_u8_list()) else {
+                bail!("expected formats to be a map of format names to bytes");
+            };
+
+            let format = RegisterClipboardFormatW(&HSTRING::from(name)) as u16;
+            set_data(&data, format, value)?;
+            is_empty = false;
+        }
+    }
+
+    if is_empty {
+        bail!("there is nothing to drag");
+    }
+
+    if let Some(image) = args.get("image").and_then(|v| v.as_u8_list()) {
+        let offset = |key: &str| args.get(key).and_then(|v| v.as_f64()).unwrap_or(0.0) as i32;
+
+        set_drag_image(
+            &data,
+            &Frame::decode(image)?,
+            offset("imageOffsetX"),
+            offset("imageOffsetY"),
+        )?;
+    }
+
+    let allowed = match args.get("effects").and_then(|v| v.as_list()) {
+        Some(effects) => effects
+            .iter()
+            .filter_map(|effect| match effect.as_string()? {
+                "copy" => Some(DROPEFFECT_COPY),
+                "move" => Some(DROPEFFECT_MOVE),
+                "link" => Some(DROPEFFECT_LINK),
+                _ => None,
+            })
+            .fold(DROPEFFECT_NONE, |a, b| a | b),
+        None => DROPEFFECT_COPY,
+    };
+
+    let source: IDropSource = DropSource {
+        buttons: pressed_mouse_buttons(),
+    }
+    .into();
+    let mut effect = DROPEFFECT_NONE;
+
+    let res = DoDragDrop(&data, &source, allowed, &mut effect);
+    if res == DRAGDROP_S_DROP {
+        Ok(effect)
+    } else {
+        // The drag was cancelled, or failed.
+        res.ok()?;
+        Ok(DROPEFFECT_NONE)
+    }
+}
+
+/// The mouse buttons that are currently held down, which are the ones that started the drag. Drags
+/// from touch or pen input are reported as the left button.
+unsafe fn pressed_mouse_buttons() -> MODIFIERKEYS_FLAGS {
+    let buttons: [(VIRTUAL_KEY, MODIFIERKEYS_FLAGS); 5] = [
+        (VK_LBUTTON, MK_LBUTTON),
+        (VK_RBUTTON, MK_RBUTTON),
+        (VK_MBUTTON, MK_MBUTTON),
+        (VK_XBUTTON1, MK_XBUTTON1),
+        (VK_XBUTTON2, MK_XBUTTON2),
+    ];
+
+    let pressed = buttons
+        .into_iter()
+        .filter(|(key, _)| GetKeyState(key.0 as i32) < 0)
+        .fold(0, |pressed, (_, flag)| pressed | flag.0);
+
+    if pressed == 0 {
+        MK_LBUTTON
+    } else {
+        MODIFIERKEYS_FLAGS(pressed)
+    }
+}
+
+#[implement(IDropSource)]
+struct DropSource {
+    /// The drop happens once all of these have been released.
+    buttons: MODIFIERKEYS_FLAGS,
+}
+
+impl IDropSource_Impl for DropSource {
+    fn QueryContinueDrag(&self, fescapepressed: BOOL, grfkeystate: MODIFIERKEYS_FLAGS) -> HRESULT {
+        if fescapepressed.as_bool() {
+            DRAGDROP_S_CANCEL
+        } else if grfkeystate.0 & self.buttons.0 == 0 {
+            DRAGDROP_S_DROP
+        } else {
+            S_OK
+        }
+    }
+
+    fn GiveFeedback(&self, _dweffect: DROPEFFECT) -> HRESULT {
+        DRAGDROP_S_USEDEFAULTCURSORS
+    }
+}
+
+/// Copies `bytes` into global memory which is then owned by `data`.
+unsafe fn set_data(data: &IDataObject, format: u16, bytes: &[u8]) -> eyre::Result<()> {
+    let memory = GlobalAlloc(GMEM_MOVEABLE, bytes.len())?;
+    let ptr = GlobalLock(memory).cast::<u8>();
+    std::ptr::copy_nonoverlapping(bytes.as_ptr(), ptr, bytes.len());
+    let _ = GlobalUnlock(memory);
+
+    let medium = STGMEDIUM {
+        tymed: TYMED_HGLOBAL.0 as u32,
+        u: STGMEDIUM_0 { hGlobal: memory },
+        pUnkForRelease: ManuallyDrop::new(None),
+    };
+
+    data.SetData(&hglobal_format(format), &medium, true)?;
+
+    Ok(())
+}
+
+fn encode_utf16(text: &str) -> Vec<u8> {
+    text.encode_utf16()
+        .chain([0])
+        .flat_map(u16::to_le_bytes)
+        .collect()
+}
+
+/// Encodes a `DROPFILES` struct followed by the double nul terminated list of paths, for
+/// `CF_HDROP`.
+fn encode_drop_files<'a>(files: impl Iterator<Item = &'a str>) -> Vec<u8> {
+    const DROPFILES_SIZE: u32 = 20;
+
+    let mut data = vec![];
+    data.extend_from_slice(&DROPFILES_SIZE.to_le_bytes()); // pFiles
+    data.extend_from_slice(&[0; 8]); // pt
+    data.extend_from_slice(&0u32.to_le_bytes()); // fNC
+    data.extend_from_slice(&1u32.to_le_bytes()); // fWide
+
+    for file in files {
+        data.extend(encode_utf16(file));
+    }
+
+    data.extend_from_slice(&[0, 0]);
+    data
+}
+
+unsafe fn set_drag_image(
+    data: &IDataObject,
+    image: &Frame,
+    offset_x: i32,
+    offset_y: i32,
+) -> eyre::Result<()> {
+    let info = BITMAPINFO {
+        bmiHeader: BITMAPINFOHEADER {
+            biSize: std::mem::size_of::<BITMAPINFOHEADER>() as u32,
+            biWidth: image.width as i32,
+            // Negative for rows from top to bottom.
+            biHeight: -(image.height as i32),
+            biPlanes: 1,
+            biBitCount: 32,
+            biCompression: BI_RGB.0,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    let mut bits = std::ptr::null_mut();
+    let bitmap = CreateDIBSection(None, &info, DIB_RGB_COLORS, &mut bits, None, 0)?;
+
+    // The drag image is drawn with premultiplied BGRA pixels.
+    let pixels = std::slice::from_raw_parts_mut(bits.cast::<u8>(), image.pixels.len());
+    for (dst, src) in pixels.chunks_exact_mut(4).zip(image.pixels.chunks_exact(4)) {
+        let premultiply = |c: u8| (c as u32 * src[3] as u32 / 255) as u8;
+        dst.copy_from_slice(&[
+            premultiply(src[2]),
+            premultiply(src[1]),
+            premultiply(src[0]),
+            src[3],
+        ]);
+    }
+
+    let drag_image = SHDRAGIMAGE {
+        sizeDragImage: SIZE {
+            cx: image.width as i32,
+            cy: image.height as i32,
+        },
+        ptOffset: POINT {
+            x: offset_x,
+            y: offset_y,
+        },
+        hbmpDragImage: bitmap,
+        // CLR_NONE, since the image has an alpha channel.
+        crColorKey: COLORREF(0xffffffff),
+    };
+
+    let helper: IDragSourceHelper =
+        CoCreateInstance(&CLSID_DragDropHelper, None, CLSCTX_INPROC_SERVER)?;
+
+    // The helper takes ownership of the bitmap if this succeeds.
+    if let Err(e) = helper.InitializeFromBitmap(&drag_image, data) {
+        DeleteObject(bitmap);
+        return Err(e.into());
+    }
+
+    Ok(())
+}
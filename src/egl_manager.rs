@@ -1,13 +1,14 @@
 use std::ffi::c_void;
-use std::ptr;
-use std::sync::Arc;
+use std::mem;
+use std::sync::{Arc, RwLock, RwLockReadGuard};
 
-use color_eyre::eyre::{self, bail};
 use egl::ClientBuffer;
 use khronos_egl as egl;
 use windows::core::Interface;
 use windows::Win32::Graphics::Direct3D11::{ID3D11Device, ID3D11Texture2D};
 
+use crate::error::{self, FlionError};
+
 const EGL_PLATFORM_DEVICE_EXT: egl::Enum = 0x313F;
 
 const EGL_D3D11_DEVICE_ANGLE: egl::Int = 0x33A1;
@@ -18,6 +19,12 @@ const EGL_TEXTURE_OFFSET_Y_ANGLE: i32 = 0x3491;
 
 pub struct EglManager {
     egl: egl::Instance<egl::Static>,
+    /// Replaced when the D3D device is lost.
+    state: RwLock<EglState>,
+}
+
+struct EglState {
+    device: ID3D11Device,
     angle_device: *mut c_void,
     display: egl::Display,
     config: egl::Config,
@@ -29,89 +36,88 @@ unsafe impl Send for EglManager {}
 unsafe impl Sync for EglManager {}
 
 impl EglManager {
-    pub fn create(device: &ID3D11Device) -> eyre::Result<Arc<EglManager>> {
+    pub fn create(device: &ID3D11Device) -> error::Result<Arc<EglManager>> {
         let egl = egl::Instance::new(egl::Static);
-
-        let angle_device = unsafe {
-            eglCreateDeviceANGLE(EGL_D3D11_DEVICE_ANGLE, device.as_raw(), &egl::ATTRIB_NONE)
-        };
-
-        if angle_device.is_null() {
-            bail!("failed to create angle device");
-        }
-
-        // let attribs = [egl::NONE as egl::Attrib];
-        // unsafe { eglDebugMessageControlKHR(debug_callback, attribs.as_ptr()) };
-
-        let display = unsafe {
-            egl.get_platform_display(EGL_PLATFORM_DEVICE_EXT, angle_device, &[egl::ATTRIB_NONE])?
-        };
-
-        egl.initialize(display)?;
-
-        let mut configs = Vec::with_capacity(1);
-        let config_attribs = [
-            egl::RED_SIZE,
-            8,
-            egl::GREEN_SIZE,
-            8,
-            egl::BLUE_SIZE,
-            8,
-            egl::ALPHA_SIZE,
-            8,
-            egl::DEPTH_SIZE,
-            8,
-            egl::STENCIL_SIZE,
-            8,
-            egl::NONE,
-        ];
-
-        egl.choose_config(display, &config_attribs, &mut configs)?;
-
-        let config = configs[0];
-
-        let context_attribs = [egl::CONTEXT_CLIENT_VERSION, 2, egl::NONE];
-        let context = egl.create_context(display, config, None, &context_attribs)?;
-        let resource_context =
-            egl.create_context(display, config, Some(context), &context_attribs)?;
+        let state = EglState::create(&egl, device)?;
 
         Ok(Arc::new(EglManager {
             egl,
-            angle_device: ptr::null_mut(),
-            display,
-            config,
-            context,
-            resource_context,
+            state: RwLock::new(state),
         }))
     }
 
-    pub fn make_surface_current(&self, surface: egl::Surface) -> eyre::Result<()> {
+    /// Recreates the display and contexts on a new D3D device, e.g. after the previous one was
+    /// removed. Any surfaces created from the old device must already have been destroyed.
+    pub fn reset(&self, device: &ID3D11Device) -> error::Result<()> {
+        let new_state = EglState::create(&self.egl, device)?;
+        let old_state = mem::replace(&mut *self.state.write().unwrap(), new_state);
+        old_state.release(&self.egl);
+        Ok(())
+    }
+
+    fn state(&self) -> RwLockReadGuard<EglState> {
+        self.state.read().unwrap()
+    }
+
+    pub fn make_surface_current(&self, surface: egl::Surface) -> error::Result<()> {
+        let state = self.state();
         self.egl.make_current(
-            self.display,
+            state.display,
             Some(surface),
             Some(surface),
-            Some(self.context),
+            Some(state.context),
         )?;
         Ok(())
     }
 
-    pub fn make_context_current(&self) -> eyre::Result<()> {
+    pub fn make_context_current(&self) -> error::Result<()> {
+        let state = self.state();
         self.egl
-            .make_current(self.display, None, None, Some(self.context))?;
+            .make_current(state.display, None, None, Some(state.context))?;
         Ok(())
     }
 
-    pub fn make_resource_context_current(&self) -> eyre::Result<()> {
+    pub fn make_resource_context_current(&self) -> error::Result<()> {
+        let state = self.state();
         self.egl
-            .make_current(self.display, None, None, Some(self.resource_context))?;
+            .make_current(state.display, None, None, Some(state.resource_context))?;
         Ok(())
     }
 
-    pub fn clear_current(&self) -> eyre::Result<()> {
-        self.egl.make_current(self.display, None, None, None)?;
+    pub fn clear_current(&self) -> error::Result<()> {
+        self.egl
+            .make_current(self.state().display, None, None, None)?;
         Ok(())
     }
 
+    /// The D3D device that the display was created on.
+    pub fn device(&self) -> ID3D11Device {
+        self.state().device.clone()
+    }
+
+    pub fn display(&self) -> egl::Display {
+        self.state().display
+    }
+
+    pub fn config(&self) -> egl::Config {
+        self.state().config
+    }
+
+    /// Creates a context that shares objects with the engine's contexts, e.g. for a plugin to
+    /// render into textures on its own thread. The caller must destroy it before the display is
+    /// reset.
+    pub fn create_share_context(&self) -> error::Result<egl::Context> {
+        let state = self.state();
+        let context_attribs = [egl::CONTEXT_CLIENT_VERSION, 2, egl::NONE];
+        let context = self.egl.create_context(
+            state.display,
+            state.config,
+            Some(state.context),
+            &context_attribs,
+        )?;
+        Ok(context)
+    }
+
     pub fn get_proc_address(&self, name: &str) -> Option<*mut c_void> {
         self.egl.get_proc_address(name).map(|f| f as *mut c_void)
     }
@@ -120,14 +126,15 @@ impl EglManager {
         &self,
         texture: &ID3D11Texture2D,
         offset: (i32, i32),
-    ) -> eyre::Result<egl::Surface> {
+    ) -> error::Result<egl::Surface> {
         let buffer = unsafe { ClientBuffer::from_ptr(texture.as_raw()) };
+        let state = self.state();
 
         let surface = self.egl.create_pbuffer_from_client_buffer(
-            self.display,
+            state.display,
             EGL_D3D_TEXTURE_ANGLE,
             buffer,
-            self.config,
+            state.config,
             &[
                 egl::TEXTURE_FORMAT,
                 egl::TEXTURE_RGBA,
@@ -143,28 +150,95 @@ impl EglManager {
         Ok(surface)
     }
 
-    pub fn bind_tex_image(&self, surface: egl::Surface, buffer: egl::Int) -> eyre::Result<()> {
-        self.egl.bind_tex_image(self.display, surface, buffer)?;
+    pub fn bind_tex_image(&self, surface: egl::Surface, buffer: egl::Int) -> error::Result<()> {
+        self.egl
+            .bind_tex_image(self.state().display, surface, buffer)?;
         Ok(())
     }
 
-    pub fn destroy_surface(&self, surface: egl::Surface) -> eyre::Result<()> {
-        self.egl.destroy_surface(self.display, surface)?;
+    pub fn destroy_surface(&self, surface: egl::Surface) -> error::Result<()> {
+        self.egl.destroy_surface(self.state().display, surface)?;
         Ok(())
     }
 }
 
-impl Drop for EglManager {
-    fn drop(&mut self) {
-        unsafe { eglReleaseDeviceANGLE(self.angle_device) }
+impl EglState {
+    fn create(egl: &egl::Instance<egl::Static>, device: &ID3D11Device) -> error::Result<EglState> {
+        let angle_device = unsafe {
+            eglCreateDeviceANGLE(EGL_D3D11_DEVICE_ANGLE, device.as_raw(), &egl::ATTRIB_NONE)
+        };
 
-        self.egl
-            .destroy_context(self.display, self.resource_context)
-            .unwrap();
+        if angle_device.is_null() {
+            return Err(FlionError::AngleDevice);
+        }
 
-        self.egl
-            .destroy_context(self.display, self.context)
-            .unwrap();
+        // let attribs = [egl::NONE as egl::Attrib];
+        // unsafe { eglDebugMessageControlKHR(debug_callback, attribs.as_ptr()) };
+
+        let display = unsafe {
+            egl.get_platform_display(EGL_PLATFORM_DEVICE_EXT, angle_device, &[egl::ATTRIB_NONE])?
+        };
+
+        egl.initialize(display)?;
+
+        let mut configs = Vec::with_capacity(1);
+        let config_attribs = [
+            egl::RED_SIZE,
+            8,
+            egl::GREEN_SIZE,
+            8,
+            egl::BLUE_SIZE,
+            8,
+            egl::ALPHA_SIZE,
+            8,
+            egl::DEPTH_SIZE,
+            8,
+            egl::STENCIL_SIZE,
+            8,
+            egl::NONE,
+        ];
+
+        egl.choose_config(display, &config_attribs, &mut configs)?;
+
+        let config = configs[0];
+
+        let context_attribs = [egl::CONTEXT_CLIENT_VERSION, 2, egl::NONE];
+        let context = egl.create_context(display, config, None, &context_attribs)?;
+        let resource_context =
+            egl.create_context(display, config, Some(context), &context_attribs)?;
+
+        Ok(EglState {
+            device: device.clone(),
+            angle_device,
+            display,
+            config,
+            context,
+            resource_context,
+        })
+    }
+
+    fn release(&self, egl: &egl::Instance<egl::Static>) {
+        if let Err(e) = egl.destroy_context(self.display, self.resource_context) {
+            tracing::error!("failed to destroy resource context: {e}");
+        }
+
+        if let Err(e) = egl.destroy_context(self.display, self.context) {
+            tracing::error!("failed to destroy context: {e}");
+        }
+
+        if let Err(e) = egl.terminate(self.display) {
+            tracing::error!("failed to terminate display: {e}");
+        }
+
+        unsafe { eglReleaseDeviceANGLE(self.angle_device) }
+    }
+}
+
+impl Drop for EglManager {
+    fn drop(&mut self) {
+        if let Ok(state) = self.state.get_mut() {
+            state.release(&self.egl);
+        }
     }
 }
 
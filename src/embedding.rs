@@ -0,0 +1,906 @@
+use std::cell::RefCell;
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use std::{mem, ptr, slice};
+
+use color_eyre::eyre::{self, bail, OptionExt};
+use windows::core::{ComInterface, Interface};
+use windows::Foundation::Numerics::{Matrix4x4, Vector2, Vector3};
+use windows::System::DispatcherQueueController;
+use windows::Win32::Foundation::{HWND, LPARAM, LRESULT, RECT, WPARAM};
+use windows::Win32::Graphics::Direct3D11::ID3D11Device;
+use windows::Win32::System::WinRT::Composition::ICompositorDesktopInterop;
+use windows::Win32::System::WinRT::{
+    CreateDispatcherQueueController, DispatcherQueueOptions, DQTAT_COM_ASTA, DQTYPE_THREAD_CURRENT,
+};
+use windows::Win32::UI::HiDpi::GetDpiForWindow;
+use windows::Win32::UI::Shell::{DefSubclassProc, RemoveWindowSubclass, SetWindowSubclass};
+use windows::Win32::UI::WindowsAndMessaging::{GetClientRect, PostMessageW, WM_APP, WM_SIZE};
+use windows::UI::Composition::Core::CompositorController;
+use windows::UI::Composition::Desktop::DesktopWindowTarget;
+use windows::UI::Composition::{ContainerVisual, Visual};
+
+use crate::commit_batcher::CommitBatcher;
+use crate::compositor::{Compositor, NativeVisuals, SurfaceFormat, VisualPlacement};
+use crate::d3d;
+use crate::egl_manager::EglManager;
+use crate::engine::{
+    BinaryMessageHandler, BinaryMessageReply, FlutterEngine, FlutterEngineConfig, PointerButtons,
+    PointerPhase,
+};
+use crate::engine_library;
+use crate::frame_stats::{self, FrameTimings};
+use crate::platform_views::PlatformViewRegistry;
+use crate::resize_controller::ResizeController;
+use crate::screenshot;
+use crate::task_runner::{Task, TaskRunnerExecutor};
+use crate::texture_registry::TextureRegistry;
+use crate::vsync_waiter::{FramePacing, VsyncWaiter};
+
+/// Posted to the view's window when there are platform tasks to run.
+const WM_RUN_TASKS: u32 = WM_APP + 0x464c;
+
+const SUBCLASS_ID: usize = 0x666c696f;
+
+/// Passed to [`flion_engine_create`]. See `include/flion.h`.
+#[repr(C)]
+pub struct FlionEngineConfig {
+    pub struct_size: usize,
+    pub assets_path: *const c_char,
+    pub icu_data_path: *const c_char,
+    /// May be null.
+    pub initial_route: *const c_char,
+    /// A `FlionFramePacing`. Only read if `struct_size` includes it.
+    pub frame_pacing: i32,
+    pub max_frame_rate: u32,
+    /// A `FlionSurfaceFormat`. Only read if `struct_size` includes it.
+    pub surface_format: i32,
+    pub msaa_samples: u32,
+    /// May be null. Only read if `struct_size` includes it.
+    pub engine_path: *const c_char,
+    /// May be null. Only read if `struct_size` includes it.
+    pub persistent_cache_path: *const c_char,
+    pub persistent_cache_read_only: bool,
+    /// May be null if the count is 0. Only read if `struct_size` includes them.
+    pub engine_switches: *const *const c_char,
+    pub engine_switch_count: usize,
+    pub dart_flags: *const *const c_char,
+    pub dart_flag_count: usize,
+}
+
+pub type FlionMessageCallback = unsafe extern "C" fn(
+    message: *const u8,
+    message_size: usize,
+    response: *mut FlionResponseHandle,
+    user_data: *mut c_void,
+);
+
+pub struct FlionResponseHandle(BinaryMessageReply);
+
+pub type FlionFrameCallback =
+    unsafe extern "C" fn(pixels: *const u8, width: u32, height: u32, user_data: *mut c_void);
+
+/// An engine created through the C API, or by the test harness. The engine is launched once it has
+/// been attached to a window, which it then renders into.
+pub struct FlionEngine {
+    assets_path: CString,
+    icu_data_path: CString,
+    initial_route: Option<String>,
+    virtual_clock: bool,
+    frame_pacing: FramePacing,
+    surface_format: SurfaceFormat,
+    msaa_samples: u32,
+    persistent_cache_path: Option<CString>,
+    persistent_cache_read_only: bool,
+    engine_switches: Vec<String>,
+    dart_flags: Vec<String>,
+    view: Option<Box<View>>,
+}
+
+/// The state of an engine that has been attached to a window. Fields are dropped in order, so
+/// the engine is shut down before the composition objects that it renders to are released.
+struct View {
+    hwnd: HWND,
+    device: ID3D11Device,
+    engine: FlutterEngine,
+    vsync_waiter: Arc<VsyncWaiter>,
+    executor: RefCell<TaskRunnerExecutor>,
+    /// Tasks posted by the engine (from any thread) that haven't been given to the executor yet.
+    pending_tasks: Arc<Mutex<Vec<Task>>>,
+    root: ContainerVisual,
+    native_visuals: NativeVisuals,
+    /// The host's visual that `root` was inserted into, if the engine doesn't own the window's
+    /// visual tree.
+    parent: Option<ContainerVisual>,
+    _composition_target: Option<DesktopWindowTarget>,
+    _compositor_controller: Option<CompositorController>,
+    _dispatcher_queue_controller: Option<DispatcherQueueController>,
+}
+
+/// Where an attached engine renders.
+enum RenderTarget {
+    /// The engine creates a composition target for the window, and fills it.
+    Window,
+    /// The engine renders into a visual tree owned by the host, which commits its changes.
+    Visual(ContainerVisual),
+}
+
+impl FlionEngine {
+    pub fn new(
+        assets_path: &Path,
+        icu_data_path: &Path,
+        initial_route: Option<&str>,
+    ) -> eyre::Result<FlionEngine> {
+        fn path_to_cstring(path: &Path) -> eyre::Result<CString> {
+            let path = path.to_str().ok_or_eyre("path is not valid unicode")?;
+            Ok(CString::new(path)?)
+        }
+
+        engine_library::load(None)?;
+
+        Ok(FlionEngine {
+            assets_path: path_to_cstring(assets_path)?,
+            icu_data_path: path_to_cstring(icu_data_path)?,
+            initial_route: initial_route.map(str::to_owned),
+            virtual_clock: false,
+            frame_pacing: FramePacing::Vsync,
+            surface_format: SurfaceFormat::default(),
+            msaa_samples: 1,
+            persistent_cache_path: None,
+            persistent_cache_read_only: false,
+            engine_switches: vec![],
+            dart_flags: vec![],
+            view: None,
+        })
+    }
+
+    /// Makes the engine's frames be driven by [`FlionEngine::advance_frame`] rather than the
+    /// display. This must be called before the engine is attached.
+    pub fn use_virtual_clock(&mut self) {
+        self.virtual_clock = true;
+    }
+
+    /// Sets how frames are paced when they are driven by the display. This must be called before
+    /// the engine is attached.
+    pub fn set_frame_pacing(&mut self, pacing: FramePacing) {
+        self.frame_pacing = pacing;
+    }
+
+    /// Sets the pixel format and number of samples per pixel that layers are rendered with. The
+    /// sample count is limited to what the GPU supports, and 1 disables multisampling. This must
+    /// be called before the engine is attached.
+    pub fn set_surface_config(&mut self, format: SurfaceFormat, msaa_samples: u32) {
+        self.surface_format = format;
+        self.msaa_samples = msaa_samples;
+    }
+
+    /// Sets extra command line switches for the engine, e.g. `--trace-startup`. This must be
+    /// called before the engine is attached.
+    pub fn set_engine_switches(&mut self, switches: Vec<String>) {
+        self.engine_switches = switches;
+    }
+
+    /// Sets flags for the Dart VM, e.g. `--enable-asserts`, which can't contain commas. Release
+    /// builds of the engine only accept a few of them. This must be called before the engine is
+    /// attached.
+    pub fn set_dart_flags(&mut self, flags: Vec<String>) {
+        self.dart_flags = flags;
+    }
+
+    /// Adds a listener that is called with the timings of every presented frame, on the raster
+    /// thread or a thread pool thread. Frames from every engine in the process are reported.
+    pub fn add_frame_timings_listener(&self, listener: impl Fn(&FrameTimings) + Send + 'static) {
+        frame_stats::add_listener(listener);
+    }
+
+    /// See [`flion_engine_attach_hwnd`].
+    pub fn attach_hwnd(&mut self, hwnd: HWND) -> eyre::Result<()> {
+        self.attach(hwnd, RenderTarget::Window)
+    }
+
+    /// Produces a frame lasting `interval`, if the engine is using a virtual clock and has
+    /// requested one. Returns whether a frame was started.
+    pub fn advance_frame(&self, interval: Duration) -> eyre::Result<bool> {
+        Ok(self.view()?.vsync_waiter.advance(interval))
+    }
+
+    /// Returns whether the engine is waiting for a frame to be started.
+    pub fn has_pending_frame(&self) -> eyre::Result<bool> {
+        Ok(self.view()?.vsync_waiter.has_pending_request())
+    }
+
+    fn attach(&mut self, hwnd: HWND, target: RenderTarget) -> eyre::Result<()> {
+        if self.view.is_some() {
+            bail!("the engine is already attached to a window");
+        }
+
+        let mut dispatcher_queue_controller = None;
+        let mut compositor_controller = None;
+        let mut composition_target = None;
+        let mut parent = None;
+
+        let compositor = match target {
+            RenderTarget::Window => {
+                // The host may have already created a dispatcher queue for the thread, in which
+                // case that one is used.
+                dispatcher_queue_controller = unsafe {
+                    CreateDispatcherQueueController(DispatcherQueueOptions {
+                        dwSize: mem::size_of::<DispatcherQueueOptions>() as u32,
+                        threadType: DQTYPE_THREAD_CURRENT,
+                        apartmentType: DQTAT_COM_ASTA,
+                    })
+                    .ok()
+                };
+
+                let controller = CompositorController::new()?;
+                let compositor = controller.Compositor()?;
+
+                composition_target = Some(unsafe {
+                    compositor
+                        .cast::<ICompositorDesktopInterop>()?
+                        .CreateDesktopWindowTarget(hwnd, false)?
+                });
+
+                compositor_controller = Some(controller);
+                compositor
+            }
+            RenderTarget::Visual(visual) => {
+                let compositor = visual.Compositor()?;
+                parent = Some(visual);
+                compositor
+            }
+        };
+
+        let root = compositor.CreateContainerVisual()?;
+
+        root.SetTransformMatrix(Matrix4x4 {
+            M11: 1.0,
+            M22: -1.0,
+            M33: 1.0,
+            M44: 1.0,
+            ..Default::default()
+        })?;
+
+        if let Some(composition_target) = &composition_target {
+            composition_target.SetRoot(&root)?;
+        }
+
+        if let Some(parent) = &parent {
+            parent.Children()?.InsertAtTop(&root)?;
+        }
+
+        let device = d3d::create_device()?;
+        let egl_manager = EglManager::create(&device)?;
+
+        // The host owns the message loop, so resizes can't be synchronized with rendering.
+        let resize_controller = Arc::new(ResizeController::new(false));
+        let vsync_waiter = if self.virtual_clock {
+            VsyncWaiter::with_virtual_clock(hwnd, resize_controller.clone())
+        } else {
+            VsyncWaiter::new(hwnd, resize_controller.clone(), self.frame_pacing)?
+        };
+
+        let mut compositor = match &compositor_controller {
+            Some(compositor_controller) => Compositor::new(
+                device.clone(),
+                CommitBatcher::new(compositor_controller.clone()),
+                egl_manager.clone(),
+                resize_controller,
+                root.clone(),
+                PlatformViewRegistry::new(),
+            )?,
+            None => Compositor::with_external_visual(
+                device.clone(),
+                compositor,
+                egl_manager.clone(),
+                resize_controller,
+                root.clone(),
+                PlatformViewRegistry::new(),
+            )?,
+        };
+
+        compositor.set_surface_format(self.surface_format);
+        compositor.set_msaa_samples(self.msaa_samples);
+
+        let native_visuals = compositor.native_visuals();
+
+        let pending_tasks = Arc::new(Mutex::new(Vec::new()));
+
+        let engine = FlutterEngine::new(FlutterEngineConfig {
+            egl_manager,
+            compositor,
+            vsync_waiter: vsync_waiter.clone(),
+            texture_registry: TextureRegistry::new(),
+            platform_task_handler: Box::new({
+                let pending_tasks = pending_tasks.clone();
+                move |task| {
+                    pending_tasks.lock().unwrap().push(task);
+                    post_run_tasks(hwnd);
+                }
+            }),
+            platform_message_handlers: vec![],
+            assets_path: self.assets_path.clone(),
+            icu_data_path: self.icu_data_path.clone(),
+            initial_route: self.initial_route.clone(),
+            engine_switches: self.engine_switches.clone(),
+            dart_flags: self.dart_flags.clone(),
+            merged_platform_ui_thread: false,
+            persistent_cache_path: self.persistent_cache_path.clone(),
+            persistent_cache_read_only: self.persistent_cache_read_only,
+        })?;
+
+        let executor = TaskRunnerExecutor::new(move || post_run_tasks(hwnd))?;
+
+        let view = Box::new(View {
+            hwnd,
+            device,
+            engine,
+            vsync_waiter,
+            executor: RefCell::new(executor),
+            pending_tasks,
+            root,
+            native_visuals,
+            parent,
+            _composition_target: composition_target,
+            _compositor_controller: compositor_controller,
+            _dispatcher_queue_controller: dispatcher_queue_controller,
+        });
+
+        let mut rect = RECT::default();
+        unsafe { GetClientRect(hwnd, &mut rect)? };
+        view.update_metrics(rect.right - rect.left, rect.bottom - rect.top)?;
+
+        let data = &*view as *const View as usize;
+        if !unsafe { SetWindowSubclass(hwnd, Some(view_proc), SUBCLASS_ID, data) }.as_bool() {
+            bail!("failed to install window subclass");
+        }
+
+        self.view = Some(view);
+
+        Ok(())
+    }
+
+    fn view(&self) -> eyre::Result<&View> {
+        match &self.view {
+            Some(view) => Ok(view),
+            None => bail!("the engine is not attached to a window"),
+        }
+    }
+
+    /// Copies the latest composited frame out of the compositor. See [`screenshot::capture_frame`].
+    pub fn capture_frame(&self) -> eyre::Result<screenshot::Frame> {
+        let view = self.view()?;
+        screenshot::capture_frame(&view.device, &view.root.cast()?)
+    }
+
+    /// Draws a visual owned by the host below or above the Flutter content, positioned in physical
+    /// pixels from the top left of the view.
+    pub fn insert_visual(&self, visual: &Visual, placement: VisualPlacement) -> eyre::Result<()> {
+        self.view()?.native_visuals.insert(visual, placement)
+    }
+
+    /// Removes a visual that was inserted with [`FlionEngine::insert_visual`].
+    pub fn remove_visual(&self, visual: &Visual) -> eyre::Result<()> {
+        self.view()?.native_visuals.remove(visual)
+    }
+}
+
+impl Drop for FlionEngine {
+    fn drop(&mut self) {
+        let Some(view) = self.view.take() else {
+            return;
+        };
+
+        if let Some(parent) = &view.parent {
+            if let Err(e) = parent.Children().and_then(|c| c.Remove(&view.root)) {
+                tracing::error!("failed to remove root visual: {e}");
+            }
+        }
+
+        if unsafe { RemoveWindowSubclass(view.hwnd, Some(view_proc), SUBCLASS_ID) }.as_bool() {
+            view.executor.borrow_mut().clear();
+            drop(view);
+        } else {
+            // The window proc could still be called with the view, so it has to be leaked.
+            tracing::error!("failed to remove window subclass");
+            mem::forget(view);
+        }
+    }
+}
+
+impl View {
+    fn update_metrics(&self, width: i32, height: i32) -> eyre::Result<()> {
+        self.root
+            .SetSize(Vector2::new(width as f32, height as f32))?;
+        self.root.SetOffset(Vector3::new(0.0, height as f32, 0.0))?;
+
+        let scale_factor = unsafe { GetDpiForWindow(self.hwnd) } as f64 / 96.0;
+
+        self.engine
+            .send_window_metrics_event(width as usize, height as usize, scale_factor)?;
+
+        Ok(())
+    }
+
+    fn run_tasks(&self) {
+        let mut executor = self.executor.borrow_mut();
+
+        for task in self.pending_tasks.lock().unwrap().drain(..) {
+            executor.enqueue(task);
+        }
+
+        executor.run_due_tasks(&self.engine);
+    }
+}
+
+fn post_run_tasks(hwnd: HWND) {
+    if let Err(e) = unsafe { PostMessageW(hwnd, WM_RUN_TASKS, WPARAM(0), LPARAM(0)) } {
+        tracing::error!("failed to post tasks: {e}");
+    }
+}
+
+unsafe extern "system" fn view_proc(
+    hwnd: HWND,
+    msg: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+    _uidsubclass: usize,
+    dwrefdata: usize,
+) -> LRESULT {
+    let view = &*(dwrefdata as *const View);
+    match msg {
+        WM_RUN_TASKS => {
+            view.run_tasks();
+            return LRESULT(0);
+        }
+        WM_SIZE => {
+            let width = (lparam.0 & 0xffff) as i32;
+            let height = ((lparam.0 >> 16) & 0xffff) as i32;
+            if let Err(e) = view.update_metrics(width, height) {
+                tracing::error!("{e:?}");
+            }
+        }
+        _ => {}
+    }
+    DefSubclassProc(hwnd, msg, wparam, lparam)
+}
+
+/// Forwards messages on a channel to a callback set by the host.
+struct HostMessageHandler {
+    callback: FlionMessageCallback,
+    user_data: *mut c_void,
+}
+
+impl BinaryMessageHandler for HostMessageHandler {
+    fn handle(&self, message: &[u8], reply: BinaryMessageReply) {
+        // Freed by `flion_engine_send_response`.
+        let response = Box::into_raw(Box::new(FlionResponseHandle(reply)));
+        unsafe { (self.callback)(message.as_ptr(), message.len(), response, self.user_data) };
+    }
+}
+
+unsafe fn optional_str<'a>(s: *const c_char) -> eyre::Result<Option<&'a str>> {
+    if s.is_null() {
+        Ok(None)
+    } else {
+        Ok(Some(CStr::from_ptr(s).to_str()?))
+    }
+}
+
+/// Reads `count` strings from `strings`, which may be null if `count` is 0.
+unsafe fn string_array(strings: *const *const c_char, count: usize) -> eyre::Result<Vec<String>> {
+    if count == 0 {
+        return Ok(vec![]);
+    }
+
+    if strings.is_null() {
+        bail!("the array is null");
+    }
+
+    let mut result = Vec::with_capacity(count);
+    for &s in slice::from_raw_parts(strings, count) {
+        let s = optional_str(s)?.ok_or_eyre("a string is null")?;
+        result.push(s.to_owned());
+    }
+
+    Ok(result)
+}
+
+/// Creates an engine, which isn't run until it is attached to a window. Returns null on failure.
+///
+/// # Safety
+///
+/// `config` must point to a valid config, whose strings are nul terminated UTF-8.
+#[no_mangle]
+pub unsafe extern "C" fn flion_engine_create(config: *const FlionEngineConfig) -> *mut FlionEngine {
+    let Some(config) = config.as_ref() else {
+        return ptr::null_mut();
+    };
+
+    if config.assets_path.is_null() || config.icu_data_path.is_null() {
+        tracing::error!("the assets and icu data paths are required");
+        return ptr::null_mut();
+    }
+
+    let initial_route = match optional_str(config.initial_route) {
+        Ok(route) => route.map(str::to_owned),
+        Err(e) => {
+            tracing::error!("invalid initial route: {e}");
+            return ptr::null_mut();
+        }
+    };
+
+    // Older hosts pass a smaller struct without the newer options.
+    let has_field = |end: usize| config.struct_size >= end;
+
+    let frame_pacing =
+        if has_field(mem::offset_of!(FlionEngineConfig, max_frame_rate) + mem::size_of::<u32>()) {
+            match config.frame_pacing {
+                0 => FramePacing::Vsync,
+                1 => FramePacing::Capped(config.max_frame_rate),
+                2 => FramePacing::Uncapped,
+                pacing => {
+                    tracing::error!(pacing, "invalid frame pacing");
+                    return ptr::null_mut();
+                }
+            }
+        } else {
+            FramePacing::Vsync
+        };
+
+    let (surface_format, msaa_samples) =
+        if has_field(mem::offset_of!(FlionEngineConfig, msaa_samples) + mem::size_of::<u32>()) {
+            let format = match config.surface_format {
+                0 => SurfaceFormat::Bgra8,
+                1 => SurfaceFormat::Rgba8,
+                format => {
+                    tracing::error!(format, "invalid surface format");
+                    return ptr::null_mut();
+                }
+            };
+
+            (format, config.msaa_samples.max(1))
+        } else {
+            (SurfaceFormat::default(), 1)
+        };
+
+    let engine_path = if has_field(
+        mem::offset_of!(FlionEngineConfig, engine_path) + mem::size_of::<*const c_char>(),
+    ) {
+        match optional_str(config.engine_path) {
+            Ok(path) => path.map(Path::new),
+            Err(e) => {
+                tracing::error!("invalid engine path: {e}");
+                return ptr::null_mut();
+            }
+        }
+    } else {
+        None
+    };
+
+    let (persistent_cache_path, persistent_cache_read_only) = if has_field(
+        mem::offset_of!(FlionEngineConfig, persistent_cache_read_only) + mem::size_of::<bool>(),
+    ) {
+        let path = if config.persistent_cache_path.is_null() {
+            None
+        } else {
+            Some(CStr::from_ptr(config.persistent_cache_path).to_owned())
+        };
+
+        (path, config.persistent_cache_read_only)
+    } else {
+        (None, false)
+    };
+
+    let (engine_switches, dart_flags) = if has_field(mem::size_of::<FlionEngineConfig>()) {
+        let switches = string_array(config.engine_switches, config.engine_switch_count);
+        let flags = string_array(config.dart_flags, config.dart_flag_count);
+        match (switches, flags) {
+            (Ok(switches), Ok(flags)) => (switches, flags),
+            (Err(e), _) | (_, Err(e)) => {
+                tracing::error!("invalid engine switches or dart flags: {e}");
+                return ptr::null_mut();
+            }
+        }
+    } else {
+        (vec![], vec![])
+    };
+
+    if let Err(e) = engine_library::load(engine_path) {
+        tracing::error!("{e:?}");
+        return ptr::null_mut();
+    }
+
+    Box::into_raw(Box::new(FlionEngine {
+        assets_path: CStr::from_ptr(config.assets_path).to_owned(),
+        icu_data_path: CStr::from_ptr(config.icu_data_path).to_owned(),
+        initial_route,
+        virtual_clock: false,
+        frame_pacing,
+        surface_format,
+        msaa_samples,
+        persistent_cache_path,
+        persistent_cache_read_only,
+        engine_switches,
+        dart_flags,
+        view: None,
+    }))
+}
+
+/// Launches the engine, rendering into `hwnd`, which it fills. The engine's tasks are run by the
+/// window's message loop, so this and every other function must be called on the thread that
+/// owns the window.
+///
+/// # Safety
+///
+/// `engine` must have been returned by [`flion_engine_create`], and `hwnd` must be a window owned
+/// by the current thread that outlives the engine.
+#[no_mangle]
+pub unsafe extern "C" fn flion_engine_attach_hwnd(engine: *mut FlionEngine, hwnd: HWND) -> bool {
+    match (*engine).attach(hwnd, RenderTarget::Window) {
+        Ok(()) => true,
+        Err(e) => {
+            tracing::error!("failed to attach engine: {e:?}");
+            false
+        }
+    }
+}
+
+/// Launches the engine, rendering into a new child of `visual` rather than creating a composition
+/// target for the window. `hwnd` is still used to run the engine's tasks, and the view is sized to
+/// its client area.
+///
+/// The host owns the visual tree, so the engine doesn't commit its changes. They are committed by
+/// the compositor that created `visual`.
+///
+/// # Safety
+///
+/// `engine` must have been returned by [`flion_engine_create`], `hwnd` must be a window owned by
+/// the current thread that outlives the engine, and `visual` must point to an
+/// `ABI::Windows::UI::Composition::IContainerVisual`.
+#[no_mangle]
+pub unsafe extern "C" fn flion_engine_attach_visual(
+    engine: *mut FlionEngine,
+    hwnd: HWND,
+    visual: *mut c_void,
+) -> bool {
+    let Some(visual) = ContainerVisual::from_raw_borrowed(&visual) else {
+        return false;
+    };
+
+    match (*engine).attach(hwnd, RenderTarget::Visual(visual.clone())) {
+        Ok(()) => true,
+        Err(e) => {
+            tracing::error!("failed to attach engine: {e:?}");
+            false
+        }
+    }
+}
+
+/// Sends a mouse event to the engine, at a position in physical pixels relative to the window,
+/// with the buttons that are pressed.
+///
+/// # Safety
+///
+/// `engine` must have been returned by [`flion_engine_create`].
+#[no_mangle]
+pub unsafe extern "C" fn flion_engine_send_pointer(
+    engine: *mut FlionEngine,
+    phase: i32,
+    x: f64,
+    y: f64,
+    buttons: i64,
+) -> bool {
+    let phase = match phase {
+        0 => PointerPhase::Down,
+        1 => PointerPhase::Up,
+        2 => PointerPhase::Move,
+        3 => PointerPhase::Hover,
+        4 => PointerPhase::Add,
+        5 => PointerPhase::Remove,
+        6 => PointerPhase::Cancel,
+        _ => return false,
+    };
+
+    let res = (*engine).view().and_then(|view| {
+        Ok(view.engine.send_pointer_event(
+            phase,
+            x,
+            y,
+            PointerButtons::from_bits_truncate(buttons),
+        )?)
+    });
+
+    match res {
+        Ok(()) => true,
+        Err(e) => {
+            tracing::error!("{e:?}");
+            false
+        }
+    }
+}
+
+/// Sends a platform message to the framework.
+///
+/// # Safety
+///
+/// `engine` must have been returned by [`flion_engine_create`], `channel` must be nul terminated,
+/// and `message` must point to `message_size` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn flion_engine_send_message(
+    engine: *mut FlionEngine,
+    channel: *const c_char,
+    message: *const u8,
+    message_size: usize,
+) -> bool {
+    let message = if message.is_null() {
+        &[]
+    } else {
+        slice::from_raw_parts(message, message_size)
+    };
+
+    let res = (*engine).view().and_then(|view| {
+        Ok(view
+            .engine
+            .send_platform_message(CStr::from_ptr(channel), message)?)
+    });
+
+    match res {
+        Ok(()) => true,
+        Err(e) => {
+            tracing::error!("{e:?}");
+            false
+        }
+    }
+}
+
+/// Sets the callback for platform messages on `channel`, or removes it if `callback` is null. The
+/// callback must respond to every message with [`flion_engine_send_response`].
+///
+/// # Safety
+///
+/// `engine` must have been attached to a window, and `channel` must be nul terminated UTF-8.
+#[no_mangle]
+pub unsafe extern "C" fn flion_engine_set_message_handler(
+    engine: *mut FlionEngine,
+    channel: *const c_char,
+    callback: Option<FlionMessageCallback>,
+    user_data: *mut c_void,
+) -> bool {
+    let res = (*engine).view().and_then(|view| {
+        let channel = CStr::from_ptr(channel).to_str()?;
+        let handler = callback.map(|callback| {
+            Box::new(HostMessageHandler {
+                callback,
+                user_data,
+            }) as Box<dyn BinaryMessageHandler>
+        });
+        view.engine.set_message_handler(channel, handler);
+        Ok(())
+    });
+
+    match res {
+        Ok(()) => true,
+        Err(e) => {
+            tracing::error!("{e:?}");
+            false
+        }
+    }
+}
+
+/// Responds to a message received by a message handler. A null `data` is treated as the message
+/// not being handled.
+///
+/// # Safety
+///
+/// `response` must have been passed to a message callback and not responded to yet, and `data`
+/// must point to `data_size` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn flion_engine_send_response(
+    response: *mut FlionResponseHandle,
+    data: *const u8,
+    data_size: usize,
+) {
+    let response = Box::from_raw(response);
+    if data.is_null() {
+        response.0.not_implemented();
+    } else {
+        response.0.send(slice::from_raw_parts(data, data_size));
+    }
+}
+
+/// Captures the latest composited frame, and passes it to `callback` as straight alpha RGBA rows
+/// from top to bottom. The pixels are only valid for the duration of the callback, which is called
+/// before this returns.
+///
+/// # Safety
+///
+/// `engine` must have been attached to a window.
+#[no_mangle]
+pub unsafe extern "C" fn flion_engine_capture_frame(
+    engine: *mut FlionEngine,
+    callback: FlionFrameCallback,
+    user_data: *mut c_void,
+) -> bool {
+    match (*engine).capture_frame() {
+        Ok(frame) => {
+            callback(frame.pixels.as_ptr(), frame.width, frame.height, user_data);
+            true
+        }
+        Err(e) => {
+            tracing::error!("failed to capture frame: {e:?}");
+            false
+        }
+    }
+}
+
+/// Inserts a visual owned by the host below or above the Flutter content, positioned in physical
+/// pixels from the top left of the view. Visuals are drawn above those that were inserted before
+/// them on the same side.
+///
+/// # Safety
+///
+/// `engine` must have been attached to a window, and `visual` must point to an
+/// `ABI::Windows::UI::Composition::IVisual` created by the same compositor as the view.
+#[no_mangle]
+pub unsafe extern "C" fn flion_engine_insert_visual(
+    engine: *mut FlionEngine,
+    visual: *mut c_void,
+    above: bool,
+) -> bool {
+    let Some(visual) = Visual::from_raw_borrowed(&visual) else {
+        return false;
+    };
+
+    let placement = if above {
+        VisualPlacement::AboveLayers
+    } else {
+        VisualPlacement::BelowLayers
+    };
+
+    match (*engine).insert_visual(visual, placement) {
+        Ok(()) => true,
+        Err(e) => {
+            tracing::error!("failed to insert visual: {e:?}");
+            false
+        }
+    }
+}
+
+/// Removes a visual that was inserted with [`flion_engine_insert_visual`].
+///
+/// # Safety
+///
+/// `engine` must have been attached to a window, and `visual` must point to an
+/// `ABI::Windows::UI::Composition::IVisual`.
+#[no_mangle]
+pub unsafe extern "C" fn flion_engine_remove_visual(
+    engine: *mut FlionEngine,
+    visual: *mut c_void,
+) -> bool {
+    let Some(visual) = Visual::from_raw_borrowed(&visual) else {
+        return false;
+    };
+
+    match (*engine).remove_visual(visual) {
+        Ok(()) => true,
+        Err(e) => {
+            tracing::error!("failed to remove visual: {e:?}");
+            false
+        }
+    }
+}
+
+/// Shuts down the engine and frees it.
+///
+/// # Safety
+///
+/// `engine` must have been returned by [`flion_engine_create`], and must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn flion_engine_destroy(engine: *mut FlionEngine) {
+    if !engine.is_null() {
+        drop(Box::from_raw(engine));
+    }
+}
@@ -1,51 +1,156 @@
+use std::cell::{Cell, RefCell};
 use std::collections::BTreeMap;
-use std::ffi::{c_char, c_void, CStr};
-use std::sync::Arc;
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::ptr::NonNull;
+use std::rc::Rc;
+use std::sync::{Arc, RwLock, RwLockReadGuard};
 use std::{mem, ptr};
 
-use color_eyre::eyre::{self, bail};
+use bitflags::bitflags;
 use flutter_embedder::{
-    FlutterBackingStore, FlutterBackingStoreConfig, FlutterCompositor, FlutterCustomTaskRunners,
-    FlutterEngineGetCurrentTime, FlutterEngineInitialize, FlutterEngineResult_kSuccess,
-    FlutterEngineRunInitialized, FlutterEngineRunTask, FlutterEngineSendKeyEvent,
-    FlutterEngineSendPlatformMessage, FlutterEngineSendPlatformMessageResponse,
-    FlutterEngineSendPointerEvent, FlutterEngineSendWindowMetricsEvent, FlutterKeyEvent,
+    FlutterAccessibilityFeature_kFlutterAccessibilityFeatureAccessibleNavigation,
+    FlutterAccessibilityFeature_kFlutterAccessibilityFeatureDisableAnimations,
+    FlutterAccessibilityFeature_kFlutterAccessibilityFeatureHighContrast,
+    FlutterAccessibilityFeature_kFlutterAccessibilityFeatureReduceMotion, FlutterBackingStore,
+    FlutterBackingStoreConfig, FlutterCompositor, FlutterCustomTaskRunners,
+    FlutterEngineDartObject, FlutterEngineDispatchSemanticsAction, FlutterEngineDisplay,
+    FlutterEngineDisplaysUpdateType_kFlutterEngineDisplaysUpdateTypeStartup,
+    FlutterEngineGetCurrentTime, FlutterEngineInitialize, FlutterEngineNotifyDisplayUpdate,
+    FlutterEnginePostDartObject, FlutterEngineResult_kInvalidLibraryVersion,
+    FlutterEngineResult_kSuccess, FlutterEngineRunInitialized, FlutterEngineRunTask,
+    FlutterEngineSendKeyEvent, FlutterEngineSendPlatformMessage,
+    FlutterEngineSendPlatformMessageResponse, FlutterEngineSendPointerEvent,
+    FlutterEngineSendWindowMetricsEvent, FlutterEngineShutdown,
+    FlutterEngineUpdateAccessibilityFeatures, FlutterEngineUpdateLocales,
+    FlutterEngineUpdateSemanticsEnabled, FlutterKeyEvent,
     FlutterKeyEventDeviceType_kFlutterKeyEventDeviceTypeKeyboard,
     FlutterKeyEventType_kFlutterKeyEventTypeDown, FlutterKeyEventType_kFlutterKeyEventTypeRepeat,
-    FlutterKeyEventType_kFlutterKeyEventTypeUp, FlutterLayer, FlutterOpenGLRendererConfig,
-    FlutterPlatformMessage, FlutterPlatformMessageCreateResponseHandle,
-    FlutterPlatformMessageReleaseResponseHandle, FlutterPlatformMessageResponseHandle,
-    FlutterPointerEvent, FlutterPointerPhase, FlutterPointerPhase_kAdd, FlutterPointerPhase_kDown,
+    FlutterKeyEventType_kFlutterKeyEventTypeUp, FlutterLayer, FlutterLocale,
+    FlutterOpenGLRendererConfig, FlutterOpenGLTexture, FlutterPlatformMessage,
+    FlutterPlatformMessageCreateResponseHandle, FlutterPlatformMessageReleaseResponseHandle,
+    FlutterPlatformMessageResponseHandle, FlutterPointerDeviceKind,
+    FlutterPointerDeviceKind_kFlutterPointerDeviceKindMouse,
+    FlutterPointerDeviceKind_kFlutterPointerDeviceKindStylus,
+    FlutterPointerDeviceKind_kFlutterPointerDeviceKindTouch, FlutterPointerEvent,
+    FlutterPointerMouseButtons_kFlutterPointerButtonMouseBack,
+    FlutterPointerMouseButtons_kFlutterPointerButtonMouseForward,
+    FlutterPointerMouseButtons_kFlutterPointerButtonMouseMiddle,
+    FlutterPointerMouseButtons_kFlutterPointerButtonMousePrimary,
+    FlutterPointerMouseButtons_kFlutterPointerButtonMouseSecondary, FlutterPointerPhase,
+    FlutterPointerPhase_kAdd, FlutterPointerPhase_kCancel, FlutterPointerPhase_kDown,
     FlutterPointerPhase_kHover, FlutterPointerPhase_kMove, FlutterPointerPhase_kRemove,
-    FlutterPointerPhase_kUp, FlutterProjectArgs, FlutterRendererConfig,
-    FlutterRendererType_kOpenGL, FlutterTask, FlutterTaskRunnerDescription,
+    FlutterPointerPhase_kUp, FlutterPointerSignalKind_kFlutterPointerSignalKindScroll,
+    FlutterProjectArgs, FlutterRendererConfig, FlutterRendererType_kOpenGL, FlutterSemanticsAction,
+    FlutterSemanticsAction_kFlutterSemanticsActionDecrease,
+    FlutterSemanticsAction_kFlutterSemanticsActionIncrease,
+    FlutterSemanticsAction_kFlutterSemanticsActionSetText,
+    FlutterSemanticsAction_kFlutterSemanticsActionShowOnScreen,
+    FlutterSemanticsAction_kFlutterSemanticsActionTap,
+    FlutterSemanticsFlag_kFlutterSemanticsFlagHasCheckedState,
+    FlutterSemanticsFlag_kFlutterSemanticsFlagHasEnabledState,
+    FlutterSemanticsFlag_kFlutterSemanticsFlagHasToggledState,
+    FlutterSemanticsFlag_kFlutterSemanticsFlagIsButton,
+    FlutterSemanticsFlag_kFlutterSemanticsFlagIsCheckStateMixed,
+    FlutterSemanticsFlag_kFlutterSemanticsFlagIsChecked,
+    FlutterSemanticsFlag_kFlutterSemanticsFlagIsEnabled,
+    FlutterSemanticsFlag_kFlutterSemanticsFlagIsFocusable,
+    FlutterSemanticsFlag_kFlutterSemanticsFlagIsFocused,
+    FlutterSemanticsFlag_kFlutterSemanticsFlagIsHeader,
+    FlutterSemanticsFlag_kFlutterSemanticsFlagIsHidden,
+    FlutterSemanticsFlag_kFlutterSemanticsFlagIsImage,
+    FlutterSemanticsFlag_kFlutterSemanticsFlagIsLink,
+    FlutterSemanticsFlag_kFlutterSemanticsFlagIsObscured,
+    FlutterSemanticsFlag_kFlutterSemanticsFlagIsReadOnly,
+    FlutterSemanticsFlag_kFlutterSemanticsFlagIsSelected,
+    FlutterSemanticsFlag_kFlutterSemanticsFlagIsSlider,
+    FlutterSemanticsFlag_kFlutterSemanticsFlagIsTextField,
+    FlutterSemanticsFlag_kFlutterSemanticsFlagIsToggled, FlutterSemanticsNode2,
+    FlutterSemanticsUpdate2, FlutterTask, FlutterTaskRunnerDescription, FlutterTransformation,
     FlutterWindowMetricsEvent, FLUTTER_ENGINE_VERSION,
 };
+use serde::{Deserialize, Serialize};
 use smol_str::SmolStr;
+use windows::Win32::Graphics::Direct3D11::ID3D11Device;
 
+use crate::channel_log::{self, Direction, PendingReply};
 use crate::compositor::Compositor;
+use crate::dart_log;
 use crate::egl_manager::EglManager;
+use crate::engine_library;
+use crate::error::{self, check_engine_result, FlionError};
+use crate::flight_recorder::{self, EventKind};
+use crate::input_recording::{self, InputEvent, InputRecorder};
+use crate::navigation;
 use crate::task_runner::{self, Task, TaskRunner};
+use crate::texture_registry::TextureRegistry;
+use crate::vsync_waiter::VsyncWaiter;
+
+pub const ASSETS_PATH: &CStr = c"example/build/flutter_assets";
+pub const ICU_DATA_PATH: &CStr = c"icudtl.dat";
 
 pub struct FlutterEngineConfig<'a> {
     pub egl_manager: Arc<EglManager>,
     pub compositor: Compositor,
+    pub vsync_waiter: Arc<VsyncWaiter>,
+    pub texture_registry: Arc<TextureRegistry>,
     pub platform_task_handler: Box<dyn Fn(Task)>,
     pub platform_message_handlers: Vec<(&'a str, Box<dyn BinaryMessageHandler + 'static>)>,
+    pub assets_path: CString,
+    pub icu_data_path: CString,
+    pub initial_route: Option<String>,
+    /// Extra command line switches passed to the engine.
+    pub engine_switches: Vec<String>,
+    /// Flags passed to the Dart VM, e.g. `--enable-asserts`. Release builds of the engine only
+    /// accept a few of them. Flags can't contain commas.
+    pub dart_flags: Vec<String>,
+    /// Run Dart code on the platform thread instead of a separate UI thread.
+    pub merged_platform_ui_thread: bool,
+    /// Where the engine keeps compiled shaders across runs, so that they are only compiled once.
+    /// Nothing is persisted if this is `None`.
+    pub persistent_cache_path: Option<CString>,
+    /// Read from the cache without adding to it, e.g. for a cache that is shipped with the app.
+    pub persistent_cache_read_only: bool,
 }
 
+/// Owns a running engine. The engine is shut down and all of its state is freed when this is
+/// dropped.
 pub struct FlutterEngine {
-    inner: &'static FlutterEngineInner,
+    inner: NonNull<FlutterEngineInner>,
 }
 
 struct FlutterEngineInner {
-    handle: flutter_embedder::FlutterEngine,
+    /// Replaced when the engine is restarted.
+    handle: Cell<flutter_embedder::FlutterEngine>,
+    /// A copy of `handle` for messengers, which may outlive the engine that they were created for.
+    shared_handle: Arc<SharedHandle>,
     egl_manager: Arc<EglManager>,
-    platform_message_handlers: BTreeMap<String, Box<dyn BinaryMessageHandler + 'static>>,
+    vsync_waiter: Arc<VsyncWaiter>,
+    texture_registry: Arc<TextureRegistry>,
+    /// Handlers can be added or removed while a message is being handled (e.g. by plugins), so
+    /// they are cloned out of the map before being called.
+    platform_message_handlers: RefCell<BTreeMap<String, Rc<dyn BinaryMessageHandler + 'static>>>,
+    platform_task_runner: FlutterTaskRunnerDescription,
+    /// Referenced by `platform_task_runner`, so it must outlive the engine.
+    _platform_task_runner_state: Box<TaskRunner<Box<dyn Fn(Task)>>>,
+    merged_platform_ui_thread: bool,
+    compositor: *mut Compositor,
+    engine_switches: Vec<CString>,
+    assets_path: CString,
+    icu_data_path: CString,
+    initial_route: Option<String>,
+    persistent_cache_path: Option<CString>,
+    persistent_cache_read_only: bool,
+    input_recorder: RefCell<Option<InputRecorder>>,
+    /// Kept so that semantics can be re-enabled when the engine is relaunched.
+    semantics_enabled: Cell<bool>,
+    semantics_update_handler: RefCell<Option<Rc<dyn Fn(Vec<SemanticsNode>)>>>,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 #[repr(i32)]
 pub enum PointerPhase {
+    Cancel = FlutterPointerPhase_kCancel,
     Up = FlutterPointerPhase_kUp,
     Down = FlutterPointerPhase_kDown,
     Add = FlutterPointerPhase_kAdd,
@@ -54,6 +159,180 @@ pub enum PointerPhase {
     Move = FlutterPointerPhase_kMove,
 }
 
+/// The kind of device that a pointer event came from.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[repr(i32)]
+pub enum PointerDeviceKind {
+    #[default]
+    Mouse = FlutterPointerDeviceKind_kFlutterPointerDeviceKindMouse,
+    Touch = FlutterPointerDeviceKind_kFlutterPointerDeviceKindTouch,
+    Stylus = FlutterPointerDeviceKind_kFlutterPointerDeviceKindStylus,
+}
+
+bitflags! {
+    /// The mouse buttons that are pressed during a pointer event.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub struct PointerButtons: i64 {
+        const PRIMARY = FlutterPointerMouseButtons_kFlutterPointerButtonMousePrimary as i64;
+        const SECONDARY = FlutterPointerMouseButtons_kFlutterPointerButtonMouseSecondary as i64;
+        const MIDDLE = FlutterPointerMouseButtons_kFlutterPointerButtonMouseMiddle as i64;
+        const BACK = FlutterPointerMouseButtons_kFlutterPointerButtonMouseBack as i64;
+        const FORWARD = FlutterPointerMouseButtons_kFlutterPointerButtonMouseForward as i64;
+    }
+}
+
+bitflags! {
+    /// The accessibility settings that the framework exposes through `MediaQuery`.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub struct AccessibilityFeatures: i32 {
+        const ACCESSIBLE_NAVIGATION =
+            FlutterAccessibilityFeature_kFlutterAccessibilityFeatureAccessibleNavigation as i32;
+        const DISABLE_ANIMATIONS =
+            FlutterAccessibilityFeature_kFlutterAccessibilityFeatureDisableAnimations as i32;
+        const REDUCE_MOTION =
+            FlutterAccessibilityFeature_kFlutterAccessibilityFeatureReduceMotion as i32;
+        const HIGH_CONTRAST =
+            FlutterAccessibilityFeature_kFlutterAccessibilityFeatureHighContrast as i32;
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Display {
+    pub id: u64,
+    pub refresh_rate: f64,
+    /// The size of the display in physical pixels.
+    pub width: usize,
+    pub height: usize,
+    pub device_pixel_ratio: f64,
+}
+
+/// The semantics actions that assistive technology can perform on a node.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(i32)]
+pub enum SemanticsAction {
+    Tap = FlutterSemanticsAction_kFlutterSemanticsActionTap as i32,
+    Increase = FlutterSemanticsAction_kFlutterSemanticsActionIncrease as i32,
+    Decrease = FlutterSemanticsAction_kFlutterSemanticsActionDecrease as i32,
+    ShowOnScreen = FlutterSemanticsAction_kFlutterSemanticsActionShowOnScreen as i32,
+    /// Replaces a text field's text, which is sent as the action's data.
+    SetText = FlutterSemanticsAction_kFlutterSemanticsActionSetText as i32,
+}
+
+bitflags! {
+    /// The actions that a semantics node supports, out of those in [`SemanticsAction`].
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub struct SemanticsActions: i32 {
+        const TAP = SemanticsAction::Tap as i32;
+        const INCREASE = SemanticsAction::Increase as i32;
+        const DECREASE = SemanticsAction::Decrease as i32;
+        const SHOW_ON_SCREEN = SemanticsAction::ShowOnScreen as i32;
+        const SET_TEXT = SemanticsAction::SetText as i32;
+    }
+}
+
+bitflags! {
+    /// The state of a semantics node that assistive technology is told about.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub struct SemanticsFlags: i32 {
+        const HAS_CHECKED_STATE = FlutterSemanticsFlag_kFlutterSemanticsFlagHasCheckedState as i32;
+        const IS_CHECKED = FlutterSemanticsFlag_kFlutterSemanticsFlagIsChecked as i32;
+        const IS_CHECK_STATE_MIXED =
+            FlutterSemanticsFlag_kFlutterSemanticsFlagIsCheckStateMixed as i32;
+        const IS_SELECTED = FlutterSemanticsFlag_kFlutterSemanticsFlagIsSelected as i32;
+        const IS_BUTTON = FlutterSemanticsFlag_kFlutterSemanticsFlagIsButton as i32;
+        const IS_TEXT_FIELD = FlutterSemanticsFlag_kFlutterSemanticsFlagIsTextField as i32;
+        const IS_READ_ONLY = FlutterSemanticsFlag_kFlutterSemanticsFlagIsReadOnly as i32;
+        const IS_OBSCURED = FlutterSemanticsFlag_kFlutterSemanticsFlagIsObscured as i32;
+        const IS_FOCUSABLE = FlutterSemanticsFlag_kFlutterSemanticsFlagIsFocusable as i32;
+        const IS_FOCUSED = FlutterSemanticsFlag_kFlutterSemanticsFlagIsFocused as i32;
+        const HAS_ENABLED_STATE = FlutterSemanticsFlag_kFlutterSemanticsFlagHasEnabledState as i32;
+        const IS_ENABLED = FlutterSemanticsFlag_kFlutterSemanticsFlagIsEnabled as i32;
+        const HAS_TOGGLED_STATE = FlutterSemanticsFlag_kFlutterSemanticsFlagHasToggledState as i32;
+        const IS_TOGGLED = FlutterSemanticsFlag_kFlutterSemanticsFlagIsToggled as i32;
+        const IS_HEADER = FlutterSemanticsFlag_kFlutterSemanticsFlagIsHeader as i32;
+        const IS_HIDDEN = FlutterSemanticsFlag_kFlutterSemanticsFlagIsHidden as i32;
+        const IS_IMAGE = FlutterSemanticsFlag_kFlutterSemanticsFlagIsImage as i32;
+        const IS_LINK = FlutterSemanticsFlag_kFlutterSemanticsFlagIsLink as i32;
+        const IS_SLIDER = FlutterSemanticsFlag_kFlutterSemanticsFlagIsSlider as i32;
+    }
+}
+
+/// A node of the framework's semantics tree, copied out of a semantics update.
+#[derive(Clone, Debug)]
+pub struct SemanticsNode {
+    pub id: i32,
+    pub flags: SemanticsFlags,
+    pub actions: SemanticsActions,
+    pub label: String,
+    pub hint: String,
+    pub value: String,
+    pub tooltip: String,
+    /// The node's bounds, as (left, top, right, bottom) in its own coordinate space.
+    pub rect: [f64; 4],
+    /// Maps the node's coordinate space to its parent's, as a row-major 3x3 matrix. The root's
+    /// transform maps to physical pixels in the view.
+    pub transform: [f64; 9],
+    pub children: Vec<i32>,
+    /// The children from top to bottom, in the order that they should be hit tested.
+    pub hit_test_children: Vec<i32>,
+}
+
+impl SemanticsNode {
+    unsafe fn from_raw(node: &FlutterSemanticsNode2) -> SemanticsNode {
+        let string = |s: *const c_char| {
+            if s.is_null() {
+                String::new()
+            } else {
+                CStr::from_ptr(s).to_string_lossy().into_owned()
+            }
+        };
+
+        let children = |ids: *const i32| {
+            if ids.is_null() || node.child_count == 0 {
+                vec![]
+            } else {
+                std::slice::from_raw_parts(ids, node.child_count).to_vec()
+            }
+        };
+
+        let FlutterTransformation {
+            scaleX,
+            skewX,
+            transX,
+            skewY,
+            scaleY,
+            transY,
+            pers0,
+            pers1,
+            pers2,
+        } = node.transform;
+
+        SemanticsNode {
+            id: node.id,
+            flags: SemanticsFlags::from_bits_truncate(node.flags as i32),
+            actions: SemanticsActions::from_bits_truncate(node.actions as i32),
+            label: string(node.label),
+            hint: string(node.hint),
+            value: string(node.value),
+            tooltip: string(node.tooltip),
+            rect: [
+                node.rect.left,
+                node.rect.top,
+                node.rect.right,
+                node.rect.bottom,
+            ],
+            transform: [
+                scaleX, skewX, transX, skewY, scaleY, transY, pers0, pers1, pers2,
+            ],
+            children: children(node.children_in_traversal_order),
+            hit_test_children: children(node.children_in_hit_test_order),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 #[repr(i32)]
 pub enum KeyEventType {
     Up = FlutterKeyEventType_kFlutterKeyEventTypeUp,
@@ -61,6 +340,44 @@ pub enum KeyEventType {
     Repeat = FlutterKeyEventType_kFlutterKeyEventTypeRepeat,
 }
 
+/// A pointer event, with the time at which it happened.
+#[derive(Clone, Copy, Debug)]
+pub struct PointerEvent {
+    pub phase: PointerPhase,
+    pub x: f64,
+    pub y: f64,
+    pub buttons: PointerButtons,
+    pub kind: PointerDeviceKind,
+    /// Identifies the pointer, so that the framework can track simultaneous pointers separately.
+    /// The mouse is always device 0.
+    pub device: i32,
+    timestamp: usize,
+}
+
+impl PointerEvent {
+    /// Creates a mouse event that happened now.
+    pub fn new(phase: PointerPhase, x: f64, y: f64, buttons: PointerButtons) -> PointerEvent {
+        PointerEvent {
+            phase,
+            x,
+            y,
+            buttons,
+            kind: PointerDeviceKind::Mouse,
+            device: 0,
+            timestamp: unsafe { FlutterEngineGetCurrentTime() } as usize,
+        }
+    }
+
+    /// Changes the device that the event came from.
+    pub fn with_device(self, kind: PointerDeviceKind, device: i32) -> PointerEvent {
+        PointerEvent {
+            kind,
+            device,
+            ..self
+        }
+    }
+}
+
 pub struct KeyEvent<'a> {
     pub event_type: KeyEventType,
     pub synthesized: bool,
@@ -70,15 +387,67 @@ pub struct KeyEvent<'a> {
 }
 
 impl FlutterEngine {
-    pub fn new(config: FlutterEngineConfig) -> eyre::Result<FlutterEngine> {
-        let platform_task_runner = create_task_runner(
-            1,
-            // TODO: Cleanup task runner on engine shutdown
-            Box::leak(Box::new(TaskRunner::new(move |task| {
-                (config.platform_task_handler)(task);
-            }))),
-        );
+    pub fn new(config: FlutterEngineConfig) -> error::Result<FlutterEngine> {
+        let platform_task_runner_state = Box::new(TaskRunner::new(config.platform_task_handler));
+        let platform_task_runner = create_task_runner(1, &platform_task_runner_state);
 
+        // The engine splits the VM flags on commas, and there is no way of escaping them.
+        if let Some(flag) = config.dart_flags.iter().find(|flag| flag.contains(',')) {
+            return Err(FlionError::DartFlag(flag.clone()));
+        }
+
+        let dart_flags = (!config.dart_flags.is_empty())
+            .then(|| format!("--dart-flags={}", config.dart_flags.join(",")));
+
+        // The engine ignores the first argument, as it is expected to be the executable name.
+        let engine_switches = ["fluyt".to_owned()]
+            .into_iter()
+            .chain(config.engine_switches)
+            .chain(dart_flags)
+            .map(CString::new)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let inner = Box::new(FlutterEngineInner {
+            handle: Cell::new(ptr::null_mut()),
+            shared_handle: Arc::new(SharedHandle::new()),
+            egl_manager: config.egl_manager,
+            vsync_waiter: config.vsync_waiter,
+            texture_registry: config.texture_registry,
+            platform_message_handlers: RefCell::new(BTreeMap::from_iter(
+                config
+                    .platform_message_handlers
+                    .into_iter()
+                    .map(|(channel, handler)| (channel.to_owned(), Rc::from(handler))),
+            )),
+            platform_task_runner,
+            _platform_task_runner_state: platform_task_runner_state,
+            merged_platform_ui_thread: config.merged_platform_ui_thread,
+            compositor: Box::into_raw(Box::new(config.compositor)),
+            engine_switches,
+            assets_path: config.assets_path,
+            icu_data_path: config.icu_data_path,
+            initial_route: config.initial_route,
+            persistent_cache_path: config.persistent_cache_path,
+            persistent_cache_read_only: config.persistent_cache_read_only,
+            input_recorder: RefCell::new(None),
+            semantics_enabled: Cell::new(false),
+            semantics_update_handler: RefCell::new(None),
+        });
+
+        let flutter_engine = FlutterEngine {
+            inner: NonNull::from(Box::leak(inner)),
+        };
+
+        flutter_engine.launch()?;
+
+        Ok(flutter_engine)
+    }
+
+    fn inner(&self) -> &FlutterEngineInner {
+        unsafe { self.inner.as_ref() }
+    }
+
+    fn launch(&self) -> error::Result<()> {
         let renderer_config = FlutterRendererConfig {
             type_: FlutterRendererType_kOpenGL,
             __bindgen_anon_1: flutter_embedder::FlutterRendererConfig__bindgen_ty_1 {
@@ -91,19 +460,34 @@ impl FlutterEngine {
                     fbo_callback: Some(gl_fbo_callback),
                     fbo_reset_after_present: true,
                     gl_proc_resolver: Some(gl_get_proc_address),
+                    gl_external_texture_frame_callback: Some(gl_external_texture_frame),
                     ..Default::default()
                 },
             },
         };
 
+        let switch_ptrs = self
+            .inner
+            .engine_switches
+            .iter()
+            .map(|s| s.as_ptr())
+            .collect::<Vec<_>>();
+
         let project_args = FlutterProjectArgs {
             struct_size: mem::size_of::<FlutterProjectArgs>(),
-            assets_path: c"example/build/flutter_assets".as_ptr(),
-            icu_data_path: c"icudtl.dat".as_ptr(),
+            assets_path: self.inner().assets_path.as_ptr(),
+            icu_data_path: self.inner().icu_data_path.as_ptr(),
             custom_task_runners: &FlutterCustomTaskRunners {
                 struct_size: mem::size_of::<FlutterCustomTaskRunners>(),
-                platform_task_runner: &platform_task_runner,
+                platform_task_runner: &self.inner().platform_task_runner,
                 render_task_runner: ptr::null(),
+                // Using the same runner (and identifier) as the platform thread tells the engine to
+                // merge the two threads.
+                ui_task_runner: if self.inner().merged_platform_ui_thread {
+                    &self.inner().platform_task_runner
+                } else {
+                    ptr::null()
+                },
                 thread_priority_setter: Some(task_runner::set_thread_priority),
             },
             compositor: &FlutterCompositor {
@@ -112,24 +496,25 @@ impl FlutterEngine {
                 collect_backing_store_callback: Some(compositor_collect_backing_store),
                 present_layers_callback: Some(compositor_present_layers),
                 present_view_callback: None,
-                user_data: Box::leak(Box::new(config.compositor)) as *mut Compositor as *mut c_void,
+                user_data: self.inner().compositor.cast(),
                 avoid_backing_store_cache: false,
             },
             platform_message_callback: Some(platform_message_callback),
+            command_line_argc: switch_ptrs.len() as i32,
+            command_line_argv: switch_ptrs.as_ptr(),
+            log_message_callback: Some(dart_log::log_message_callback),
+            log_tag: dart_log::LOG_TAG.as_ptr(),
+            vsync_callback: Some(vsync_callback),
+            update_semantics_callback2: Some(update_semantics_callback),
+            persistent_cache_path: self
+                .inner()
+                .persistent_cache_path
+                .as_ref()
+                .map_or(ptr::null(), |path| path.as_ptr()),
+            is_persistent_cache_read_only: self.inner().persistent_cache_read_only,
             ..Default::default()
         };
 
-        let engine = Box::leak(Box::new(FlutterEngineInner {
-            handle: ptr::null_mut(),
-            egl_manager: config.egl_manager,
-            platform_message_handlers: BTreeMap::from_iter(
-                config
-                    .platform_message_handlers
-                    .into_iter()
-                    .map(|(channel, handler)| (channel.to_owned(), handler)),
-            ),
-        }));
-
         let engine_handle = unsafe {
             let mut engine_ptr = ptr::null_mut();
 
@@ -137,24 +522,114 @@ impl FlutterEngine {
                 FLUTTER_ENGINE_VERSION as usize,
                 &renderer_config,
                 &project_args,
-                engine as *mut FlutterEngineInner as _,
+                self.inner.as_ptr() as _,
                 &mut engine_ptr,
             );
 
             if result != FlutterEngineResult_kSuccess || engine_ptr.is_null() {
-                panic!("could not run the flutter engine");
+                if result == FlutterEngineResult_kInvalidLibraryVersion {
+                    fatal_error(&format!(
+                        "the flutter engine doesn't support embedder api version {}. {}",
+                        FLUTTER_ENGINE_VERSION,
+                        engine_library::download_hint()
+                    ));
+                } else {
+                    fatal_error(&format!(
+                        "failed to initialize the flutter engine: {result}"
+                    ));
+                }
+                return Err(FlionError::Engine {
+                    operation: "initialize the flutter engine",
+                    result,
+                });
             }
 
             engine_ptr
         };
 
-        engine.handle = engine_handle;
+        self.inner().handle.set(engine_handle);
+        self.inner().shared_handle.set(engine_handle);
 
-        unsafe {
-            FlutterEngineRunInitialized(engine_handle);
+        // The initial route must be set before the engine is run in order for it to be picked up
+        // as the default route name.
+        if let Some(route) = &self.inner().initial_route {
+            navigation::set_initial_route(self, route)?;
+        }
+
+        let result = unsafe { FlutterEngineRunInitialized(engine_handle) };
+        if result != FlutterEngineResult_kSuccess {
+            fatal_error(&format!("failed to run the flutter engine: {result}"));
         }
 
-        Ok(FlutterEngine { inner: engine })
+        check_engine_result("run the flutter engine", result)?;
+
+        if self.inner().semantics_enabled.get() {
+            let result = unsafe { FlutterEngineUpdateSemanticsEnabled(engine_handle, true) };
+            check_engine_result("enable semantics", result)?;
+        }
+
+        self.inner().texture_registry.attach(engine_handle)
+    }
+
+    /// Shuts down the running engine and launches a new one in its place, keeping the same
+    /// compositor, task runner and message handlers.
+    ///
+    /// This must not be called while the engine is running a task, and any tasks that were posted
+    /// by the old engine should be discarded.
+    pub fn restart(&self) -> error::Result<()> {
+        self.shutdown_engine()?;
+        self.launch()
+    }
+
+    /// Shuts down the engine, which joins the threads that it owns and releases their GL
+    /// contexts, and then stops the vsync thread. The engine must not be used afterwards.
+    pub fn shutdown(&self) -> error::Result<()> {
+        self.shutdown_engine()?;
+        self.inner().vsync_waiter.stop();
+        Ok(())
+    }
+
+    /// Recovers from the loss of the D3D device by shutting down the engine, moving rendering onto
+    /// `device` and relaunching the engine. Dart state is lost, as the engine's GPU resources can't
+    /// be moved between devices, and messengers from before the relaunch are disconnected like
+    /// after a restart.
+    pub fn recover_from_device_loss(&self, device: ID3D11Device) -> error::Result<()> {
+        // Shutting down the engine collects all backing stores, which destroys their surfaces on
+        // the old display.
+        self.shutdown_engine()?;
+
+        self.inner().egl_manager.reset(&device)?;
+        unsafe { (*self.inner().compositor).set_device(device)? };
+
+        self.launch()
+    }
+
+    fn shutdown_engine(&self) -> error::Result<()> {
+        if self.inner().handle.get().is_null() {
+            return Ok(());
+        }
+
+        // Outstanding vsync requests refer to the engine that is being shut down.
+        self.inner().vsync_waiter.cancel();
+        self.inner().texture_registry.detach();
+
+        // Waits for messages that are being sent on other threads.
+        self.inner().shared_handle.clear();
+
+        let result = unsafe { FlutterEngineShutdown(self.inner().handle.get()) };
+        check_engine_result("shut down the flutter engine", result)?;
+
+        // Any further calls into the embedder API will fail gracefully with a null handle, rather
+        // than using the engine after it has been freed.
+        self.inner().handle.set(ptr::null_mut());
+
+        Ok(())
+    }
+
+    /// Whether the engine has been launched and not shut down. It isn't running while it is being
+    /// restarted, or after a failed restart.
+    pub fn is_running(&self) -> bool {
+        !self.inner().handle.get().is_null()
     }
 
     pub fn send_window_metrics_event(
@@ -162,10 +637,10 @@ impl FlutterEngine {
         width: usize,
         height: usize,
         pixel_ratio: f64,
-    ) -> eyre::Result<()> {
+    ) -> error::Result<()> {
         let result = unsafe {
             FlutterEngineSendWindowMetricsEvent(
-                self.inner.handle,
+                self.inner().handle.get(),
                 &FlutterWindowMetricsEvent {
                     struct_size: mem::size_of::<FlutterWindowMetricsEvent>(),
                     width,
@@ -176,47 +651,248 @@ impl FlutterEngine {
             )
         };
 
-        if result != FlutterEngineResult_kSuccess {
-            bail!("failed to send window metrics event: {result}");
-        }
+        check_engine_result("send window metrics event", result)?;
 
         Ok(())
     }
 
-    pub fn run_task(&self, task: &FlutterTask) -> eyre::Result<()> {
-        let result = unsafe { FlutterEngineRunTask(self.inner.handle, task) };
+    /// Tells the engine about the connected displays, which it uses for frame pacing. This should
+    /// be called on startup and whenever the displays change.
+    pub fn notify_display_update(&self, displays: &[Display]) -> error::Result<()> {
+        let displays = displays
+            .iter()
+            .map(|display| FlutterEngineDisplay {
+                struct_size: mem::size_of::<FlutterEngineDisplay>(),
+                display_id: display.id,
+                single_display: displays.len() == 1,
+                refresh_rate: display.refresh_rate,
+                width: display.width,
+                height: display.height,
+                device_pixel_ratio: display.device_pixel_ratio,
+            })
+            .collect::<Vec<_>>();
 
-        if result != FlutterEngineResult_kSuccess {
-            bail!("failed to run task: {result}");
+        let result = unsafe {
+            FlutterEngineNotifyDisplayUpdate(
+                self.inner().handle.get(),
+                FlutterEngineDisplaysUpdateType_kFlutterEngineDisplaysUpdateTypeStartup,
+                displays.as_ptr(),
+                displays.len(),
+            )
+        };
+
+        check_engine_result("notify display update", result)?;
+
+        Ok(())
+    }
+
+    pub fn update_locales(&self, locales: &[FlutterLocale]) -> error::Result<()> {
+        let locales = locales.iter().map(|l| l as *const _).collect::<Vec<_>>();
+
+        let result = unsafe {
+            FlutterEngineUpdateLocales(self.inner().handle.get(), locales.as_ptr(), locales.len())
+        };
+
+        check_engine_result("update locales", result)?;
+
+        Ok(())
+    }
+
+    pub fn update_accessibility_features(
+        &self,
+        features: AccessibilityFeatures,
+    ) -> error::Result<()> {
+        let result = unsafe {
+            FlutterEngineUpdateAccessibilityFeatures(
+                self.inner().handle.get(),
+                features.bits() as _,
+            )
+        };
+
+        check_engine_result("update accessibility features", result)?;
+
+        Ok(())
+    }
+
+    pub fn semantics_enabled(&self) -> bool {
+        self.inner().semantics_enabled.get()
+    }
+
+    /// Turns the framework's semantics tree on or off. This is remembered across restarts.
+    pub fn update_semantics_enabled(&self, enabled: bool) -> error::Result<()> {
+        self.inner().semantics_enabled.set(enabled);
+
+        let result =
+            unsafe { FlutterEngineUpdateSemanticsEnabled(self.inner().handle.get(), enabled) };
+
+        check_engine_result("update semantics enabled", result)?;
+
+        Ok(())
+    }
+
+    /// Sets the handler for updates to the semantics tree, which is called with the nodes that
+    /// were added or changed.
+    pub fn set_semantics_update_handler(&self, handler: impl Fn(Vec<SemanticsNode>) + 'static) {
+        *self.inner().semantics_update_handler.borrow_mut() = Some(Rc::new(handler));
+    }
+
+    /// Performs `action` on the semantics node with the given id. `data` is the action's
+    /// argument, encoded with the standard message codec (or empty if it has none).
+    pub fn dispatch_semantics_action(
+        &self,
+        node_id: u64,
+        action: SemanticsAction,
+        data: &[u8],
+    ) -> error::Result<()> {
+        let result = unsafe {
+            FlutterEngineDispatchSemanticsAction(
+                self.inner().handle.get(),
+                node_id,
+                action as FlutterSemanticsAction,
+                data.as_ptr(),
+                data.len(),
+            )
+        };
+
+        check_engine_result("dispatch semantics action", result)?;
+
+        Ok(())
+    }
+
+    pub fn run_task(&self, task: &FlutterTask) -> error::Result<()> {
+        let result = unsafe { FlutterEngineRunTask(self.inner().handle.get(), task) };
+
+        check_engine_result("run task", result)?;
+
+        Ok(())
+    }
+
+    /// Starts writing input events sent to the engine to `recorder`, or stops if it is `None`.
+    pub fn set_input_recorder(&self, recorder: Option<InputRecorder>) {
+        *self.inner().input_recorder.borrow_mut() = recorder;
+    }
+
+    fn record_input(&self, event: impl FnOnce() -> InputEvent) {
+        if let Some(recorder) = &*self.inner().input_recorder.borrow() {
+            recorder.record(event());
         }
+    }
+
+    fn record_input_message(&self, channel: &CStr, message: &[u8]) {
+        let Ok(channel) = channel.to_str() else {
+            return;
+        };
+
+        if input_recording::INPUT_CHANNELS.contains(&channel) {
+            self.record_input(|| InputEvent::Message {
+                channel: channel.to_owned(),
+                data: message.to_vec(),
+            });
+        }
+    }
+
+    pub fn send_pointer_event(
+        &self,
+        phase: PointerPhase,
+        x: f64,
+        y: f64,
+        buttons: PointerButtons,
+    ) -> error::Result<()> {
+        self.send_pointer_events(&[PointerEvent::new(phase, x, y, buttons)])
+    }
+
+    /// Sends a batch of pointer events in a single call, which is cheaper than sending them
+    /// individually when there are many (e.g. from a high polling rate mouse).
+    pub fn send_pointer_events(&self, events: &[PointerEvent]) -> error::Result<()> {
+        if events.is_empty() {
+            return Ok(());
+        }
+
+        let events = events
+            .iter()
+            .map(|event| {
+                self.record_input(|| InputEvent::Pointer {
+                    phase: event.phase,
+                    x: event.x,
+                    y: event.y,
+                    buttons: event.buttons.bits(),
+                    kind: event.kind,
+                    device: event.device,
+                });
+
+                FlutterPointerEvent {
+                    struct_size: mem::size_of::<FlutterPointerEvent>(),
+                    phase: event.phase as FlutterPointerPhase,
+                    x: event.x,
+                    y: event.y,
+                    timestamp: event.timestamp,
+                    device: event.device,
+                    device_kind: event.kind as FlutterPointerDeviceKind,
+                    buttons: event.buttons.bits(),
+                    ..Default::default()
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let result = unsafe {
+            FlutterEngineSendPointerEvent(self.inner().handle.get(), events.as_ptr(), events.len())
+        };
+
+        check_engine_result("send pointer event", result)?;
 
         Ok(())
     }
 
-    pub fn send_pointer_event(&self, phase: PointerPhase, x: f64, y: f64) -> eyre::Result<()> {
+    /// Sends a scroll at a position in physical pixels, by a delta in physical pixels. Positive
+    /// deltas scroll right and down (i.e. the content moves left and up).
+    pub fn send_scroll_event(
+        &self,
+        x: f64,
+        y: f64,
+        delta_x: f64,
+        delta_y: f64,
+        buttons: PointerButtons,
+    ) -> error::Result<()> {
+        self.record_input(|| InputEvent::Scroll {
+            x,
+            y,
+            delta_x,
+            delta_y,
+            buttons: buttons.bits(),
+        });
+
+        let phase = if buttons.is_empty() {
+            PointerPhase::Hover
+        } else {
+            PointerPhase::Move
+        };
+
         let result = unsafe {
             FlutterEngineSendPointerEvent(
-                self.inner.handle,
+                self.inner().handle.get(),
                 &FlutterPointerEvent {
                     struct_size: mem::size_of::<FlutterPointerEvent>(),
                     phase: phase as FlutterPointerPhase,
                     x,
                     y,
                     timestamp: FlutterEngineGetCurrentTime() as usize,
+                    signal_kind: FlutterPointerSignalKind_kFlutterPointerSignalKindScroll,
+                    scroll_delta_x: delta_x,
+                    scroll_delta_y: delta_y,
+                    device_kind: FlutterPointerDeviceKind_kFlutterPointerDeviceKindMouse,
+                    buttons: buttons.bits(),
                     ..Default::default()
                 },
                 1,
             )
         };
 
-        if result != FlutterEngineResult_kSuccess {
-            bail!("failed to run task: {result}");
-        }
+        check_engine_result("send scroll event", result)?;
 
         Ok(())
     }
 
-    pub fn send_key_event<F>(&self, event: KeyEvent, callback: F) -> eyre::Result<()>
+    pub fn send_key_event<F>(&self, event: KeyEvent, callback: F) -> error::Result<()>
     where
         F: FnOnce(bool) + 'static,
     {
@@ -227,6 +903,14 @@ impl FlutterEngine {
             Box::from_raw(user_data.cast::<F>())(handled);
         }
 
+        self.record_input(|| InputEvent::Key {
+            event_type: event.event_type,
+            synthesized: event.synthesized,
+            character: event.character.map(|c| c.to_string()),
+            logical: event.logical,
+            physical: event.physical,
+        });
+
         let reply = Box::leak(Box::new(callback));
 
         let event = FlutterKeyEvent {
@@ -245,24 +929,214 @@ impl FlutterEngine {
 
         unsafe {
             let result = FlutterEngineSendKeyEvent(
-                self.inner.handle,
+                self.inner().handle.get(),
                 &event,
                 Some(_callback::<F>),
                 reply as *mut F as _,
             );
 
-            if result != FlutterEngineResult_kSuccess {
-                bail!("failed to send key event: {result}");
-            }
+            check_engine_result("send key event", result)?;
         }
 
         Ok(())
     }
 
-    pub fn send_platform_message(&self, channel: &CStr, message: &[u8]) -> eyre::Result<()> {
+    /// Sets the handler for messages on `channel`, replacing any existing handler. The handler is
+    /// removed if `handler` is `None`.
+    pub fn set_message_handler(
+        &self,
+        channel: &str,
+        handler: Option<Box<dyn BinaryMessageHandler + 'static>>,
+    ) {
+        let mut handlers = self.inner().platform_message_handlers.borrow_mut();
+        match handler {
+            Some(handler) => {
+                handlers.insert(channel.to_owned(), Rc::from(handler));
+            }
+            None => {
+                handlers.remove(channel);
+            }
+        }
+    }
+
+    pub fn messenger(&self) -> BinaryMessenger {
+        self.inner().messenger()
+    }
+
+    /// Returns a messenger that can be used from any thread. Unlike [`FlutterEngine::messenger`],
+    /// it keeps sending to the running engine across restarts.
+    pub fn thread_safe_messenger(&self) -> BinaryMessenger {
+        BinaryMessenger {
+            handle: self.inner().shared_handle.clone(),
+            launch: None,
+        }
+    }
+
+    pub fn send_platform_message(&self, channel: &CStr, message: &[u8]) -> error::Result<()> {
+        self.record_input_message(channel, message);
+        self.messenger().send_platform_message(channel, message)
+    }
+
+    pub fn send_platform_message_with_reply<F>(
+        &self,
+        channel: &CStr,
+        message: &[u8],
+        reply_handler: F,
+    ) -> error::Result<()>
+    where
+        F: FnOnce(&[u8]) + 'static,
+    {
+        self.record_input_message(channel, message);
+        self.messenger()
+            .send_platform_message_with_reply(channel, message, reply_handler)
+    }
+}
+
+impl Drop for FlutterEngine {
+    fn drop(&mut self) {
+        if let Err(e) = self.shutdown() {
+            tracing::error!("{e}");
+        }
+
+        unsafe {
+            let inner = Box::from_raw(self.inner.as_ptr());
+            drop(Box::from_raw(inner.compositor));
+        }
+    }
+}
+
+fn fatal_error(message: &str) {
+    flight_recorder::record(EventKind::Error, message);
+    flight_recorder::dump(message);
+}
+
+/// Creates a task runner description for `runner`, which must outlive any engine that it is
+/// passed to.
+fn create_task_runner<F: Fn(Task) + 'static>(
+    id: usize,
+    runner: &TaskRunner<F>,
+) -> FlutterTaskRunnerDescription {
+    unsafe extern "C" fn runs_tasks_on_current_thread<F>(task_runner: *mut c_void) -> bool {
+        task_runner
+            .cast::<TaskRunner<F>>()
+            .as_mut()
+            .unwrap()
+            .runs_tasks_on_current_thread()
+    }
+
+    unsafe extern "C" fn post_task_callback<F: Fn(Task)>(
+        task: FlutterTask,
+        target_time_nanos: u64,
+        user_data: *mut c_void,
+    ) {
+        let runner = user_data.cast::<TaskRunner<F>>();
+        (*runner).post_task(task, target_time_nanos);
+    }
+
+    FlutterTaskRunnerDescription {
+        struct_size: mem::size_of::<FlutterTaskRunnerDescription>(),
+        identifier: id,
+        user_data: runner as *const TaskRunner<F> as *mut c_void,
+        runs_task_on_current_thread_callback: Some(runs_tasks_on_current_thread::<F>),
+        post_task_callback: Some(post_task_callback::<F>),
+    }
+}
+
+impl FlutterEngineInner {
+    fn messenger(&self) -> BinaryMessenger {
+        BinaryMessenger {
+            handle: self.shared_handle.clone(),
+            launch: Some(self.shared_handle.launch()),
+        }
+    }
+}
+
+/// The handle of the running engine, shared with [`BinaryMessenger`]s. It is swapped under the lock
+/// when the engine is launched or shut down, so that messengers can't use an engine that has been
+/// freed.
+struct SharedHandle(RwLock<HandleState>);
+
+struct HandleState {
+    engine: flutter_embedder::FlutterEngine,
+    /// Incremented whenever the engine is launched, so that messengers can tell whether they belong
+    /// to the running engine.
+    launch: u64,
+}
+
+// The handle is only used while the lock is held, so the engine can't be shut down meanwhile.
+unsafe impl Send for HandleState {}
+unsafe impl Sync for HandleState {}
+
+impl SharedHandle {
+    fn new() -> SharedHandle {
+        SharedHandle(RwLock::new(HandleState {
+            engine: ptr::null_mut(),
+            launch: 0,
+        }))
+    }
+
+    fn set(&self, engine: flutter_embedder::FlutterEngine) {
+        let mut state = self.0.write().unwrap();
+        state.engine = engine;
+        state.launch += 1;
+    }
+
+    fn clear(&self) {
+        self.0.write().unwrap().engine = ptr::null_mut();
+    }
+
+    fn launch(&self) -> u64 {
+        self.0.read().unwrap().launch
+    }
+
+    /// Returns the engine if it is still running the given launch (or any launch if it is `None`),
+    /// or a null handle, which the embedder API rejects, otherwise. The engine can't be shut down
+    /// until the guard is dropped.
+    fn lock(
+        &self,
+        launch: Option<u64>,
+    ) -> (
+        RwLockReadGuard<'_, HandleState>,
+        flutter_embedder::FlutterEngine,
+    ) {
+        let state = self.0.read().unwrap();
+        let engine = match launch {
+            Some(launch) if launch != state.launch => ptr::null_mut(),
+            _ => state.engine,
+        };
+        (state, engine)
+    }
+}
+
+/// A handle that can be used to send platform messages to the engine, without having access to the
+/// [`FlutterEngine`] itself (e.g. from within a message handler).
+///
+/// A messenger belongs to the engine that was running when it was created. Once that engine has
+/// been restarted or shut down, sending fails instead of reaching the new isolate. Messengers from
+/// [`FlutterEngine::thread_safe_messenger`] aren't tied to a launch.
+#[derive(Clone)]
+pub struct BinaryMessenger {
+    handle: Arc<SharedHandle>,
+    launch: Option<u64>,
+}
+
+impl BinaryMessenger {
+    /// Whether the engine that this messenger was created for is still running.
+    pub fn is_connected(&self) -> bool {
+        !self.handle.lock(self.launch).1.is_null()
+    }
+
+    pub fn send_platform_message(&self, channel: &CStr, message: &[u8]) -> error::Result<()> {
+        if let Ok(name) = channel.to_str() {
+            // There is no reply to log.
+            let _ = channel_log::message(Direction::Outgoing, name, message);
+        }
+
         unsafe {
+            let (_guard, engine) = self.handle.lock(self.launch);
+
             let result = FlutterEngineSendPlatformMessage(
-                self.inner.handle,
+                engine,
                 &FlutterPlatformMessage {
                     struct_size: mem::size_of::<FlutterPlatformMessage>(),
                     channel: channel.as_ptr(),
@@ -273,7 +1147,10 @@ impl FlutterEngine {
             );
 
             if result != FlutterEngineResult_kSuccess {
-                bail!("failed to send platform message: {result}");
+                return Err(FlionError::Channel {
+                    channel: channel.to_string_lossy().into_owned(),
+                    result,
+                });
             }
 
             Ok(())
@@ -285,7 +1162,7 @@ impl FlutterEngine {
         channel: &CStr,
         message: &[u8],
         reply_handler: F,
-    ) -> eyre::Result<()>
+    ) -> error::Result<()>
     where
         F: FnOnce(&[u8]) + 'static,
     {
@@ -294,31 +1171,46 @@ impl FlutterEngine {
             size: usize,
             user_data: *mut ::std::os::raw::c_void,
         ) {
-            let reply_handler = Box::from_raw(user_data.cast::<F>());
+            let (reply_handler, pending_reply) =
+                *Box::from_raw(user_data.cast::<(F, Option<PendingReply>)>());
+
             if data.is_null() {
                 tracing::warn!("null reply from platform message");
+                if let Some(pending_reply) = pending_reply {
+                    pending_reply.finish(None);
+                }
             } else {
-                reply_handler(std::slice::from_raw_parts(data, size));
+                let reply = std::slice::from_raw_parts(data, size);
+                if let Some(pending_reply) = pending_reply {
+                    pending_reply.finish(Some(reply));
+                }
+                reply_handler(reply);
             }
         }
 
+        let pending_reply = channel
+            .to_str()
+            .ok()
+            .and_then(|name| channel_log::message(Direction::Outgoing, name, message));
+
+        // The response handle belongs to the engine, so it is released before the lock is.
+        let (_guard, engine) = self.handle.lock(self.launch);
+
         unsafe {
             let mut response_handle = ptr::null_mut();
 
-            let reply = Box::leak(Box::new(reply_handler));
+            let reply = Box::leak(Box::new((reply_handler, pending_reply)));
             let result = FlutterPlatformMessageCreateResponseHandle(
-                self.inner.handle,
+                engine,
                 Some(callback::<F>),
-                reply as *mut F as _,
+                reply as *mut (F, Option<PendingReply>) as _,
                 &mut response_handle,
             );
 
-            if result != FlutterEngineResult_kSuccess {
-                bail!("failed to create response handle: {result}");
-            }
+            check_engine_result("create response handle", result)?;
 
             let result = FlutterEngineSendPlatformMessage(
-                self.inner.handle,
+                engine,
                 &FlutterPlatformMessage {
                     struct_size: mem::size_of::<FlutterPlatformMessage>(),
                     channel: channel.as_ptr(),
@@ -329,48 +1221,38 @@ impl FlutterEngine {
             );
 
             if result != FlutterEngineResult_kSuccess {
-                bail!("failed to send platform message: {result}");
+                return Err(FlionError::Channel {
+                    channel: channel.to_string_lossy().into_owned(),
+                    result,
+                });
             }
 
-            let result =
-                FlutterPlatformMessageReleaseResponseHandle(self.inner.handle, response_handle);
+            let result = FlutterPlatformMessageReleaseResponseHandle(engine, response_handle);
 
-            if result != FlutterEngineResult_kSuccess {
-                bail!("failed to release response handle: {result}");
-            }
+            check_engine_result("release response handle", result)?;
 
             Ok(())
         }
     }
-}
 
-fn create_task_runner<F: Fn(Task) + 'static>(
-    id: usize,
-    runner: &'static TaskRunner<F>,
-) -> FlutterTaskRunnerDescription {
-    unsafe extern "C" fn runs_tasks_on_current_thread<F>(task_runner: *mut c_void) -> bool {
-        task_runner
-            .cast::<TaskRunner<F>>()
-            .as_mut()
-            .unwrap()
-            .runs_tasks_on_current_thread()
-    }
+    /// Posts `object`, built by a plugin, to the Dart `SendPort` whose `nativePort` is `port`,
+    /// without going through a platform channel.
+    ///
+    /// # Safety
+    ///
+    /// `object` must point to a valid `FlutterEngineDartObject`.
+    pub unsafe fn post_raw_dart_object(
+        &self,
+        port: i64,
+        object: *const FlutterEngineDartObject,
+    ) -> error::Result<()> {
+        let (_guard, engine) = self.handle.lock(self.launch);
 
-    unsafe extern "C" fn post_task_callback<F: Fn(Task)>(
-        task: FlutterTask,
-        target_time_nanos: u64,
-        user_data: *mut c_void,
-    ) {
-        let runner = user_data.cast::<TaskRunner<F>>();
-        (*runner).post_task(task, target_time_nanos);
-    }
+        let result = FlutterEnginePostDartObject(engine, port, object);
 
-    FlutterTaskRunnerDescription {
-        struct_size: mem::size_of::<FlutterTaskRunnerDescription>(),
-        identifier: id,
-        user_data: runner as *const TaskRunner<F> as *mut c_void,
-        runs_task_on_current_thread_callback: Some(runs_tasks_on_current_thread::<F>),
-        post_task_callback: Some(post_task_callback::<F>),
+        check_engine_result("post dart object", result)?;
+
+        Ok(())
     }
 }
 
@@ -379,15 +1261,28 @@ pub trait BinaryMessageHandler {
 }
 
 pub struct BinaryMessageReply {
-    engine: flutter_embedder::FlutterEngine,
+    messenger: BinaryMessenger,
     response_handle: *const FlutterPlatformMessageResponseHandle,
+    pending_reply: Option<PendingReply>,
 }
 
 impl BinaryMessageReply {
+    pub fn messenger(&self) -> BinaryMessenger {
+        self.messenger.clone()
+    }
+
     pub fn send(self, message: &[u8]) {
+        if let Some(pending_reply) = self.pending_reply {
+            pending_reply.finish(Some(message));
+        }
+
+        // The response handle is freed along with its engine, so replies to an engine that has
+        // been restarted are dropped.
+        let (_guard, engine) = self.messenger.handle.lock(self.messenger.launch);
+
         unsafe {
             FlutterEngineSendPlatformMessageResponse(
-                self.engine,
+                engine,
                 self.response_handle,
                 message.as_ptr(),
                 message.len(),
@@ -396,9 +1291,15 @@ impl BinaryMessageReply {
     }
 
     pub fn not_implemented(self) {
+        if let Some(pending_reply) = self.pending_reply {
+            pending_reply.finish(None);
+        }
+
+        let (_guard, engine) = self.messenger.handle.lock(self.messenger.launch);
+
         unsafe {
             FlutterEngineSendPlatformMessageResponse(
-                self.engine,
+                engine,
                 self.response_handle,
                 std::ptr::null(),
                 0,
@@ -414,9 +1315,10 @@ unsafe extern "C" fn platform_message_callback(
     let engine = user_data.cast::<FlutterEngineInner>().as_ref().unwrap();
     let message = message.as_ref().unwrap();
 
-    let reply = BinaryMessageReply {
-        engine: engine.handle,
+    let mut reply = BinaryMessageReply {
+        messenger: engine.messenger(),
         response_handle: message.response_handle,
+        pending_reply: None,
     };
 
     let channel = CStr::from_ptr(message.channel);
@@ -426,7 +1328,18 @@ unsafe extern "C" fn platform_message_callback(
         return;
     };
 
-    let Some(handler) = engine.platform_message_handlers.get(channel) else {
+    if !message.message.is_null() {
+        let bytes = std::slice::from_raw_parts(message.message, message.message_size);
+        reply.pending_reply = channel_log::message(Direction::Incoming, channel, bytes);
+    }
+
+    let handler = engine
+        .platform_message_handlers
+        .borrow()
+        .get(channel)
+        .cloned();
+
+    let Some(handler) = handler else {
         tracing::warn!(channel, "unimplemented");
         reply.not_implemented();
         return;
@@ -438,11 +1351,48 @@ unsafe extern "C" fn platform_message_callback(
         return;
     }
 
+    flight_recorder::record(
+        EventKind::Message,
+        format!("{channel} ({} bytes)", message.message_size),
+    );
+
     let bytes = std::slice::from_raw_parts(message.message, message.message_size);
 
     handler.handle(bytes, reply);
 }
 
+unsafe extern "C" fn update_semantics_callback(
+    update: *const FlutterSemanticsUpdate2,
+    user_data: *mut c_void,
+) {
+    let engine = user_data.cast::<FlutterEngineInner>().as_ref().unwrap();
+    let Some(update) = update.as_ref() else {
+        return;
+    };
+
+    // Cloned out so that the handler can replace itself.
+    let Some(handler) = engine.semantics_update_handler.borrow().clone() else {
+        return;
+    };
+
+    let nodes = if update.nodes.is_null() {
+        vec![]
+    } else {
+        std::slice::from_raw_parts(update.nodes, update.node_count)
+            .iter()
+            .filter_map(|node| node.as_ref())
+            .map(|node| SemanticsNode::from_raw(node))
+            .collect()
+    };
+
+    handler(nodes);
+}
+
+unsafe extern "C" fn vsync_callback(user_data: *mut c_void, baton: isize) {
+    let engine = user_data.cast::<FlutterEngineInner>().as_ref().unwrap();
+    engine.vsync_waiter.request(engine.handle.get(), baton);
+}
+
 unsafe extern "C" fn gl_make_current(user_data: *mut c_void) -> bool {
     let engine = user_data.cast::<FlutterEngineInner>().as_ref().unwrap();
 
@@ -489,13 +1439,34 @@ unsafe extern "C" fn gl_get_proc_address(
     name: *const c_char,
 ) -> *mut c_void {
     let engine = user_data.cast::<FlutterEngineInner>().as_ref().unwrap();
-    let name = CStr::from_ptr(name);
+    let Ok(name) = CStr::from_ptr(name).to_str() else {
+        return ptr::null_mut();
+    };
+
     engine
         .egl_manager
-        .get_proc_address(name.to_str().unwrap())
+        .get_proc_address(name)
         .unwrap_or(ptr::null_mut())
 }
 
+unsafe extern "C" fn gl_external_texture_frame(
+    user_data: *mut c_void,
+    texture_id: i64,
+    width: usize,
+    height: usize,
+    texture: *mut FlutterOpenGLTexture,
+) -> bool {
+    let engine = user_data.cast::<FlutterEngineInner>().as_ref().unwrap();
+
+    let Some(frame) = engine.texture_registry.frame(texture_id, width, height) else {
+        return false;
+    };
+
+    *texture = frame;
+
+    true
+}
+
 pub unsafe extern "C" fn compositor_create_backing_store(
     config: *const FlutterBackingStoreConfig,
     out: *mut FlutterBackingStore,
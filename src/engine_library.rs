@@ -0,0 +1,117 @@
+use std::path::{Path, PathBuf};
+use std::{env, fs};
+
+use color_eyre::eyre::{self, bail, WrapErr};
+
+const ENGINE_DLL: &str = "flutter_engine.dll";
+
+/// Overrides where the engine is loaded from, e.g. to try a different engine version.
+const ENGINE_PATH_VAR: &str = "FLUYT_ENGINE";
+
+/// Loads the engine from `path`, or else the path in `FLUYT_ENGINE`. These fail if the path doesn't
+/// exist, rather than falling back to a different engine. Otherwise, the first of these that exists
+/// is loaded:
+///
+/// 1. `flutter_engine.dll` next to the executable.
+/// 2. `%LOCALAPPDATA%\fluyt\engine\flutter_engine.dll`, which is shared by all apps.
+///
+/// Loading again after the engine has been loaded does nothing.
+///
+/// Fails if the engine isn't the build that the bindings were generated for, as far as that can be
+/// told, since calling into a mismatched engine is undefined behavior.
+pub fn load(path: Option<&Path>) -> eyre::Result<()> {
+    if let Some(path) = flutter_embedder::loaded_path() {
+        tracing::debug!(path = %path.display(), "engine already loaded");
+        return Ok(());
+    }
+
+    let explicit = path
+        .map(Path::to_owned)
+        .or_else(|| env::var_os(ENGINE_PATH_VAR).map(PathBuf::from));
+
+    if let Some(path) = &explicit {
+        if !path.exists() {
+            bail!("{} not found. {}", path.display(), download_hint());
+        }
+    }
+
+    let candidates = match explicit {
+        Some(path) => vec![path],
+        None => default_candidates(),
+    };
+
+    let Some(path) = candidates.iter().find(|path| path.exists()) else {
+        bail!(
+            "{ENGINE_DLL} not found, tried: {}. {}",
+            candidates
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", "),
+            download_hint()
+        );
+    };
+
+    check_commit(path)?;
+
+    tracing::info!(path = %path.display(), "loading engine");
+    flutter_embedder::load(path).wrap_err_with(download_hint)?;
+
+    Ok(())
+}
+
+/// Compares the engine's commit, from the `flutter_engine.version` file that `run.ps1` writes next
+/// to it, with the one that the bindings were generated for. The engine doesn't report its commit
+/// itself, so engines without the file can't be checked.
+fn check_commit(path: &Path) -> eyre::Result<()> {
+    let Some(expected) = flutter_embedder::ENGINE_COMMIT else {
+        tracing::warn!("the engine commit that the bindings were generated for is unknown");
+        return Ok(());
+    };
+
+    let Ok(found) = fs::read_to_string(path.with_extension("version")) else {
+        tracing::warn!(path = %path.display(), "engine has no version file, so it can't be checked");
+        return Ok(());
+    };
+
+    let found = found.trim();
+    if found != expected {
+        bail!(
+            "{} is from engine commit {found}, but fluyt was built for {expected}. {}",
+            path.display(),
+            download_hint()
+        );
+    }
+
+    Ok(())
+}
+
+/// Where to get the engine that the bindings were generated for.
+pub fn download_hint() -> String {
+    match flutter_embedder::ENGINE_COMMIT {
+        Some(commit) => format!(
+            "Download the matching engine from https://storage.googleapis.com/flutter_infra_release/flutter/{commit}/windows-x64/windows-x64-embedder.zip"
+        ),
+        None => "Use the engine from the embedder that the bindings were generated from".to_owned(),
+    }
+}
+
+/// Where the engine is looked for when no path is given.
+fn default_candidates() -> Vec<PathBuf> {
+    let mut candidates = vec![];
+
+    if let Some(dir) = env::current_exe().ok().as_deref().and_then(Path::parent) {
+        candidates.push(dir.join(ENGINE_DLL));
+    }
+
+    if let Some(dir) = env::var_os("LOCALAPPDATA") {
+        candidates.push(
+            PathBuf::from(dir)
+                .join("fluyt")
+                .join("engine")
+                .join(ENGINE_DLL),
+        );
+    }
+
+    candidates
+}
@@ -0,0 +1,51 @@
+use flutter_embedder::FlutterEngineResult;
+use khronos_egl as egl;
+
+/// Errors returned by the engine, compositor and EGL APIs. These are kept distinct (rather than
+/// being reported as `eyre::Report`s) so that callers can react to specific kinds of failure,
+/// e.g. by recreating the D3D device.
+#[derive(Debug, thiserror::Error)]
+pub enum FlionError {
+    /// An EGL call failed.
+    #[error("egl error: {0}")]
+    Egl(#[from] egl::Error),
+    /// ANGLE couldn't create an EGL device for the D3D device.
+    #[error("failed to create angle device")]
+    AngleDevice,
+    /// A D3D, composition or other COM call failed.
+    #[error(transparent)]
+    Windows(#[from] windows::core::Error),
+    /// A call into the embedder API failed.
+    #[error("failed to {operation}: {result}")]
+    Engine {
+        operation: &'static str,
+        result: FlutterEngineResult,
+    },
+    /// A message couldn't be sent on a platform channel.
+    #[error("failed to send platform message on {channel}: {result}")]
+    Channel {
+        channel: String,
+        result: FlutterEngineResult,
+    },
+    /// A platform channel message couldn't be encoded.
+    #[error("failed to encode platform message: {0}")]
+    Encoding(#[from] serde_json::Error),
+    /// A Dart VM flag contains a comma. The engine splits the flags on commas, so it would be
+    /// passed to the VM as several flags.
+    #[error("dart flag contains a comma: {0}")]
+    DartFlag(String),
+    /// A string passed to the engine, such as a command line switch, contains a nul.
+    #[error("invalid engine argument: {0}")]
+    Argument(#[from] std::ffi::NulError),
+}
+
+pub type Result<T, E = FlionError> = std::result::Result<T, E>;
+
+/// Converts an embedder API result into a [`FlionError::Engine`] if it wasn't successful.
+pub fn check_engine_result(operation: &'static str, result: FlutterEngineResult) -> Result<()> {
+    if result == flutter_embedder::FlutterEngineResult_kSuccess {
+        Ok(())
+    } else {
+        Err(FlionError::Engine { operation, result })
+    }
+}
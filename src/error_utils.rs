@@ -1,5 +1,7 @@
 use std::fmt::Debug;
 
+use crate::flight_recorder::{self, EventKind};
+
 pub trait ResultExt {
     fn trace_err(self) -> Self;
 }
@@ -8,6 +10,7 @@ impl<T, E: Debug> ResultExt for Result<T, E> {
     fn trace_err(self) -> Self {
         if let Err(e) = &self {
             tracing::error!("{e:#?}");
+            flight_recorder::record(EventKind::Error, format!("{e:?}"));
         }
         self
     }
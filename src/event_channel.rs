@@ -0,0 +1,69 @@
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+
+use color_eyre::eyre;
+use flutter_codec::EncodableValue;
+
+use crate::engine::BinaryMessenger;
+use crate::standard_method_channel::{
+    encode_success_envelope, StandardMethodHandler, StandardMethodReply,
+};
+
+/// The platform side of a Dart `EventChannel` using the standard method codec.
+///
+/// Events sent while there is no listener on the Dart side are dropped. Listeners are forgotten when
+/// the engine is restarted, since the isolate that they belonged to is gone.
+pub struct EventChannel {
+    name: CString,
+    sink: RefCell<Option<BinaryMessenger>>,
+}
+
+impl EventChannel {
+    pub fn new(name: &CStr) -> EventChannel {
+        EventChannel {
+            name: name.to_owned(),
+            sink: RefCell::new(None),
+        }
+    }
+
+    pub fn is_listening(&self) -> bool {
+        self.sink
+            .borrow()
+            .as_ref()
+            .is_some_and(|sink| sink.is_connected())
+    }
+
+    pub fn send(&self, event: &EncodableValue) -> eyre::Result<()> {
+        let Some(sink) = self
+            .sink
+            .borrow()
+            .clone()
+            .filter(|sink| sink.is_connected())
+        else {
+            return Ok(());
+        };
+
+        sink.send_platform_message(&self.name, &encode_success_envelope(event))?;
+
+        Ok(())
+    }
+}
+
+impl StandardMethodHandler for EventChannel {
+    fn handle(&self, method: &str, _args: EncodableValue, reply: StandardMethodReply) {
+        match method {
+            "listen" => {
+                *self.sink.borrow_mut() = Some(reply.messenger());
+                reply.success(&EncodableValue::Null);
+            }
+            "cancel" => {
+                *self.sink.borrow_mut() = None;
+                reply.success(&EncodableValue::Null);
+            }
+            _ => {
+                tracing::warn!(method, "unimplemented");
+                reply.not_implemented();
+            }
+        }
+    }
+}
@@ -0,0 +1,171 @@
+use color_eyre::eyre;
+use flutter_codec::EncodableValue;
+use windows::core::{HSTRING, PCWSTR};
+use windows::Win32::Foundation::{ERROR_CANCELLED, HWND};
+use windows::Win32::System::Com::{CoCreateInstance, CoTaskMemFree, CLSCTX_INPROC_SERVER};
+use windows::Win32::UI::Shell::Common::COMDLG_FILTERSPEC;
+use windows::Win32::UI::Shell::{
+    FileOpenDialog, FileSaveDialog, IFileDialog, IFileOpenDialog, IFileSaveDialog, IShellItem,
+    SHCreateItemFromParsingName, FOS_ALLOWMULTISELECT, FOS_FORCEFILESYSTEM, FOS_PICKFOLDERS,
+    SIGDN_FILESYSPATH,
+};
+
+use crate::standard_method_channel::{StandardMethodHandler, StandardMethodReply};
+
+pub struct FileDialogHandler {
+    hwnd: HWND,
+}
+
+impl FileDialogHandler {
+    pub fn new(hwnd: HWND) -> FileDialogHandler {
+        FileDialogHandler { hwnd }
+    }
+}
+
+impl StandardMethodHandler for FileDialogHandler {
+    fn handle(&self, method: &str, args: EncodableValue, reply: StandardMethodReply) {
+        let res = match method {
+            "openFile" => unsafe { self.open(&args, false) },
+            "openFolder" => unsafe { self.open(&args, true) },
+            "saveFile" => unsafe { self.save(&args) },
+            _ => {
+                tracing::warn!(method, "unimplemented");
+                return reply.not_implemented();
+            }
+        };
+
+        match res {
+            Ok(Some(paths)) => reply.success(&EncodableValue::List(
+                paths.iter().map(|path| EncodableValue::Str(path)).collect(),
+            )),
+            Ok(None) => reply.success(&EncodableValue::Null),
+            Err(e) => reply.error("file_dialog_error", Some(&e.to_string())),
+        }
+    }
+}
+
+struct Filter {
+    name: HSTRING,
+    spec: HSTRING,
+}
+
+impl FileDialogHandler {
+    unsafe fn open(
+        &self,
+        args: &EncodableValue,
+        folders: bool,
+    ) -> eyre::Result<Option<Vec<String>>> {
+        let dialog: IFileOpenDialog =
+            CoCreateInstance(&FileOpenDialog, None, CLSCTX_INPROC_SERVER)?;
+
+        let mut options = dialog.GetOptions()? | FOS_FORCEFILESYSTEM;
+
+        if folders {
+            options |= FOS_PICKFOLDERS;
+        }
+
+        if let Some(EncodableValue::Bool(true)) = args.get("multiple") {
+            options |= FOS_ALLOWMULTISELECT;
+        }
+
+        dialog.SetOptions(options)?;
+
+        configure(&dialog, args)?;
+
+        if !show(&dialog, self.hwnd)? {
+            return Ok(None);
+        }
+
+        let results = dialog.GetResults()?;
+        let mut paths = vec![];
+
+        for i in 0..results.GetCount()? {
+            paths.push(item_path(&results.GetItemAt(i)?)?);
+        }
+
+        Ok(Some(paths))
+    }
+
+    unsafe fn save(&self, args: &EncodableValue) -> eyre::Result<Option<Vec<String>>> {
+        let dialog: IFileSaveDialog =
+            CoCreateInstance(&FileSaveDialog, None, CLSCTX_INPROC_SERVER)?;
+
+        dialog.SetOptions(dialog.GetOptions()? | FOS_FORCEFILESYSTEM)?;
+
+        configure(&dialog, args)?;
+
+        if let Some(name) = args.get("defaultName").and_then(|v| v.as_string()) {
+            dialog.SetFileName(&HSTRING::from(name))?;
+        }
+
+        if !show(&dialog, self.hwnd)? {
+            return Ok(None);
+        }
+
+        Ok(Some(vec![item_path(&dialog.GetResult()?)?]))
+    }
+}
+
+/// Applies the options common to both open and save dialogs.
+unsafe fn configure(dialog: &IFileDialog, args: &EncodableValue) -> eyre::Result<()> {
+    if let Some(title) = args.get("title").and_then(|v| v.as_string()) {
+        dialog.SetTitle(&HSTRING::from(title))?;
+    }
+
+    if let Some(dir) = args.get("initialDirectory").and_then(|v| v.as_string()) {
+        let folder: IShellItem = SHCreateItemFromParsingName(&HSTRING::from(dir), None)?;
+        dialog.SetFolder(&folder)?;
+    }
+
+    // Filters are given as a list of `{name: "Images", extensions: ["png", "jpg"]}` maps.
+    if let Some(filters) = args.get("filters").and_then(|v| v.as_list()) {
+        let filters = filters
+            .iter()
+            .filter_map(|filter| {
+                let name = filter.get("name")?.as_string()?;
+                let spec = filter
+                    .get("extensions")?
+                    .as_list()?
+                    .iter()
+                    .filter_map(|ext| ext.as_string())
+                    .map(|ext| format!("*.{ext}"))
+                    .collect::<Vec<_>>()
+                    .join(";");
+                Some(Filter {
+                    name: HSTRING::from(name),
+                    spec: HSTRING::from(spec),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        if !filters.is_empty() {
+            let specs = filters
+                .iter()
+                .map(|filter| COMDLG_FILTERSPEC {
+                    pszName: PCWSTR(filter.name.as_ptr()),
+                    pszSpec: PCWSTR(filter.spec.as_ptr()),
+                })
+                .collect::<Vec<_>>();
+
+            dialog.SetFileTypes(&specs)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Shows the dialog, returning `false` if it was cancelled by the user.
+unsafe fn show(dialog: &IFileDialog, owner: HWND) -> eyre::Result<bool> {
+    match dialog.Show(owner) {
+        Ok(()) => Ok(true),
+        Err(e) if e.code() == ERROR_CANCELLED.to_hresult() => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+unsafe fn item_path(item: &IShellItem) -> eyre::Result<String> {
+    let name = item.GetDisplayName(SIGDN_FILESYSPATH)?;
+    let path = name.to_string();
+    CoTaskMemFree(Some(name.0 as *const _));
+    Ok(path?)
+}
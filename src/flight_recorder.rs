@@ -0,0 +1,116 @@
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use std::{fs, panic, thread};
+
+const CAPACITY: usize = 512;
+
+/// Ring buffer of recent embedder events, written to disk if the process panics or the engine
+/// fails fatally so that bug reports come with some context.
+static RECORDER: Mutex<Option<Recorder>> = Mutex::new(None);
+
+struct Recorder {
+    start: Instant,
+    entries: VecDeque<Entry>,
+}
+
+struct Entry {
+    time: Instant,
+    thread: Option<String>,
+    kind: EventKind,
+    message: String,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum EventKind {
+    Message,
+    Resize,
+    Present,
+    Error,
+}
+
+pub fn record(kind: EventKind, message: impl Into<String>) {
+    let Ok(mut recorder) = RECORDER.lock() else {
+        return;
+    };
+
+    let recorder = recorder.get_or_insert_with(|| Recorder {
+        start: Instant::now(),
+        entries: VecDeque::with_capacity(CAPACITY),
+    });
+
+    if recorder.entries.len() == CAPACITY {
+        recorder.entries.pop_front();
+    }
+
+    recorder.entries.push_back(Entry {
+        time: Instant::now(),
+        thread: thread::current().name().map(|name| name.to_owned()),
+        kind,
+        message: message.into(),
+    });
+}
+
+/// Installs a panic hook that dumps the recorded events before running the existing hook.
+pub fn install_panic_hook() {
+    let prev_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        dump(&info.to_string());
+        prev_hook(info);
+    }));
+}
+
+/// Writes the recorded events to a file in the temp directory, returning its path.
+pub fn dump(reason: &str) -> Option<PathBuf> {
+    // Don't block (or deadlock) if the panic happened while holding the lock.
+    let recorder = match RECORDER.try_lock() {
+        Ok(recorder) => recorder,
+        Err(_) => return None,
+    };
+
+    let mut report = String::new();
+    let _ = writeln!(report, "reason: {reason}");
+    let _ = writeln!(report);
+
+    if let Some(recorder) = recorder.as_ref() {
+        for entry in &recorder.entries {
+            let _ = writeln!(
+                report,
+                "[{:>12.6}] [{}] {:?}: {}",
+                entry.time.duration_since(recorder.start).as_secs_f64(),
+                entry.thread.as_deref().unwrap_or("<unnamed>"),
+                entry.kind,
+                entry.message,
+            );
+        }
+    }
+
+    drop(recorder);
+
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+
+    let path = std::env::temp_dir().join(format!("fluyt-crash-{timestamp}.log"));
+
+    let res = fs::File::create(&path).and_then(|mut file| {
+        file.write_all(report.as_bytes())?;
+        file.flush()?;
+        Ok::<_, io::Error>(())
+    });
+
+    match res {
+        Ok(()) => {
+            eprintln!("embedder event log written to {}", path.display());
+            Some(path)
+        }
+        Err(e) => {
+            eprintln!("failed to write embedder event log: {e}");
+            None
+        }
+    }
+}
@@ -0,0 +1,182 @@
+//! Frame statistics collected from the raster and vsync threads, which are shown by the
+//! performance overlay, and timings for each frame, which are sent to listeners.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use flutter_embedder::FlutterEngineGetCurrentTime;
+
+/// Resizes that block the platform thread for longer than this are counted as stalls.
+const RESIZE_STALL_THRESHOLD: Duration = Duration::from_millis(32);
+
+static FRAMES_PRESENTED: AtomicU64 = AtomicU64::new(0);
+/// Engine time (in nanoseconds) of the most recent vsync.
+static LAST_VSYNC_TIME: AtomicU64 = AtomicU64::new(0);
+static PRESENT_LATENCY_NANOS: AtomicU64 = AtomicU64::new(0);
+static RESIZE_STALLS: AtomicU64 = AtomicU64::new(0);
+static LONGEST_RESIZE_STALL_NANOS: AtomicU64 = AtomicU64::new(0);
+
+/// Timings for the frame that is currently being produced.
+static CURRENT_FRAME: Mutex<CurrentFrame> = Mutex::new(CurrentFrame {
+    vsync_start: 0,
+    vsync_target: 0,
+    raster_start: None,
+});
+
+/// Listeners are only `Send`, so each one has its own lock. They are called without holding the
+/// lock on the list, so a listener can add or remove listeners.
+static LISTENERS: Mutex<Vec<(ListenerId, Arc<Mutex<Listener>>)>> = Mutex::new(Vec::new());
+static HAS_LISTENERS: AtomicBool = AtomicBool::new(false);
+static NEXT_LISTENER_ID: AtomicU64 = AtomicU64::new(1);
+
+type Listener = Box<dyn Fn(&FrameTimings) + Send>;
+
+/// Identifies a listener added with [`add_listener`], for removing it again.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ListenerId(u64);
+
+struct CurrentFrame {
+    vsync_start: u64,
+    vsync_target: u64,
+    raster_start: Option<u64>,
+}
+
+/// Timestamps for a single frame, in engine time (nanoseconds, as returned by
+/// `FlutterEngineGetCurrentTime`).
+///
+/// The engine doesn't report when the framework built the frame. Build times are available to the
+/// app from `SchedulerBinding.addTimingsCallback`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FrameTimings {
+    pub frame_number: u64,
+    /// When the vsync that started the frame was answered.
+    pub vsync_start: u64,
+    /// When the frame should have been presented by.
+    pub vsync_target: u64,
+    /// When the engine started drawing into the frame's first layer.
+    pub raster_start: Option<u64>,
+    /// When the engine handed the finished layers to the compositor.
+    pub raster_end: u64,
+    /// When the frame was committed to DWM.
+    pub committed: u64,
+    /// When DWM finished processing the commit. This isn't known if the host owns the visual tree.
+    pub dwm_confirmed: Option<u64>,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Snapshot {
+    pub frames_presented: u64,
+    /// Time from the vsync that started the most recent frame to it being committed.
+    pub present_latency: Duration,
+    pub resize_stalls: u64,
+    pub longest_resize_stall: Duration,
+}
+
+pub fn now() -> u64 {
+    unsafe { FlutterEngineGetCurrentTime() }
+}
+
+pub fn record_vsync(frame_start: u64, frame_target: u64) {
+    LAST_VSYNC_TIME.store(frame_start, Ordering::Relaxed);
+
+    let mut frame = CURRENT_FRAME.lock().unwrap();
+    frame.vsync_start = frame_start;
+    frame.vsync_target = frame_target;
+}
+
+/// Records that the engine has started drawing a layer. Only the first layer of each frame is
+/// counted.
+pub fn record_raster_start() {
+    CURRENT_FRAME
+        .lock()
+        .unwrap()
+        .raster_start
+        .get_or_insert_with(now);
+}
+
+/// Records that a frame has been committed, with when the compositor received its layers. The
+/// frame is assumed to have been started by the most recent vsync.
+pub fn record_present(raster_end: u64) -> FrameTimings {
+    let frame_number = FRAMES_PRESENTED.fetch_add(1, Ordering::Relaxed) + 1;
+    let now = now();
+
+    let vsync_time = LAST_VSYNC_TIME.load(Ordering::Relaxed);
+    if vsync_time != 0 {
+        PRESENT_LATENCY_NANOS.store(now.saturating_sub(vsync_time), Ordering::Relaxed);
+    }
+
+    let mut frame = CURRENT_FRAME.lock().unwrap();
+
+    FrameTimings {
+        frame_number,
+        vsync_start: frame.vsync_start,
+        vsync_target: frame.vsync_target,
+        raster_start: frame.raster_start.take(),
+        raster_end,
+        committed: now,
+        dwm_confirmed: None,
+    }
+}
+
+/// Adds a listener that is called with the timings of every frame once it has been presented.
+/// Listeners are called on the raster thread or a thread pool thread, so they should return
+/// quickly.
+pub fn add_listener(listener: impl Fn(&FrameTimings) + Send + 'static) -> ListenerId {
+    let id = ListenerId(NEXT_LISTENER_ID.fetch_add(1, Ordering::Relaxed));
+    let listener: Listener = Box::new(listener);
+
+    let mut listeners = LISTENERS.lock().unwrap();
+    listeners.push((id, Arc::new(Mutex::new(listener))));
+    HAS_LISTENERS.store(true, Ordering::Relaxed);
+
+    id
+}
+
+/// Removes a listener. It may still be called with a frame that was being reported while it was
+/// removed.
+pub fn remove_listener(id: ListenerId) {
+    let mut listeners = LISTENERS.lock().unwrap();
+    listeners.retain(|(listener_id, _)| *listener_id != id);
+    HAS_LISTENERS.store(!listeners.is_empty(), Ordering::Relaxed);
+}
+
+/// Returns whether there are any listeners, so that the compositor can avoid waiting for DWM to
+/// confirm frames when nothing needs it.
+pub fn has_listeners() -> bool {
+    HAS_LISTENERS.load(Ordering::Relaxed)
+}
+
+pub fn report(timings: &FrameTimings) {
+    let listeners: Vec<_> = LISTENERS
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(_, listener)| listener.clone())
+        .collect();
+
+    for listener in listeners {
+        (listener.lock().unwrap())(timings);
+    }
+}
+
+/// Records how long the platform thread was blocked waiting for a frame during a resize.
+pub fn record_resize_wait(duration: Duration) {
+    if duration < RESIZE_STALL_THRESHOLD {
+        return;
+    }
+
+    RESIZE_STALLS.fetch_add(1, Ordering::Relaxed);
+    LONGEST_RESIZE_STALL_NANOS.fetch_max(duration.as_nanos() as u64, Ordering::Relaxed);
+}
+
+pub fn snapshot() -> Snapshot {
+    Snapshot {
+        frames_presented: FRAMES_PRESENTED.load(Ordering::Relaxed),
+        present_latency: Duration::from_nanos(PRESENT_LATENCY_NANOS.load(Ordering::Relaxed)),
+        resize_stalls: RESIZE_STALLS.load(Ordering::Relaxed),
+        longest_resize_stall: Duration::from_nanos(
+            LONGEST_RESIZE_STALL_NANOS.load(Ordering::Relaxed),
+        ),
+    }
+}
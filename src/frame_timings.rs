@@ -0,0 +1,102 @@
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use color_eyre::eyre;
+use flutter_codec::EncodableValue;
+
+use crate::event_channel::EventChannel;
+use crate::frame_stats::{self, FrameTimings, ListenerId};
+use crate::standard_method_channel::{StandardMethodHandler, StandardMethodReply};
+
+/// Handles the `flion/frame_timings` event channel. Timings are only collected while the Dart side
+/// is listening, since the compositor has to wait for DWM to confirm each frame for them.
+pub struct FrameTimingsHandler {
+    events: Rc<EventChannel>,
+    add_listener: Box<dyn Fn() -> ListenerId>,
+    listener: Cell<Option<ListenerId>>,
+}
+
+impl FrameTimingsHandler {
+    /// `forward` is called with each frame's timings on the thread that reports them, and should
+    /// pass them to [`send_event`] on the platform thread.
+    pub fn new(
+        events: Rc<EventChannel>,
+        forward: impl Fn(&FrameTimings) + Clone + Send + 'static,
+    ) -> FrameTimingsHandler {
+        FrameTimingsHandler {
+            events,
+            add_listener: Box::new(move || frame_stats::add_listener(forward.clone())),
+            listener: Cell::new(None),
+        }
+    }
+}
+
+impl StandardMethodHandler for FrameTimingsHandler {
+    fn handle(&self, method: &str, args: EncodableValue, reply: StandardMethodReply) {
+        match method {
+            "listen" => {
+                if self.listener.get().is_none() {
+                    self.listener.set(Some((self.add_listener)()));
+                }
+            }
+            "cancel" => {
+                if let Some(id) = self.listener.take() {
+                    frame_stats::remove_listener(id);
+                }
+            }
+            _ => {}
+        }
+
+        self.events.handle(method, args, reply);
+    }
+}
+
+/// Sends a frame's timings on `flion/frame_timings`, as a map of timestamps in microseconds of
+/// engine time (the same clock as `FrameTiming` in `dart:ui`). Timestamps that aren't known are
+/// null.
+pub fn send_event(events: &EventChannel, timings: &FrameTimings) -> eyre::Result<()> {
+    if !events.is_listening() {
+        return Ok(());
+    }
+
+    let micros = |nanos: Option<u64>| match nanos {
+        Some(nanos) => EncodableValue::I64((nanos / 1000) as i64),
+        None => EncodableValue::Null,
+    };
+
+    let event = BTreeMap::from_iter([
+        (
+            EncodableValue::Str("frameNumber"),
+            EncodableValue::I64(timings.frame_number as i64),
+        ),
+        (
+            EncodableValue::Str("vsyncStart"),
+            micros(Some(timings.vsync_start)),
+        ),
+        (
+            EncodableValue::Str("vsyncTarget"),
+            micros(Some(timings.vsync_target)),
+        ),
+        (
+            EncodableValue::Str("rasterStart"),
+            micros(timings.raster_start),
+        ),
+        (
+            EncodableValue::Str("rasterEnd"),
+            micros(Some(timings.raster_end)),
+        ),
+        (
+            EncodableValue::Str("committed"),
+            micros(Some(timings.committed)),
+        ),
+        (
+            EncodableValue::Str("dwmConfirmed"),
+            micros(timings.dwm_confirmed),
+        ),
+    ]);
+
+    events.send(&EncodableValue::Map(event))?;
+
+    Ok(())
+}
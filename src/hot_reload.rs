@@ -0,0 +1,178 @@
+use std::collections::BTreeMap;
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use std::{fs, thread};
+
+use color_eyre::eyre::{self, bail, eyre, OptionExt};
+use serde_json::{json, Value};
+use tungstenite::stream::MaybeTlsStream;
+use tungstenite::{Message, WebSocket};
+
+use crate::vm_service;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Name of the kernel snapshot that `flutter build bundle --debug` writes to the assets directory.
+const KERNEL_BLOB: &str = "kernel_blob.bin";
+
+type Snapshot = BTreeMap<PathBuf, SystemTime>;
+
+/// Watches `assets_dir` on a background thread. When the kernel snapshot changes the new sources
+/// are reloaded into the running isolates through the VM service, falling back to `restart` if
+/// the reload is rejected. Changes to other assets only trigger a reassemble.
+pub fn spawn_watcher(assets_dir: PathBuf, restart: impl Fn() + Send + 'static) -> eyre::Result<()> {
+    thread::Builder::new()
+        .name("hot-reload".to_owned())
+        .spawn(move || {
+            let mut snapshot = take_snapshot(&assets_dir);
+
+            loop {
+                thread::sleep(POLL_INTERVAL);
+
+                let next = take_snapshot(&assets_dir);
+                if next == snapshot {
+                    continue;
+                }
+
+                // Wait for the build to finish writing before reloading.
+                thread::sleep(POLL_INTERVAL);
+                let next = take_snapshot(&assets_dir);
+
+                let kernel_path = assets_dir.join(KERNEL_BLOB);
+                let kernel_changed = snapshot.get(&kernel_path) != next.get(&kernel_path);
+
+                snapshot = next;
+
+                let Some(uri) = vm_service::service_uri() else {
+                    tracing::warn!("assets changed but the vm service is not available");
+                    continue;
+                };
+
+                if let Err(e) = reload(uri, kernel_changed.then_some(&kernel_path)) {
+                    tracing::warn!("hot reload failed, restarting: {e:?}");
+                    restart();
+                }
+            }
+        })?;
+
+    Ok(())
+}
+
+fn take_snapshot(dir: &Path) -> Snapshot {
+    fn visit(dir: &Path, snapshot: &mut Snapshot) {
+        let Ok(entries) = fs::read_dir(dir) else {
+            return;
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+
+            if metadata.is_dir() {
+                visit(&path, snapshot);
+            } else if let Ok(modified) = metadata.modified() {
+                snapshot.insert(path, modified);
+            }
+        }
+    }
+
+    let mut snapshot = Snapshot::new();
+    visit(dir, &mut snapshot);
+    snapshot
+}
+
+fn reload(service_uri: &str, kernel_path: Option<&PathBuf>) -> eyre::Result<()> {
+    let mut client = VmServiceClient::connect(service_uri)?;
+
+    let vm = client.call("getVM", json!({}))?;
+    let isolates = vm["isolates"]
+        .as_array()
+        .ok_or_eyre("missing isolates in vm response")?
+        .iter()
+        .filter_map(|isolate| isolate["id"].as_str().map(|id| id.to_owned()))
+        .collect::<Vec<_>>();
+
+    for isolate_id in &isolates {
+        if let Some(kernel_path) = kernel_path {
+            let root_lib_uri =
+                format!("file:///{}", fs::canonicalize(kernel_path)?.display()).replace('\\', "/");
+
+            let report = client.call(
+                "reloadSources",
+                json!({
+                    "isolateId": isolate_id,
+                    "rootLibUri": root_lib_uri,
+                }),
+            )?;
+
+            if report["success"].as_bool() != Some(true) {
+                bail!("reload rejected: {report}");
+            }
+        }
+
+        client.call("ext.flutter.reassemble", json!({ "isolateId": isolate_id }))?;
+    }
+
+    tracing::info!(isolates = isolates.len(), "hot reload complete");
+
+    Ok(())
+}
+
+/// A minimal JSON-RPC client for the Dart VM service protocol.
+struct VmServiceClient {
+    socket: WebSocket<MaybeTlsStream<TcpStream>>,
+    next_id: u64,
+}
+
+impl VmServiceClient {
+    fn connect(service_uri: &str) -> eyre::Result<VmServiceClient> {
+        // The service is advertised as `http://host:port/token=/`, with the websocket endpoint
+        // at `ws://host:port/token=/ws`.
+        let ws_uri = format!(
+            "{}/ws",
+            service_uri
+                .replacen("http://", "ws://", 1)
+                .trim_end_matches('/')
+        );
+
+        let (socket, _) = tungstenite::connect(ws_uri)?;
+
+        Ok(VmServiceClient { socket, next_id: 0 })
+    }
+
+    fn call(&mut self, method: &str, params: Value) -> eyre::Result<Value> {
+        self.next_id += 1;
+        let id = self.next_id.to_string();
+
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": method,
+            "params": params,
+        });
+
+        self.socket.send(Message::Text(request.to_string()))?;
+
+        loop {
+            let Message::Text(text) = self.socket.read()? else {
+                continue;
+            };
+
+            let mut response: Value = serde_json::from_str(&text)?;
+
+            // Skip stream events and responses to other requests.
+            if response["id"].as_str() != Some(&id) {
+                continue;
+            }
+
+            if let Some(error) = response.get("error") {
+                return Err(eyre!("{method} failed: {error}"));
+            }
+
+            return Ok(response["result"].take());
+        }
+    }
+}
@@ -0,0 +1,201 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use color_eyre::eyre::{self, bail};
+use flutter_codec::EncodableValue;
+use windows::Win32::Foundation::{ERROR_HOTKEY_ALREADY_REGISTERED, HWND, WPARAM};
+use windows::Win32::UI::Input::KeyboardAndMouse::{
+    RegisterHotKey, UnregisterHotKey, HOT_KEY_MODIFIERS, MOD_ALT, MOD_CONTROL, MOD_NOREPEAT,
+    MOD_SHIFT, MOD_WIN,
+};
+
+use crate::error_utils::ResultExt;
+use crate::event_channel::EventChannel;
+use crate::standard_method_channel::{StandardMethodHandler, StandardMethodReply};
+
+/// Ids must be below this, since the ones above are reserved for shared DLLs.
+const MAX_HOTKEY_ID: i32 = 0xBFFF;
+
+/// A global shortcut, as registered with `RegisterHotKey`.
+#[derive(Clone, Copy, Debug)]
+struct Hotkey {
+    modifiers: HOT_KEY_MODIFIERS,
+    key_code: u32,
+}
+
+/// System-wide shortcuts, which are handled over `flion/hotkeys` and reported over
+/// `flion/hotkeys/events` as the id returned by `register` when they are pressed.
+///
+/// Shortcuts are registered to the window, so they are all unregistered when it is destroyed.
+/// They are also unregistered on hot restart, since the framework forgets about them.
+#[derive(Clone)]
+pub struct Hotkeys {
+    inner: Rc<Inner>,
+}
+
+struct Inner {
+    hwnd: HWND,
+    events: Rc<EventChannel>,
+    registered: RefCell<BTreeMap<i32, Hotkey>>,
+}
+
+enum RegisterError {
+    /// Another app (or this one) already has a shortcut with the same key and modifiers.
+    Conflict,
+    Other(eyre::Report),
+}
+
+impl Hotkeys {
+    pub fn new(hwnd: HWND, events: Rc<EventChannel>) -> Hotkeys {
+        Hotkeys {
+            inner: Rc::new(Inner {
+                hwnd,
+                events,
+                registered: RefCell::new(BTreeMap::new()),
+            }),
+        }
+    }
+
+    fn register(&self, hotkey: Hotkey) -> Result<i32, RegisterError> {
+        let mut registered = self.inner.registered.borrow_mut();
+
+        let Some(id) = (1..=MAX_HOTKEY_ID).find(|id| !registered.contains_key(id)) else {
+            return Err(RegisterError::Other(eyre::eyre!(
+                "too many hotkeys are registered"
+            )));
+        };
+
+        let res = unsafe { RegisterHotKey(self.inner.hwnd, id, hotkey.modifiers, hotkey.key_code) };
+
+        match res {
+            Ok(()) => {}
+            Err(e) if e.code() == ERROR_HOTKEY_ALREADY_REGISTERED.to_hresult() => {
+                return Err(RegisterError::Conflict);
+            }
+            Err(e) => return Err(RegisterError::Other(e.into())),
+        }
+
+        tracing::debug!(id, ?hotkey, "registered hotkey");
+
+        registered.insert(id, hotkey);
+
+        Ok(id)
+    }
+
+    fn unregister(&self, id: i32) -> eyre::Result<()> {
+        if self.inner.registered.borrow_mut().remove(&id).is_none() {
+            bail!("hotkey {id} is not registered");
+        }
+
+        unsafe { UnregisterHotKey(self.inner.hwnd, id)? };
+
+        Ok(())
+    }
+
+    pub fn unregister_all(&self) {
+        self.inner.unregister_all();
+    }
+
+    /// Handles `WM_HOTKEY`, returning false if the hotkey wasn't registered by the app, in which
+    /// case the message should be passed on.
+    pub fn handle_hotkey(&self, wparam: WPARAM) -> bool {
+        let id = wparam.0 as i32;
+
+        // Negative ids are used for the system's own hotkeys (e.g. IDHOT_SNAPWINDOW).
+        if !self.inner.registered.borrow().contains_key(&id) {
+            return false;
+        }
+
+        let _ = self.inner.events.send(&EncodableValue::I32(id)).trace_err();
+        true
+    }
+}
+
+impl Inner {
+    fn unregister_all(&self) {
+        for id in self.registered.take().into_keys() {
+            // This fails once the window has been destroyed, which unregisters them anyway.
+            let _ = unsafe { UnregisterHotKey(self.hwnd, id) };
+        }
+    }
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        self.unregister_all();
+    }
+}
+
+fn parse_hotkey(args: &EncodableValue) -> Option<Hotkey> {
+    let key_code = args.get("keyCode").and_then(|v| v.as_int())?;
+
+    let mut modifiers = HOT_KEY_MODIFIERS(0);
+    for modifier in args
+        .get("modifiers")
+        .and_then(|v| v.as_list())
+        .unwrap_or(&[])
+    {
+        modifiers |= match modifier.as_string()? {
+            "alt" => MOD_ALT,
+            "control" => MOD_CONTROL,
+            "shift" => MOD_SHIFT,
+            "meta" => MOD_WIN,
+            _ => return None,
+        };
+    }
+
+    // Holding the keys down only activates the shortcut once, unless repeats are asked for.
+    if args.get("repeat").and_then(|v| v.as_bool()) != Some(true) {
+        modifiers |= MOD_NOREPEAT;
+    }
+
+    Some(Hotkey {
+        modifiers,
+        key_code: u32::try_from(key_code).ok()?,
+    })
+}
+
+impl StandardMethodHandler for Hotkeys {
+    fn handle(&self, method: &str, args: EncodableValue, reply: StandardMethodReply) {
+        match method {
+            "register" => {
+                let Some(hotkey) = parse_hotkey(&args) else {
+                    return reply.error(
+                        "invalid_args",
+                        Some("expected a keyCode, and modifiers from alt, control, shift and meta"),
+                    );
+                };
+
+                match self.register(hotkey) {
+                    Ok(id) => reply.success(&EncodableValue::I32(id)),
+                    Err(RegisterError::Conflict) => reply.error(
+                        "hotkey_conflict",
+                        Some("the shortcut is already registered, by this or another application"),
+                    ),
+                    Err(RegisterError::Other(e)) => {
+                        reply.error("hotkey_error", Some(&e.to_string()))
+                    }
+                }
+            }
+            "unregister" => {
+                let Some(id) = args.as_int() else {
+                    return reply.error("invalid_args", Some("expected a hotkey id"));
+                };
+
+                match self.unregister(id as i32) {
+                    Ok(()) => reply.success(&EncodableValue::Null),
+                    Err(e) => reply.error("hotkey_error", Some(&e.to_string())),
+                }
+            }
+            "unregisterAll" => {
+                self.unregister_all();
+                reply.success(&EncodableValue::Null);
+            }
+            _ => {
+                tracing::warn!(method, "unimplemented");
+                reply.not_implemented();
+            }
+        }
+    }
+}
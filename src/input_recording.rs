@@ -0,0 +1,216 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use color_eyre::eyre::{self, Context};
+use serde::{Deserialize, Serialize};
+use smol_str::SmolStr;
+
+use crate::engine::{
+    FlutterEngine, KeyEvent, KeyEventType, PointerButtons, PointerDeviceKind, PointerEvent,
+    PointerPhase,
+};
+
+/// Channels whose messages are input, and so are recorded along with pointer and key events.
+/// Key events are also sent on `flutter/keyevent` for the framework's legacy key handling, and
+/// text editing state is sent on `flutter/textinput`.
+pub const INPUT_CHANNELS: &[&str] = &["flutter/keyevent", "flutter/textinput"];
+
+/// An input event sent to the engine, as written to a recording.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum InputEvent {
+    Pointer {
+        phase: PointerPhase,
+        x: f64,
+        y: f64,
+        /// Bits of [`PointerButtons`].
+        #[serde(default)]
+        buttons: i64,
+        #[serde(default)]
+        kind: PointerDeviceKind,
+        #[serde(default)]
+        device: i32,
+    },
+    Scroll {
+        x: f64,
+        y: f64,
+        delta_x: f64,
+        delta_y: f64,
+        buttons: i64,
+    },
+    Key {
+        event_type: KeyEventType,
+        synthesized: bool,
+        character: Option<String>,
+        logical: Option<u64>,
+        physical: Option<u64>,
+    },
+    Message {
+        channel: String,
+        data: Vec<u8>,
+    },
+}
+
+/// A line in a recording.
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    /// Nanoseconds since the recording started.
+    time: u64,
+    #[serde(flatten)]
+    event: InputEvent,
+}
+
+/// Writes every input event sent to the engine to a file, as JSON lines, so that it can be
+/// replayed with [`InputReplayer`].
+pub struct InputRecorder {
+    start: Instant,
+    writer: RefCell<BufWriter<File>>,
+}
+
+impl InputRecorder {
+    pub fn create(path: &Path) -> eyre::Result<InputRecorder> {
+        let file =
+            File::create(path).wrap_err_with(|| format!("failed to create {}", path.display()))?;
+
+        Ok(InputRecorder {
+            start: Instant::now(),
+            writer: RefCell::new(BufWriter::new(file)),
+        })
+    }
+
+    pub fn record(&self, event: InputEvent) {
+        let entry = Entry {
+            time: self.start.elapsed().as_nanos() as u64,
+            event,
+        };
+
+        let mut writer = self.writer.borrow_mut();
+
+        // Flushed after every event so that the recording is complete even if the app crashes,
+        // which is often what is being reproduced.
+        let res = serde_json::to_writer(&mut *writer, &entry)
+            .map_err(eyre::Report::from)
+            .and_then(|_| Ok(writeln!(writer)?))
+            .and_then(|_| Ok(writer.flush()?));
+
+        if let Err(e) = res {
+            tracing::error!("failed to record input event: {e:?}");
+        }
+    }
+}
+
+/// Replays a recording made by [`InputRecorder`], sending each event to the engine at the same
+/// time relative to when replay started as it was recorded.
+pub struct InputReplayer {
+    start: Instant,
+    entries: VecDeque<Entry>,
+}
+
+impl InputReplayer {
+    pub fn load(path: &Path) -> eyre::Result<InputReplayer> {
+        let file =
+            File::open(path).wrap_err_with(|| format!("failed to open {}", path.display()))?;
+
+        let entries = BufReader::new(file)
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.as_ref().is_ok_and(|line| line.trim().is_empty()))
+            .map(|(i, line)| {
+                serde_json::from_str(&line?)
+                    .wrap_err_with(|| format!("invalid event on line {}", i + 1))
+            })
+            .collect::<eyre::Result<_>>()?;
+
+        Ok(InputReplayer {
+            start: Instant::now(),
+            entries,
+        })
+    }
+
+    /// Sends the events that are due. Returns when the next event is due, or `None` once the
+    /// whole recording has been replayed.
+    pub fn replay_due(&mut self, engine: &FlutterEngine) -> eyre::Result<Option<Instant>> {
+        while let Some(entry) = self.entries.front() {
+            let due = self.start + Duration::from_nanos(entry.time);
+            if due > Instant::now() {
+                return Ok(Some(due));
+            }
+
+            let entry = self.entries.pop_front().unwrap();
+            send(engine, entry.event)?;
+        }
+
+        Ok(None)
+    }
+}
+
+fn send(engine: &FlutterEngine, event: InputEvent) -> eyre::Result<()> {
+    match event {
+        InputEvent::Pointer {
+            phase,
+            x,
+            y,
+            buttons,
+            kind,
+            device,
+        } => {
+            let buttons = match PointerButtons::from_bits_truncate(buttons) {
+                // Buttons weren't recorded by older versions, which only sent primary presses.
+                buttons
+                    if buttons.is_empty()
+                        && matches!(phase, PointerPhase::Down | PointerPhase::Move) =>
+                {
+                    PointerButtons::PRIMARY
+                }
+                buttons => buttons,
+            };
+
+            let event = PointerEvent::new(phase, x, y, buttons).with_device(kind, device);
+            engine.send_pointer_events(&[event])?
+        }
+        InputEvent::Scroll {
+            x,
+            y,
+            delta_x,
+            delta_y,
+            buttons,
+        } => engine.send_scroll_event(
+            x,
+            y,
+            delta_x,
+            delta_y,
+            PointerButtons::from_bits_truncate(buttons),
+        )?,
+        InputEvent::Key {
+            event_type,
+            synthesized,
+            character,
+            logical,
+            physical,
+        } => {
+            let character = character.map(SmolStr::from);
+            // Events that the framework didn't handle were passed on when they were recorded,
+            // which is part of the recording.
+            engine.send_key_event(
+                KeyEvent {
+                    event_type,
+                    synthesized,
+                    character: character.as_ref(),
+                    logical,
+                    physical,
+                },
+                |_| {},
+            )?;
+        }
+        InputEvent::Message { channel, data } => {
+            let channel = std::ffi::CString::new(channel)?;
+            engine.send_platform_message(&channel, &data)?;
+        }
+    }
+
+    Ok(())
+}
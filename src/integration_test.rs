@@ -0,0 +1,122 @@
+use std::fs;
+use std::path::PathBuf;
+
+use color_eyre::eyre;
+use flutter_codec::EncodableValue;
+use serde_json::{json, Map, Value};
+use windows::Win32::Graphics::Direct3D11::ID3D11Device;
+use windows::UI::Composition::Visual;
+
+use crate::screenshot;
+use crate::standard_method_channel::{StandardMethodHandler, StandardMethodReply};
+
+/// The value reported for tests that passed. Failed tests report their failure details instead.
+const SUCCESS: &str = "success";
+
+/// Handles `plugins.flutter.io/integration_test`, which `package:integration_test` uses to take
+/// screenshots and to report results once all tests have run.
+///
+/// Results are written to `results.json` in the output directory, alongside screenshots, and then
+/// `on_finished` is called with whether every test passed.
+pub struct IntegrationTestHandler {
+    output_dir: PathBuf,
+    device: ID3D11Device,
+    visual: Visual,
+    on_finished: Box<dyn Fn(bool)>,
+}
+
+impl IntegrationTestHandler {
+    pub fn new(
+        output_dir: PathBuf,
+        device: ID3D11Device,
+        visual: Visual,
+        on_finished: impl Fn(bool) + 'static,
+    ) -> IntegrationTestHandler {
+        IntegrationTestHandler {
+            output_dir,
+            device,
+            visual,
+            on_finished: Box::new(on_finished),
+        }
+    }
+
+    fn capture_screenshot(&self, name: &str) -> eyre::Result<Vec<u8>> {
+        let png = screenshot::capture_frame(&self.device, &self.visual)?.encode_png()?;
+
+        fs::create_dir_all(&self.output_dir)?;
+        fs::write(self.output_dir.join(format!("{name}.png")), &png)?;
+
+        Ok(png)
+    }
+
+    fn write_results(&self, results: &Map<String, Value>, passed: bool) -> eyre::Result<()> {
+        fs::create_dir_all(&self.output_dir)?;
+        fs::write(
+            self.output_dir.join("results.json"),
+            serde_json::to_vec_pretty(&json!({
+                "passed": passed,
+                "results": results,
+            }))?,
+        )?;
+        Ok(())
+    }
+}
+
+impl StandardMethodHandler for IntegrationTestHandler {
+    fn handle(&self, method: &str, args: EncodableValue, reply: StandardMethodReply) {
+        match method {
+            "allTestsFinished" => {
+                let Some(EncodableValue::Map(results)) = args.get("results") else {
+                    return reply.error("invalid_args", Some("expected results"));
+                };
+
+                let mut passed = true;
+                let mut report = Map::new();
+
+                for (name, result) in results {
+                    let Some(name) = name.as_string() else {
+                        continue;
+                    };
+
+                    let result = result.as_string().unwrap_or("unknown failure");
+                    if result == SUCCESS {
+                        tracing::info!(test = name, "passed");
+                    } else {
+                        tracing::error!(test = name, "failed: {result}");
+                        passed = false;
+                    }
+
+                    report.insert(name.to_owned(), Value::from(result));
+                }
+
+                if let Err(e) = self.write_results(&report, passed) {
+                    tracing::error!("failed to write test results: {e:?}");
+                    passed = false;
+                }
+
+                reply.success(&EncodableValue::Null);
+
+                (self.on_finished)(passed);
+            }
+            "captureScreenshot" => {
+                let Some(name) = args.get("name").and_then(|v| v.as_string()) else {
+                    return reply.error("invalid_args", Some("expected a screenshot name"));
+                };
+
+                match self.capture_screenshot(name) {
+                    Ok(png) => reply.success(&EncodableValue::U8List(&png)),
+                    Err(e) => reply.error("capture_failed", Some(&format!("{e:?}"))),
+                }
+            }
+            // These are only needed on Android, where the Flutter surface has to be converted to
+            // an image before it can be captured.
+            "convertFlutterSurfaceToImage" | "revertFlutterImage" => {
+                reply.success(&EncodableValue::Null)
+            }
+            _ => {
+                tracing::warn!(method, "unimplemented");
+                reply.not_implemented();
+            }
+        }
+    }
+}
@@ -1,24 +1,51 @@
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
 use std::rc::Rc;
 
 use bitflags::bitflags;
 use color_eyre::eyre::{self, Context};
 use serde::{Deserialize, Serialize};
+use smol_str::SmolStr;
+use windows::Win32::UI::Input::KeyboardAndMouse::{
+    GetKeyState, GetKeyboardState, MapVirtualKeyW, MAPVK_VSC_TO_VK_EX, VIRTUAL_KEY, VK_CAPITAL,
+    VK_LCONTROL, VK_LMENU, VK_LSHIFT, VK_LWIN, VK_NUMLOCK, VK_RCONTROL, VK_RMENU, VK_RSHIFT,
+    VK_RWIN, VK_SCROLL,
+};
 use winit::event::{ElementState, Modifiers};
-use winit::keyboard::{Key, NamedKey};
+use winit::keyboard::{Key, KeyCode, NamedKey, PhysicalKey};
 use winit::platform::scancode::PhysicalKeyExtScancode;
 
 use crate::engine::{FlutterEngine, KeyEvent, KeyEventType};
 use crate::error_utils::ResultExt;
 use crate::keymap;
 use crate::text_input::TextInputState;
+use crate::undo_manager::UndoManager;
 
 pub struct Keyboard {
     engine: Rc<FlutterEngine>,
     text_input: Rc<RefCell<TextInputState>>,
+    undo_manager: UndoManager,
     modifiers: ModifierState,
+    /// Whether AltGr is held, which Windows reports as Ctrl+Alt.
+    alt_graph: bool,
+    /// Physical keys that the framework has been told are down, with the logical keys that they
+    /// were pressed as.
+    pressed: BTreeMap<u64, u64>,
 }
 
+/// Modifiers that are pressed when the window is focused are reported to the framework, since
+/// they may have been pressed (e.g. with Alt+Tab) while another window was focused.
+const MODIFIER_KEYS: &[(VIRTUAL_KEY, KeyCode, NamedKey)] = &[
+    (VK_LSHIFT, KeyCode::ShiftLeft, NamedKey::Shift),
+    (VK_RSHIFT, KeyCode::ShiftRight, NamedKey::Shift),
+    (VK_LCONTROL, KeyCode::ControlLeft, NamedKey::Control),
+    (VK_RCONTROL, KeyCode::ControlRight, NamedKey::Control),
+    (VK_LMENU, KeyCode::AltLeft, NamedKey::Alt),
+    (VK_RMENU, KeyCode::AltRight, NamedKey::Alt),
+    (VK_LWIN, KeyCode::SuperLeft, NamedKey::Super),
+    (VK_RWIN, KeyCode::SuperRight, NamedKey::Super),
+];
+
 bitflags! {
     #[derive(Clone, Copy, Default, Debug)]
     struct ModifierState: u32 {
@@ -40,64 +67,153 @@ bitflags! {
 }
 
 impl Keyboard {
-    pub fn new(engine: Rc<FlutterEngine>, text_input: Rc<RefCell<TextInputState>>) -> Keyboard {
+    pub fn new(
+        engine: Rc<FlutterEngine>,
+        text_input: Rc<RefCell<TextInputState>>,
+        undo_manager: UndoManager,
+    ) -> Keyboard {
         Keyboard {
             engine,
             text_input,
+            undo_manager,
             modifiers: ModifierState::default(),
+            alt_graph: false,
+            pressed: BTreeMap::new(),
         }
     }
 
+    /// Sends a key event to the framework, then to the text input and undo manager if the
+    /// framework doesn't handle it. `on_handled` is called with whether anything handled it.
     pub fn handle_keyboard_input(
         &mut self,
         event: winit::event::KeyEvent,
         is_synthetic: bool,
+        on_handled: impl FnOnce(bool) + 'static,
     ) -> eyre::Result<()> {
-        if let Key::Named(key) = event.logical_key {
-            match key {
-                NamedKey::CapsLock => {
-                    self.modifiers
-                        .set(ModifierState::CAPS_LOCK, event.state.is_pressed());
-                }
-                NamedKey::NumLock => {
-                    self.modifiers
-                        .set(ModifierState::NUM_LOCK, event.state.is_pressed());
-                }
-                NamedKey::ScrollLock => {
-                    self.modifiers
-                        .set(ModifierState::SCROLL_LOCK, event.state.is_pressed());
+        if let Some(physical) = event.physical_key.to_scancode().map(u64::from) {
+            let was_pressed = self.pressed.contains_key(&physical);
+
+            // winit also synthesizes events for keys that changed while the window wasn't
+            // focused, which may already have been reconciled by `handle_focus_gained`.
+            if is_synthetic && was_pressed == event.state.is_pressed() {
+                return Ok(());
+            }
+
+            if event.state.is_pressed() {
+                if let Some(logical) = keymap::to_flutter(&event.logical_key) {
+                    self.pressed.insert(physical, logical);
                 }
-                _ => {}
+            } else {
+                self.pressed.remove(&physical);
             }
         }
 
+        if let PhysicalKey::Code(code) = event.physical_key {
+            let side = match code {
+                KeyCode::ShiftLeft => ModifierState::SHIFT_LEFT,
+                KeyCode::ShiftRight => ModifierState::SHIFT_RIGHT,
+                KeyCode::ControlLeft => ModifierState::CONTROL_LEFT,
+                KeyCode::ControlRight => ModifierState::CONTROL_RIGHT,
+                KeyCode::AltLeft => ModifierState::ALT_LEFT,
+                KeyCode::AltRight => ModifierState::ALT_RIGHT,
+                KeyCode::SuperLeft => ModifierState::WIN_LEFT,
+                KeyCode::SuperRight => ModifierState::WIN_RIGHT,
+                _ => ModifierState::empty(),
+            };
+
+            self.modifiers.set(side, event.state.is_pressed());
+        }
+
+        if event.logical_key == Key::Named(NamedKey::AltGraph) {
+            self.alt_graph = event.state.is_pressed();
+        }
+
+        // Lock keys are reported by whether they are toggled on, not whether they are held down,
+        // which can change while the window isn't focused.
+        for (flag, key) in [
+            (ModifierState::CAPS_LOCK, VK_CAPITAL),
+            (ModifierState::NUM_LOCK, VK_NUMLOCK),
+            (ModifierState::SCROLL_LOCK, VK_SCROLL),
+        ] {
+            self.modifiers.set(flag, is_toggled(key));
+        }
+
+        // Characters typed with AltGr aren't shortcuts, so the Ctrl+Alt that Windows reports for it
+        // is hidden from the framework.
+        let modifiers = if self.alt_graph {
+            self.modifiers
+                - (ModifierState::CONTROL
+                    | ModifierState::CONTROL_LEFT
+                    | ModifierState::ALT
+                    | ModifierState::ALT_RIGHT)
+        } else {
+            self.modifiers
+        };
+
+        // Each handler passes the event on to the next one if it doesn't handle it, so this is
+        // called by whichever one is last to see it.
+        let on_handled = {
+            let on_handled = Rc::new(Cell::new(Some(on_handled)));
+            move |handled: bool| {
+                if let Some(on_handled) = on_handled.take() {
+                    on_handled(handled);
+                }
+            }
+        };
+
         let process_text_input = {
             let engine = self.engine.clone();
             let text_input = self.text_input.clone();
+            let undo_manager = self.undo_manager.clone();
+            let on_handled = on_handled.clone();
             move |event: winit::event::KeyEvent| {
+                if event.state.is_pressed() {
+                    let is_shortcut = undo_manager
+                        .handle_shortcut(
+                            &event.logical_key,
+                            modifiers.contains(ModifierState::CONTROL),
+                            modifiers.contains(ModifierState::SHIFT),
+                        )
+                        .trace_err()
+                        .unwrap_or(false);
+
+                    if is_shortcut {
+                        return on_handled(true);
+                    }
+                }
+
                 let mut text_input = text_input.borrow_mut();
                 let _ = text_input
                     .process_key_event(&event, &engine)
                     .wrap_err("text input plugin failed to process key event")
                     .trace_err();
+
+                on_handled(false);
             }
         };
 
         let send_channel = {
             let engine = self.engine.clone();
-            let modifiers = self.modifiers;
+            let on_handled = on_handled.clone();
             move |event: winit::event::KeyEvent| {
-                let _ = send_channel_key_event(&engine, event, modifiers, process_text_input)
-                    .trace_err();
+                let _ = send_channel_key_event(
+                    &engine,
+                    event,
+                    modifiers,
+                    process_text_input,
+                    on_handled,
+                )
+                .trace_err();
             }
         };
 
         let send_embedder = {
             let engine = self.engine.clone();
             move |event: winit::event::KeyEvent| {
-                let _ = send_embedder_key_event(&engine, event, is_synthetic, send_channel)
-                    .wrap_err("failed to send embedder key event")
-                    .trace_err();
+                let _ =
+                    send_embedder_key_event(&engine, event, is_synthetic, send_channel, on_handled)
+                        .wrap_err("failed to send embedder key event")
+                        .trace_err();
             }
         };
 
@@ -106,35 +222,129 @@ impl Keyboard {
         Ok(())
     }
 
+    /// Brings the framework's set of pressed keys up to date with the keyboard, since keys can be
+    /// pressed or released while the window isn't focused.
+    pub fn handle_focus_gained(&mut self) -> eyre::Result<()> {
+        let mut state = [0u8; 256];
+        unsafe { GetKeyboardState(&mut state)? };
+
+        let is_down = |vk: u32| vk != 0 && vk < 256 && state[vk as usize] & 0x80 != 0;
+
+        let released = self
+            .pressed
+            .iter()
+            .filter(|(&physical, _)| {
+                !is_down(unsafe { MapVirtualKeyW(physical as u32, MAPVK_VSC_TO_VK_EX) })
+            })
+            .map(|(&physical, &logical)| (physical, logical))
+            .collect::<Vec<_>>();
+
+        for (physical, logical) in released {
+            self.pressed.remove(&physical);
+            self.send_synthesized(KeyEventType::Up, physical, logical)?;
+        }
+
+        for &(vk, code, key) in MODIFIER_KEYS {
+            let Some(physical) = PhysicalKey::Code(code).to_scancode().map(u64::from) else {
+                continue;
+            };
+
+            let Some(logical) = keymap::to_flutter(&Key::Named(key)) else {
+                continue;
+            };
+
+            if is_down(vk.0 as u32) && !self.pressed.contains_key(&physical) {
+                self.pressed.insert(physical, logical);
+                self.send_synthesized(KeyEventType::Down, physical, logical)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn send_synthesized(
+        &self,
+        event_type: KeyEventType,
+        physical: u64,
+        logical: u64,
+    ) -> eyre::Result<()> {
+        self.engine.send_key_event(
+            KeyEvent {
+                event_type,
+                synthesized: true,
+                character: None,
+                logical: Some(logical),
+                physical: Some(physical),
+            },
+            |_| {},
+        )?;
+
+        Ok(())
+    }
+
     pub fn handle_modifiers_changed(&mut self, modifiers: Modifiers) -> eyre::Result<()> {
-        self.modifiers
-            .set(ModifierState::SHIFT, modifiers.state().shift_key());
-        self.modifiers
-            .set(ModifierState::CONTROL, modifiers.state().control_key());
-        self.modifiers
-            .set(ModifierState::ALT, modifiers.state().alt_key());
+        let state = modifiers.state();
+
+        // The side that is held down is tracked from key events, but is cleared here too in case
+        // a key was released while the window wasn't focused.
+        for (pressed, flag, sides) in [
+            (
+                state.shift_key(),
+                ModifierState::SHIFT,
+                ModifierState::SHIFT_LEFT | ModifierState::SHIFT_RIGHT,
+            ),
+            (
+                state.control_key(),
+                ModifierState::CONTROL,
+                ModifierState::CONTROL_LEFT | ModifierState::CONTROL_RIGHT,
+            ),
+            (
+                state.alt_key(),
+                ModifierState::ALT,
+                ModifierState::ALT_LEFT | ModifierState::ALT_RIGHT,
+            ),
+            (
+                state.super_key(),
+                ModifierState::empty(),
+                ModifierState::WIN_LEFT | ModifierState::WIN_RIGHT,
+            ),
+        ] {
+            self.modifiers.set(flag, pressed);
+            if !pressed {
+                self.modifiers.remove(sides);
+            }
+        }
+
         Ok(())
     }
 }
 
+/// Returns the text typed by a key press, if any. This is composed with a preceding dead key (e.g.
+/// `´` then `e` types `é`) and includes AltGr combinations, following the rules of `ToUnicodeEx`.
+/// Control characters typed with Ctrl are excluded, since they are shortcuts.
+pub fn typed_text(event: &winit::event::KeyEvent) -> Option<&SmolStr> {
+    if !event.state.is_pressed() || matches!(event.logical_key, Key::Dead(_)) {
+        return None;
+    }
+
+    event
+        .text
+        .as_ref()
+        .filter(|text| !text.is_empty() && !text.chars().any(char::is_control))
+}
+
+fn is_toggled(key: VIRTUAL_KEY) -> bool {
+    unsafe { GetKeyState(key.0 as i32) & 1 != 0 }
+}
+
 fn send_embedder_key_event(
     engine: &FlutterEngine,
     event: winit::event::KeyEvent,
     is_synthetic: bool,
     next_handler: impl FnOnce(winit::event::KeyEvent) + 'static,
+    on_handled: impl FnOnce(bool) + 'static,
 ) -> eyre::Result<()> {
-    let character = match &event.logical_key {
-        Key::Named(_) => None,
-        Key::Character(c) => {
-            if event.state == ElementState::Released {
-                None
-            } else {
-                Some(c.clone())
-            }
-        }
-        Key::Unidentified(_) => None,
-        Key::Dead(_) => None,
-    };
+    let character = typed_text(&event).cloned();
 
     let key_event = KeyEvent {
         event_type: match event.state {
@@ -149,10 +359,14 @@ fn send_embedder_key_event(
     };
 
     engine.send_key_event(key_event, move |handled| {
-        if !handled {
+        if handled {
+            on_handled(true);
+        } else {
             next_handler(event);
         }
-    })
+    })?;
+
+    Ok(())
 }
 
 fn send_channel_key_event(
@@ -160,6 +374,7 @@ fn send_channel_key_event(
     event: winit::event::KeyEvent,
     modifiers: ModifierState,
     next_handler: impl FnOnce(winit::event::KeyEvent) + 'static,
+    on_handled: impl FnOnce(bool) + 'static,
 ) -> eyre::Result<()> {
     #[derive(Serialize)]
     #[serde(rename_all = "camelCase")]
@@ -178,18 +393,9 @@ fn send_channel_key_event(
         handled: bool,
     }
 
-    let character = match &event.logical_key {
-        Key::Character(c) => {
-            if (1..=4).contains(&c.len()) {
-                let mut bytes = [0u8; 4];
-                bytes[..c.len()].copy_from_slice(c.as_bytes());
-                Some(u32::from_le_bytes(bytes) as u64)
-            } else {
-                None
-            }
-        }
-        _ => None,
-    };
+    let character = typed_text(&event)
+        .and_then(|text| text.chars().next())
+        .map(|c| c as u64);
 
     let message = Message {
         keymap: "windows",
@@ -211,14 +417,16 @@ fn send_channel_key_event(
                 .wrap_err("invalid response from flutter/keyevent")
                 .trace_err()
             else {
-                return;
+                return on_handled(false);
             };
 
             if response.handled {
-                return;
+                return on_handled(true);
             }
 
             next_handler(event);
         },
-    )
+    )?;
+
+    Ok(())
 }
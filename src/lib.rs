@@ -0,0 +1,63 @@
+//! The embedder as a library (`flion.dll`), with a C API for hosting a Flutter view in an existing
+//! Win32 application. See `include/flion.h`.
+//!
+//! [`FlionEngine`] is also usable from Rust, which is how `flion-test` drives the engine.
+//!
+//! The engine, compositor and channel modules are public so that the `fluyt` binary can use them,
+//! but they aren't part of the library's API.
+
+#![feature(lint_reasons)]
+
+#[doc(hidden)]
+pub mod channel_log;
+#[doc(hidden)]
+pub mod commit_batcher;
+#[doc(hidden)]
+pub mod compositor;
+#[doc(hidden)]
+pub mod d3d;
+#[doc(hidden)]
+pub mod dart_log;
+#[doc(hidden)]
+pub mod egl_manager;
+mod embedding;
+#[doc(hidden)]
+pub mod engine;
+#[doc(hidden)]
+pub mod engine_library;
+#[doc(hidden)]
+pub mod error;
+#[doc(hidden)]
+pub mod error_utils;
+#[doc(hidden)]
+pub mod flight_recorder;
+#[doc(hidden)]
+pub mod frame_stats;
+#[doc(hidden)]
+pub mod input_recording;
+#[doc(hidden)]
+pub mod navigation;
+#[doc(hidden)]
+pub mod platform_views;
+#[doc(hidden)]
+pub mod resize_controller;
+#[doc(hidden)]
+pub mod screenshot;
+#[doc(hidden)]
+pub mod standard_method_channel;
+#[doc(hidden)]
+pub mod task_runner;
+#[doc(hidden)]
+pub mod texture_registry;
+#[doc(hidden)]
+pub mod timeline;
+#[doc(hidden)]
+pub mod vm_service;
+#[doc(hidden)]
+pub mod vsync_waiter;
+
+pub use compositor::{SurfaceFormat, VisualPlacement};
+pub use embedding::FlionEngine;
+pub use frame_stats::FrameTimings;
+pub use screenshot::Frame;
+pub use vsync_waiter::FramePacing;
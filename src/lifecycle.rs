@@ -0,0 +1,176 @@
+use std::cell::Cell;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use color_eyre::eyre;
+use windows::Win32::Foundation::{HWND, WPARAM};
+use windows::Win32::System::RemoteDesktop::{
+    WTSRegisterSessionNotification, WTSUnRegisterSessionNotification, NOTIFY_FOR_THIS_SESSION,
+};
+use windows::Win32::UI::WindowsAndMessaging::{
+    PBT_APMRESUMEAUTOMATIC, PBT_APMRESUMESUSPEND, PBT_APMSUSPEND, WTS_SESSION_LOCK,
+    WTS_SESSION_UNLOCK,
+};
+
+use crate::engine::FlutterEngine;
+use crate::error_utils::ResultExt;
+use crate::vsync_waiter::VsyncWaiter;
+
+/// The states of `AppLifecycleState` in `dart:ui`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum LifecycleState {
+    Resumed,
+    Inactive,
+    Hidden,
+    Paused,
+}
+
+impl LifecycleState {
+    fn name(self) -> &'static str {
+        match self {
+            LifecycleState::Resumed => "AppLifecycleState.resumed",
+            LifecycleState::Inactive => "AppLifecycleState.inactive",
+            LifecycleState::Hidden => "AppLifecycleState.hidden",
+            LifecycleState::Paused => "AppLifecycleState.paused",
+        }
+    }
+}
+
+/// Reports the app's lifecycle state to the framework over `flutter/lifecycle`, from the window's
+/// focus and visibility, whether the session is locked and whether the system is going to sleep.
+///
+/// Rendering is paused while the session is locked or the system is asleep. The D3D device can be
+/// lost while the system sleeps without anything failing until the next frame, so it is checked
+/// when the system resumes, which avoids a black window after waking.
+#[derive(Clone)]
+pub struct Lifecycle {
+    inner: Rc<Inner>,
+}
+
+struct Inner {
+    hwnd: HWND,
+    vsync_waiter: Arc<VsyncWaiter>,
+    focused: Cell<bool>,
+    minimized: Cell<bool>,
+    locked: Cell<bool>,
+    suspended: Cell<bool>,
+    /// The state that was last sent to the framework.
+    sent: Cell<Option<LifecycleState>>,
+    on_resumed: Box<dyn Fn()>,
+}
+
+impl Lifecycle {
+    /// `on_resumed` is called when the system wakes from sleep, and should check that the D3D
+    /// device is still usable.
+    pub fn new(
+        hwnd: HWND,
+        vsync_waiter: Arc<VsyncWaiter>,
+        on_resumed: impl Fn() + 'static,
+    ) -> eyre::Result<Lifecycle> {
+        unsafe { WTSRegisterSessionNotification(hwnd, NOTIFY_FOR_THIS_SESSION)? };
+
+        Ok(Lifecycle {
+            inner: Rc::new(Inner {
+                hwnd,
+                vsync_waiter,
+                focused: Cell::new(true),
+                minimized: Cell::new(false),
+                locked: Cell::new(false),
+                suspended: Cell::new(false),
+                sent: Cell::new(None),
+                on_resumed: Box::new(on_resumed),
+            }),
+        })
+    }
+
+    fn state(&self) -> LifecycleState {
+        let inner = &self.inner;
+        if inner.suspended.get() || inner.locked.get() {
+            LifecycleState::Paused
+        } else if inner.minimized.get() {
+            LifecycleState::Hidden
+        } else if !inner.focused.get() {
+            LifecycleState::Inactive
+        } else {
+            LifecycleState::Resumed
+        }
+    }
+
+    /// Sends the current state, e.g. after the engine has been (re)launched.
+    pub fn send_current(&self, engine: &FlutterEngine) -> eyre::Result<()> {
+        let state = self.state();
+        self.inner.sent.set(Some(state));
+        engine.send_platform_message(c"flutter/lifecycle", state.name().as_bytes())?;
+        Ok(())
+    }
+
+    fn update(&self, engine: &FlutterEngine) {
+        let inner = &self.inner;
+        inner
+            .vsync_waiter
+            .set_suspended(inner.suspended.get() || inner.locked.get());
+
+        if inner.sent.get() != Some(self.state()) {
+            let _ = self.send_current(engine).trace_err();
+        }
+    }
+
+    /// Should be called on `WM_SETFOCUS` and `WM_KILLFOCUS`.
+    pub fn handle_focus_changed(&self, engine: &FlutterEngine, focused: bool) {
+        self.inner.focused.set(focused);
+        self.update(engine);
+    }
+
+    /// Should be called on `WM_SIZE`.
+    pub fn handle_minimized_changed(&self, engine: &FlutterEngine, minimized: bool) {
+        self.inner.minimized.set(minimized);
+        self.update(engine);
+    }
+
+    /// Handles `WM_WTSSESSION_CHANGE`.
+    pub fn handle_session_change(&self, engine: &FlutterEngine, wparam: WPARAM) {
+        match wparam.0 as u32 {
+            WTS_SESSION_LOCK => {
+                tracing::info!("session locked");
+                self.inner.locked.set(true);
+            }
+            WTS_SESSION_UNLOCK => {
+                tracing::info!("session unlocked");
+                self.inner.locked.set(false);
+            }
+            _ => return,
+        }
+
+        self.update(engine);
+    }
+
+    /// Handles `WM_POWERBROADCAST`.
+    pub fn handle_power_broadcast(&self, engine: &FlutterEngine, wparam: WPARAM) {
+        match wparam.0 as u32 {
+            PBT_APMSUSPEND => {
+                tracing::info!("system is suspending");
+                self.inner.suspended.set(true);
+                self.update(engine);
+            }
+            // Automatic resumes are always sent. Resumes caused by the user are followed by
+            // another message, which is ignored unless the first one was missed.
+            PBT_APMRESUMEAUTOMATIC | PBT_APMRESUMESUSPEND => {
+                if !self.inner.suspended.replace(false) {
+                    return;
+                }
+
+                tracing::info!("system resumed");
+                (self.inner.on_resumed)();
+                self.update(engine);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        // This fails if the window has already been destroyed, which unregisters it anyway.
+        let _ = unsafe { WTSUnRegisterSessionNotification(self.hwnd) };
+    }
+}
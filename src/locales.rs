@@ -0,0 +1,101 @@
+use std::ffi::CString;
+use std::{mem, ptr};
+
+use color_eyre::eyre;
+use flutter_embedder::FlutterLocale;
+use windows::core::PWSTR;
+use windows::Win32::Globalization::{GetUserPreferredUILanguages, MUI_LANGUAGE_NAME};
+
+use crate::engine::FlutterEngine;
+
+/// A locale parsed from a BCP 47 language tag such as `en-US` or `zh-Hans-CN`.
+struct Locale {
+    language: CString,
+    country: Option<CString>,
+    script: Option<CString>,
+}
+
+impl Locale {
+    fn parse(tag: &str) -> Option<Locale> {
+        let mut parts = tag.split('-');
+
+        let language = parts.next().filter(|s| !s.is_empty())?;
+        let mut script = None;
+        let mut country = None;
+
+        for part in parts {
+            if part.len() == 4 && script.is_none() && country.is_none() {
+                script = Some(part);
+            } else if (part.len() == 2 || part.len() == 3) && country.is_none() {
+                country = Some(part);
+            }
+        }
+
+        Some(Locale {
+            language: CString::new(language).ok()?,
+            country: country.and_then(|s| CString::new(s).ok()),
+            script: script.and_then(|s| CString::new(s).ok()),
+        })
+    }
+
+    fn as_flutter_locale(&self) -> FlutterLocale {
+        FlutterLocale {
+            struct_size: mem::size_of::<FlutterLocale>(),
+            language_code: self.language.as_ptr(),
+            country_code: self.country.as_ref().map_or(ptr::null(), |s| s.as_ptr()),
+            script_code: self.script.as_ref().map_or(ptr::null(), |s| s.as_ptr()),
+            variant_code: ptr::null(),
+        }
+    }
+}
+
+/// Returns the user's preferred UI languages, most preferred first.
+fn preferred_languages() -> eyre::Result<Vec<String>> {
+    let mut count = 0;
+    let mut len = 0;
+
+    unsafe {
+        GetUserPreferredUILanguages(MUI_LANGUAGE_NAME, &mut count, PWSTR::null(), &mut len).ok()?;
+    }
+
+    let mut buf = vec![0u16; len as usize];
+
+    unsafe {
+        GetUserPreferredUILanguages(
+            MUI_LANGUAGE_NAME,
+            &mut count,
+            PWSTR(buf.as_mut_ptr()),
+            &mut len,
+        )
+        .ok()?;
+    }
+
+    // The buffer contains a list of null terminated strings, terminated by an empty string.
+    Ok(buf
+        .split(|&c| c == 0)
+        .filter(|s| !s.is_empty())
+        .map(String::from_utf16_lossy)
+        .collect())
+}
+
+/// Sends the user's preferred locales to the engine.
+pub fn send_to_engine(engine: &FlutterEngine) -> eyre::Result<()> {
+    let locales = preferred_languages()?
+        .iter()
+        .filter_map(|tag| Locale::parse(tag))
+        .collect::<Vec<_>>();
+
+    if locales.is_empty() {
+        tracing::warn!("no preferred ui languages found");
+        return Ok(());
+    }
+
+    let flutter_locales = locales
+        .iter()
+        .map(Locale::as_flutter_locale)
+        .collect::<Vec<_>>();
+
+    engine.update_locales(&flutter_locales)?;
+
+    Ok(())
+}
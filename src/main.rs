@@ -1,76 +1,315 @@
 #![feature(lint_reasons)]
 
-mod compositor;
-mod egl_manager;
-mod engine;
-mod error_utils;
+mod app_exit;
+mod bundle;
+mod cli;
+mod clipboard;
+mod cursor_grab;
+mod dart_ffi;
+mod deep_link;
+mod device_loss;
+mod displays;
+mod drag_drop;
+mod event_channel;
+mod file_dialog;
+mod frame_timings;
+mod hot_reload;
+mod hotkeys;
+mod integration_test;
 mod keyboard;
 mod keymap;
+mod lifecycle;
+mod locales;
+mod modal_loop;
 mod mouse_cursor;
-mod resize_controller;
+mod native_port;
+mod notifications;
+mod path_provider;
+mod paths;
+mod perf_overlay;
+mod platform;
+mod platform_menu;
+mod plugin_compat;
+mod plugin_registrar;
+mod pointer;
+mod power;
+mod raw_input;
+mod registry;
+mod restoration;
+mod screen_capture;
+mod semantics;
 mod settings;
-mod standard_method_channel;
-mod task_runner;
+mod shared_preferences;
+mod size_constraints;
+mod splash;
+mod system_keys;
+mod taskbar;
 mod text_input;
+mod touch_keyboard;
+mod uia;
+mod undo_manager;
+mod url_launcher;
+mod video;
+mod webview;
+mod window_control;
+mod window_effects;
+mod window_events;
+mod window_placement;
 
 use std::cell::{Cell, RefCell};
-use std::ffi::c_void;
-use std::mem;
+use std::ffi::CString;
+use std::path::PathBuf;
 use std::rc::Rc;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::{fs, mem};
 
+use clap::Parser;
 use color_eyre::eyre::OptionExt;
-use color_eyre::Result;
+use color_eyre::{eyre, Result};
+use flion::{
+    channel_log, commit_batcher, compositor, d3d, dart_log, egl_manager, engine, engine_library,
+    error, error_utils, flight_recorder, frame_stats, input_recording, navigation, platform_views,
+    resize_controller, screenshot, standard_method_channel, task_runner, texture_registry,
+    timeline, vm_service, vsync_waiter,
+};
 use raw_window_handle::{HasWindowHandle, RawWindowHandle};
 use resize_controller::ResizeController;
 use task_runner::Task;
-use windows::core::ComInterface;
+use windows::core::{w, ComInterface, HSTRING, PCWSTR};
 use windows::Foundation::Numerics::{Matrix4x4, Vector2, Vector3};
 use windows::Win32::Foundation::{HWND, LPARAM, LRESULT, RECT, WPARAM};
-use windows::Win32::Graphics::Direct3D::D3D_DRIVER_TYPE_HARDWARE;
-use windows::Win32::Graphics::Direct3D11::{
-    D3D11CreateDevice, D3D11_CREATE_DEVICE_FLAG, D3D11_SDK_VERSION,
-};
-use windows::Win32::Graphics::Dwm::{
-    DwmSetWindowAttribute, DWMSBT_MAINWINDOW, DWMWA_SYSTEMBACKDROP_TYPE, DWM_SYSTEMBACKDROP_TYPE,
-};
+use windows::Win32::System::Ole::OleInitialize;
 use windows::Win32::System::WinRT::Composition::ICompositorDesktopInterop;
 use windows::Win32::System::WinRT::{
     CreateDispatcherQueueController, DispatcherQueueOptions, DQTAT_COM_ASTA, DQTYPE_THREAD_CURRENT,
 };
-use windows::Win32::UI::Shell::{DefSubclassProc, SetWindowSubclass};
-use windows::Win32::UI::WindowsAndMessaging::WM_NCCALCSIZE;
+use windows::Win32::UI::HiDpi::GetDpiForWindow;
+use windows::Win32::UI::Shell::{DefSubclassProc, RemoveWindowSubclass, SetWindowSubclass};
+use windows::Win32::UI::WindowsAndMessaging::{
+    GetClientRect, MessageBoxW, MB_ICONERROR, MB_OK, SIZE_MAXIMIZED, SIZE_MINIMIZED, SIZE_RESTORED,
+    SPI_SETCLIENTAREAANIMATION, SPI_SETHIGHCONTRAST, SPI_SETSCREENREADER,
+    SYSTEM_PARAMETERS_INFO_ACTION, WM_ACTIVATE, WM_CLIPBOARDUPDATE, WM_COMMAND, WM_COPYDATA,
+    WM_DISPLAYCHANGE, WM_DPICHANGED, WM_ENTERSIZEMOVE, WM_EXITSIZEMOVE, WM_GETMINMAXINFO,
+    WM_GETOBJECT, WM_HOTKEY, WM_KILLFOCUS, WM_LBUTTONDOWN, WM_MBUTTONDOWN, WM_NCCALCSIZE,
+    WM_NCDESTROY, WM_POINTERLEAVE, WM_POWERBROADCAST, WM_RBUTTONDOWN, WM_SETFOCUS,
+    WM_SETTINGCHANGE, WM_SIZE, WM_SIZING, WM_SYSCOMMAND, WM_TIMER, WM_WINDOWPOSCHANGED,
+    WM_WTSSESSION_CHANGE,
+};
 use windows::UI::Composition::ContainerVisual;
 use windows::UI::Composition::Core::CompositorController;
-use winit::dpi::{LogicalSize, PhysicalPosition, PhysicalSize};
-use winit::event::{ElementState, Event, WindowEvent};
-use winit::event_loop::{ControlFlow, EventLoopBuilder};
+use winit::dpi::{LogicalSize, PhysicalSize};
+use winit::event::{Event, WindowEvent};
+use winit::event_loop::{ControlFlow, DeviceEvents, EventLoopBuilder, EventLoopProxy};
+use winit::keyboard::{KeyCode, ModifiersState, PhysicalKey};
 use winit::platform::windows::WindowBuilderExtWindows;
-use winit::window::WindowBuilder;
+use winit::window::{Window, WindowBuilder};
 
-use crate::compositor::Compositor;
+use crate::app_exit::{AppExit, ExitType};
+use crate::cli::Args;
+use crate::clipboard::ClipboardHandler;
+use crate::commit_batcher::CommitBatcher;
+use crate::compositor::{Compositor, ContentAnchor};
+use crate::cursor_grab::{CursorGrab, CursorGrabHandler, CursorGrabMode};
+use crate::device_loss::DeviceLossHandler;
+use crate::displays::DisplayTracker;
+use crate::drag_drop::DragDropHandler;
 use crate::egl_manager::EglManager;
-use crate::engine::{FlutterEngine, FlutterEngineConfig, PointerPhase};
+use crate::engine::{BinaryMessageHandler, FlutterEngine, FlutterEngineConfig};
 use crate::error_utils::ResultExt;
+use crate::event_channel::EventChannel;
+use crate::file_dialog::FileDialogHandler;
+use crate::flight_recorder::EventKind;
+use crate::frame_stats::FrameTimings;
+use crate::frame_timings::FrameTimingsHandler;
+use crate::hotkeys::Hotkeys;
+use crate::input_recording::{InputRecorder, InputReplayer};
+use crate::integration_test::IntegrationTestHandler;
 use crate::keyboard::Keyboard;
+use crate::lifecycle::Lifecycle;
+use crate::modal_loop::{self, ModalLoopPump};
 use crate::mouse_cursor::MouseCursorHandler;
-use crate::task_runner::TaskRunnerExecutor;
+use crate::native_port::NativePortsHandler;
+use crate::navigation::NavigationHandler;
+use crate::notifications::{NotificationEvent, NotificationsHandler};
+use crate::path_provider::PathProviderHandler;
+use crate::perf_overlay::{PerfOverlay, PerfOverlayHandler};
+use crate::platform::PlatformHandler;
+use crate::platform_menu::PlatformMenuHandler;
+use crate::platform_views::{PlatformViewRegistry, PlatformViewsHandler};
+use crate::plugin_compat::PluginHost;
+use crate::pointer::Pointer;
+use crate::power::{PowerMonitor, PowerPolicy};
+use crate::raw_input::RawInput;
+use crate::restoration::Restoration;
+use crate::screen_capture::ScreenCaptureHandler;
+use crate::screenshot::ScreenshotHandler;
+use crate::semantics::SemanticsActivation;
+use crate::shared_preferences::SharedPreferencesHandler;
+use crate::size_constraints::SizeConstraints;
+use crate::splash::Splash;
+use crate::system_keys::{ShortcutPolicies, SystemKeys};
+use crate::task_runner::{TaskRunnerExecutor, ThreadConfig};
+use crate::taskbar::TaskbarHandler;
 use crate::text_input::{TextInputHandler, TextInputState};
+use crate::texture_registry::TextureRegistry;
+use crate::touch_keyboard::TouchKeyboard;
+use crate::uia::UiaTree;
+use crate::undo_manager::UndoManager;
+use crate::url_launcher::UrlLauncherHandler;
+use crate::video::VideoHandler;
+use crate::vm_service::{DevToolsHandler, VmServiceConfig};
+use crate::vsync_waiter::{FramePacing, VsyncWaiter};
+use crate::webview::{WebViewFactory, WebViewHandler, WebViews};
+use crate::window_control::{WindowControlHandler, WindowController};
+use crate::window_effects::{CornerPreference, WindowEffects, WindowEffectsHandler};
+use crate::window_events::WindowEvents;
 
 struct WindowData {
-    engine: *const engine::FlutterEngine,
+    engine: Rc<FlutterEngine>,
     resize_controller: Arc<ResizeController>,
+    /// Set while a resize is waiting for the engine to present a frame at the new size.
+    resizing: Cell<bool>,
+    /// Set once the subclass has been removed because the window was destroyed.
+    removed: Cell<bool>,
     scale_factor: Cell<f64>,
+    vsync_waiter: Arc<VsyncWaiter>,
     root_visual: ContainerVisual,
+    content_anchor: ContentAnchor,
+    deep_link_scheme: Option<String>,
+    window_controller: WindowController,
+    plugins: Rc<PluginHost>,
+    touch_keyboard: TouchKeyboard,
+    clipboard_events: Rc<EventChannel>,
+    window_effects: WindowEffects,
+    semantics: SemanticsActivation,
+    uia: UiaTree,
+    system_keys: SystemKeys,
+    displays: DisplayTracker,
+    lifecycle: Lifecycle,
+    power: PowerMonitor,
+    hotkeys: Hotkeys,
+    platform_menu: PlatformMenuHandler,
+    window_events: WindowEvents,
+    event_loop: EventLoopProxy<PlatformEvent>,
+}
+
+impl WindowData {
+    /// Resizes the root visual and sends new window metrics to the engine, for a client area of
+    /// the given size in physical pixels.
+    fn update_metrics(&self, width: i32, height: i32) -> Result<()> {
+        flight_recorder::record(EventKind::Resize, format!("{width}x{height}"));
+
+        self.root_visual
+            .SetSize(Vector2::new(width as f32, height as f32))?;
+
+        self.root_visual
+            .SetOffset(Vector3::new(0.0, height as f32, 0.0))?;
+
+        self.content_anchor.handle_view_resized(width, height)?;
+
+        // The metrics are sent again once the engine has been relaunched.
+        if self.engine.is_running() {
+            self.engine.send_window_metrics_event(
+                width as usize,
+                height as usize,
+                self.scale_factor.get(),
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+const SUBCLASS_ID: usize = 696969;
+
+/// Installs [`wnd_proc`] on a window until this is dropped, or the window is destroyed.
+///
+/// The data is reference counted, and each call to [`wnd_proc`] holds a reference, so that it
+/// isn't freed if the subclass is removed while a message is being handled (e.g. by a nested
+/// message loop).
+struct WindowSubclass {
+    hwnd: HWND,
+    data: *const WindowData,
+}
+
+impl WindowSubclass {
+    fn install(hwnd: HWND, data: WindowData) -> eyre::Result<WindowSubclass> {
+        let data = Rc::into_raw(Rc::new(data));
+        if !unsafe { SetWindowSubclass(hwnd, Some(wnd_proc), SUBCLASS_ID, data as usize) }.as_bool()
+        {
+            drop(unsafe { Rc::from_raw(data) });
+            eyre::bail!("failed to install window subclass");
+        }
+        Ok(WindowSubclass { hwnd, data })
+    }
+}
+
+impl Drop for WindowSubclass {
+    fn drop(&mut self) {
+        let removed = unsafe { (*self.data).removed.get() }
+            || unsafe { RemoveWindowSubclass(self.hwnd, Some(wnd_proc), SUBCLASS_ID) }.as_bool();
+
+        if removed {
+            drop(unsafe { Rc::from_raw(self.data) });
+        } else {
+            // The window proc could still be called with the data, so it has to be leaked.
+            tracing::error!("failed to remove window subclass");
+        }
+    }
 }
 
 #[derive(Debug)]
 enum PlatformEvent {
-    PostFlutterTask(Task),
+    /// Sent when tasks have been posted, or the task timer fires.
+    RunTasks,
+    FirstFrameRendered,
+    /// Restarts the engine, discarding all Dart state.
+    HotRestart,
+    /// Sent by the compositor when rendering has failed repeatedly.
+    RenderingFailed(String),
+    /// Sent by the compositor when the D3D device has been removed.
+    DeviceLost,
+    Notification(NotificationEvent),
+    /// Sent when an integration test run has finished, with whether all tests passed.
+    IntegrationTestFinished(bool),
+    /// Sent when a drag started with `flion/dragdrop` has finished.
+    DragFinished,
+    /// Sent when a touch or pen pointer leaves the window, with its pointer id.
+    PointerLeft(u64),
+    /// Sent once a frame has been presented.
+    FrameTimings(FrameTimings),
+    /// Sent when the framework asks for the app to exit, with the exit code.
+    ExitRequested(ExitType, i32),
+    /// Sent once the app should exit, after the framework has agreed to it.
+    Exit(i32),
 }
 
 fn main() -> Result<()> {
     color_eyre::install()?;
+    flight_recorder::install_panic_hook();
+
+    let args = Args::parse();
+
+    if let Some(scheme) = &args.protocol {
+        let _ = deep_link::register_scheme(scheme).trace_err();
+
+        // Let the running instance handle the link instead of starting a new one.
+        if let Some(uri) = &args.uri {
+            if deep_link::forward_to_running_instance(uri) {
+                return Ok(());
+            }
+        }
+    }
+
+    if let Some(path) = &args.trace_to_file {
+        timeline::trace_to_file(path)?;
+    }
+
+    let initial_route = args.route.clone().or_else(|| {
+        let scheme = args.protocol.as_ref()?;
+        deep_link::route_from_uri(scheme, args.uri.as_ref()?)
+    });
 
     #[cfg(debug_assertions)]
     {
@@ -82,10 +321,32 @@ fn main() -> Result<()> {
             .init();
     }
 
+    // Release builds don't log by default, so channel logging installs its own subscriber.
+    #[cfg(not(debug_assertions))]
+    if args.log_channels.is_some() {
+        tracing_subscriber::fmt()
+            .with_thread_names(true)
+            .with_max_level(tracing::Level::INFO)
+            .init();
+    }
+
+    if let Some(filter) = args.log_channels.clone() {
+        channel_log::enable(filter);
+    }
+
+    engine_library::load(args.engine.as_deref())?;
+
+    // Drag and drop is handled by our own drop target instead of winit's, which requires OLE to be
+    // initialized on this thread.
+    unsafe { OleInitialize(None)? };
+
     let event_loop = EventLoopBuilder::<PlatformEvent>::with_user_event().build()?;
     let window = WindowBuilder::new()
         .with_inner_size(LogicalSize::new(800, 600))
         .with_no_redirection_bitmap(true)
+        .with_drag_and_drop(false)
+        .with_visible(!args.wait_for_first_frame)
+        .with_class_name(deep_link::WINDOW_CLASS_NAME)
         .build(&event_loop)?;
 
     let hwnd = match window.window_handle()?.as_raw() {
@@ -93,14 +354,22 @@ fn main() -> Result<()> {
         _ => unreachable!(),
     };
 
-    unsafe {
-        DwmSetWindowAttribute(
-            hwnd,
-            DWMWA_SYSTEMBACKDROP_TYPE,
-            &DWMSBT_MAINWINDOW as *const DWM_SYSTEMBACKDROP_TYPE as *const c_void,
-            mem::size_of::<DWM_SYSTEMBACKDROP_TYPE>() as u32,
-        )
-    }?;
+    dart_ffi::set_window(hwnd);
+
+    let maximize_on_show = args.remember_window_placement
+        && window_placement::restore(hwnd).trace_err().unwrap_or(false);
+
+    let window_effects = WindowEffects::new(hwnd, args.backdrop);
+    window_effects.apply()?;
+
+    // These are only supported on Windows 11, and fail on earlier versions.
+    if args.corner_preference != CornerPreference::Default {
+        let _ = window_effects::set_corner_preference(hwnd, args.corner_preference).trace_err();
+    }
+
+    if let Some(color) = args.border_color {
+        let _ = window_effects::set_border_color(hwnd, color).trace_err();
+    }
 
     let PhysicalSize { width, height } = window.inner_size();
 
@@ -143,132 +412,637 @@ fn main() -> Result<()> {
 
     composition_target.SetRoot(&root)?;
 
-    let device = unsafe {
-        let mut device = Default::default();
-
-        D3D11CreateDevice(
-            None,
-            D3D_DRIVER_TYPE_HARDWARE,
-            None,
-            D3D11_CREATE_DEVICE_FLAG::default(),
-            None,
-            D3D11_SDK_VERSION,
-            Some(&mut device),
-            None,
-            None,
-        )?;
-
-        device.ok_or_eyre("failed to create D3D11 device")?
-    };
+    let device = d3d::create_device()?;
 
     let egl_manager = EglManager::create(&device)?;
-    let resize_controller = Arc::new(ResizeController::new());
+    let resize_controller = Arc::new(ResizeController::new(!args.merged_platform_ui_thread));
+    let frame_pacing = match args.max_fps {
+        _ if args.no_vsync => FramePacing::Uncapped,
+        Some(max_fps) => FramePacing::Capped(max_fps),
+        None => FramePacing::Vsync,
+    };
+
+    let vsync_waiter = VsyncWaiter::new(hwnd, resize_controller.clone(), frame_pacing)?;
 
     let window = Rc::new(window);
     let text_input = Rc::new(RefCell::new(TextInputState::new()));
+    let touch_keyboard = TouchKeyboard::new(hwnd);
+    let undo_manager = UndoManager::new();
+    let app_exit = AppExit::new();
+    let restoration = Restoration::load();
+    let drag_drop_events = Rc::new(EventChannel::new(c"flion/dragdrop"));
+    let window_controller = WindowController::new(
+        window.clone(),
+        hwnd,
+        SizeConstraints {
+            min_size: args.min_size,
+            max_size: args.max_size,
+            aspect_ratio: args.aspect_ratio,
+        },
+    );
+
+    if args.content_protection {
+        window_controller.set_content_protection(true)?;
+    }
+
+    let platform_views = PlatformViewRegistry::new();
+
+    let commits = CommitBatcher::new(compositor_controller.clone());
+
+    let mut compositor = Compositor::new(
+        device.clone(),
+        commits.clone(),
+        egl_manager.clone(),
+        resize_controller.clone(),
+        root.clone(),
+        platform_views.clone(),
+    )?;
+
+    compositor.set_surface_format(args.surface_format);
+    compositor.set_msaa_samples(args.msaa_samples);
+    compositor.set_resize_anchor(args.resize_anchor);
+
+    if let Some(color) = args.resize_letterbox_color {
+        compositor.set_resize_letterbox_color(color.0)?;
+    }
+
+    let content_anchor = compositor.content_anchor();
+
+    // The splash is inserted after the compositor's layer visual so that it is drawn on top.
+    let mut splash = if args.splash_color.is_some() || args.splash_image.is_some() {
+        Some(Splash::new(
+            &commits,
+            &device,
+            &root,
+            args.splash_color.map(|c| c.0).unwrap_or_default(),
+            args.splash_image.as_deref(),
+        )?)
+    } else {
+        None
+    };
+
+    let perf_overlay = Rc::new(RefCell::new(PerfOverlay::new(&commits, &device, &root)?));
+
+    if args.perf_overlay {
+        perf_overlay.borrow_mut().set_visible(true)?;
+    }
+
+    compositor.set_error_handler({
+        let event_loop = event_loop.create_proxy();
+        move |message| {
+            let _ = event_loop
+                .send_event(PlatformEvent::RenderingFailed(message))
+                .trace_err();
+        }
+    });
+
+    compositor.set_device_lost_handler({
+        let event_loop = event_loop.create_proxy();
+        move || {
+            let _ = event_loop.send_event(PlatformEvent::DeviceLost).trace_err();
+        }
+    });
+
+    let device_loss_check = compositor.device_loss_check();
+
+    if args.wait_for_first_frame || splash.is_some() {
+        let event_loop = event_loop.create_proxy();
+        compositor.set_first_frame_callback(move || {
+            let _ = event_loop
+                .send_event(PlatformEvent::FirstFrameRendered)
+                .trace_err();
+        });
+    }
+
+    let vm_service_config = VmServiceConfig {
+        enabled: !args.disable_vm_service || args.integration_test_output.is_some(),
+        host: args.vm_service_host.clone(),
+        port: args.vm_service_port,
+    };
+
+    task_runner::configure_threads(ThreadConfig {
+        worker_priority: args.worker_thread_priority,
+        ui_priority: args.ui_thread_priority,
+        raster_priority: args.raster_thread_priority,
+        raster_mmcss: args.raster_mmcss,
+    });
+
+    let webview_events = Rc::new(EventChannel::new(c"flion/webview/events"));
+    let notification_events = Rc::new(EventChannel::new(c"flion/notifications/events"));
+    let raw_input_events = Rc::new(EventChannel::new(c"flion/raw_input"));
+    let clipboard_events = Rc::new(EventChannel::new(c"flion/clipboard/events"));
+    let frame_timings_events = Rc::new(EventChannel::new(c"flion/frame_timings"));
+    let power_events = Rc::new(EventChannel::new(c"flion/power/events"));
+    let hotkey_events = Rc::new(EventChannel::new(c"flion/hotkeys/events"));
+    let hotkeys = Hotkeys::new(hwnd, hotkey_events.clone());
+    let window_events = Rc::new(EventChannel::new(c"flion/window_events"));
+    let device_loss = Rc::new(DeviceLossHandler::new());
+    let platform_menu = PlatformMenuHandler::new(hwnd);
+
+    let power = PowerMonitor::new(
+        hwnd,
+        vsync_waiter.clone(),
+        window_effects.clone(),
+        args.power_saving_max_fps
+            .map(|max_fps| PowerPolicy { max_fps }),
+        power_events.clone(),
+    )?;
+
+    let cursor_grab = CursorGrab::new(hwnd, window.clone());
+    let webviews = WebViews::new(hwnd, webview_events.clone());
+
+    let mut platform_views_handler =
+        PlatformViewsHandler::new(compositor_controller.Compositor()?, platform_views);
+    platform_views_handler.register_factory(
+        webview::VIEW_TYPE,
+        Box::new(WebViewFactory(webviews.clone())),
+    );
+
+    let mut platform_message_handlers: Vec<(&str, Box<dyn BinaryMessageHandler>)> = vec![
+        (
+            "flutter/mousecursor",
+            Box::new(MouseCursorHandler::new(window.clone())),
+        ),
+        (
+            "flutter/textinput",
+            Box::new(TextInputHandler::new(
+                text_input.clone(),
+                touch_keyboard.clone(),
+            )),
+        ),
+        (
+            "flutter/platform",
+            Box::new(PlatformHandler::new(
+                hwnd,
+                window.clone(),
+                text_input.clone(),
+                app_exit.clone(),
+                {
+                    let event_loop = event_loop.create_proxy();
+                    move |exit_type, exit_code| {
+                        let _ = event_loop
+                            .send_event(PlatformEvent::ExitRequested(exit_type, exit_code))
+                            .trace_err();
+                    }
+                },
+            )),
+        ),
+        ("flutter/navigation", Box::new(NavigationHandler)),
+        ("flutter/undomanager", Box::new(undo_manager.clone())),
+        ("flutter/restoration", Box::new(restoration.clone())),
+        (
+            "plugins.flutter.io/path_provider",
+            Box::new(PathProviderHandler),
+        ),
+        (
+            "plugins.flutter.io/url_launcher",
+            Box::new(UrlLauncherHandler::new(hwnd)),
+        ),
+        (
+            "plugins.flutter.io/shared_preferences",
+            Box::new(SharedPreferencesHandler::new()),
+        ),
+        (
+            "flion/window_effects",
+            Box::new(WindowEffectsHandler::new(window_effects.clone())),
+        ),
+        ("flion/clipboard", Box::new(ClipboardHandler::new(hwnd))),
+        ("flion/clipboard/events", Box::new(clipboard_events.clone())),
+        ("flion/native_ports", Box::new(NativePortsHandler)),
+        ("flion/power", Box::new(power.clone())),
+        ("flion/power/events", Box::new(power_events)),
+        ("flion/hotkeys", Box::new(hotkeys.clone())),
+        ("flion/hotkeys/events", Box::new(hotkey_events)),
+        ("flion/window_events", Box::new(window_events.clone())),
+        ("flion/device_loss", Box::new(device_loss.clone())),
+        (
+            "flion/frame_timings",
+            // Frame timings are reported from other threads, so they are sent to the channel from
+            // the event loop.
+            Box::new(FrameTimingsHandler::new(frame_timings_events.clone(), {
+                let event_loop = event_loop.create_proxy();
+                move |timings: &FrameTimings| {
+                    // Fails once the event loop has exited, while the engine is shutting down.
+                    let _ = event_loop.send_event(PlatformEvent::FrameTimings(*timings));
+                }
+            })),
+        ),
+        (
+            "flion/dragdrop",
+            Box::new(DragDropHandler::new(drag_drop_events.clone(), {
+                let event_loop = event_loop.create_proxy();
+                move || {
+                    let _ = event_loop
+                        .send_event(PlatformEvent::DragFinished)
+                        .trace_err();
+                }
+            })),
+        ),
+        ("flion/file_dialog", Box::new(FileDialogHandler::new(hwnd))),
+        ("flutter/menu", Box::new(platform_menu.clone())),
+        ("flion/taskbar", Box::new(TaskbarHandler::new(hwnd))),
+        (
+            "flion/window",
+            Box::new(WindowControlHandler::new(window_controller.clone())),
+        ),
+        ("flutter/platform_views", Box::new(platform_views_handler)),
+        ("flion/webview", Box::new(WebViewHandler(webviews))),
+        ("flion/webview/events", Box::new(webview_events)),
+        (
+            "flion/notifications",
+            Box::new(NotificationsHandler::new({
+                let event_loop = event_loop.create_proxy();
+                move |event| {
+                    let _ = event_loop
+                        .send_event(PlatformEvent::Notification(event))
+                        .trace_err();
+                }
+            })),
+        ),
+        (
+            "flion/notifications/events",
+            Box::new(notification_events.clone()),
+        ),
+        ("flion/raw_input", Box::new(raw_input_events.clone())),
+        (
+            "flion/cursor_grab",
+            Box::new(CursorGrabHandler::new(cursor_grab.clone())),
+        ),
+        (
+            "flion/screenshot",
+            Box::new(ScreenshotHandler::new(device.clone(), root.cast()?)),
+        ),
+        (
+            "flion/perf_overlay",
+            Box::new(PerfOverlayHandler(perf_overlay.clone())),
+        ),
+    ];
+
+    if vm_service_config.enabled {
+        platform_message_handlers.push(("flion/devtools", Box::new(DevToolsHandler)));
+    }
+
+    if let Some(output_dir) = &args.integration_test_output {
+        let event_loop = event_loop.create_proxy();
+        platform_message_handlers.push((
+            "plugins.flutter.io/integration_test",
+            Box::new(IntegrationTestHandler::new(
+                output_dir.clone(),
+                device.clone(),
+                root.cast()?,
+                move |passed| {
+                    let _ = event_loop
+                        .send_event(PlatformEvent::IntegrationTestFinished(passed))
+                        .trace_err();
+                },
+            )),
+        ));
+    }
+
+    let texture_registry = TextureRegistry::new();
+
+    match VideoHandler::new(
+        device.clone(),
+        egl_manager.clone(),
+        texture_registry.clone(),
+    ) {
+        Ok(handler) => platform_message_handlers.push(("flion/video", Box::new(handler))),
+        Err(e) => tracing::error!("video playback is unavailable: {e:?}"),
+    }
+
+    match ScreenCaptureHandler::new(&device, egl_manager.clone(), texture_registry.clone()) {
+        Ok(handler) => {
+            platform_message_handlers.push(("flion/screen_capture", Box::new(handler)));
+        }
+        Err(e) => tracing::error!("screen capture is unavailable: {e:?}"),
+    }
+
+    // Tasks are queued here rather than sent to the event loop, since winit holds on to events
+    // while a modal loop is running inside one of its callbacks.
+    let pending_tasks = Arc::new(Mutex::new(Vec::<Task>::new()));
 
     let engine = Rc::new(FlutterEngine::new(FlutterEngineConfig {
         egl_manager: egl_manager.clone(),
-        compositor: Compositor::new(
-            device,
-            compositor_controller,
-            egl_manager.clone(),
-            resize_controller.clone(),
-            root.clone(),
-        )?,
+        compositor,
+        vsync_waiter: vsync_waiter.clone(),
+        texture_registry: texture_registry.clone(),
         platform_task_handler: Box::new({
+            let pending_tasks = pending_tasks.clone();
             let event_loop = event_loop.create_proxy();
             move |task| {
-                if let Err(e) = event_loop.send_event(PlatformEvent::PostFlutterTask(task)) {
+                pending_tasks.lock().unwrap().push(task);
+                if let Err(e) = event_loop.send_event(PlatformEvent::RunTasks) {
                     tracing::error!("{e}");
                 }
             }
         }),
-        platform_message_handlers: vec![
-            (
-                "flutter/mousecursor",
-                Box::new(MouseCursorHandler::new(window.clone())),
-            ),
-            (
-                "flutter/textinput",
-                Box::new(TextInputHandler::new(text_input.clone())),
-            ),
-        ],
+        platform_message_handlers,
+        assets_path: bundle::assets_path()?,
+        icu_data_path: bundle::icu_data_path()?,
+        initial_route,
+        engine_switches: vm_service_config
+            .engine_switches()
+            .into_iter()
+            .chain(args.engine_switches.iter().cloned())
+            .collect(),
+        dart_flags: args.dart_flags.clone(),
+        merged_platform_ui_thread: args.merged_platform_ui_thread,
+        persistent_cache_path: persistent_cache_path(&args)?,
+        persistent_cache_read_only: args.persistent_cache_read_only,
     })?);
 
-    engine.send_window_metrics_event(width as usize, height as usize, window.scale_factor())?;
+    // The device can be lost while the system is asleep, which would otherwise only be noticed when
+    // a frame fails to render.
+    let lifecycle = Lifecycle::new(hwnd, vsync_waiter.clone(), move || {
+        device_loss_check.check()
+    })?;
+
+    send_initial_state(&engine, &window, &lifecycle)?;
 
-    settings::send_to_engine(&engine)?;
+    let semantics = SemanticsActivation::new();
+    let _ = semantics.refresh(&engine).trace_err();
+    let uia = UiaTree::new(hwnd, &engine);
 
-    let window_data = Box::leak(Box::new(WindowData {
-        engine: &*engine,
-        resize_controller,
-        scale_factor: Cell::new(window.scale_factor()),
-        root_visual: root,
-    }));
+    let displays = DisplayTracker::new(hwnd, vsync_waiter.clone());
+    displays.update();
 
-    unsafe { SetWindowSubclass(hwnd, Some(wnd_proc), 696969, window_data as *mut _ as _) };
+    let system_keys = SystemKeys::new(
+        hwnd,
+        ShortcutPolicies {
+            close: args.close_shortcut,
+            window_menu: args.window_menu_shortcut,
+            menu_bar: args.menu_bar_shortcut,
+        },
+    );
+
+    if let Some(path) = &args.record_input {
+        engine.set_input_recorder(Some(InputRecorder::create(path)?));
+    }
 
-    let mut cursor_pos = PhysicalPosition::new(0.0, 0.0);
-    let mut task_executor = TaskRunnerExecutor::default();
-    let mut keyboard = Keyboard::new(engine.clone(), text_input);
+    let mut input_replayer = args
+        .replay_input
+        .as_deref()
+        .map(InputReplayer::load)
+        .transpose()?;
 
-    let mut pointer_is_down = false;
+    drag_drop::register(hwnd, drag_drop_events)?;
+
+    let plugins = Rc::new(PluginHost::new(
+        engine.clone(),
+        hwnd,
+        device.clone(),
+        egl_manager.clone(),
+        texture_registry,
+    ));
+
+    for path in &args.plugins {
+        if let Err(e) = plugins.load(path) {
+            tracing::error!("failed to load plugin {}: {e:?}", path.display());
+        }
+    }
+
+    if args.watch {
+        if bundle::assets_embedded() {
+            tracing::warn!("--watch has no effect when the assets are embedded");
+        } else if vm_service_config.enabled {
+            let event_loop = event_loop.create_proxy();
+            hot_reload::spawn_watcher(PathBuf::from(engine::ASSETS_PATH.to_str()?), move || {
+                let _ = event_loop.send_event(PlatformEvent::HotRestart).trace_err();
+            })?;
+        } else {
+            tracing::warn!("--watch has no effect when the vm service is disabled");
+        }
+    }
+
+    let mut window_subclass = Some(WindowSubclass::install(
+        hwnd,
+        WindowData {
+            engine: engine.clone(),
+            resize_controller,
+            resizing: Cell::new(false),
+            removed: Cell::new(false),
+            scale_factor: Cell::new(window.scale_factor()),
+            vsync_waiter,
+            root_visual: root,
+            content_anchor,
+            deep_link_scheme: args.protocol.clone(),
+            window_controller: window_controller.clone(),
+            plugins: plugins.clone(),
+            touch_keyboard,
+            clipboard_events,
+            window_effects,
+            semantics,
+            uia: uia.clone(),
+            system_keys: system_keys.clone(),
+            displays,
+            lifecycle: lifecycle.clone(),
+            power,
+            hotkeys: hotkeys.clone(),
+            platform_menu,
+            window_events: WindowEvents::new(hwnd, window_events),
+            event_loop: event_loop.create_proxy(),
+        },
+    )?);
+
+    clipboard::listen_for_changes(hwnd)?;
+
+    let mut plugins = Some(plugins);
+
+    let hover_throttle = args.throttle_hover.then(|| {
+        let refresh_rate_millihertz = window
+            .current_monitor()
+            .and_then(|monitor| monitor.refresh_rate_millihertz())
+            .unwrap_or(60_000);
+        Duration::from_secs_f64(1000.0 / refresh_rate_millihertz as f64)
+    });
+
+    let show_window = move || {
+        window_controller.show();
+        if maximize_on_show {
+            window_controller.maximize();
+        }
+    };
+
+    let mut first_frame_deadline = args
+        .wait_for_first_frame
+        .then(|| Instant::now() + Duration::from_millis(args.first_frame_timeout));
+
+    let task_executor = Rc::new(RefCell::new(TaskRunnerExecutor::new({
+        let event_loop = event_loop.create_proxy();
+        move || {
+            let _ = event_loop.send_event(PlatformEvent::RunTasks).trace_err();
+        }
+    })?));
+
+    let _modal_loop_pump = ModalLoopPump::install({
+        let engine = engine.clone();
+        let task_executor = task_executor.clone();
+        let pending_tasks = pending_tasks.clone();
+        move || run_tasks(&engine, &task_executor, &pending_tasks)
+    })?;
+    let mut keyboard = Keyboard::new(engine.clone(), text_input, undo_manager);
+    let mut pointer = Pointer::new(engine.clone(), hover_throttle);
+    let mut raw_input = RawInput::new(raw_input_events);
+    let mut device_events = raw_input.device_events();
+    event_loop.listen_device_events(device_events);
+    let mut modifiers = ModifiersState::empty();
+    let restart_proxy = event_loop.create_proxy();
+    let exit_proxy = event_loop.create_proxy();
+
+    let exit_code = Rc::new(Cell::new(0));
+    let loop_exit_code = exit_code.clone();
 
     event_loop.run(move |event, target| {
+        let is_about_to_wait = matches!(event, Event::AboutToWait);
+
         match event {
             Event::UserEvent(event) => match event {
-                PlatformEvent::PostFlutterTask(task) => {
-                    task_executor.enqueue(task);
+                // Due tasks are run below.
+                PlatformEvent::RunTasks => {}
+                PlatformEvent::RenderingFailed(message) => {
+                    show_fatal_error(hwnd, &message);
+                    task_executor.borrow_mut().clear();
+                    let _ = engine.shutdown().trace_err();
+                    target.exit();
                 }
-            },
-            Event::WindowEvent { event, .. } => match event {
-                WindowEvent::CloseRequested => {
+                PlatformEvent::DeviceLost if !args.relaunch_on_device_loss => {
+                    show_fatal_error(hwnd, "The graphics device was lost or reset.");
+                    task_executor.borrow_mut().clear();
+                    let _ = engine.shutdown().trace_err();
                     target.exit();
                 }
-                WindowEvent::ScaleFactorChanged {
-                    scale_factor,
-                    inner_size_writer: _,
-                } => {
-                    window_data.scale_factor.set(scale_factor);
+                PlatformEvent::DeviceLost => {
+                    // The relaunched app has lost its Dart state, like after a hot restart. Event
+                    // channel listeners go away with the old engine's messengers, but hotkeys and
+                    // the accessibility tree are kept by the window.
+                    hotkeys.unregister_all();
+                    uia.clear();
+                    match recover_from_device_loss(
+                        &engine,
+                        &window,
+                        &lifecycle,
+                        &mut task_executor.borrow_mut(),
+                    ) {
+                        Ok(()) => device_loss.record_relaunch(),
+                        Err(e) => {
+                            show_fatal_error(hwnd, &format!("{e:?}"));
+                            target.exit();
+                        }
+                    }
                 }
-                WindowEvent::CursorMoved { position, .. } => {
-                    cursor_pos = position;
+                PlatformEvent::Notification(event) => {
+                    let _ = event.send(&notification_events).trace_err();
+                }
+                PlatformEvent::IntegrationTestFinished(passed) => {
+                    loop_exit_code.set(if passed { 0 } else { 1 });
+                    task_executor.borrow_mut().clear();
+                    let _ = engine.shutdown().trace_err();
+                    target.exit();
+                }
+                PlatformEvent::ExitRequested(exit_type, exit_code) => {
+                    let exit_proxy = exit_proxy.clone();
+                    let _ = app_exit
+                        .request(&engine, exit_type, move || {
+                            let _ = exit_proxy
+                                .send_event(PlatformEvent::Exit(exit_code))
+                                .trace_err();
+                        })
+                        .trace_err();
+                }
+                PlatformEvent::Exit(exit_code) => {
+                    if args.remember_window_placement {
+                        let _ = window_placement::save(hwnd).trace_err();
+                    }
 
-                    let phase = if pointer_is_down {
-                        PointerPhase::Move
-                    } else {
-                        PointerPhase::Hover
-                    };
+                    // Tasks posted by the engine can't be run once it has been shut down.
+                    task_executor.borrow_mut().clear();
+                    let _ = engine.shutdown().trace_err();
 
-                    engine
-                        .send_pointer_event(phase, position.x, position.y)
-                        .unwrap();
+                    loop_exit_code.set(exit_code);
+                    target.exit();
+                }
+                PlatformEvent::DragFinished => {
+                    let _ = pointer.cancel().trace_err();
+                }
+                PlatformEvent::PointerLeft(id) => {
+                    let _ = pointer.handle_pointer_left(id).trace_err();
+                }
+                PlatformEvent::FrameTimings(timings) => {
+                    let _ = frame_timings::send_event(&frame_timings_events, &timings).trace_err();
+                }
+                PlatformEvent::HotRestart => {
+                    // The restarted app would otherwise conflict with its own shortcuts.
+                    hotkeys.unregister_all();
+                    // The restarted framework sends its whole semantics tree again.
+                    uia.clear();
+                    let _ = hot_restart(
+                        &engine,
+                        &window,
+                        &lifecycle,
+                        &mut task_executor.borrow_mut(),
+                    )
+                    .trace_err();
+                }
+                PlatformEvent::FirstFrameRendered => {
+                    if let Some(splash) = splash.take() {
+                        let _ = splash.dismiss().trace_err();
+                    }
+
+                    if first_frame_deadline.take().is_some() {
+                        show_window();
+                    }
+                }
+            },
+            Event::WindowEvent { event, .. } => match event {
+                WindowEvent::CloseRequested => {
+                    // The window is only closed once the framework agrees to it, so that it can
+                    // e.g. ask to save changes first.
+                    let exit_proxy = exit_proxy.clone();
+                    let _ = app_exit
+                        .request(&engine, ExitType::Cancelable, move || {
+                            let _ = exit_proxy.send_event(PlatformEvent::Exit(0)).trace_err();
+                        })
+                        .trace_err();
+                }
+                WindowEvent::CursorMoved { position, .. } => {
+                    let _ = pointer.handle_cursor_moved(position).trace_err();
                 }
                 WindowEvent::CursorEntered { .. } => {
-                    engine
-                        .send_pointer_event(PointerPhase::Add, cursor_pos.x, cursor_pos.y)
-                        .unwrap();
+                    let _ = pointer.handle_cursor_entered().trace_err();
                 }
                 WindowEvent::CursorLeft { .. } => {
-                    engine
-                        .send_pointer_event(PointerPhase::Remove, cursor_pos.x, cursor_pos.y)
-                        .unwrap();
+                    let _ = pointer.handle_cursor_left().trace_err();
                 }
-                WindowEvent::MouseInput { state, .. } => {
-                    let phase = match state {
-                        ElementState::Pressed => PointerPhase::Down,
-                        ElementState::Released => PointerPhase::Up,
-                    };
-
-                    pointer_is_down = state == ElementState::Pressed;
-
-                    engine
-                        .send_pointer_event(phase, cursor_pos.x, cursor_pos.y)
-                        .unwrap();
+                WindowEvent::MouseWheel { delta, .. } => {
+                    let _ = pointer
+                        .handle_mouse_wheel(
+                            delta,
+                            modifiers.shift_key(),
+                            window.scale_factor(),
+                            window.inner_size(),
+                        )
+                        .trace_err();
+                }
+                WindowEvent::Focused(focused) => {
+                    if focused {
+                        let _ = keyboard.handle_focus_gained().trace_err();
+                    } else {
+                        let _ = pointer.cancel().trace_err();
+                    }
+                    let _ = cursor_grab.handle_focus_changed(focused).trace_err();
+                }
+                WindowEvent::Moved(_) | WindowEvent::Resized(_) => {
+                    let _ = cursor_grab.refresh().trace_err();
+                }
+                WindowEvent::MouseInput { state, button, .. } => {
+                    let _ = pointer.handle_mouse_input(state, button).trace_err();
+                }
+                WindowEvent::Touch(touch) => {
+                    let _ = pointer.handle_touch(touch).trace_err();
                 }
-                WindowEvent::ModifiersChanged(modifiers) => {
-                    let _ = keyboard.handle_modifiers_changed(modifiers).trace_err();
+                WindowEvent::ModifiersChanged(new_modifiers) => {
+                    modifiers = new_modifiers.state();
+                    let _ = keyboard.handle_modifiers_changed(new_modifiers).trace_err();
                 }
                 WindowEvent::KeyboardInput {
                     device_id: _,
@@ -282,21 +1056,218 @@ fn main() -> Result<()> {
                         "keyboard event"
                     );
 
-                    let _ = keyboard
-                        .handle_keyboard_input(event, is_synthetic)
-                        .trace_err();
+                    // Ctrl+Shift+R triggers a hot restart in debug builds.
+                    if cfg!(debug_assertions)
+                        && event.state.is_pressed()
+                        && modifiers.control_key()
+                        && modifiers.shift_key()
+                        && event.physical_key == PhysicalKey::Code(KeyCode::KeyR)
+                    {
+                        let _ = restart_proxy
+                            .send_event(PlatformEvent::HotRestart)
+                            .trace_err();
+                    } else if event.state.is_pressed()
+                        && modifiers.control_key()
+                        && modifiers.shift_key()
+                        && event.physical_key == PhysicalKey::Code(KeyCode::KeyP)
+                    {
+                        // Ctrl+Shift+P toggles the performance overlay.
+                        let mut perf_overlay = perf_overlay.borrow_mut();
+                        let visible = !perf_overlay.is_visible();
+                        let _ = perf_overlay.set_visible(visible).trace_err();
+                    } else {
+                        let on_handled = system_keys.begin_key_event(&event, modifiers);
+                        let _ = keyboard
+                            .handle_keyboard_input(event, is_synthetic, on_handled)
+                            .trace_err();
+                    }
                 }
                 _ => {}
             },
-
+            Event::DeviceEvent { event, .. } => {
+                let _ = raw_input.handle_device_event(event).trace_err();
+            }
+            Event::LoopExiting => {
+                // Stop handling window messages before the engine is shut down for good.
+                drop(window_subclass.take());
+                // Plugins are destroyed before the engine, which they hold on to.
+                drop(plugins.take());
+                // The clip would otherwise outlive the window.
+                let _ = cursor_grab.set_mode(CursorGrabMode::None).trace_err();
+                let _ = restoration.save().trace_err();
+                timeline::finish_trace_file();
+            }
             _ => (),
         }
 
-        if let Some(next_task_target_time) = task_executor.process_all(&engine) {
-            target.set_control_flow(ControlFlow::WaitUntil(next_task_target_time));
+        if first_frame_deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            tracing::warn!("timed out waiting for the first frame");
+            first_frame_deadline = None;
+            show_window();
+        }
+
+        let next_replay_time = input_replayer
+            .as_mut()
+            .and_then(|replayer| replayer.replay_due(&engine).trace_err().ok().flatten());
+
+        run_tasks(&engine, &task_executor, &pending_tasks);
+
+        let next_hover_time = pointer.flush().trace_err().ok().flatten();
+
+        // Moves are sent once all of the pending window messages have been handled.
+        if is_about_to_wait {
+            let _ = pointer.send_batch().trace_err();
+            let _ = raw_input.flush().trace_err();
+        }
+
+        // Raw input is only registered for while something is listening for it.
+        if raw_input.device_events() != device_events {
+            device_events = raw_input.device_events();
+            target.listen_device_events(device_events);
+        }
+
+        let next_overlay_time = perf_overlay
+            .borrow_mut()
+            .update()
+            .trace_err()
+            .ok()
+            .flatten();
+
+        let next_restoration_save_time = restoration.save_if_due().trace_err().ok().flatten();
+
+        if is_about_to_wait {
+            let _ = commits.flush().trace_err();
+        }
+
+        let next_wake_time = [
+            next_hover_time,
+            first_frame_deadline,
+            next_replay_time,
+            next_overlay_time,
+            next_restoration_save_time,
+        ]
+        .into_iter()
+        .flatten()
+        .min();
+
+        if let Some(next_wake_time) = next_wake_time {
+            target.set_control_flow(ControlFlow::WaitUntil(next_wake_time));
+        } else {
+            target.set_control_flow(ControlFlow::Wait);
         }
     })?;
 
+    if exit_code.get() != 0 {
+        std::process::exit(exit_code.get());
+    }
+
+    Ok(())
+}
+
+/// Tells the user that the app can't continue, and where to find the embedder event log.
+fn show_fatal_error(hwnd: HWND, message: &str) {
+    let log = flight_recorder::dump(message);
+
+    let mut text = format!("The app stopped rendering and needs to close.\n\n{message}");
+    if let Some(log) = log {
+        text.push_str(&format!("\n\nDetails were saved to {}", log.display()));
+    }
+
+    unsafe {
+        MessageBoxW(
+            hwnd,
+            &HSTRING::from(text),
+            w!("Rendering failed"),
+            MB_OK | MB_ICONERROR,
+        );
+    }
+}
+
+fn persistent_cache_path(args: &Args) -> Result<Option<CString>> {
+    if args.no_persistent_cache {
+        return Ok(None);
+    }
+
+    let dir = match &args.persistent_cache_dir {
+        Some(dir) => dir.clone(),
+        None => paths::local_app_data_dir()?.join("shader_cache"),
+    };
+
+    fs::create_dir_all(&dir)?;
+
+    let dir = dir.to_str().ok_or_eyre("cache path is not valid unicode")?;
+    Ok(Some(CString::new(dir)?))
+}
+
+/// Runs the tasks that are due, including those that have been posted since the last time. Does
+/// nothing if the executor is in use, e.g. if a modal loop was started while the engine was being
+/// restarted.
+fn run_tasks(
+    engine: &FlutterEngine,
+    executor: &RefCell<TaskRunnerExecutor>,
+    pending_tasks: &Mutex<Vec<Task>>,
+) {
+    let Ok(mut executor_ref) = executor.try_borrow_mut() else {
+        return;
+    };
+
+    for task in pending_tasks.lock().unwrap().drain(..) {
+        executor_ref.enqueue(task);
+    }
+
+    drop(executor_ref);
+
+    TaskRunnerExecutor::run_due_tasks_reentrant(executor, engine);
+}
+
+/// Restarts the engine, discarding all Dart state.
+fn hot_restart(
+    engine: &FlutterEngine,
+    window: &Window,
+    lifecycle: &Lifecycle,
+    task_executor: &mut TaskRunnerExecutor,
+) -> Result<()> {
+    tracing::info!("performing hot restart");
+
+    task_executor.clear();
+    engine.restart()?;
+
+    send_initial_state(engine, window, lifecycle)
+}
+
+/// Recreates the D3D device after it has been lost, and relaunches the engine on it.
+fn recover_from_device_loss(
+    engine: &FlutterEngine,
+    window: &Window,
+    lifecycle: &Lifecycle,
+    task_executor: &mut TaskRunnerExecutor,
+) -> Result<()> {
+    tracing::warn!("recovering from device loss");
+
+    task_executor.clear();
+    engine.recover_from_device_loss(d3d::create_device()?)?;
+
+    send_initial_state(engine, window, lifecycle)
+}
+
+/// Sends the state that the engine needs to receive after it has been launched.
+fn send_initial_state(
+    engine: &FlutterEngine,
+    window: &Window,
+    lifecycle: &Lifecycle,
+) -> Result<()> {
+    let size = window.inner_size();
+    engine.send_window_metrics_event(
+        size.width as usize,
+        size.height as usize,
+        window.scale_factor(),
+    )?;
+
+    settings::send_to_engine(engine)?;
+    locales::send_to_engine(engine)?;
+    displays::send_to_engine(engine)?;
+    lifecycle.send_current(engine)?;
+
     Ok(())
 }
 
@@ -308,7 +1279,29 @@ unsafe extern "system" fn wnd_proc(
     _uidsubclass: usize,
     dwrefdata: usize,
 ) -> LRESULT {
-    let data = (dwrefdata as *const WindowData).as_ref().unwrap();
+    let data = dwrefdata as *const WindowData;
+    if data.is_null() {
+        return DefSubclassProc(window, msg, wparam, lparam);
+    }
+
+    // Keeps the data alive until the message has been handled, even if the subclass is removed in
+    // the meantime.
+    Rc::increment_strong_count(data);
+    let data = Rc::from_raw(data);
+
+    if msg == WM_NCDESTROY {
+        // The subclass must be removed before the window is gone. The data is freed once the
+        // `WindowSubclass` is dropped.
+        if RemoveWindowSubclass(window, Some(wnd_proc), SUBCLASS_ID).as_bool() {
+            data.removed.set(true);
+        }
+        return DefSubclassProc(window, msg, wparam, lparam);
+    }
+
+    if let Some(result) = data.plugins.handle_window_proc(window, msg, wparam, lparam) {
+        return result;
+    }
+
     match msg {
         WM_NCCALCSIZE => {
             DefSubclassProc(window, msg, wparam, lparam);
@@ -317,27 +1310,224 @@ unsafe extern "system" fn wnd_proc(
             let rect = rect.as_ref().unwrap();
 
             if rect.right > rect.left && rect.bottom > rect.top {
-                data.resize_controller.begin_and_wait(|| {
-                    let width = rect.right - rect.left;
-                    let height = rect.bottom - rect.top;
-
-                    data.root_visual
-                        .SetSize(Vector2::new(width as f32, height as f32))
-                        .unwrap();
-
-                    data.root_visual
-                        .SetOffset(Vector3::new(0.0, height as f32, 0.0))
-                        .unwrap();
-
-                    (*data.engine)
-                        .send_window_metrics_event(
-                            width as usize,
-                            height as usize,
-                            data.scale_factor.get(),
-                        )
-                        .unwrap();
-                });
+                let (width, height) = (rect.right - rect.left, rect.bottom - rect.top);
+
+                // A resize can start while another one is being handled (e.g. when a DPI change
+                // moves the window), which can't wait for a frame as well. There is also no frame
+                // to wait for while the engine isn't running.
+                if data.resizing.get() || !data.engine.is_running() {
+                    let _ = data.update_metrics(width, height).trace_err();
+                } else {
+                    data.resizing.set(true);
+                    data.resize_controller.begin_and_wait(|| {
+                        let _ = data.update_metrics(width, height).trace_err();
+                        timeline::instant(c"ResizeMetricsSent");
+                    });
+                    data.resizing.set(false);
+                }
+            }
+        }
+        WM_DPICHANGED => {
+            // The new dpi is in the low word of wparam. It needs to be updated before the window
+            // is moved to the suggested rect, so that the resulting resize uses the new scale.
+            let dpi = (wparam.0 & 0xffff) as f64;
+            data.scale_factor.set(dpi / 96.0);
+
+            let result = DefSubclassProc(window, msg, wparam, lparam);
+
+            // The physical size may not have changed (e.g. if the window was clamped to the
+            // monitor), in which case no resize happens, so metrics are sent again here to make
+            // sure the engine picks up the new pixel ratio.
+            let mut rect = RECT::default();
+            if GetClientRect(window, &mut rect).is_ok() {
+                let _ = data
+                    .update_metrics(rect.right - rect.left, rect.bottom - rect.top)
+                    .trace_err();
             }
+
+            return result;
+        }
+        WM_DISPLAYCHANGE => {
+            let result = DefSubclassProc(window, msg, wparam, lparam);
+
+            // A display has been connected, disconnected or changed resolution, which may have
+            // left the window off screen.
+            let _ = window_placement::ensure_on_screen(window).trace_err();
+
+            let _ = displays::send_to_engine(&*data.engine).trace_err();
+            data.displays.update();
+
+            // The window's scale can change without a WM_DPICHANGED, e.g. if the system moved it
+            // off a display that was disconnected, so metrics are sent again to be sure.
+            data.scale_factor.set(GetDpiForWindow(window) as f64 / 96.0);
+
+            let mut rect = RECT::default();
+            if GetClientRect(window, &mut rect).is_ok() {
+                let _ = data
+                    .update_metrics(rect.right - rect.left, rect.bottom - rect.top)
+                    .trace_err();
+            }
+
+            return result;
+        }
+        WM_WINDOWPOSCHANGED => {
+            data.displays.handle_window_moved();
+            data.window_events.handle_window_pos_changed(lparam);
+            return DefSubclassProc(window, msg, wparam, lparam);
+        }
+        WM_ENTERSIZEMOVE => {
+            modal_loop::handle_enter_size_move(window);
+            return DefSubclassProc(window, msg, wparam, lparam);
+        }
+        WM_EXITSIZEMOVE => {
+            modal_loop::handle_exit_size_move(window);
+            data.content_anchor.handle_exit_size_move();
+            return DefSubclassProc(window, msg, wparam, lparam);
+        }
+        WM_TIMER => {
+            if !modal_loop::handle_timer(wparam) {
+                return DefSubclassProc(window, msg, wparam, lparam);
+            }
+        }
+        WM_ACTIVATE => {
+            data.window_events.handle_activate(wparam);
+            return DefSubclassProc(window, msg, wparam, lparam);
+        }
+        WM_SIZE => {
+            // Rendering is paused while the window is minimized, so the vsync waiter needs to
+            // know as soon as it is restored.
+            data.vsync_waiter.wake();
+
+            // The other values are sent when other windows are maximized or restored.
+            let kind = wparam.0 as u32;
+            if matches!(kind, SIZE_RESTORED | SIZE_MINIMIZED | SIZE_MAXIMIZED) {
+                data.lifecycle
+                    .handle_minimized_changed(&*data.engine, kind == SIZE_MINIMIZED);
+            }
+
+            return DefSubclassProc(window, msg, wparam, lparam);
+        }
+        WM_SETFOCUS | WM_KILLFOCUS => {
+            data.lifecycle
+                .handle_focus_changed(&*data.engine, msg == WM_SETFOCUS);
+            return DefSubclassProc(window, msg, wparam, lparam);
+        }
+        WM_WTSSESSION_CHANGE => {
+            data.lifecycle.handle_session_change(&*data.engine, wparam);
+            return DefSubclassProc(window, msg, wparam, lparam);
+        }
+        WM_POWERBROADCAST => {
+            data.lifecycle.handle_power_broadcast(&*data.engine, wparam);
+            data.power.handle_power_broadcast(wparam);
+            return DefSubclassProc(window, msg, wparam, lparam);
+        }
+        WM_HOTKEY => {
+            if !data.hotkeys.handle_hotkey(wparam) {
+                return DefSubclassProc(window, msg, wparam, lparam);
+            }
+        }
+        WM_GETMINMAXINFO => {
+            DefSubclassProc(window, msg, wparam, lparam);
+            data.window_controller
+                .size_constraints()
+                .handle_get_min_max_info(window, lparam, data.scale_factor.get());
+        }
+        WM_SIZING => {
+            data.content_anchor.handle_sizing(wparam);
+
+            let handled = data
+                .window_controller
+                .size_constraints()
+                .handle_sizing(window, wparam, lparam);
+
+            if !handled {
+                return DefSubclassProc(window, msg, wparam, lparam);
+            }
+
+            return LRESULT(1);
+        }
+        WM_COMMAND => {
+            let handled = data
+                .platform_menu
+                .handle_command(&*data.engine, wparam)
+                .trace_err()
+                .unwrap_or(false);
+
+            if !handled {
+                return DefSubclassProc(window, msg, wparam, lparam);
+            }
+        }
+        WM_COPYDATA => {
+            let handled = data.deep_link_scheme.as_ref().is_some_and(|scheme| {
+                deep_link::handle_copy_data(&*data.engine, scheme, lparam)
+            });
+
+            if !handled {
+                return DefSubclassProc(window, msg, wparam, lparam);
+            }
+
+            return LRESULT(1);
+        }
+        WM_LBUTTONDOWN | WM_RBUTTONDOWN | WM_MBUTTONDOWN => {
+            data.touch_keyboard.handle_mouse_button_message();
+            return DefSubclassProc(window, msg, wparam, lparam);
+        }
+        WM_POINTERLEAVE => {
+            // The low word is the pointer id, which winit also uses as the touch id.
+            let id = wparam.0 as u64 & 0xffff;
+            let _ = data.event_loop.send_event(PlatformEvent::PointerLeft(id));
+            return DefSubclassProc(window, msg, wparam, lparam);
+        }
+        WM_GETOBJECT => {
+            let _ = data
+                .semantics
+                .handle_get_object(&*data.engine, lparam)
+                .trace_err();
+            if let Some(result) = data.uia.handle_get_object(wparam, lparam) {
+                return result;
+            }
+            return DefSubclassProc(window, msg, wparam, lparam);
+        }
+        WM_SYSCOMMAND => {
+            if !data.system_keys.handle_sys_command(wparam, lparam) {
+                return DefSubclassProc(window, msg, wparam, lparam);
+            }
+        }
+        WM_CLIPBOARDUPDATE => {
+            let _ = clipboard::handle_clipboard_update(window, &data.clipboard_events).trace_err();
+            return LRESULT(0);
+        }
+        WM_SETTINGCHANGE => {
+            // Sent with "intl" when the user changes their language settings.
+            let _ = locales::send_to_engine(&*data.engine).trace_err();
+
+            let area = PCWSTR(lparam.0 as *const u16);
+            if !area.is_null()
+                && area
+                    .to_string()
+                    .is_ok_and(|area| area == "ImmersiveColorSet")
+            {
+                let _ = settings::send_to_engine(&*data.engine).trace_err();
+                let _ = data.window_effects.handle_theme_changed().trace_err();
+            }
+
+            // Sent with the setting that was changed in wparam.
+            let action = SYSTEM_PARAMETERS_INFO_ACTION(wparam.0 as u32);
+            if action == SPI_SETHIGHCONTRAST
+                || action == SPI_SETCLIENTAREAANIMATION
+                || action == SPI_SETSCREENREADER
+            {
+                let _ = settings::send_to_engine(&*data.engine).trace_err();
+            }
+
+            if action == SPI_SETSCREENREADER {
+                let _ = data.semantics.refresh(&*data.engine).trace_err();
+                if !data.engine.semantics_enabled() {
+                    data.uia.clear();
+                }
+            }
+
+            return DefSubclassProc(window, msg, wparam, lparam);
         }
         _ => return DefSubclassProc(window, msg, wparam, lparam),
     }
@@ -0,0 +1,102 @@
+use std::cell::RefCell;
+
+use color_eyre::eyre;
+use windows::Win32::Foundation::{HWND, LPARAM, LRESULT, WPARAM};
+use windows::Win32::System::Threading::GetCurrentThreadId;
+use windows::Win32::UI::WindowsAndMessaging::{
+    CallNextHookEx, KillTimer, SetTimer, SetWindowsHookExW, UnhookWindowsHookEx, HHOOK,
+    USER_TIMER_MINIMUM, WH_MSGFILTER,
+};
+
+use crate::error_utils::ResultExt;
+
+/// The id of the timer that pumps tasks while the window is being moved or resized.
+const SIZE_MOVE_TIMER_ID: usize = 0x666c;
+
+thread_local! {
+    static PUMP: RefCell<Option<Box<dyn Fn()>>> = RefCell::new(None);
+}
+
+/// Keeps platform tasks running while a modal loop (e.g. a message box, file dialog or menu) is
+/// running on the current thread.
+///
+/// Tasks are normally run by the event loop, which doesn't get control back until the modal loop
+/// exits, so animations and timers would stop. Modal loops pass each message that they retrieve
+/// to the thread's `WH_MSGFILTER` hook, which calls `pump`. Waking the event loop (e.g. when a task
+/// is posted or the task timer fires) posts a message, so the hook is called whenever there is
+/// something to run.
+///
+/// The move/size loop that runs while the window is dragged by its caption or borders isn't
+/// guaranteed to call the hook, and nothing wakes it up for delayed tasks, so the window also needs
+/// to forward `WM_ENTERSIZEMOVE`, `WM_EXITSIZEMOVE` and `WM_TIMER` to [`handle_enter_size_move`],
+/// [`handle_exit_size_move`] and [`handle_timer`], which pump on a timer for the duration.
+///
+/// `pump` is called re-entrantly if a task that it runs starts another modal loop.
+pub struct ModalLoopPump {
+    hook: HHOOK,
+}
+
+impl ModalLoopPump {
+    pub fn install(pump: impl Fn() + 'static) -> eyre::Result<ModalLoopPump> {
+        PUMP.with(|p| *p.borrow_mut() = Some(Box::new(pump)));
+
+        let hook = unsafe {
+            SetWindowsHookExW(
+                WH_MSGFILTER,
+                Some(msg_filter_proc),
+                None,
+                GetCurrentThreadId(),
+            )?
+        };
+
+        Ok(ModalLoopPump { hook })
+    }
+}
+
+impl Drop for ModalLoopPump {
+    fn drop(&mut self) {
+        let _ = unsafe { UnhookWindowsHookEx(self.hook) };
+        PUMP.with(|p| p.borrow_mut().take());
+    }
+}
+
+/// Starts pumping tasks on a timer, since the move/size loop only retrieves timer messages for
+/// the window while the user isn't moving the mouse.
+pub fn handle_enter_size_move(hwnd: HWND) {
+    if unsafe { SetTimer(hwnd, SIZE_MOVE_TIMER_ID, USER_TIMER_MINIMUM, None) } == 0 {
+        let error = windows::core::Error::from_win32();
+        tracing::error!(?error, "failed to start move/size timer");
+    }
+}
+
+pub fn handle_exit_size_move(hwnd: HWND) {
+    let _ = unsafe { KillTimer(hwnd, SIZE_MOVE_TIMER_ID) }.trace_err();
+}
+
+/// Handles `WM_TIMER`, returning whether it was for the move/size timer.
+pub fn handle_timer(wparam: WPARAM) -> bool {
+    if wparam.0 != SIZE_MOVE_TIMER_ID {
+        return false;
+    }
+
+    pump();
+
+    true
+}
+
+fn pump() {
+    PUMP.with(|pump| {
+        if let Some(pump) = &*pump.borrow() {
+            pump();
+        }
+    });
+}
+
+unsafe extern "system" fn msg_filter_proc(code: i32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+    // Negative codes must be passed on without being processed.
+    if code >= 0 {
+        pump();
+    }
+
+    CallNextHookEx(None, code, wparam, lparam)
+}
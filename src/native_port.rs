@@ -0,0 +1,202 @@
+//! Native ports, which Dart isolates can send to directly with `SendPort.send`, so that plugins
+//! can receive large or frequent messages without platform channel encoding.
+//!
+//! The embedder API can only post to Dart ports, so creating native ports goes through the Dart
+//! VM's dynamically linked API. Dart has to hand over its `NativeApi.initializeApiDLData` first:
+//!
+//! ```dart
+//! await const MethodChannel('flion/native_ports')
+//!     .invokeMethod('initialize', NativeApi.initializeApiDLData.address);
+//! ```
+
+use std::collections::BTreeMap;
+use std::ffi::{c_char, c_int, c_void, CStr};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use color_eyre::eyre::{self, bail};
+use flutter_codec::EncodableValue;
+use fluyt_plugin::{RawDestroyCallback, RawNativePortCallback};
+
+use crate::standard_method_channel::{StandardMethodHandler, StandardMethodReply};
+
+/// The version of `dart_api_dl.h` that this was written against. Minor versions only add functions.
+const DART_API_DL_MAJOR_VERSION: c_int = 2;
+
+type NativeMessageHandler = unsafe extern "C" fn(port: i64, message: *mut c_void);
+type NewNativePortFn =
+    unsafe extern "C" fn(*const c_char, NativeMessageHandler, handle_concurrently: bool) -> i64;
+type CloseNativePortFn = unsafe extern "C" fn(i64) -> bool;
+type PostCObjectFn = unsafe extern "C" fn(i64, *mut c_void) -> bool;
+
+/// The layout of the data behind `NativeApi.initializeApiDLData`.
+#[repr(C)]
+struct DartApi {
+    major: c_int,
+    minor: c_int,
+    functions: *const DartApiEntry,
+}
+
+/// The functions are terminated by an entry with a null name.
+#[repr(C)]
+struct DartApiEntry {
+    name: *const c_char,
+    function: *const c_void,
+}
+
+struct DartApiFunctions {
+    new_native_port: NewNativePortFn,
+    close_native_port: CloseNativePortFn,
+    post_c_object: PostCObjectFn,
+}
+
+/// Set once, since the data belongs to the VM and stays the same across hot restarts.
+static DART_API: OnceLock<DartApiFunctions> = OnceLock::new();
+
+struct PortHandler {
+    callback: RawNativePortCallback,
+    user_data: *mut c_void,
+    destroy: Option<RawDestroyCallback>,
+}
+
+// Plugins must make their callbacks safe to call from the VM's threads.
+unsafe impl Send for PortHandler {}
+unsafe impl Sync for PortHandler {}
+
+impl Drop for PortHandler {
+    fn drop(&mut self) {
+        if let Some(destroy) = self.destroy {
+            unsafe { destroy(self.user_data) };
+        }
+    }
+}
+
+/// The VM calls the message handler with only the port, so handlers are looked up by it. They are
+/// reference counted so that a message that arrives while the port is being closed doesn't use a
+/// freed handler.
+static HANDLERS: Mutex<BTreeMap<i64, Arc<PortHandler>>> = Mutex::new(BTreeMap::new());
+
+unsafe fn init_dart_api(data: *const DartApi) -> eyre::Result<DartApiFunctions> {
+    let Some(api) = data.as_ref() else {
+        bail!("dart api data is null");
+    };
+
+    if api.major != DART_API_DL_MAJOR_VERSION {
+        bail!(
+            "unsupported dart api version {}.{} (expected {DART_API_DL_MAJOR_VERSION}.x)",
+            api.major,
+            api.minor
+        );
+    }
+
+    let lookup = |name: &CStr| {
+        let mut entry = api.functions;
+        while !(*entry).name.is_null() {
+            if CStr::from_ptr((*entry).name) == name {
+                return Ok((*entry).function);
+            }
+            entry = entry.add(1);
+        }
+        bail!("dart api is missing {name:?}")
+    };
+
+    Ok(DartApiFunctions {
+        new_native_port: std::mem::transmute(lookup(c"Dart_NewNativePort")?),
+        close_native_port: std::mem::transmute(lookup(c"Dart_CloseNativePort")?),
+        post_c_object: std::mem::transmute(lookup(c"Dart_PostCObject")?),
+    })
+}
+
+unsafe extern "C" fn handle_message(port: i64, message: *mut c_void) {
+    let handler = HANDLERS.lock().unwrap().get(&port).cloned();
+    if let Some(handler) = handler {
+        (handler.callback)(handler.user_data, port, message.cast());
+    }
+}
+
+/// Creates a native port that calls `callback` with each `Dart_CObject` sent to it, on one of the
+/// VM's threads and one message at a time. Returns `None` if Dart hasn't handed over its API yet.
+///
+/// `destroy` is called with `user_data` once the port has been closed and no more messages are
+/// being handled, or immediately if the port couldn't be created.
+pub fn open(
+    name: &CStr,
+    callback: RawNativePortCallback,
+    user_data: *mut c_void,
+    destroy: Option<RawDestroyCallback>,
+) -> Option<i64> {
+    let handler = Arc::new(PortHandler {
+        callback,
+        user_data,
+        destroy,
+    });
+
+    let api = DART_API.get()?;
+
+    // Holding the lock means that messages can't be handled before the handler has been added.
+    let mut handlers = HANDLERS.lock().unwrap();
+    let port = unsafe { (api.new_native_port)(name.as_ptr(), handle_message, false) };
+    if port == 0 {
+        tracing::error!(?name, "failed to create native port");
+        return None;
+    }
+
+    handlers.insert(port, handler);
+
+    Some(port)
+}
+
+pub fn close(port: i64) -> bool {
+    let Some(api) = DART_API.get() else {
+        return false;
+    };
+
+    let closed = unsafe { (api.close_native_port)(port) };
+
+    // Messages that are still being handled hold on to the handler until they are done.
+    let handler = HANDLERS.lock().unwrap().remove(&port);
+    drop(handler);
+
+    closed
+}
+
+/// Posts a `Dart_CObject` to a Dart port, which (unlike `FlutterEnginePostDartObject`) can also be
+/// an array or a `SendPort`, e.g. to give Dart a native port.
+pub fn post_c_object(port: i64, object: *mut c_void) -> bool {
+    match DART_API.get() {
+        Some(api) => unsafe { (api.post_c_object)(port, object) },
+        None => false,
+    }
+}
+
+/// Handles `flion/native_ports`.
+pub struct NativePortsHandler;
+
+impl StandardMethodHandler for NativePortsHandler {
+    fn handle(&self, method: &str, args: EncodableValue, reply: StandardMethodReply) {
+        match method {
+            "initialize" => {
+                let address = match args {
+                    EncodableValue::I32(address) => address as usize,
+                    EncodableValue::I64(address) => address as usize,
+                    _ => return reply.error("invalid_args", Some("expected an address")),
+                };
+
+                if DART_API.get().is_some() {
+                    return reply.success(&EncodableValue::Null);
+                }
+
+                match unsafe { init_dart_api(address as *const DartApi) } {
+                    Ok(api) => {
+                        let _ = DART_API.set(api);
+                        reply.success(&EncodableValue::Null);
+                    }
+                    Err(e) => reply.error("native_ports_error", Some(&e.to_string())),
+                }
+            }
+            _ => {
+                tracing::warn!(method, "unimplemented");
+                reply.not_implemented();
+            }
+        }
+    }
+}
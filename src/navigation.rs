@@ -0,0 +1,58 @@
+use serde_json::json;
+
+use crate::engine::{BinaryMessageHandler, BinaryMessageReply, FlutterEngine};
+use crate::error;
+
+pub fn set_initial_route(engine: &FlutterEngine, route: &str) -> error::Result<()> {
+    let message = json!({
+        "method": "setInitialRoute",
+        "args": route,
+    });
+
+    engine.send_platform_message(c"flutter/navigation", &serde_json::to_vec(&message)?)
+}
+
+/// Asks the framework to navigate to `location`, e.g. in response to a deep link that arrived
+/// after startup.
+pub fn push_route_information(engine: &FlutterEngine, location: &str) -> error::Result<()> {
+    let message = json!({
+        "method": "pushRouteInformation",
+        "args": {
+            "location": location,
+            "state": null,
+        },
+    });
+
+    engine.send_platform_message(c"flutter/navigation", &serde_json::to_vec(&message)?)
+}
+
+/// Handles route updates reported by the framework. There is no system navigation history on
+/// desktop, so these are acknowledged without any further action.
+pub struct NavigationHandler;
+
+impl BinaryMessageHandler for NavigationHandler {
+    fn handle(&self, message: &[u8], reply: BinaryMessageReply) {
+        #[derive(Debug, serde::Deserialize)]
+        struct Request {
+            method: String,
+        }
+
+        let Ok(req) = serde_json::from_slice::<Request>(message) else {
+            let message = String::from_utf8_lossy(message);
+            tracing::warn!("invalid navigation message: {message}");
+            reply.not_implemented();
+            return;
+        };
+
+        match req.method.as_str() {
+            "routeInformationUpdated" | "selectSingleEntryHistory" | "selectMultiEntryHistory" => {
+                tracing::debug!(method = %req.method, "navigation");
+                reply.send(c"[null]".to_bytes());
+            }
+            method => {
+                tracing::warn!(method, "unimplemented");
+                reply.not_implemented();
+            }
+        }
+    }
+}
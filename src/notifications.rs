@@ -0,0 +1,350 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use color_eyre::eyre::{self, OptionExt};
+use flutter_codec::EncodableValue;
+use windows::core::{ComInterface, IInspectable, HSTRING};
+use windows::Data::Xml::Dom::XmlDocument;
+use windows::Foundation::TypedEventHandler;
+use windows::Win32::UI::Shell::SetCurrentProcessExplicitAppUserModelID;
+use windows::UI::Notifications::{
+    ToastActivatedEventArgs, ToastDismissedEventArgs, ToastNotification, ToastNotificationManager,
+};
+
+use crate::event_channel::EventChannel;
+use crate::paths;
+use crate::registry;
+use crate::standard_method_channel::{StandardMethodHandler, StandardMethodReply};
+
+/// The group that all notifications shown by the app are placed in.
+const GROUP: &str = "flion";
+
+/// Something that happened to a notification, which is sent to the framework on
+/// `flion/notifications/events`.
+#[derive(Debug)]
+pub enum NotificationEvent {
+    /// The notification, or one of its buttons, was clicked.
+    Activated { tag: String, action: Option<String> },
+    /// The notification was closed by the user, timed out, or was hidden by the app.
+    Dismissed { tag: String },
+}
+
+impl NotificationEvent {
+    pub fn send(&self, events: &EventChannel) -> eyre::Result<()> {
+        let mut event = BTreeMap::new();
+
+        match self {
+            NotificationEvent::Activated { tag, action } => {
+                event.insert(
+                    EncodableValue::Str("type"),
+                    EncodableValue::Str("activated"),
+                );
+                event.insert(EncodableValue::Str("tag"), EncodableValue::Str(tag));
+                event.insert(
+                    EncodableValue::Str("action"),
+                    action
+                        .as_deref()
+                        .map(EncodableValue::Str)
+                        .unwrap_or(EncodableValue::Null),
+                );
+            }
+            NotificationEvent::Dismissed { tag } => {
+                event.insert(
+                    EncodableValue::Str("type"),
+                    EncodableValue::Str("dismissed"),
+                );
+                event.insert(EncodableValue::Str("tag"), EncodableValue::Str(tag));
+            }
+        }
+
+        events.send(&EncodableValue::Map(event))
+    }
+}
+
+/// Registers an app user model id for the current user, so that notifications can be shown with
+/// the given name and icon, and applies it to the current process.
+///
+/// Unpackaged apps need this, since toasts are attributed to an app by its id.
+pub fn register_app_user_model_id(
+    aumid: &str,
+    display_name: &str,
+    icon: Option<&Path>,
+) -> eyre::Result<()> {
+    let key = format!("Software\\Classes\\AppUserModelId\\{aumid}");
+
+    registry::set_string_value(&key, Some("DisplayName"), display_name)?;
+
+    if let Some(icon) = icon {
+        let icon = icon.to_str().ok_or_eyre("icon path is not valid unicode")?;
+        registry::set_string_value(&key, Some("IconUri"), icon)?;
+    }
+
+    unsafe { SetCurrentProcessExplicitAppUserModelID(&HSTRING::from(aumid))? };
+
+    Ok(())
+}
+
+/// Handles `flion/notifications`, which shows toast notifications.
+///
+/// Toast events are raised on background threads, and are passed to `on_event` so that they can
+/// be forwarded to the platform thread.
+pub struct NotificationsHandler {
+    aumid: RefCell<Option<String>>,
+    on_event: Arc<dyn Fn(NotificationEvent) + Send + Sync>,
+    /// Notifications that are currently shown. These are kept alive so that their event handlers
+    /// stay registered.
+    notifications: Arc<Mutex<BTreeMap<String, ToastNotification>>>,
+    next_tag: AtomicU64,
+}
+
+#[derive(Default)]
+struct NotificationContent<'a> {
+    tag: Option<&'a str>,
+    title: &'a str,
+    body: Option<&'a str>,
+    image: Option<&'a str>,
+    buttons: Vec<(&'a str, &'a str)>,
+}
+
+impl NotificationsHandler {
+    pub fn new(
+        on_event: impl Fn(NotificationEvent) + Send + Sync + 'static,
+    ) -> NotificationsHandler {
+        NotificationsHandler {
+            aumid: RefCell::new(None),
+            on_event: Arc::new(on_event),
+            notifications: Arc::new(Mutex::new(BTreeMap::new())),
+            next_tag: AtomicU64::new(1),
+        }
+    }
+
+    /// Returns the app user model id, registering one derived from the executable name if the
+    /// app hasn't registered its own.
+    fn aumid(&self) -> eyre::Result<String> {
+        if let Some(aumid) = self.aumid.borrow().as_ref() {
+            return Ok(aumid.clone());
+        }
+
+        let name = paths::app_name()?;
+        let aumid = format!("flion.{name}");
+
+        register_app_user_model_id(&aumid, &name, None)?;
+
+        *self.aumid.borrow_mut() = Some(aumid.clone());
+
+        Ok(aumid)
+    }
+
+    fn show(&self, content: &NotificationContent) -> eyre::Result<String> {
+        let tag = match content.tag {
+            Some(tag) => tag.to_owned(),
+            None => self.next_tag.fetch_add(1, Ordering::Relaxed).to_string(),
+        };
+
+        let xml = XmlDocument::new()?;
+        xml.LoadXml(&HSTRING::from(toast_xml(content)))?;
+
+        let notification = ToastNotification::CreateToastNotification(&xml)?;
+        notification.SetTag(&HSTRING::from(&tag))?;
+        notification.SetGroup(&HSTRING::from(GROUP))?;
+
+        notification.Activated(&TypedEventHandler::<ToastNotification, IInspectable>::new(
+            {
+                let tag = tag.clone();
+                let on_event = self.on_event.clone();
+                move |_, args| {
+                    let action = args
+                        .as_ref()
+                        .and_then(|args| args.cast::<ToastActivatedEventArgs>().ok())
+                        .and_then(|args| args.Arguments().ok())
+                        .map(|args| args.to_string_lossy())
+                        .filter(|args| !args.is_empty());
+
+                    on_event(NotificationEvent::Activated {
+                        tag: tag.clone(),
+                        action,
+                    });
+
+                    Ok(())
+                }
+            },
+        ))?;
+
+        notification.Dismissed(&TypedEventHandler::<
+            ToastNotification,
+            ToastDismissedEventArgs,
+        >::new({
+            let tag = tag.clone();
+            let on_event = self.on_event.clone();
+            let notifications = self.notifications.clone();
+            move |_, _| {
+                notifications.lock().unwrap().remove(&tag);
+                on_event(NotificationEvent::Dismissed { tag: tag.clone() });
+                Ok(())
+            }
+        }))?;
+
+        let notifier =
+            ToastNotificationManager::CreateToastNotifierWithId(&HSTRING::from(self.aumid()?))?;
+
+        notifier.Show(&notification)?;
+
+        self.notifications
+            .lock()
+            .unwrap()
+            .insert(tag.clone(), notification);
+
+        Ok(tag)
+    }
+
+    fn hide(&self, tag: &str) -> eyre::Result<()> {
+        ToastNotificationManager::History()?.RemoveGroupedTagWithId(
+            &HSTRING::from(tag),
+            &HSTRING::from(GROUP),
+            &HSTRING::from(self.aumid()?),
+        )?;
+
+        self.notifications.lock().unwrap().remove(tag);
+
+        Ok(())
+    }
+
+    fn clear(&self) -> eyre::Result<()> {
+        ToastNotificationManager::History()?.ClearWithId(&HSTRING::from(self.aumid()?))?;
+        self.notifications.lock().unwrap().clear();
+        Ok(())
+    }
+}
+
+impl StandardMethodHandler for NotificationsHandler {
+    fn handle(&self, method: &str, args: EncodableValue, reply: StandardMethodReply) {
+        match method {
+            "register" => {
+                let Some(aumid) = args.get("appUserModelId").and_then(|v| v.as_string()) else {
+                    return reply.error("invalid_args", Some("expected an app user model id"));
+                };
+
+                let Some(display_name) = args.get("displayName").and_then(|v| v.as_string()) else {
+                    return reply.error("invalid_args", Some("expected a display name"));
+                };
+
+                let icon = args
+                    .get("iconPath")
+                    .and_then(|v| v.as_string())
+                    .map(Path::new);
+
+                match register_app_user_model_id(aumid, display_name, icon) {
+                    Ok(()) => {
+                        *self.aumid.borrow_mut() = Some(aumid.to_owned());
+                        reply.success(&EncodableValue::Null);
+                    }
+                    Err(e) => reply.error("register_failed", Some(&format!("{e:?}"))),
+                }
+            }
+            "show" => {
+                let Some(title) = args.get("title").and_then(|v| v.as_string()) else {
+                    return reply.error("invalid_args", Some("expected a title"));
+                };
+
+                let mut content = NotificationContent {
+                    tag: args.get("tag").and_then(|v| v.as_string()),
+                    title,
+                    body: args.get("body").and_then(|v| v.as_string()),
+                    image: args.get("imagePath").and_then(|v| v.as_string()),
+                    ..Default::default()
+                };
+
+                if let Some(EncodableValue::List(buttons)) = args.get("buttons") {
+                    for button in buttons {
+                        let id = button.get("id").and_then(|v| v.as_string());
+                        let label = button.get("label").and_then(|v| v.as_string());
+                        let (Some(id), Some(label)) = (id, label) else {
+                            return reply
+                                .error("invalid_args", Some("buttons need an id and a label"));
+                        };
+                        content.buttons.push((id, label));
+                    }
+                }
+
+                match self.show(&content) {
+                    Ok(tag) => reply.success(&EncodableValue::Str(&tag)),
+                    Err(e) => reply.error("show_failed", Some(&format!("{e:?}"))),
+                }
+            }
+            "hide" => {
+                let Some(tag) = args.as_string() else {
+                    return reply.error("invalid_args", Some("expected a tag"));
+                };
+
+                match self.hide(tag) {
+                    Ok(()) => reply.success(&EncodableValue::Null),
+                    Err(e) => reply.error("hide_failed", Some(&format!("{e:?}"))),
+                }
+            }
+            "clear" => match self.clear() {
+                Ok(()) => reply.success(&EncodableValue::Null),
+                Err(e) => reply.error("hide_failed", Some(&format!("{e:?}"))),
+            },
+            _ => {
+                tracing::warn!(method, "unimplemented");
+                reply.not_implemented();
+            }
+        }
+    }
+}
+
+/// Builds the toast content, using the generic template.
+///
+/// See https://learn.microsoft.com/en-us/windows/apps/design/shell/tiles-and-notifications/adaptive-interactive-toasts.
+fn toast_xml(content: &NotificationContent) -> String {
+    let mut xml = String::from("<toast><visual><binding template=\"ToastGeneric\">");
+
+    xml += &format!("<text>{}</text>", escape(content.title));
+
+    if let Some(body) = content.body {
+        xml += &format!("<text>{}</text>", escape(body));
+    }
+
+    if let Some(image) = content.image {
+        xml += &format!(
+            "<image placement=\"appLogoOverride\" src=\"file:///{}\"/>",
+            escape(&image.replace('\\', "/"))
+        );
+    }
+
+    xml += "</binding></visual>";
+
+    if !content.buttons.is_empty() {
+        xml += "<actions>";
+        // The button id is passed back as the activation arguments.
+        for (id, label) in &content.buttons {
+            xml += &format!(
+                "<action content=\"{}\" arguments=\"{}\" activationType=\"foreground\"/>",
+                escape(label),
+                escape(id)
+            );
+        }
+        xml += "</actions>";
+    }
+
+    xml += "</toast>";
+    xml
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped += "&amp;",
+            '<' => escaped += "&lt;",
+            '>' => escaped += "&gt;",
+            '"' => escaped += "&quot;",
+            '\'' => escaped += "&apos;",
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,41 @@
+use std::path::PathBuf;
+
+use color_eyre::eyre;
+use flutter_codec::EncodableValue;
+use windows::Win32::UI::Shell::{FOLDERID_Documents, FOLDERID_Downloads};
+
+use crate::paths;
+use crate::standard_method_channel::{StandardMethodHandler, StandardMethodReply};
+
+/// Handles `plugins.flutter.io/path_provider`, so that the `path_provider` package can be used
+/// without its native plugin.
+pub struct PathProviderHandler;
+
+impl PathProviderHandler {
+    fn path(method: &str) -> Option<eyre::Result<PathBuf>> {
+        let path = match method {
+            "getTemporaryDirectory" => Ok(std::env::temp_dir()),
+            "getApplicationSupportDirectory" => paths::app_data_dir(),
+            "getApplicationCacheDirectory" => paths::local_app_data_dir(),
+            "getApplicationDocumentsDirectory" => paths::known_folder(&FOLDERID_Documents),
+            "getDownloadsDirectory" => paths::known_folder(&FOLDERID_Downloads),
+            _ => return None,
+        };
+
+        Some(path)
+    }
+}
+
+impl StandardMethodHandler for PathProviderHandler {
+    fn handle(&self, method: &str, _args: EncodableValue, reply: StandardMethodReply) {
+        let Some(path) = Self::path(method) else {
+            tracing::warn!(method, "unimplemented");
+            return reply.not_implemented();
+        };
+
+        match path {
+            Ok(path) => reply.success(&EncodableValue::Str(&path.to_string_lossy())),
+            Err(e) => reply.error("path_error", Some(&format!("{e:?}"))),
+        }
+    }
+}
@@ -0,0 +1,40 @@
+use std::path::PathBuf;
+
+use color_eyre::eyre::{self, OptionExt};
+use windows::core::GUID;
+use windows::Win32::System::Com::CoTaskMemFree;
+use windows::Win32::UI::Shell::{
+    FOLDERID_LocalAppData, FOLDERID_RoamingAppData, SHGetKnownFolderPath, KF_FLAG_DEFAULT,
+};
+
+pub fn known_folder(id: &GUID) -> eyre::Result<PathBuf> {
+    unsafe {
+        let path = SHGetKnownFolderPath(id, KF_FLAG_DEFAULT, None)?;
+        let res = path.to_string();
+        CoTaskMemFree(Some(path.0 as *const _));
+        Ok(PathBuf::from(res?))
+    }
+}
+
+/// The name used to isolate per-app data, derived from the executable name.
+pub fn app_name() -> eyre::Result<String> {
+    let exe = std::env::current_exe()?;
+    let name = exe.file_stem().ok_or_eyre("invalid executable path")?;
+    Ok(name.to_string_lossy().into_owned())
+}
+
+/// Per-user directory for app data, e.g. `%APPDATA%\<app>`. The directory is created if it
+/// doesn't exist.
+pub fn app_data_dir() -> eyre::Result<PathBuf> {
+    let dir = known_folder(&FOLDERID_RoamingAppData)?.join(app_name()?);
+    std::fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+/// Per-user directory for data that doesn't roam, e.g. `%LOCALAPPDATA%\<app>`. The directory is
+/// created if it doesn't exist.
+pub fn local_app_data_dir() -> eyre::Result<PathBuf> {
+    let dir = known_folder(&FOLDERID_LocalAppData)?.join(app_name()?);
+    std::fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
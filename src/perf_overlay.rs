@@ -0,0 +1,322 @@
+use std::cell::RefCell;
+use std::ffi::c_void;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use color_eyre::eyre::{self, bail};
+use flutter_codec::EncodableValue;
+use windows::core::ComInterface;
+use windows::Foundation::Numerics::{Vector2, Vector3};
+use windows::Foundation::Size;
+use windows::Graphics::DirectX::{DirectXAlphaMode, DirectXPixelFormat};
+use windows::Win32::Foundation::{COLORREF, POINT};
+use windows::Win32::Graphics::Direct3D11::{ID3D11Device, ID3D11Texture2D, D3D11_BOX};
+use windows::Win32::Graphics::Dxgi::{IDXGIAdapter3, IDXGIDevice, DXGI_MEMORY_SEGMENT_GROUP_LOCAL};
+use windows::Win32::Graphics::Gdi::{
+    CreateCompatibleDC, CreateDIBSection, DeleteDC, DeleteObject, GdiFlush, GetStockObject,
+    SelectObject, SetBkMode, SetTextColor, TextOutW, BITMAPINFO, BITMAPINFOHEADER, BI_RGB,
+    DEFAULT_GUI_FONT, DIB_RGB_COLORS, TRANSPARENT,
+};
+use windows::Win32::System::WinRT::Composition::{
+    ICompositionDrawingSurfaceInterop, ICompositorInterop,
+};
+use windows::UI::Composition::{
+    CompositionDrawingSurface, CompositionGraphicsDevice, ContainerVisual, SpriteVisual,
+};
+
+use crate::commit_batcher::CommitBatcher;
+use crate::frame_stats::{self, Snapshot};
+use crate::standard_method_channel::{StandardMethodHandler, StandardMethodReply};
+
+/// Size of the overlay in physical pixels.
+const WIDTH: u32 = 240;
+const HEIGHT: u32 = 84;
+
+/// Distance of the overlay from the top left corner of the window.
+const MARGIN: f32 = 8.0;
+
+const LINE_HEIGHT: i32 = 18;
+const PADDING: i32 = 6;
+
+/// Opacity of the overlay's background, out of 255.
+const BACKGROUND_ALPHA: u32 = 180;
+
+const UPDATE_INTERVAL: Duration = Duration::from_millis(500);
+
+/// A heads up display showing frame statistics, drawn into its own visual above Flutter's layers
+/// so that it doesn't affect (or depend on) the frames that it is measuring.
+pub struct PerfOverlay {
+    commits: Arc<CommitBatcher>,
+    device: ID3D11Device,
+    parent: ContainerVisual,
+    visual: SpriteVisual,
+    surface: CompositionDrawingSurface,
+    visible: bool,
+    last_update: Option<(Instant, Snapshot)>,
+}
+
+impl PerfOverlay {
+    pub fn new(
+        commits: &Arc<CommitBatcher>,
+        device: &ID3D11Device,
+        parent: &ContainerVisual,
+    ) -> eyre::Result<PerfOverlay> {
+        let compositor = commits.compositor_controller().Compositor()?;
+
+        let graphics_device: CompositionGraphicsDevice = unsafe {
+            compositor
+                .cast::<ICompositorInterop>()?
+                .CreateGraphicsDevice(device)?
+        };
+
+        let surface = graphics_device.CreateDrawingSurface(
+            Size {
+                Width: WIDTH as f32,
+                Height: HEIGHT as f32,
+            },
+            DirectXPixelFormat::B8G8R8A8UIntNormalized,
+            DirectXAlphaMode::Premultiplied,
+        )?;
+
+        let visual = compositor.CreateSpriteVisual()?;
+        visual.SetSize(Vector2::new(WIDTH as f32, HEIGHT as f32))?;
+        visual.SetBrush(&compositor.CreateSurfaceBrushWithSurface(&surface)?)?;
+
+        // The parent is flipped vertically, so the overlay is anchored to its bottom edge to
+        // appear at the top of the window.
+        visual.SetRelativeOffsetAdjustment(Vector3::new(0.0, 1.0, 0.0))?;
+        visual.SetOffset(Vector3::new(MARGIN, -MARGIN - HEIGHT as f32, 0.0))?;
+
+        Ok(PerfOverlay {
+            commits: commits.clone(),
+            device: device.clone(),
+            parent: parent.clone(),
+            visual,
+            surface,
+            visible: false,
+            last_update: None,
+        })
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    pub fn set_visible(&mut self, visible: bool) -> eyre::Result<()> {
+        if visible == self.visible {
+            return Ok(());
+        }
+
+        if visible {
+            self.last_update = None;
+            self.parent.Children()?.InsertAtTop(&self.visual)?;
+        } else {
+            self.parent.Children()?.Remove(&self.visual)?;
+        }
+
+        self.visible = visible;
+        self.commits.request_commit();
+
+        Ok(())
+    }
+
+    /// Redraws the overlay if it is visible and due to be updated. Returns when it should next be
+    /// updated.
+    pub fn update(&mut self) -> eyre::Result<Option<Instant>> {
+        if !self.visible {
+            return Ok(None);
+        }
+
+        let now = Instant::now();
+        let stats = frame_stats::snapshot();
+
+        let fps = match self.last_update {
+            Some((time, _)) if now < time + UPDATE_INTERVAL => {
+                return Ok(Some(time + UPDATE_INTERVAL));
+            }
+            Some((time, last)) => {
+                let frames = stats.frames_presented - last.frames_presented;
+                Some(frames as f64 / (now - time).as_secs_f64())
+            }
+            None => None,
+        };
+
+        self.last_update = Some((now, stats));
+
+        let gpu_memory = match self.gpu_memory_usage() {
+            Ok(bytes) => format!("{:.1} MB", bytes as f64 / (1024.0 * 1024.0)),
+            Err(_) => "n/a".to_owned(),
+        };
+
+        let lines = [
+            match fps {
+                Some(fps) => format!("FPS: {fps:.1}"),
+                None => "FPS: -".to_owned(),
+            },
+            format!(
+                "Present latency: {:.1} ms",
+                stats.present_latency.as_secs_f64() * 1000.0
+            ),
+            format!(
+                "Resize stalls: {} (max {} ms)",
+                stats.resize_stalls,
+                stats.longest_resize_stall.as_millis()
+            ),
+            format!("GPU memory: {gpu_memory}"),
+        ];
+
+        self.draw(&lines)?;
+        self.commits.request_commit();
+
+        Ok(Some(now + UPDATE_INTERVAL))
+    }
+
+    fn gpu_memory_usage(&self) -> eyre::Result<u64> {
+        let adapter = unsafe { self.device.cast::<IDXGIDevice>()?.GetAdapter()? };
+        let mut info = Default::default();
+        unsafe {
+            adapter.cast::<IDXGIAdapter3>()?.QueryVideoMemoryInfo(
+                0,
+                DXGI_MEMORY_SEGMENT_GROUP_LOCAL,
+                &mut info,
+            )?
+        };
+        Ok(info.CurrentUsage)
+    }
+
+    fn draw(&self, lines: &[String]) -> eyre::Result<()> {
+        let pixels = unsafe { render_text(lines)? };
+
+        let interop = self.surface.cast::<ICompositionDrawingSurfaceInterop>()?;
+
+        unsafe {
+            let mut offset = POINT::default();
+            let texture: ID3D11Texture2D = interop.BeginDraw(None, &mut offset)?;
+
+            let mut context = None;
+            self.device.GetImmediateContext(&mut context);
+
+            if let Some(context) = context {
+                context.UpdateSubresource(
+                    &texture,
+                    0,
+                    Some(&D3D11_BOX {
+                        left: offset.x as u32,
+                        top: offset.y as u32,
+                        front: 0,
+                        right: offset.x as u32 + WIDTH,
+                        bottom: offset.y as u32 + HEIGHT,
+                        back: 1,
+                    }),
+                    pixels.as_ptr() as *const c_void,
+                    WIDTH * 4,
+                    0,
+                );
+            }
+
+            interop.EndDraw()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Draws white text onto a translucent black background with GDI, returning premultiplied BGRA
+/// pixels.
+unsafe fn render_text(lines: &[String]) -> eyre::Result<Vec<u8>> {
+    let dc = CreateCompatibleDC(None);
+    if dc.is_invalid() {
+        bail!("failed to create device context");
+    }
+
+    let info = BITMAPINFO {
+        bmiHeader: BITMAPINFOHEADER {
+            biSize: std::mem::size_of::<BITMAPINFOHEADER>() as u32,
+            biWidth: WIDTH as i32,
+            // Negative for rows from top to bottom.
+            biHeight: -(HEIGHT as i32),
+            biPlanes: 1,
+            biBitCount: 32,
+            biCompression: BI_RGB.0,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    let mut bits = std::ptr::null_mut();
+    let bitmap = match CreateDIBSection(dc, &info, DIB_RGB_COLORS, &mut bits, None, 0) {
+        Ok(bitmap) => bitmap,
+        Err(e) => {
+            DeleteDC(dc);
+            return Err(e.into());
+        }
+    };
+
+    let previous_bitmap = SelectObject(dc, bitmap);
+    let previous_font = SelectObject(dc, GetStockObject(DEFAULT_GUI_FONT));
+
+    SetTextColor(dc, COLORREF(0x00ffffff));
+    SetBkMode(dc, TRANSPARENT);
+
+    for (i, line) in lines.iter().enumerate() {
+        let text = line.encode_utf16().collect::<Vec<_>>();
+        TextOutW(dc, PADDING, PADDING + i as i32 * LINE_HEIGHT, &text);
+    }
+
+    GdiFlush();
+
+    let stride = (WIDTH * 4) as usize;
+    let drawn = std::slice::from_raw_parts(bits.cast::<u8>(), stride * HEIGHT as usize);
+
+    // GDI doesn't write alpha, so the coverage of each pixel is taken from its brightness and the
+    // text is composited over the background. The root visual is flipped vertically, so the rows
+    // are flipped too.
+    let pixels = drawn
+        .chunks_exact(stride)
+        .rev()
+        .flat_map(|row| row.chunks_exact(4))
+        .flat_map(|pixel| {
+            let coverage = pixel[0].max(pixel[1]).max(pixel[2]) as u32;
+            let alpha = coverage + (255 - coverage) * BACKGROUND_ALPHA / 255;
+            let value = coverage as u8;
+            [value, value, value, alpha as u8]
+        })
+        .collect();
+
+    SelectObject(dc, previous_font);
+    SelectObject(dc, previous_bitmap);
+    DeleteObject(bitmap);
+    DeleteDC(dc);
+
+    Ok(pixels)
+}
+
+/// Handles `flion/perf_overlay`, which shows and hides the performance overlay.
+pub struct PerfOverlayHandler(pub Rc<RefCell<PerfOverlay>>);
+
+impl StandardMethodHandler for PerfOverlayHandler {
+    fn handle(&self, method: &str, _args: EncodableValue, reply: StandardMethodReply) {
+        let mut overlay = self.0.borrow_mut();
+
+        let visible = match method {
+            "show" => true,
+            "hide" => false,
+            "toggle" => !overlay.is_visible(),
+            "isVisible" => return reply.success(&EncodableValue::Bool(overlay.is_visible())),
+            _ => {
+                tracing::warn!(method, "unimplemented");
+                return reply.not_implemented();
+            }
+        };
+
+        let res = overlay
+            .set_visible(visible)
+            .and_then(|_| overlay.update().map(|_| ()));
+
+        match res {
+            Ok(()) => reply.success(&EncodableValue::Bool(visible)),
+            Err(e) => reply.error("overlay_error", Some(&format!("{e:?}"))),
+        }
+    }
+}
@@ -0,0 +1,343 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use color_eyre::eyre;
+use serde::Deserialize;
+use serde_json::json;
+use windows::core::HSTRING;
+use windows::Win32::Foundation::{HWND, POINT, RECT};
+use windows::Win32::Graphics::Gdi::ClientToScreen;
+use windows::Win32::UI::WindowsAndMessaging::{
+    AppendMenuW, CreatePopupMenu, DestroyMenu, EndMenu, TrackPopupMenuEx, MF_GRAYED, MF_STRING,
+    TPMPARAMS, TPM_LEFTALIGN, TPM_NONOTIFY, TPM_RETURNCMD, TPM_TOPALIGN, TPM_VERTICAL,
+};
+use winit::window::Window;
+
+use crate::app_exit::{AppExit, ExitType};
+use crate::clipboard;
+use crate::engine::{BinaryMessageHandler, BinaryMessageReply, BinaryMessenger};
+use crate::error_utils::ResultExt;
+use crate::text_input::TextInputState;
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "method", content = "args")]
+enum PlatformRequest {
+    #[serde(rename = "Clipboard.getData")]
+    GetClipboardData(String),
+    #[serde(rename = "Clipboard.setData")]
+    SetClipboardData(ClipboardData),
+    #[serde(rename = "Clipboard.hasStrings")]
+    ClipboardHasStrings(#[allow(unused)] String),
+    #[serde(rename = "ContextMenu.showSystemContextMenu")]
+    ShowSystemContextMenu(ContextMenuArgs),
+    #[serde(rename = "ContextMenu.hideSystemContextMenu")]
+    HideSystemContextMenu,
+    #[serde(rename = "System.initializationComplete")]
+    InitializationComplete,
+    #[serde(rename = "System.exitApplication")]
+    ExitApplication(ExitApplicationArgs),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ExitApplicationArgs {
+    #[serde(rename = "type")]
+    exit_type: ExitType,
+    #[serde(default)]
+    exit_code: i32,
+}
+
+#[derive(Debug, Deserialize)]
+struct ClipboardData {
+    text: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ContextMenuArgs {
+    target_rect: TargetRect,
+    /// Older versions of the framework don't send any items, and expect the default ones.
+    #[serde(default)]
+    items: Vec<ContextMenuItem>,
+}
+
+/// The area of the selection that the menu is shown for, in logical pixels.
+#[derive(Debug, Deserialize)]
+struct TargetRect {
+    x: f64,
+    y: f64,
+    width: f64,
+    height: f64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ContextMenuItem {
+    #[serde(rename = "type")]
+    kind: String,
+    title: Option<String>,
+    callback_id: Option<serde_json::Value>,
+}
+
+impl ContextMenuItem {
+    fn builtin(kind: &str) -> ContextMenuItem {
+        ContextMenuItem {
+            kind: kind.to_owned(),
+            title: None,
+            callback_id: None,
+        }
+    }
+}
+
+/// Handles `flutter/platform`, which is used for the clipboard, the system context menu and
+/// exiting the app.
+pub struct PlatformHandler {
+    hwnd: HWND,
+    window: Rc<Window>,
+    text_input: Rc<RefCell<TextInputState>>,
+    app_exit: AppExit,
+    on_exit_requested: Box<dyn Fn(ExitType, i32)>,
+}
+
+impl PlatformHandler {
+    /// `on_exit_requested` is called with the exit type and code when the framework asks for the
+    /// app to exit, which should then go through [`AppExit::request`].
+    pub fn new(
+        hwnd: HWND,
+        window: Rc<Window>,
+        text_input: Rc<RefCell<TextInputState>>,
+        app_exit: AppExit,
+        on_exit_requested: impl Fn(ExitType, i32) + 'static,
+    ) -> PlatformHandler {
+        PlatformHandler {
+            hwnd,
+            window,
+            text_input,
+            app_exit,
+            on_exit_requested: Box::new(on_exit_requested),
+        }
+    }
+
+    /// Shows a popup menu below the selection, and performs the chosen item. The menu is modal,
+    /// so this only returns once it has been dismissed.
+    fn show_context_menu(
+        &self,
+        args: ContextMenuArgs,
+        messenger: &BinaryMessenger,
+    ) -> eyre::Result<()> {
+        let items = if args.items.is_empty() {
+            ["cut", "copy", "paste", "selectAll"]
+                .map(ContextMenuItem::builtin)
+                .into()
+        } else {
+            args.items
+        };
+
+        let (has_client, has_selection) = {
+            let text_input = self.text_input.borrow();
+            (
+                text_input.has_client(),
+                text_input.selected_text().is_some(),
+            )
+        };
+
+        let menu = unsafe { CreatePopupMenu()? };
+
+        for (i, item) in items.iter().enumerate() {
+            let (label, enabled) = match item.kind.as_str() {
+                "cut" => ("Cu&t", has_selection),
+                "copy" => ("&Copy", has_selection),
+                "paste" => ("&Paste", has_client && clipboard::has_text()),
+                "selectAll" => ("Select &All", has_client),
+                "custom" => ("", item.callback_id.is_some()),
+                kind => {
+                    tracing::debug!(kind, "unsupported context menu item");
+                    continue;
+                }
+            };
+
+            let label = HSTRING::from(item.title.as_deref().unwrap_or(label));
+            let flags = if enabled {
+                MF_STRING
+            } else {
+                MF_STRING | MF_GRAYED
+            };
+
+            // Menu ids start at 1, since 0 means that the menu was dismissed.
+            unsafe { AppendMenuW(menu, flags, i + 1, &label)? };
+        }
+
+        let scale_factor = self.window.scale_factor();
+        let mut origin = POINT::default();
+        unsafe { ClientToScreen(self.hwnd, &mut origin) };
+
+        let rect = args.target_rect;
+        let exclude = RECT {
+            left: origin.x + (rect.x * scale_factor) as i32,
+            top: origin.y + (rect.y * scale_factor) as i32,
+            right: origin.x + ((rect.x + rect.width) * scale_factor) as i32,
+            bottom: origin.y + ((rect.y + rect.height) * scale_factor) as i32,
+        };
+
+        // The menu prefers to open below the selection, without covering it.
+        let params = TPMPARAMS {
+            cbSize: std::mem::size_of::<TPMPARAMS>() as u32,
+            rcExclude: exclude,
+        };
+
+        let selected = unsafe {
+            let selected = TrackPopupMenuEx(
+                menu,
+                (TPM_LEFTALIGN | TPM_TOPALIGN | TPM_VERTICAL | TPM_RETURNCMD | TPM_NONOTIFY).0,
+                exclude.left,
+                exclude.bottom,
+                self.hwnd,
+                Some(&params as *const _),
+            );
+
+            DestroyMenu(menu)?;
+
+            selected.0 as usize
+        };
+
+        send_platform_message(
+            messenger,
+            "ContextMenu.onDismissSystemContextMenu",
+            json!(null),
+        )?;
+
+        if let Some(item) = selected.checked_sub(1).and_then(|i| items.get(i)) {
+            self.perform_context_menu_item(item, messenger)?;
+        }
+
+        Ok(())
+    }
+
+    fn perform_context_menu_item(
+        &self,
+        item: &ContextMenuItem,
+        messenger: &BinaryMessenger,
+    ) -> eyre::Result<()> {
+        let mut text_input = self.text_input.borrow_mut();
+
+        match item.kind.as_str() {
+            "cut" | "copy" => {
+                if let Some(text) = text_input.selected_text() {
+                    clipboard::set_text(self.hwnd, text)?;
+                }
+
+                if item.kind == "cut" {
+                    text_input.delete_selected();
+                    text_input.send_editing_state(messenger)?;
+                }
+            }
+            "paste" => {
+                if let Some(text) = clipboard::get_text(self.hwnd)? {
+                    text_input.insert_text(&text);
+                    text_input.send_editing_state(messenger)?;
+                }
+            }
+            "selectAll" => {
+                text_input.select_all();
+                text_input.send_editing_state(messenger)?;
+            }
+            "custom" => {
+                send_platform_message(
+                    messenger,
+                    "ContextMenu.onPerformCustomAction",
+                    json!([item.callback_id]),
+                )?;
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
+
+impl BinaryMessageHandler for PlatformHandler {
+    fn handle(&self, message: &[u8], reply: BinaryMessageReply) {
+        let Ok(req) = serde_json::from_slice::<PlatformRequest>(message) else {
+            let message = String::from_utf8_lossy(message);
+            tracing::warn!("unimplemented: {message}");
+            reply.not_implemented();
+            return;
+        };
+
+        tracing::debug!("{req:?}");
+
+        match req {
+            PlatformRequest::GetClipboardData(format) => {
+                if format != "text/plain" {
+                    return send_json(reply, json!([null]));
+                }
+
+                match clipboard::get_text(self.hwnd) {
+                    Ok(Some(text)) => send_json(reply, json!([{ "text": text }])),
+                    Ok(None) => send_json(reply, json!([null])),
+                    Err(e) => send_error(reply, "clipboard_error", &e),
+                }
+            }
+            PlatformRequest::SetClipboardData(data) => {
+                match clipboard::set_text(self.hwnd, data.text.as_deref().unwrap_or_default()) {
+                    Ok(()) => send_json(reply, json!([null])),
+                    Err(e) => send_error(reply, "clipboard_error", &e),
+                }
+            }
+            PlatformRequest::ClipboardHasStrings(_) => {
+                send_json(reply, json!([{ "value": clipboard::has_text() }]));
+            }
+            PlatformRequest::ShowSystemContextMenu(args) => {
+                // The framework isn't waiting on the menu, so reply before it is shown.
+                let messenger = reply.messenger();
+                send_json(reply, json!([null]));
+
+                let _ = self.show_context_menu(args, &messenger).trace_err();
+            }
+            PlatformRequest::HideSystemContextMenu => {
+                // The menu can only be open here if this arrived while its modal loop is running.
+                let _ = unsafe { EndMenu() };
+                send_json(reply, json!([null]));
+            }
+            PlatformRequest::InitializationComplete => {
+                self.app_exit.set_initialized();
+                send_json(reply, json!([null]));
+            }
+            PlatformRequest::ExitApplication(args) => {
+                // A cancelable exit is only decided once the framework has responded to
+                // `System.requestAppExit`, so it is reported as cancelled for now.
+                let response = match args.exit_type {
+                    ExitType::Required => "exit",
+                    ExitType::Cancelable => "cancel",
+                };
+
+                send_json(reply, json!([{ "response": response }]));
+                (self.on_exit_requested)(args.exit_type, args.exit_code);
+            }
+        }
+    }
+}
+
+fn send_platform_message(
+    messenger: &BinaryMessenger,
+    method: &str,
+    args: serde_json::Value,
+) -> eyre::Result<()> {
+    let message = json!({
+        "method": method,
+        "args": args,
+    });
+
+    messenger.send_platform_message(c"flutter/platform", &serde_json::to_vec(&message)?)?;
+
+    Ok(())
+}
+
+fn send_json(reply: BinaryMessageReply, value: serde_json::Value) {
+    reply.send(&serde_json::to_vec(&value).unwrap());
+}
+
+fn send_error(reply: BinaryMessageReply, code: &str, error: &eyre::Report) {
+    tracing::error!("{error:?}");
+    send_json(reply, json!([code, error.to_string(), null]));
+}
@@ -0,0 +1,155 @@
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeSet;
+use std::rc::Rc;
+
+use color_eyre::eyre;
+use flutter_codec::EncodableValue;
+use windows::core::{HSTRING, PCWSTR};
+use windows::Win32::Foundation::{HWND, WPARAM};
+use windows::Win32::UI::WindowsAndMessaging::{
+    AppendMenuW, CreateMenu, CreatePopupMenu, DestroyMenu, DrawMenuBar, SetMenu, HMENU,
+    MENU_ITEM_FLAGS, MF_GRAYED, MF_POPUP, MF_SEPARATOR, MF_STRING,
+};
+
+use crate::engine::FlutterEngine;
+use crate::standard_method_channel::{
+    encode_method_call, StandardMethodHandler, StandardMethodReply,
+};
+
+/// Implements the `flutter/menu` channel used by `PlatformMenuBar`, by building a native menu bar
+/// for the window.
+#[derive(Clone)]
+pub struct PlatformMenuHandler {
+    inner: Rc<Inner>,
+}
+
+struct Inner {
+    hwnd: HWND,
+    menu: Cell<Option<HMENU>>,
+    /// The ids of the items in the current menu bar, which are the only commands reported to the
+    /// framework.
+    ids: RefCell<BTreeSet<u16>>,
+}
+
+impl PlatformMenuHandler {
+    pub fn new(hwnd: HWND) -> PlatformMenuHandler {
+        PlatformMenuHandler {
+            inner: Rc::new(Inner {
+                hwnd,
+                menu: Cell::new(None),
+                ids: RefCell::new(BTreeSet::new()),
+            }),
+        }
+    }
+
+    unsafe fn set_menus(&self, items: &[EncodableValue]) -> eyre::Result<()> {
+        let mut ids = BTreeSet::new();
+
+        let menu = if items.is_empty() {
+            None
+        } else {
+            let menu = CreateMenu()?;
+            append_items(menu, items, &mut ids)?;
+            Some(menu)
+        };
+
+        SetMenu(self.inner.hwnd, menu.unwrap_or_default())?;
+        DrawMenuBar(self.inner.hwnd)?;
+
+        self.inner.ids.replace(ids);
+
+        if let Some(prev) = self.inner.menu.replace(menu) {
+            DestroyMenu(prev)?;
+        }
+
+        Ok(())
+    }
+
+    /// Handles a `WM_COMMAND` message, notifying the framework if it originated from an item in
+    /// the menu bar.
+    pub fn handle_command(&self, engine: &FlutterEngine, wparam: WPARAM) -> eyre::Result<bool> {
+        let source = (wparam.0 >> 16) & 0xffff;
+        if source != 0 {
+            return Ok(false);
+        }
+
+        let id = (wparam.0 & 0xffff) as u16;
+
+        // Other menus, such as the system menu, send commands for ids the framework didn't set.
+        if !self.inner.ids.borrow().contains(&id) {
+            return Ok(false);
+        }
+
+        engine.send_platform_message(
+            c"flutter/menu",
+            &encode_method_call("Menu.selectedCallback", &EncodableValue::I32(id.into())),
+        )?;
+
+        Ok(true)
+    }
+}
+
+impl StandardMethodHandler for PlatformMenuHandler {
+    fn handle(&self, method: &str, args: EncodableValue, reply: StandardMethodReply) {
+        match method {
+            "Menu.isPluginAvailable" => reply.success(&EncodableValue::Bool(true)),
+            "Menu.setMenus" => {
+                // The menus are keyed by view id, but there is only ever a single view.
+                let items = args.get("0").and_then(|v| v.as_list()).unwrap_or_default();
+
+                match unsafe { self.set_menus(items) } {
+                    Ok(()) => reply.success(&EncodableValue::Null),
+                    Err(e) => reply.error("menu_error", Some(&e.to_string())),
+                }
+            }
+            _ => {
+                tracing::warn!(method, "unimplemented");
+                reply.not_implemented();
+            }
+        }
+    }
+}
+
+unsafe fn append_items(
+    menu: HMENU,
+    items: &[EncodableValue],
+    ids: &mut BTreeSet<u16>,
+) -> eyre::Result<()> {
+    for item in items {
+        if let Some(EncodableValue::Bool(true)) = item.get("isDivider") {
+            AppendMenuW(menu, MF_SEPARATOR, 0, PCWSTR::null())?;
+            continue;
+        }
+
+        let label = HSTRING::from(item.get("label").and_then(|v| v.as_string()).unwrap_or(""));
+
+        let mut flags = MENU_ITEM_FLAGS::default();
+        if let Some(EncodableValue::Bool(false)) = item.get("enabled") {
+            flags |= MF_GRAYED;
+        }
+
+        if let Some(children) = item.get("children").and_then(|v| v.as_list()) {
+            let submenu = CreatePopupMenu()?;
+            append_items(submenu, children, ids)?;
+            AppendMenuW(menu, flags | MF_POPUP, submenu.0 as usize, &label)?;
+        } else {
+            let Some(id) = item.get("id").and_then(as_id) else {
+                tracing::warn!("menu item without an id: {item:?}");
+                continue;
+            };
+
+            AppendMenuW(menu, flags | MF_STRING, id as usize, &label)?;
+            ids.insert(id);
+        }
+    }
+
+    Ok(())
+}
+
+fn as_id(value: &EncodableValue) -> Option<u16> {
+    match value {
+        EncodableValue::I32(v) => (*v).try_into().ok(),
+        EncodableValue::I64(v) => (*v).try_into().ok(),
+        _ => None,
+    }
+}
@@ -0,0 +1,154 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::io::Cursor;
+use std::sync::{Arc, Mutex};
+
+use color_eyre::eyre;
+use flutter_codec::EncodableValue;
+use windows::UI::Composition::{Compositor, ContainerVisual};
+
+use crate::standard_method_channel::{StandardMethodHandler, StandardMethodReply};
+
+/// Creates the native content for a type of platform view.
+pub trait PlatformViewFactory {
+    /// Creates a view, placing its content in `visual`. The visual is positioned and sized by the
+    /// compositor to match the platform view layer.
+    fn create(
+        &self,
+        id: i64,
+        visual: &ContainerVisual,
+        params: &EncodableValue,
+    ) -> eyre::Result<()>;
+
+    fn dispose(&self, id: i64);
+}
+
+/// The visuals of the platform views that currently exist, which are shared with the compositor
+/// so that they can be placed among the flutter layers.
+pub struct PlatformViewRegistry {
+    visuals: Mutex<BTreeMap<i64, ContainerVisual>>,
+}
+
+impl PlatformViewRegistry {
+    pub fn new() -> Arc<PlatformViewRegistry> {
+        Arc::new(PlatformViewRegistry {
+            visuals: Mutex::new(BTreeMap::new()),
+        })
+    }
+
+    pub fn visual(&self, id: i64) -> Option<ContainerVisual> {
+        self.visuals.lock().unwrap().get(&id).cloned()
+    }
+}
+
+/// Handles `flutter/platform_views`, which the framework uses to create and dispose platform
+/// views.
+pub struct PlatformViewsHandler {
+    compositor: Compositor,
+    registry: Arc<PlatformViewRegistry>,
+    factories: BTreeMap<String, Box<dyn PlatformViewFactory>>,
+    /// The view type of each view, so that it can be disposed by the right factory.
+    views: RefCell<BTreeMap<i64, String>>,
+}
+
+impl PlatformViewsHandler {
+    pub fn new(
+        compositor: Compositor,
+        registry: Arc<PlatformViewRegistry>,
+    ) -> PlatformViewsHandler {
+        PlatformViewsHandler {
+            compositor,
+            registry,
+            factories: BTreeMap::new(),
+            views: RefCell::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn register_factory(&mut self, view_type: &str, factory: Box<dyn PlatformViewFactory>) {
+        self.factories.insert(view_type.to_owned(), factory);
+    }
+
+    fn create(&self, id: i64, view_type: &str, params: &EncodableValue) -> eyre::Result<()> {
+        let Some(factory) = self.factories.get(view_type) else {
+            eyre::bail!("unknown view type: {view_type}");
+        };
+
+        if self.views.borrow().contains_key(&id) {
+            eyre::bail!("a platform view with id {id} already exists");
+        }
+
+        let visual = self.compositor.CreateContainerVisual()?;
+
+        factory.create(id, &visual, params)?;
+
+        self.registry.visuals.lock().unwrap().insert(id, visual);
+        self.views.borrow_mut().insert(id, view_type.to_owned());
+
+        Ok(())
+    }
+
+    fn dispose(&self, id: i64) -> bool {
+        let Some(view_type) = self.views.borrow_mut().remove(&id) else {
+            return false;
+        };
+
+        self.registry.visuals.lock().unwrap().remove(&id);
+
+        if let Some(factory) = self.factories.get(&view_type) {
+            factory.dispose(id);
+        }
+
+        true
+    }
+}
+
+impl StandardMethodHandler for PlatformViewsHandler {
+    fn handle(&self, method: &str, args: EncodableValue, reply: StandardMethodReply) {
+        match method {
+            "create" => {
+                let Some(id) = args.get("id").and_then(|v| v.as_int()) else {
+                    return reply.error("invalid_args", Some("expected an id"));
+                };
+
+                let Some(view_type) = args.get("viewType").and_then(|v| v.as_string()) else {
+                    return reply.error("invalid_args", Some("expected a view type"));
+                };
+
+                // The framework encodes the creation params itself, with the codec that the view
+                // was created with, which is assumed to be the standard message codec.
+                let params = match args.get("params").and_then(|v| v.as_u8_list()) {
+                    Some(bytes) => match flutter_codec::read_value(&mut Cursor::new(bytes)) {
+                        Ok(params) => params,
+                        Err(e) => {
+                            let message = format!("invalid creation params: {e}");
+                            return reply.error("invalid_args", Some(&message));
+                        }
+                    },
+                    None => EncodableValue::Null,
+                };
+
+                match self.create(id, view_type, &params) {
+                    Ok(()) => reply.success(&EncodableValue::Null),
+                    Err(e) => reply.error("create_failed", Some(&e.to_string())),
+                }
+            }
+            "dispose" => {
+                let Some(id) = args.as_int() else {
+                    return reply.error("invalid_args", Some("expected an id"));
+                };
+
+                if self.dispose(id) {
+                    reply.success(&EncodableValue::Null);
+                } else {
+                    reply.error("invalid_args", Some("unknown platform view"));
+                }
+            }
+            // Gestures are forwarded to the views explicitly, so there is nothing to do here.
+            "acceptGesture" | "rejectGesture" => reply.success(&EncodableValue::Null),
+            _ => {
+                tracing::warn!(method, "unimplemented");
+                reply.not_implemented();
+            }
+        }
+    }
+}
@@ -0,0 +1,782 @@
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicPtr, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::{mem, ptr, slice};
+
+use color_eyre::eyre::{self, OptionExt};
+use flutter_embedder::FlutterOpenGLTexture;
+use windows::core::{ComInterface, Interface, HSTRING, PCSTR};
+use windows::Win32::Foundation::{HANDLE, HMODULE, HWND, LPARAM, LRESULT, WPARAM};
+use windows::Win32::Graphics::Direct3D11::{ID3D11Device, ID3D11Texture2D};
+use windows::Win32::Graphics::Dxgi::IDXGIDevice;
+use windows::Win32::System::Diagnostics::Debug::{
+    IMAGE_DIRECTORY_ENTRY_EXPORT, IMAGE_NT_HEADERS64,
+};
+use windows::Win32::System::LibraryLoader::{GetProcAddress, LoadLibraryW};
+use windows::Win32::System::SystemServices::{IMAGE_DOS_HEADER, IMAGE_EXPORT_DIRECTORY};
+
+use crate::egl_manager::EglManager;
+use crate::engine::{BinaryMessageHandler, BinaryMessageReply, BinaryMessenger, FlutterEngine};
+use crate::error_utils::ResultExt;
+use crate::plugin_registrar::RustPluginRegistrar;
+use crate::texture_registry::{ExternalTexture, GlTexture, TextureRegistry};
+
+type FlutterDesktopMessengerRef = *const FlutterDesktopMessenger;
+type FlutterDesktopPluginRegistrarRef = *const FlutterDesktopPluginRegistrar;
+type FlutterDesktopTextureRegistrarRef = *const FlutterDesktopTextureRegistrar;
+type FlutterDesktopViewRef = *const FlutterDesktopView;
+
+type FlutterDesktopMessageCallback =
+    unsafe extern "C" fn(FlutterDesktopMessengerRef, *const FlutterDesktopMessage, *mut c_void);
+type FlutterDesktopBinaryReply = unsafe extern "C" fn(*const u8, usize, *mut c_void);
+type FlutterDesktopOnPluginRegistrarDestroyed =
+    unsafe extern "C" fn(FlutterDesktopPluginRegistrarRef);
+type FlutterDesktopWindowProcCallback =
+    unsafe extern "C" fn(HWND, u32, WPARAM, LPARAM, *mut c_void, *mut LRESULT) -> bool;
+type FlutterDesktopReleaseCallback = unsafe extern "C" fn(*mut c_void);
+type FlutterDesktopPixelBufferTextureCallback =
+    unsafe extern "C" fn(usize, usize, *mut c_void) -> *const FlutterDesktopPixelBuffer;
+type FlutterDesktopGpuSurfaceTextureCallback =
+    unsafe extern "C" fn(usize, usize, *mut c_void) -> *const FlutterDesktopGpuSurfaceDescriptor;
+type RegisterWithRegistrar = unsafe extern "C" fn(FlutterDesktopPluginRegistrarRef);
+
+const TEXTURE_TYPE_PIXEL_BUFFER: i32 = 0;
+const TEXTURE_TYPE_GPU_SURFACE: i32 = 1;
+
+const GPU_SURFACE_TYPE_DXGI_SHARED_HANDLE: i32 = 1;
+const GPU_SURFACE_TYPE_D3D11_TEXTURE_2D: i32 = 2;
+
+#[repr(C)]
+struct FlutterDesktopMessage {
+    struct_size: usize,
+    channel: *const c_char,
+    message: *const u8,
+    message_size: usize,
+    response_handle: *const FlutterDesktopMessageResponseHandle,
+}
+
+#[repr(C)]
+struct FlutterDesktopTextureInfo {
+    texture_type: i32,
+    config: FlutterDesktopTextureConfig,
+}
+
+#[repr(C)]
+union FlutterDesktopTextureConfig {
+    pixel_buffer: FlutterDesktopPixelBufferTextureConfig,
+    gpu_surface: FlutterDesktopGpuSurfaceTextureConfig,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct FlutterDesktopPixelBufferTextureConfig {
+    callback: Option<FlutterDesktopPixelBufferTextureCallback>,
+    user_data: *mut c_void,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct FlutterDesktopGpuSurfaceTextureConfig {
+    struct_size: usize,
+    surface_type: i32,
+    callback: Option<FlutterDesktopGpuSurfaceTextureCallback>,
+    user_data: *mut c_void,
+}
+
+#[repr(C)]
+struct FlutterDesktopPixelBuffer {
+    buffer: *const u8,
+    width: usize,
+    height: usize,
+    release_callback: Option<FlutterDesktopReleaseCallback>,
+    release_context: *mut c_void,
+}
+
+#[repr(C)]
+struct FlutterDesktopGpuSurfaceDescriptor {
+    struct_size: usize,
+    handle: *mut c_void,
+    width: usize,
+    height: usize,
+    visible_width: usize,
+    visible_height: usize,
+    format: i32,
+    release_callback: Option<FlutterDesktopReleaseCallback>,
+    release_context: *mut c_void,
+}
+
+/// Loads plugins from DLLs. These can be Rust plugins built with the `fluyt-plugin` crate, or C++
+/// Flutter plugins built against the official Windows embedder, which are provided with a
+/// registrar that is backed by our engine.
+///
+/// The parts of Flutter's C API that plugins use (`flutter_messenger.h`,
+/// `flutter_plugin_registrar.h`, `flutter_texture_registrar.h` and `flutter_windows.h`) are
+/// implemented below and exported from the executable (see `build.rs`). Plugins import them from
+/// `flutter_windows.dll`, which is provided by `flutter-windows-shim` and forwards them here.
+///
+/// This must be dropped before the engine, which happens after the plugins' destruction handler
+/// has been called.
+pub struct PluginHost {
+    registrar: Box<FlutterDesktopPluginRegistrar>,
+    rust_registrar: &'static RustPluginRegistrar,
+    _engine: Rc<FlutterEngine>,
+}
+
+impl PluginHost {
+    pub fn new(
+        engine: Rc<FlutterEngine>,
+        hwnd: HWND,
+        device: ID3D11Device,
+        egl_manager: Arc<EglManager>,
+        texture_registry: Arc<TextureRegistry>,
+    ) -> PluginHost {
+        let messenger = Arc::new(FlutterDesktopMessenger {
+            engine: AtomicPtr::new(Rc::as_ptr(&engine).cast_mut()),
+            sender: engine.thread_safe_messenger(),
+            locked: Mutex::new(false),
+            unlocked: Condvar::new(),
+        });
+
+        let registrar = Box::new(FlutterDesktopPluginRegistrar {
+            messenger: Arc::into_raw(messenger),
+            texture_registrar: FlutterDesktopTextureRegistrar {
+                device: device.clone(),
+                egl_manager: egl_manager.clone(),
+                texture_registry,
+                textures: Mutex::new(BTreeMap::new()),
+            },
+            view: FlutterDesktopView { hwnd, device },
+            destruction_handler: Cell::new(None),
+            window_proc_delegates: RefCell::new(Vec::new()),
+        });
+
+        PluginHost {
+            registrar,
+            rust_registrar: RustPluginRegistrar::new(&engine, hwnd, egl_manager),
+            _engine: engine,
+        }
+    }
+
+    /// Loads a plugin DLL and calls its entry point, or for C++ plugins its
+    /// `RegisterWithRegistrar` functions.
+    ///
+    /// Libraries are never unloaded, since plugins may still be running code (e.g. on their own
+    /// threads) after they have been destroyed.
+    pub fn load(&self, path: &Path) -> eyre::Result<()> {
+        let module = unsafe { LoadLibraryW(&HSTRING::from(path.as_os_str()))? };
+
+        let entry_point = CString::new(fluyt_plugin::ENTRY_POINT)?;
+        if let Some(f) = unsafe { GetProcAddress(module, PCSTR(entry_point.as_ptr().cast())) } {
+            tracing::info!(plugin = %path.display(), "registering rust plugin");
+
+            let registered = unsafe {
+                let register = mem::transmute::<_, fluyt_plugin::RawEntryPoint>(f);
+                register(&self.rust_registrar.raw())
+            };
+
+            if !registered {
+                eyre::bail!(
+                    "{} was built for an incompatible version or failed to register",
+                    path.display()
+                );
+            }
+
+            return Ok(());
+        }
+
+        let entry_points = unsafe { registration_functions(module) };
+        if entry_points.is_empty() {
+            eyre::bail!("{} has no RegisterWithRegistrar function", path.display());
+        }
+
+        for name in entry_points {
+            tracing::info!(plugin = ?name, "registering plugin");
+
+            let f = unsafe { GetProcAddress(module, PCSTR(name.as_ptr().cast())) }
+                .ok_or_eyre("failed to get plugin entry point")?;
+
+            unsafe {
+                let register = mem::transmute::<_, RegisterWithRegistrar>(f);
+                register(&*self.registrar);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Gives plugins a chance to handle a message sent to the top level window. Returns the result
+    /// if one of them handled it.
+    pub fn handle_window_proc(
+        &self,
+        hwnd: HWND,
+        msg: u32,
+        wparam: WPARAM,
+        lparam: LPARAM,
+    ) -> Option<LRESULT> {
+        // Delegates may be (un)registered while being called.
+        let delegates = self.registrar.window_proc_delegates.borrow().clone();
+        for (delegate, user_data) in delegates {
+            let mut result = LRESULT(0);
+            if unsafe { delegate(hwnd, msg, wparam, lparam, user_data, &mut result) } {
+                return Some(result);
+            }
+        }
+        None
+    }
+}
+
+impl Drop for PluginHost {
+    fn drop(&mut self) {
+        if let Some(handler) = self.registrar.destruction_handler.take() {
+            unsafe { handler(&*self.registrar) };
+        }
+
+        let messenger = unsafe { Arc::from_raw(self.registrar.messenger) };
+
+        self.rust_registrar.detach();
+
+        // Plugins may still hold references to the messenger, which must stop using the engine.
+        messenger.lock();
+        messenger.engine.store(ptr::null_mut(), Ordering::Release);
+        messenger.unlock();
+    }
+}
+
+/// Finds the functions exported by a plugin that register it. Plugins using the C API export a
+/// `<Name>CApiRegisterWithRegistrar` function, which is preferred if it exists.
+unsafe fn registration_functions(module: HMODULE) -> Vec<CString> {
+    let base = module.0 as *const u8;
+    let dos_header = &*base.cast::<IMAGE_DOS_HEADER>();
+    let nt_headers = &*base
+        .offset(dos_header.e_lfanew as isize)
+        .cast::<IMAGE_NT_HEADERS64>();
+
+    let directory =
+        nt_headers.OptionalHeader.DataDirectory[IMAGE_DIRECTORY_ENTRY_EXPORT.0 as usize];
+    if directory.Size == 0 {
+        return vec![];
+    }
+
+    let exports = &*base
+        .add(directory.VirtualAddress as usize)
+        .cast::<IMAGE_EXPORT_DIRECTORY>();
+
+    let names = slice::from_raw_parts(
+        base.add(exports.AddressOfNames as usize).cast::<u32>(),
+        exports.NumberOfNames as usize,
+    );
+
+    let names = names
+        .iter()
+        .map(|&name| CStr::from_ptr(base.add(name as usize).cast()))
+        .filter(|name| name.to_bytes().ends_with(b"RegisterWithRegistrar"))
+        .map(CStr::to_owned)
+        .collect::<Vec<_>>();
+
+    if names
+        .iter()
+        .any(|name| name.to_bytes().ends_with(b"CApiRegisterWithRegistrar"))
+    {
+        names
+            .into_iter()
+            .filter(|name| name.to_bytes().ends_with(b"CApiRegisterWithRegistrar"))
+            .collect()
+    } else {
+        names
+    }
+}
+
+unsafe fn bytes<'a>(data: *const u8, len: usize) -> &'a [u8] {
+    if data.is_null() {
+        &[]
+    } else {
+        slice::from_raw_parts(data, len)
+    }
+}
+
+pub struct FlutterDesktopPluginRegistrar {
+    /// Created with `Arc::into_raw`, since plugins manage its reference count themselves.
+    messenger: FlutterDesktopMessengerRef,
+    texture_registrar: FlutterDesktopTextureRegistrar,
+    view: FlutterDesktopView,
+    destruction_handler: Cell<Option<FlutterDesktopOnPluginRegistrarDestroyed>>,
+    window_proc_delegates: RefCell<Vec<(FlutterDesktopWindowProcCallback, *mut c_void)>>,
+}
+
+pub struct FlutterDesktopView {
+    hwnd: HWND,
+    device: ID3D11Device,
+}
+
+pub struct FlutterDesktopMessenger {
+    /// Null once the engine has been destroyed. This must only be dereferenced on the platform
+    /// thread.
+    engine: AtomicPtr<FlutterEngine>,
+    /// Plugins can send messages from any thread, so they don't go through `engine`.
+    sender: BinaryMessenger,
+    locked: Mutex<bool>,
+    unlocked: Condvar,
+}
+
+impl FlutterDesktopMessenger {
+    fn engine(&self) -> Option<&FlutterEngine> {
+        unsafe { self.engine.load(Ordering::Acquire).as_ref() }
+    }
+
+    /// Whether the engine hasn't been destroyed. Unlike `engine`, this can be used on any thread.
+    fn is_available(&self) -> bool {
+        !self.engine.load(Ordering::Acquire).is_null()
+    }
+
+    /// Plugins lock the messenger (from any thread) while checking that it is available and
+    /// sending a message, so the lock can't be tied to a guard.
+    fn lock(&self) {
+        let mut locked = self.locked.lock().unwrap();
+        while *locked {
+            locked = self.unlocked.wait(locked).unwrap();
+        }
+        *locked = true;
+    }
+
+    fn unlock(&self) {
+        *self.locked.lock().unwrap() = false;
+        self.unlocked.notify_one();
+    }
+}
+
+pub struct FlutterDesktopMessageResponseHandle(BinaryMessageReply);
+
+/// Forwards messages on a channel to a callback set by a plugin.
+struct PluginMessageHandler {
+    messenger: FlutterDesktopMessengerRef,
+    channel: CString,
+    callback: FlutterDesktopMessageCallback,
+    user_data: *mut c_void,
+}
+
+impl BinaryMessageHandler for PluginMessageHandler {
+    fn handle(&self, message: &[u8], reply: BinaryMessageReply) {
+        // Freed when the plugin responds.
+        let response_handle = Box::into_raw(Box::new(FlutterDesktopMessageResponseHandle(reply)));
+
+        let message = FlutterDesktopMessage {
+            struct_size: mem::size_of::<FlutterDesktopMessage>(),
+            channel: self.channel.as_ptr(),
+            message: message.as_ptr(),
+            message_size: message.len(),
+            response_handle,
+        };
+
+        unsafe { (self.callback)(self.messenger, &message, self.user_data) };
+    }
+}
+
+pub struct FlutterDesktopTextureRegistrar {
+    device: ID3D11Device,
+    egl_manager: Arc<EglManager>,
+    texture_registry: Arc<TextureRegistry>,
+    textures: Mutex<BTreeMap<i64, Arc<PluginTexture>>>,
+}
+
+enum TextureSource {
+    PixelBuffer(FlutterDesktopPixelBufferTextureConfig),
+    GpuSurface(FlutterDesktopGpuSurfaceTextureConfig),
+}
+
+/// An external texture whose frames are provided by a plugin's callback.
+struct PluginTexture {
+    device: ID3D11Device,
+    egl_manager: Arc<EglManager>,
+    /// Cleared when the texture is unregistered, after which the plugin may free the callback's
+    /// user data. Holding the lock while calling the callback means that it can't be freed while
+    /// a frame is being produced.
+    source: Mutex<Option<TextureSource>>,
+}
+
+unsafe impl Send for PluginTexture {}
+unsafe impl Sync for PluginTexture {}
+
+impl ExternalTexture for PluginTexture {
+    fn frame(&self, width: usize, height: usize) -> Option<FlutterOpenGLTexture> {
+        let source = self.source.lock().unwrap();
+        match source.as_ref()? {
+            TextureSource::PixelBuffer(config) => unsafe {
+                let buffer = (config.callback?)(width, height, config.user_data).as_ref()?;
+                Some(upload_pixel_buffer(buffer))
+            },
+            TextureSource::GpuSurface(config) => unsafe {
+                let surface = (config.callback?)(width, height, config.user_data).as_ref()?;
+                match self.wrap_gpu_surface(config.surface_type, surface) {
+                    Ok(texture) => Some(texture),
+                    Err(e) => {
+                        tracing::error!("failed to get plugin texture: {e:?}");
+                        None
+                    }
+                }
+            },
+        }
+    }
+}
+
+impl PluginTexture {
+    unsafe fn wrap_gpu_surface(
+        &self,
+        surface_type: i32,
+        surface: &FlutterDesktopGpuSurfaceDescriptor,
+    ) -> eyre::Result<FlutterOpenGLTexture> {
+        let texture = match surface_type {
+            GPU_SURFACE_TYPE_D3D11_TEXTURE_2D => {
+                ID3D11Texture2D::from_raw_borrowed(&surface.handle)
+                    .cloned()
+                    .ok_or_eyre("texture is null")?
+            }
+            GPU_SURFACE_TYPE_DXGI_SHARED_HANDLE => self
+                .device
+                .OpenSharedResource::<_, ID3D11Texture2D>(HANDLE(surface.handle as isize))?,
+            _ => eyre::bail!("unsupported surface type: {surface_type}"),
+        };
+
+        let gl_texture = GlTexture::new(self.egl_manager.clone(), &texture);
+
+        // Once the surface has been bound, the plugin can reuse the descriptor.
+        if let Some(release) = surface.release_callback {
+            release(surface.release_context);
+        }
+
+        let gl_texture = Box::new(gl_texture?);
+
+        unsafe extern "C" fn release_texture(user_data: *mut c_void) {
+            drop(Box::from_raw(user_data.cast::<GlTexture>()));
+        }
+
+        Ok(FlutterOpenGLTexture {
+            target: gl::TEXTURE_2D,
+            name: gl_texture.name(),
+            format: gl::RGBA8,
+            width: surface.width,
+            height: surface.height,
+            user_data: Box::into_raw(gl_texture).cast(),
+            destruction_callback: Some(release_texture),
+        })
+    }
+}
+
+/// Copies a pixel buffer into a new GL texture, which is deleted once the engine is done with it.
+unsafe fn upload_pixel_buffer(buffer: &FlutterDesktopPixelBuffer) -> FlutterOpenGLTexture {
+    let mut name = 0;
+    gl::GenTextures(1, &mut name);
+    gl::BindTexture(gl::TEXTURE_2D, name);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+    gl::TexImage2D(
+        gl::TEXTURE_2D,
+        0,
+        gl::RGBA as i32,
+        buffer.width as i32,
+        buffer.height as i32,
+        0,
+        gl::RGBA,
+        gl::UNSIGNED_BYTE,
+        buffer.buffer.cast(),
+    );
+
+    if let Some(release) = buffer.release_callback {
+        release(buffer.release_context);
+    }
+
+    unsafe extern "C" fn delete_texture(user_data: *mut c_void) {
+        let name = user_data as usize as u32;
+        gl::DeleteTextures(1, &name);
+    }
+
+    FlutterOpenGLTexture {
+        target: gl::TEXTURE_2D,
+        name,
+        format: gl::RGBA8,
+        width: buffer.width,
+        height: buffer.height,
+        user_data: name as usize as *mut c_void,
+        destruction_callback: Some(delete_texture),
+    }
+}
+
+#[no_mangle]
+unsafe extern "C" fn FlutterDesktopMessengerSend(
+    messenger: FlutterDesktopMessengerRef,
+    channel: *const c_char,
+    message: *const u8,
+    message_size: usize,
+) -> bool {
+    if !(*messenger).is_available() {
+        return false;
+    }
+
+    (*messenger)
+        .sender
+        .send_platform_message(CStr::from_ptr(channel), bytes(message, message_size))
+        .trace_err()
+        .is_ok()
+}
+
+#[no_mangle]
+unsafe extern "C" fn FlutterDesktopMessengerSendWithReply(
+    messenger: FlutterDesktopMessengerRef,
+    channel: *const c_char,
+    message: *const u8,
+    message_size: usize,
+    reply: Option<FlutterDesktopBinaryReply>,
+    user_data: *mut c_void,
+) -> bool {
+    let Some(reply) = reply else {
+        return FlutterDesktopMessengerSend(messenger, channel, message, message_size);
+    };
+
+    if !(*messenger).is_available() {
+        return false;
+    }
+
+    (*messenger)
+        .sender
+        .send_platform_message_with_reply(
+            CStr::from_ptr(channel),
+            bytes(message, message_size),
+            move |data| reply(data.as_ptr(), data.len(), user_data),
+        )
+        .trace_err()
+        .is_ok()
+}
+
+#[no_mangle]
+unsafe extern "C" fn FlutterDesktopMessengerSendResponse(
+    _messenger: FlutterDesktopMessengerRef,
+    handle: *const FlutterDesktopMessageResponseHandle,
+    data: *const u8,
+    data_length: usize,
+) {
+    let handle = Box::from_raw(handle.cast_mut());
+    handle.0.send(bytes(data, data_length));
+}
+
+#[no_mangle]
+unsafe extern "C" fn FlutterDesktopMessengerSetCallback(
+    messenger: FlutterDesktopMessengerRef,
+    channel: *const c_char,
+    callback: Option<FlutterDesktopMessageCallback>,
+    user_data: *mut c_void,
+) {
+    let Some(engine) = (*messenger).engine() else {
+        return;
+    };
+
+    let channel = CStr::from_ptr(channel);
+    let Ok(channel_name) = channel.to_str() else {
+        tracing::error!("invalid channel name: {channel:?}");
+        return;
+    };
+
+    let handler = callback.map(|callback| {
+        Box::new(PluginMessageHandler {
+            messenger,
+            channel: channel.to_owned(),
+            callback,
+            user_data,
+        }) as Box<dyn BinaryMessageHandler>
+    });
+
+    engine.set_message_handler(channel_name, handler);
+}
+
+#[no_mangle]
+unsafe extern "C" fn FlutterDesktopMessengerAddRef(
+    messenger: FlutterDesktopMessengerRef,
+) -> FlutterDesktopMessengerRef {
+    Arc::increment_strong_count(messenger);
+    messenger
+}
+
+#[no_mangle]
+unsafe extern "C" fn FlutterDesktopMessengerRelease(messenger: FlutterDesktopMessengerRef) {
+    Arc::decrement_strong_count(messenger);
+}
+
+#[no_mangle]
+unsafe extern "C" fn FlutterDesktopMessengerIsAvailable(
+    messenger: FlutterDesktopMessengerRef,
+) -> bool {
+    (*messenger).is_available()
+}
+
+#[no_mangle]
+unsafe extern "C" fn FlutterDesktopMessengerLock(
+    messenger: FlutterDesktopMessengerRef,
+) -> FlutterDesktopMessengerRef {
+    (*messenger).lock();
+    messenger
+}
+
+#[no_mangle]
+unsafe extern "C" fn FlutterDesktopMessengerUnlock(messenger: FlutterDesktopMessengerRef) {
+    (*messenger).unlock();
+}
+
+#[no_mangle]
+unsafe extern "C" fn FlutterDesktopPluginRegistrarGetMessenger(
+    registrar: FlutterDesktopPluginRegistrarRef,
+) -> FlutterDesktopMessengerRef {
+    (*registrar).messenger
+}
+
+#[no_mangle]
+unsafe extern "C" fn FlutterDesktopRegistrarGetTextureRegistrar(
+    registrar: FlutterDesktopPluginRegistrarRef,
+) -> FlutterDesktopTextureRegistrarRef {
+    &(*registrar).texture_registrar
+}
+
+#[no_mangle]
+unsafe extern "C" fn FlutterDesktopPluginRegistrarSetDestructionHandler(
+    registrar: FlutterDesktopPluginRegistrarRef,
+    callback: Option<FlutterDesktopOnPluginRegistrarDestroyed>,
+) {
+    (*registrar).destruction_handler.set(callback);
+}
+
+#[no_mangle]
+unsafe extern "C" fn FlutterDesktopPluginRegistrarGetView(
+    registrar: FlutterDesktopPluginRegistrarRef,
+) -> FlutterDesktopViewRef {
+    &(*registrar).view
+}
+
+#[no_mangle]
+unsafe extern "C" fn FlutterDesktopPluginRegistrarGetViewById(
+    registrar: FlutterDesktopPluginRegistrarRef,
+    view_id: i64,
+) -> FlutterDesktopViewRef {
+    // There is only the implicit view.
+    if view_id == 0 {
+        &(*registrar).view
+    } else {
+        ptr::null()
+    }
+}
+
+#[no_mangle]
+unsafe extern "C" fn FlutterDesktopPluginRegistrarRegisterTopLevelWindowProcDelegate(
+    registrar: FlutterDesktopPluginRegistrarRef,
+    delegate: FlutterDesktopWindowProcCallback,
+    user_data: *mut c_void,
+) {
+    (*registrar)
+        .window_proc_delegates
+        .borrow_mut()
+        .push((delegate, user_data));
+}
+
+#[no_mangle]
+unsafe extern "C" fn FlutterDesktopPluginRegistrarUnregisterTopLevelWindowProcDelegate(
+    registrar: FlutterDesktopPluginRegistrarRef,
+    delegate: FlutterDesktopWindowProcCallback,
+) {
+    (*registrar)
+        .window_proc_delegates
+        .borrow_mut()
+        .retain(|&(d, _)| d as usize != delegate as usize);
+}
+
+#[no_mangle]
+unsafe extern "C" fn FlutterDesktopViewGetHWND(view: FlutterDesktopViewRef) -> HWND {
+    (*view).hwnd
+}
+
+#[no_mangle]
+unsafe extern "C" fn FlutterDesktopViewGetGraphicsAdapter(
+    view: FlutterDesktopViewRef,
+) -> *mut c_void {
+    let adapter = (*view)
+        .device
+        .cast::<IDXGIDevice>()
+        .and_then(|device| device.GetAdapter());
+
+    // The caller owns the returned reference.
+    match adapter.trace_err() {
+        Ok(adapter) => adapter.into_raw(),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+#[no_mangle]
+unsafe extern "C" fn FlutterDesktopTextureRegistrarRegisterExternalTexture(
+    texture_registrar: FlutterDesktopTextureRegistrarRef,
+    info: *const FlutterDesktopTextureInfo,
+) -> i64 {
+    let registrar = &*texture_registrar;
+    let info = &*info;
+
+    let source = match info.texture_type {
+        TEXTURE_TYPE_PIXEL_BUFFER => TextureSource::PixelBuffer(info.config.pixel_buffer),
+        TEXTURE_TYPE_GPU_SURFACE => TextureSource::GpuSurface(info.config.gpu_surface),
+        texture_type => {
+            tracing::error!(texture_type, "unsupported texture type");
+            return -1;
+        }
+    };
+
+    let texture = Arc::new(PluginTexture {
+        device: registrar.device.clone(),
+        egl_manager: registrar.egl_manager.clone(),
+        source: Mutex::new(Some(source)),
+    });
+
+    match registrar
+        .texture_registry
+        .register(texture.clone())
+        .trace_err()
+    {
+        Ok(id) => {
+            registrar.textures.lock().unwrap().insert(id, texture);
+            id
+        }
+        Err(_) => -1,
+    }
+}
+
+#[no_mangle]
+unsafe extern "C" fn FlutterDesktopTextureRegistrarUnregisterExternalTexture(
+    texture_registrar: FlutterDesktopTextureRegistrarRef,
+    texture_id: i64,
+    callback: Option<FlutterDesktopReleaseCallback>,
+    user_data: *mut c_void,
+) {
+    let registrar = &*texture_registrar;
+
+    if let Some(texture) = registrar.textures.lock().unwrap().remove(&texture_id) {
+        *texture.source.lock().unwrap() = None;
+        let _ = registrar
+            .texture_registry
+            .unregister(texture_id)
+            .trace_err();
+    }
+
+    if let Some(callback) = callback {
+        callback(user_data);
+    }
+}
+
+#[no_mangle]
+unsafe extern "C" fn FlutterDesktopTextureRegistrarMarkExternalTextureFrameAvailable(
+    texture_registrar: FlutterDesktopTextureRegistrarRef,
+    texture_id: i64,
+) -> bool {
+    (*texture_registrar)
+        .texture_registry
+        .mark_frame_available(texture_id)
+        .trace_err()
+        .is_ok()
+}
@@ -0,0 +1,254 @@
+use std::ffi::{c_char, c_void, CStr};
+use std::sync::{Arc, RwLock};
+use std::{ptr, slice};
+
+use fluyt_plugin::{
+    RawDartCObject, RawDartObject, RawDestroyCallback, RawMessageCallback, RawNativePortCallback,
+    RawRegistrar, RawResponseHandle, ABI_VERSION,
+};
+use windows::core::Interface;
+use windows::Win32::Foundation::HWND;
+
+use crate::egl_manager::EglManager;
+use crate::engine::{BinaryMessageHandler, BinaryMessageReply, BinaryMessenger, FlutterEngine};
+use crate::error_utils::ResultExt;
+use crate::native_port;
+
+/// The state behind the registrar given to Rust plugins (see the `fluyt-plugin` crate).
+///
+/// Plugins may keep using their messenger for as long as the process is running, so this is
+/// leaked, and only the engine pointer is cleared once the engine is gone. Calls hold a read lock
+/// on the engine pointer, which `detach` takes exclusively.
+///
+/// The engine itself is only used on the platform thread. Messages and Dart objects can be sent
+/// from any thread, so they go through a thread-safe messenger instead.
+pub struct RustPluginRegistrar {
+    engine: RwLock<Option<*const FlutterEngine>>,
+    messenger: BinaryMessenger,
+    hwnd: HWND,
+    egl_manager: Arc<EglManager>,
+}
+
+impl RustPluginRegistrar {
+    pub fn new(
+        engine: &FlutterEngine,
+        hwnd: HWND,
+        egl_manager: Arc<EglManager>,
+    ) -> &'static RustPluginRegistrar {
+        Box::leak(Box::new(RustPluginRegistrar {
+            engine: RwLock::new(Some(engine as *const FlutterEngine)),
+            messenger: engine.thread_safe_messenger(),
+            hwnd,
+            egl_manager,
+        }))
+    }
+
+    pub fn raw(&'static self) -> RawRegistrar {
+        RawRegistrar {
+            abi_version: ABI_VERSION,
+            context: self as *const RustPluginRegistrar as *const c_void,
+            window_handle: self.hwnd.0,
+            set_message_handler,
+            send_message,
+            send_response,
+            get_d3d11_device,
+            get_egl_display,
+            create_egl_share_context,
+            post_dart_object,
+            open_native_port,
+            close_native_port,
+            post_c_object,
+        }
+    }
+
+    /// Called before the engine is destroyed. Waits for plugin calls that are using the engine on
+    /// other threads to finish.
+    pub fn detach(&self) {
+        *self.engine.write().unwrap() = None;
+    }
+
+    /// Calls `f` with the engine, unless it has been detached. The engine can't be detached until
+    /// `f` returns.
+    unsafe fn with_engine<R>(
+        context: *const c_void,
+        f: impl FnOnce(&FlutterEngine) -> R,
+    ) -> Option<R> {
+        let registrar = &*context.cast::<RustPluginRegistrar>();
+        let engine = registrar.engine.read().unwrap();
+        (*engine).map(|engine| f(&*engine))
+    }
+
+    /// Calls `f` with the thread-safe messenger, unless the engine has been detached.
+    unsafe fn with_messenger<R>(
+        context: *const c_void,
+        f: impl FnOnce(&BinaryMessenger) -> R,
+    ) -> Option<R> {
+        let registrar = &*context.cast::<RustPluginRegistrar>();
+        let engine = registrar.engine.read().unwrap();
+        (*engine).map(|_| f(&registrar.messenger))
+    }
+
+    /// The GPU resources outlive the engine, so these are available after it has been detached.
+    unsafe fn egl_manager_from_context<'a>(context: *const c_void) -> &'a EglManager {
+        &(*context.cast::<RustPluginRegistrar>()).egl_manager
+    }
+}
+
+struct RustPluginHandler {
+    callback: RawMessageCallback,
+    user_data: *mut c_void,
+    destroy: Option<RawDestroyCallback>,
+}
+
+impl BinaryMessageHandler for RustPluginHandler {
+    fn handle(&self, message: &[u8], reply: BinaryMessageReply) {
+        // Freed by `send_response`.
+        let response = Box::into_raw(Box::new(reply)).cast::<RawResponseHandle>();
+        unsafe { (self.callback)(self.user_data, message.as_ptr(), message.len(), response) };
+    }
+}
+
+impl Drop for RustPluginHandler {
+    fn drop(&mut self) {
+        if let Some(destroy) = self.destroy {
+            unsafe { destroy(self.user_data) };
+        }
+    }
+}
+
+unsafe fn channel_name<'a>(channel: *const c_char) -> Option<&'a str> {
+    let channel = CStr::from_ptr(channel);
+    match channel.to_str() {
+        Ok(channel) => Some(channel),
+        Err(_) => {
+            tracing::error!("invalid channel name: {channel:?}");
+            None
+        }
+    }
+}
+
+unsafe extern "C" fn set_message_handler(
+    context: *const c_void,
+    channel: *const c_char,
+    callback: Option<RawMessageCallback>,
+    user_data: *mut c_void,
+    destroy: Option<RawDestroyCallback>,
+) {
+    let handler = callback.map(|callback| RustPluginHandler {
+        callback,
+        user_data,
+        destroy,
+    });
+
+    let Some(channel) = channel_name(channel) else {
+        // Dropping the handler frees the plugin's data.
+        return;
+    };
+
+    // If the engine has been detached, the handler is dropped along with the closure.
+    RustPluginRegistrar::with_engine(context, |engine| {
+        engine.set_message_handler(
+            channel,
+            handler.map(|handler| Box::new(handler) as Box<dyn BinaryMessageHandler>),
+        );
+    });
+}
+
+unsafe extern "C" fn send_message(
+    context: *const c_void,
+    channel: *const c_char,
+    message: *const u8,
+    message_size: usize,
+) -> bool {
+    let message = if message.is_null() {
+        &[]
+    } else {
+        slice::from_raw_parts(message, message_size)
+    };
+
+    RustPluginRegistrar::with_messenger(context, |messenger| {
+        messenger
+            .send_platform_message(CStr::from_ptr(channel), message)
+            .trace_err()
+            .is_ok()
+    })
+    .unwrap_or(false)
+}
+
+unsafe extern "C" fn send_response(response: *mut RawResponseHandle, data: *const u8, size: usize) {
+    let reply = Box::from_raw(response.cast::<BinaryMessageReply>());
+    if data.is_null() {
+        reply.not_implemented();
+    } else {
+        reply.send(slice::from_raw_parts(data, size));
+    }
+}
+
+unsafe extern "C" fn get_d3d11_device(context: *const c_void) -> *mut c_void {
+    // The caller owns the returned reference.
+    RustPluginRegistrar::egl_manager_from_context(context)
+        .device()
+        .into_raw()
+}
+
+unsafe extern "C" fn get_egl_display(
+    context: *const c_void,
+    config: *mut *mut c_void,
+) -> *mut c_void {
+    let egl_manager = RustPluginRegistrar::egl_manager_from_context(context);
+    if let Some(config) = config.as_mut() {
+        *config = egl_manager.config().as_ptr();
+    }
+    egl_manager.display().as_ptr()
+}
+
+unsafe extern "C" fn create_egl_share_context(context: *const c_void) -> *mut c_void {
+    match RustPluginRegistrar::egl_manager_from_context(context)
+        .create_share_context()
+        .trace_err()
+    {
+        Ok(context) => context.as_ptr(),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+unsafe extern "C" fn post_dart_object(
+    context: *const c_void,
+    port: i64,
+    object: *const RawDartObject,
+) -> bool {
+    let Some(object) = object.as_ref() else {
+        return false;
+    };
+
+    // `RawDartObject` has the same layout as `FlutterEngineDartObject`.
+    RustPluginRegistrar::with_messenger(context, |messenger| {
+        messenger
+            .post_raw_dart_object(port, (object as *const RawDartObject).cast())
+            .trace_err()
+            .is_ok()
+    })
+    .unwrap_or(false)
+}
+
+unsafe extern "C" fn open_native_port(
+    _context: *const c_void,
+    name: *const c_char,
+    callback: RawNativePortCallback,
+    user_data: *mut c_void,
+    destroy: Option<RawDestroyCallback>,
+) -> i64 {
+    native_port::open(CStr::from_ptr(name), callback, user_data, destroy).unwrap_or(0)
+}
+
+unsafe extern "C" fn close_native_port(_context: *const c_void, port: i64) -> bool {
+    native_port::close(port)
+}
+
+unsafe extern "C" fn post_c_object(
+    _context: *const c_void,
+    port: i64,
+    object: *mut RawDartCObject,
+) -> bool {
+    native_port::post_c_object(port, object.cast())
+}
@@ -0,0 +1,423 @@
+use std::collections::BTreeMap;
+use std::ffi::c_void;
+use std::mem;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use color_eyre::eyre;
+use windows::Win32::UI::Input::Pointer::GetPointerType;
+use windows::Win32::UI::WindowsAndMessaging::{
+    GetMessageExtraInfo, SystemParametersInfoW, POINTER_INPUT_TYPE, PT_PEN,
+    SPI_GETWHEELSCROLLCHARS, SPI_GETWHEELSCROLLLINES, SYSTEM_PARAMETERS_INFO_ACTION,
+    SYSTEM_PARAMETERS_INFO_UPDATE_FLAGS,
+};
+use winit::dpi::{PhysicalPosition, PhysicalSize};
+use winit::event::{ElementState, MouseButton, MouseScrollDelta, Touch, TouchPhase};
+
+use crate::engine::{FlutterEngine, PointerButtons, PointerDeviceKind, PointerEvent, PointerPhase};
+
+/// Logical pixels scrolled per line (or character, horizontally).
+const SCROLL_LINE_HEIGHT: f64 = 20.0;
+
+/// The Windows default for lines (and characters) scrolled per wheel notch.
+const DEFAULT_WHEEL_SCROLL_LINES: u32 = 3;
+
+/// Returned for the lines per notch when the wheel is set to scroll a page at a time.
+const WHEEL_PAGESCROLL: u32 = u32::MAX;
+
+/// Mouse messages that Windows synthesizes from touch or pen input have this signature in their
+/// extra info.
+const MI_WP_SIGNATURE: isize = 0xFF515700;
+const SIGNATURE_MASK: isize = 0xFFFFFF00;
+
+/// A touch or pen contact, tracked from when it comes into range until it is lifted or leaves.
+#[derive(Clone, Copy)]
+struct Contact {
+    kind: PointerDeviceKind,
+    position: PhysicalPosition<f64>,
+    down: bool,
+}
+
+pub struct Pointer {
+    engine: Rc<FlutterEngine>,
+    position: PhysicalPosition<f64>,
+    buttons: PointerButtons,
+    /// Set when the cursor leaves the window during a drag, which keeps receiving events until
+    /// the buttons are released.
+    pending_remove: bool,
+    hover_throttle: Option<Duration>,
+    pending_hover: bool,
+    last_hover_time: Option<Instant>,
+    /// Touch and pen contacts, by their `WM_POINTER` pointer id, which is also used as their
+    /// device id. Each is added and removed separately from the mouse (device 0), so that using
+    /// them at the same time doesn't confuse the framework's gesture tracking.
+    contacts: BTreeMap<u64, Contact>,
+    /// Moves and hovers are held here until [`Pointer::send_batch`] is called, so that all of the
+    /// moves received while the event loop is busy are sent to the engine in one call.
+    batch: Vec<PointerEvent>,
+}
+
+impl Pointer {
+    pub fn new(engine: Rc<FlutterEngine>, hover_throttle: Option<Duration>) -> Pointer {
+        Pointer {
+            engine,
+            position: PhysicalPosition::new(0.0, 0.0),
+            buttons: PointerButtons::empty(),
+            pending_remove: false,
+            hover_throttle,
+            pending_hover: false,
+            last_hover_time: None,
+            contacts: BTreeMap::new(),
+            batch: vec![],
+        }
+    }
+
+    pub fn handle_cursor_moved(&mut self, position: PhysicalPosition<f64>) -> eyre::Result<()> {
+        if is_synthesized_mouse_message() {
+            return Ok(());
+        }
+
+        self.position = position;
+
+        if !self.buttons.is_empty() {
+            self.queue(PointerPhase::Move);
+            return Ok(());
+        }
+
+        if self.hover_throttle.is_some() {
+            self.pending_hover = true;
+            return self.flush().map(|_| ());
+        }
+
+        self.queue(PointerPhase::Hover);
+
+        Ok(())
+    }
+
+    pub fn handle_cursor_entered(&mut self) -> eyre::Result<()> {
+        // The pointer was never removed if it left during a drag.
+        if mem::take(&mut self.pending_remove) {
+            return Ok(());
+        }
+
+        self.send(PointerPhase::Add)
+    }
+
+    pub fn handle_cursor_left(&mut self) -> eyre::Result<()> {
+        self.flush_pending_hover()?;
+
+        if !self.buttons.is_empty() {
+            self.pending_remove = true;
+            return Ok(());
+        }
+
+        self.send(PointerPhase::Remove)
+    }
+
+    /// Cancels the current gestures, if any buttons are pressed or there are any touch or pen
+    /// contacts, for when their release won't be received (e.g. once the window has lost focus).
+    pub fn cancel(&mut self) -> eyre::Result<()> {
+        for (id, contact) in mem::take(&mut self.contacts) {
+            if contact.down {
+                self.queue_contact(id, &contact, PointerPhase::Cancel);
+            }
+            self.queue_contact(id, &contact, PointerPhase::Remove);
+        }
+
+        if self.buttons.is_empty() {
+            return self.send_batch();
+        }
+
+        self.buttons = PointerButtons::empty();
+        self.send(PointerPhase::Cancel)?;
+        self.send_pending_remove()
+    }
+
+    pub fn handle_mouse_input(
+        &mut self,
+        state: ElementState,
+        button: MouseButton,
+    ) -> eyre::Result<()> {
+        if is_synthesized_mouse_message() {
+            return Ok(());
+        }
+
+        // Hover events must be delivered before any button changes so that the down event is not
+        // reported at a stale position.
+        self.flush_pending_hover()?;
+
+        let button = match button {
+            MouseButton::Left => PointerButtons::PRIMARY,
+            MouseButton::Right => PointerButtons::SECONDARY,
+            MouseButton::Middle => PointerButtons::MIDDLE,
+            MouseButton::Back => PointerButtons::BACK,
+            MouseButton::Forward => PointerButtons::FORWARD,
+            MouseButton::Other(_) => return Ok(()),
+        };
+
+        let was_down = !self.buttons.is_empty();
+        self.buttons.set(button, state == ElementState::Pressed);
+
+        // Flutter expects a single down and up for each gesture, so pressing or releasing other
+        // buttons in between is reported as a move with the new set of buttons.
+        let phase = match (was_down, !self.buttons.is_empty()) {
+            (false, true) => PointerPhase::Down,
+            (true, false) => PointerPhase::Up,
+            (true, true) => PointerPhase::Move,
+            (false, false) => return Ok(()),
+        };
+
+        self.send(phase)?;
+
+        if phase == PointerPhase::Up {
+            self.send_pending_remove()?;
+        }
+
+        Ok(())
+    }
+
+    /// Handles a touch or pen event. Contacts are added when they are first seen (which for pens
+    /// can be while hovering, before they touch the screen) and removed once they are lifted.
+    pub fn handle_touch(&mut self, touch: Touch) -> eyre::Result<()> {
+        let id = touch.id;
+        let is_new = !self.contacts.contains_key(&id);
+        let contact = self.contacts.entry(id).or_insert_with(|| Contact {
+            kind: pointer_kind(id),
+            position: touch.location,
+            down: false,
+        });
+
+        contact.position = touch.location;
+
+        let mut phases = vec![];
+        if is_new {
+            phases.push(PointerPhase::Add);
+        }
+
+        match touch.phase {
+            TouchPhase::Started => {
+                contact.down = true;
+                phases.push(PointerPhase::Down);
+            }
+            TouchPhase::Moved if contact.down => phases.push(PointerPhase::Move),
+            TouchPhase::Moved => phases.push(PointerPhase::Hover),
+            TouchPhase::Ended | TouchPhase::Cancelled => {
+                if mem::take(&mut contact.down) {
+                    phases.push(match touch.phase {
+                        TouchPhase::Ended => PointerPhase::Up,
+                        _ => PointerPhase::Cancel,
+                    });
+                }
+                phases.push(PointerPhase::Remove);
+            }
+        }
+
+        let contact = match phases.last() {
+            Some(PointerPhase::Remove) => self.contacts.remove(&id).unwrap(),
+            _ => *contact,
+        };
+
+        for &phase in &phases {
+            self.queue_contact(id, &contact, phase);
+        }
+
+        // Only moves are batched, so that presses are delivered in order with other input.
+        if phases.iter().all(|&phase| phase == PointerPhase::Move) {
+            return Ok(());
+        }
+
+        self.send_batch()
+    }
+
+    /// Handles `WM_POINTERLEAVE`, which is sent when a pen leaves the range of the digitizer. winit
+    /// only reports pens that are lifted, so a pen that was hovering would otherwise never be
+    /// removed. Contacts that have already been removed are ignored.
+    pub fn handle_pointer_left(&mut self, id: u64) -> eyre::Result<()> {
+        let Some(contact) = self.contacts.remove(&id) else {
+            return Ok(());
+        };
+
+        if contact.down {
+            self.queue_contact(id, &contact, PointerPhase::Cancel);
+        }
+        self.queue_contact(id, &contact, PointerPhase::Remove);
+
+        self.send_batch()
+    }
+
+    /// Sends a scroll for a mouse wheel or touchpad. Holding shift scrolls vertical wheels
+    /// horizontally, as is conventional on Windows.
+    ///
+    /// Wheel notches scroll by the number of lines (or a page) set in the user's mouse settings.
+    /// Precision touchpads report fractions of a notch, which are scrolled proportionally.
+    pub fn handle_mouse_wheel(
+        &mut self,
+        delta: MouseScrollDelta,
+        shift: bool,
+        scale_factor: f64,
+        view_size: PhysicalSize<u32>,
+    ) -> eyre::Result<()> {
+        self.flush_pending_hover()?;
+
+        let (mut notches_x, mut notches_y) = match delta {
+            MouseScrollDelta::LineDelta(x, y) => (x as f64, y as f64),
+            MouseScrollDelta::PixelDelta(delta) => {
+                // winit's deltas are positive when the content should move right or down, which
+                // is the opposite of Flutter's.
+                return self.send_scroll(-delta.x, -delta.y);
+            }
+        };
+
+        if shift && notches_x == 0.0 {
+            (notches_x, notches_y) = (notches_y, 0.0);
+        }
+
+        let line_size = SCROLL_LINE_HEIGHT * scale_factor;
+        let delta_x =
+            -notches_x * wheel_scroll_amount(SPI_GETWHEELSCROLLCHARS, line_size, view_size.width);
+        let delta_y =
+            -notches_y * wheel_scroll_amount(SPI_GETWHEELSCROLLLINES, line_size, view_size.height);
+
+        self.send_scroll(delta_x, delta_y)
+    }
+
+    fn send_scroll(&mut self, delta_x: f64, delta_y: f64) -> eyre::Result<()> {
+        self.send_batch()?;
+        self.engine.send_scroll_event(
+            self.position.x,
+            self.position.y,
+            delta_x,
+            delta_y,
+            self.buttons,
+        )?;
+
+        Ok(())
+    }
+
+    fn send_pending_remove(&mut self) -> eyre::Result<()> {
+        if mem::take(&mut self.pending_remove) {
+            self.send(PointerPhase::Remove)?;
+        }
+        Ok(())
+    }
+
+    /// Sends the pending throttled hover event if the throttle interval has elapsed.
+    ///
+    /// Returns the time at which the pending event should be sent, if it is still being held back.
+    pub fn flush(&mut self) -> eyre::Result<Option<Instant>> {
+        if !self.pending_hover {
+            return Ok(None);
+        }
+
+        if let (Some(throttle), Some(last_hover_time)) = (self.hover_throttle, self.last_hover_time)
+        {
+            let next_hover_time = last_hover_time + throttle;
+            if Instant::now() < next_hover_time {
+                return Ok(Some(next_hover_time));
+            }
+        }
+
+        self.flush_pending_hover()?;
+
+        Ok(None)
+    }
+
+    fn flush_pending_hover(&mut self) -> eyre::Result<()> {
+        if self.pending_hover {
+            self.pending_hover = false;
+            self.last_hover_time = Some(Instant::now());
+            self.queue(PointerPhase::Hover);
+        }
+        Ok(())
+    }
+
+    /// Sends the batched moves. This should be called once the event loop has finished handling
+    /// the events that it received.
+    pub fn send_batch(&mut self) -> eyre::Result<()> {
+        let res = self.engine.send_pointer_events(&self.batch);
+        self.batch.clear();
+        Ok(res?)
+    }
+
+    fn queue(&mut self, phase: PointerPhase) {
+        let event = PointerEvent::new(phase, self.position.x, self.position.y, self.buttons);
+        self.queue_event(event);
+    }
+
+    fn queue_contact(&mut self, id: u64, contact: &Contact, phase: PointerPhase) {
+        // Touch and pen contacts are reported as a primary press while they are down.
+        let buttons = match phase {
+            PointerPhase::Down | PointerPhase::Move => PointerButtons::PRIMARY,
+            _ => PointerButtons::empty(),
+        };
+
+        let event = PointerEvent::new(phase, contact.position.x, contact.position.y, buttons)
+            .with_device(contact.kind, id as i32);
+
+        self.queue_event(event);
+    }
+
+    fn queue_event(&mut self, event: PointerEvent) {
+        // Nothing is pressed while hovering, so only the latest position matters.
+        match self.batch.last_mut() {
+            Some(last)
+                if last.phase == PointerPhase::Hover
+                    && event.phase == PointerPhase::Hover
+                    && last.device == event.device =>
+            {
+                *last = event;
+            }
+            _ => self.batch.push(event),
+        }
+    }
+
+    /// Sends an event immediately, along with any batched moves before it.
+    fn send(&mut self, phase: PointerPhase) -> eyre::Result<()> {
+        self.queue(phase);
+        self.send_batch()
+    }
+}
+
+/// Returns whether the mouse message being handled was synthesized from touch or pen input, which
+/// is already handled through [`Pointer::handle_touch`].
+fn is_synthesized_mouse_message() -> bool {
+    let extra_info = unsafe { GetMessageExtraInfo() };
+    extra_info.0 & SIGNATURE_MASK == MI_WP_SIGNATURE
+}
+
+/// Returns whether a pointer is a pen or a touch. This is only known while the pointer is active,
+/// so it is looked up when the contact is first seen.
+fn pointer_kind(id: u64) -> PointerDeviceKind {
+    let mut pointer_type = POINTER_INPUT_TYPE::default();
+    match unsafe { GetPointerType(id as u32, &mut pointer_type) } {
+        Ok(()) if pointer_type == PT_PEN => PointerDeviceKind::Stylus,
+        _ => PointerDeviceKind::Touch,
+    }
+}
+
+/// Returns how far (in physical pixels) a wheel notch scrolls, using the lines or characters per
+/// notch read with `setting`.
+fn wheel_scroll_amount(
+    setting: SYSTEM_PARAMETERS_INFO_ACTION,
+    line_size: f64,
+    page_size: u32,
+) -> f64 {
+    let mut lines = DEFAULT_WHEEL_SCROLL_LINES;
+
+    // The setting can change at any time, and is cheap to read. The default is used if it can't
+    // be read.
+    let _ = unsafe {
+        SystemParametersInfoW(
+            setting,
+            0,
+            Some(&mut lines as *mut u32 as *mut c_void),
+            SYSTEM_PARAMETERS_INFO_UPDATE_FLAGS(0),
+        )
+    };
+
+    if lines == WHEEL_PAGESCROLL {
+        page_size as f64
+    } else {
+        lines as f64 * line_size
+    }
+}
@@ -0,0 +1,189 @@
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use color_eyre::eyre;
+use flutter_codec::EncodableValue;
+use windows::core::GUID;
+use windows::Win32::Foundation::{HANDLE, HWND, WPARAM};
+use windows::Win32::System::Power::{
+    GetSystemPowerStatus, RegisterPowerSettingNotification, UnregisterPowerSettingNotification,
+    DEVICE_NOTIFY_WINDOW_HANDLE, HPOWERNOTIFY, SYSTEM_POWER_STATUS,
+};
+use windows::Win32::System::SystemServices::{GUID_ACDC_POWER_SOURCE, GUID_POWER_SAVING_STATUS};
+use windows::Win32::UI::WindowsAndMessaging::PBT_POWERSETTINGCHANGE;
+
+use crate::error_utils::ResultExt;
+use crate::event_channel::EventChannel;
+use crate::standard_method_channel::{StandardMethodHandler, StandardMethodReply};
+use crate::vsync_waiter::VsyncWaiter;
+use crate::window_effects::WindowEffects;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct PowerState {
+    on_battery: bool,
+    battery_saver: bool,
+    /// `None` if there is no battery, or its charge isn't known.
+    battery_percent: Option<u8>,
+}
+
+impl PowerState {
+    fn query() -> eyre::Result<PowerState> {
+        let mut status = SYSTEM_POWER_STATUS::default();
+        unsafe { GetSystemPowerStatus(&mut status)? };
+
+        Ok(PowerState {
+            on_battery: status.ACLineStatus == 0,
+            battery_saver: status.SystemStatusFlag == 1,
+            battery_percent: (status.BatteryLifePercent <= 100)
+                .then_some(status.BatteryLifePercent),
+        })
+    }
+
+    fn is_saving(&self) -> bool {
+        self.on_battery || self.battery_saver
+    }
+}
+
+/// When to lower the frame cap and turn off the backdrop to save power.
+#[derive(Clone, Copy, Debug)]
+pub struct PowerPolicy {
+    /// The frame cap while on battery or in Battery Saver.
+    pub max_fps: u32,
+}
+
+/// Tracks whether the system is on battery or in Battery Saver, applying the power policy (if
+/// there is one) and reporting changes over `flion/power/events`.
+#[derive(Clone)]
+pub struct PowerMonitor {
+    inner: Rc<Inner>,
+}
+
+struct Inner {
+    vsync_waiter: Arc<VsyncWaiter>,
+    window_effects: WindowEffects,
+    policy: Option<PowerPolicy>,
+    events: Rc<EventChannel>,
+    state: Cell<Option<PowerState>>,
+    notifications: Vec<HPOWERNOTIFY>,
+}
+
+impl PowerMonitor {
+    pub fn new(
+        hwnd: HWND,
+        vsync_waiter: Arc<VsyncWaiter>,
+        window_effects: WindowEffects,
+        policy: Option<PowerPolicy>,
+        events: Rc<EventChannel>,
+    ) -> eyre::Result<PowerMonitor> {
+        // These are sent to the window as WM_POWERBROADCAST with PBT_POWERSETTINGCHANGE.
+        let notifications = [GUID_ACDC_POWER_SOURCE, GUID_POWER_SAVING_STATUS]
+            .iter()
+            .map(|guid| register_notification(hwnd, guid))
+            .collect::<eyre::Result<Vec<_>>>()?;
+
+        let monitor = PowerMonitor {
+            inner: Rc::new(Inner {
+                vsync_waiter,
+                window_effects,
+                policy,
+                events,
+                state: Cell::new(None),
+                notifications,
+            }),
+        };
+
+        monitor.update()?;
+
+        Ok(monitor)
+    }
+
+    fn state(&self) -> eyre::Result<PowerState> {
+        match self.inner.state.get() {
+            Some(state) => Ok(state),
+            None => PowerState::query(),
+        }
+    }
+
+    fn update(&self) -> eyre::Result<()> {
+        let state = PowerState::query()?;
+        if self.inner.state.replace(Some(state)) == Some(state) {
+            return Ok(());
+        }
+
+        tracing::info!(?state, "power state changed");
+
+        if let Some(policy) = &self.inner.policy {
+            let saving = state.is_saving();
+            self.inner
+                .vsync_waiter
+                .set_power_saving_max_fps(saving.then_some(policy.max_fps));
+            self.inner.window_effects.set_power_saving(saving)?;
+        }
+
+        self.inner.events.send(&self.encode(state))?;
+
+        Ok(())
+    }
+
+    /// Handles `WM_POWERBROADCAST`.
+    pub fn handle_power_broadcast(&self, wparam: WPARAM) {
+        if wparam.0 as u32 == PBT_POWERSETTINGCHANGE {
+            let _ = self.update().trace_err();
+        }
+    }
+
+    fn encode(&self, state: PowerState) -> EncodableValue<'static> {
+        let throttled = self.inner.policy.is_some() && state.is_saving();
+        EncodableValue::Map(BTreeMap::from_iter([
+            (
+                EncodableValue::Str("onBattery"),
+                EncodableValue::Bool(state.on_battery),
+            ),
+            (
+                EncodableValue::Str("batterySaver"),
+                EncodableValue::Bool(state.battery_saver),
+            ),
+            (
+                EncodableValue::Str("batteryPercent"),
+                state
+                    .battery_percent
+                    .map_or(EncodableValue::Null, |p| EncodableValue::I32(p as i32)),
+            ),
+            (
+                EncodableValue::Str("throttled"),
+                EncodableValue::Bool(throttled),
+            ),
+        ]))
+    }
+}
+
+fn register_notification(hwnd: HWND, guid: &GUID) -> eyre::Result<HPOWERNOTIFY> {
+    Ok(unsafe {
+        RegisterPowerSettingNotification(HANDLE(hwnd.0), guid, DEVICE_NOTIFY_WINDOW_HANDLE)?
+    })
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        for notification in &self.notifications {
+            let _ = unsafe { UnregisterPowerSettingNotification(*notification) };
+        }
+    }
+}
+
+impl StandardMethodHandler for PowerMonitor {
+    fn handle(&self, method: &str, _args: EncodableValue, reply: StandardMethodReply) {
+        match method {
+            "getPowerState" => match self.state() {
+                Ok(state) => reply.success(&self.encode(state)),
+                Err(e) => reply.error("power_error", Some(&e.to_string())),
+            },
+            _ => {
+                tracing::warn!(method, "unimplemented");
+                reply.not_implemented();
+            }
+        }
+    }
+}
@@ -0,0 +1,116 @@
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use color_eyre::eyre;
+use flutter_codec::EncodableValue;
+use flutter_embedder::FlutterEngineGetCurrentTime;
+use winit::event::{DeviceEvent, ElementState};
+use winit::event_loop::DeviceEvents;
+
+use crate::event_channel::EventChannel;
+
+/// Forwards raw mouse input (unaccelerated motion deltas and button presses, from `WM_INPUT`) to
+/// `flion/raw_input`, for apps that want relative motion such as games and canvas tools.
+///
+/// Raw input is only received while the Dart side is listening, and while the window is focused.
+/// Motion received while the event loop is busy is summed into a single event.
+pub struct RawInput {
+    events: Rc<EventChannel>,
+    delta: (f64, f64),
+    samples: i64,
+}
+
+impl RawInput {
+    pub fn new(events: Rc<EventChannel>) -> RawInput {
+        RawInput {
+            events,
+            delta: (0.0, 0.0),
+            samples: 0,
+        }
+    }
+
+    /// The device events that the event loop should listen for.
+    pub fn device_events(&self) -> DeviceEvents {
+        if self.events.is_listening() {
+            DeviceEvents::WhenFocused
+        } else {
+            DeviceEvents::Never
+        }
+    }
+
+    pub fn handle_device_event(&mut self, event: DeviceEvent) -> eyre::Result<()> {
+        if !self.events.is_listening() {
+            return Ok(());
+        }
+
+        match event {
+            DeviceEvent::MouseMotion { delta: (dx, dy) } => {
+                self.delta.0 += dx;
+                self.delta.1 += dy;
+                self.samples += 1;
+            }
+            DeviceEvent::Button { button, state } => {
+                // Motion before the press is sent first, so that it is at the right position.
+                self.flush()?;
+                self.send(
+                    "button",
+                    [
+                        ("button", EncodableValue::I64(button as i64)),
+                        (
+                            "pressed",
+                            EncodableValue::Bool(state == ElementState::Pressed),
+                        ),
+                    ],
+                )?;
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    /// Sends the motion received since the last flush.
+    pub fn flush(&mut self) -> eyre::Result<()> {
+        if self.samples == 0 {
+            return Ok(());
+        }
+
+        let (dx, dy) = std::mem::take(&mut self.delta);
+        let samples = std::mem::take(&mut self.samples);
+
+        self.send(
+            "motion",
+            [
+                ("dx", EncodableValue::F64(dx.into())),
+                ("dy", EncodableValue::F64(dy.into())),
+                ("samples", EncodableValue::I64(samples)),
+            ],
+        )
+    }
+
+    fn send<const N: usize>(
+        &self,
+        event_type: &str,
+        fields: [(&str, EncodableValue); N],
+    ) -> eyre::Result<()> {
+        // In microseconds, on the engine clock. Pointer events use the same clock, but are sent in
+        // nanoseconds, so their timestamps are 1000 times larger.
+        let timestamp = unsafe { FlutterEngineGetCurrentTime() } / 1000;
+
+        let mut event = BTreeMap::from_iter([
+            (EncodableValue::Str("type"), EncodableValue::Str(event_type)),
+            (
+                EncodableValue::Str("timestamp"),
+                EncodableValue::I64(timestamp as i64),
+            ),
+        ]);
+
+        event.extend(
+            fields
+                .into_iter()
+                .map(|(key, value)| (EncodableValue::Str(key), value)),
+        );
+
+        self.events.send(&EncodableValue::Map(event))
+    }
+}
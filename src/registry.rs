@@ -0,0 +1,26 @@
+//! Helpers for writing the per-user registry keys that the app registers itself with.
+
+use color_eyre::eyre;
+use windows::core::{HSTRING, PCWSTR};
+use windows::Win32::System::Registry::{RegSetKeyValueW, HKEY_CURRENT_USER, REG_SZ};
+
+/// Sets a string value under `HKEY_CURRENT_USER`, creating the key if needed.
+pub fn set_string_value(key: &str, name: Option<&str>, value: &str) -> eyre::Result<()> {
+    let key = HSTRING::from(key);
+    let name = name.map(HSTRING::from);
+    let value = value.encode_utf16().chain([0]).collect::<Vec<u16>>();
+
+    unsafe {
+        RegSetKeyValueW(
+            HKEY_CURRENT_USER,
+            &key,
+            name.as_ref().map(PCWSTR::from).unwrap_or(PCWSTR::null()),
+            REG_SZ.0,
+            Some(value.as_ptr().cast()),
+            (value.len() * 2) as u32,
+        )
+        .ok()?;
+    }
+
+    Ok(())
+}
@@ -1,30 +1,52 @@
 use std::sync::{Condvar, Mutex, MutexGuard};
+use std::time::Instant;
+
+use crate::{frame_stats, timeline};
 
 pub struct ResizeController {
     is_resizing: Mutex<bool>,
     condvar: Condvar,
+    synchronous: bool,
 }
 
 impl ResizeController {
-    pub fn new() -> ResizeController {
+    /// If `synchronous` is false, resizes don't wait for a new frame to be presented. This is
+    /// needed when the UI thread is merged with the platform thread, as the frame could never be
+    /// produced while the platform thread is blocked.
+    pub fn new(synchronous: bool) -> ResizeController {
         ResizeController {
             is_resizing: Mutex::new(false),
             condvar: Condvar::new(),
+            synchronous,
         }
     }
 
     pub fn begin_and_wait<T>(&self, block: impl FnOnce() -> T) -> T {
+        if !self.synchronous {
+            return block();
+        }
+
         let mut is_resizing = self.is_resizing.lock().unwrap();
 
         *is_resizing = true;
 
+        timeline::duration_begin(c"Resize");
+        timeline::instant(c"ResizeStarted");
+
         let res = block();
 
+        let wait_start = Instant::now();
+
         let _unused = self
             .condvar
             .wait_while(is_resizing, |is_resizing| *is_resizing)
             .unwrap();
 
+        frame_stats::record_resize_wait(wait_start.elapsed());
+
+        timeline::instant(c"ResizeDone");
+        timeline::duration_end(c"Resize");
+
         res
     }
 
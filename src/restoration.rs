@@ -0,0 +1,139 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use color_eyre::eyre;
+use flutter_codec::EncodableValue;
+
+use crate::paths;
+use crate::standard_method_channel::{StandardMethodHandler, StandardMethodReply};
+
+/// How long restoration data is kept in memory after it changes before it is written to disk.
+/// The framework sends the whole bundle every time any restorable property changes, so writes
+/// are batched.
+const SAVE_DELAY: Duration = Duration::from_secs(5);
+
+/// Persists the framework's restoration data (used by `RestorationMixin`) across runs, and
+/// provides it at startup over `flutter/restoration`.
+#[derive(Clone)]
+pub struct Restoration {
+    inner: Rc<RefCell<Inner>>,
+}
+
+struct Inner {
+    data: Option<Vec<u8>>,
+    /// When the data was first changed since it was last saved.
+    dirty_since: Option<Instant>,
+}
+
+impl Restoration {
+    pub fn load() -> Restoration {
+        let data = match load() {
+            Ok(data) => data,
+            Err(e) => {
+                tracing::error!("failed to load restoration data: {e:?}");
+                None
+            }
+        };
+
+        Restoration {
+            inner: Rc::new(RefCell::new(Inner {
+                data,
+                dirty_since: None,
+            })),
+        }
+    }
+
+    /// Saves the data if it has been changed for long enough, returning when it should next be
+    /// called otherwise.
+    pub fn save_if_due(&self) -> eyre::Result<Option<Instant>> {
+        let Some(dirty_since) = self.inner.borrow().dirty_since else {
+            return Ok(None);
+        };
+
+        let deadline = dirty_since + SAVE_DELAY;
+        if Instant::now() < deadline {
+            return Ok(Some(deadline));
+        }
+
+        self.save()?;
+
+        Ok(None)
+    }
+
+    /// Saves the data now if it has changed, e.g. when exiting.
+    pub fn save(&self) -> eyre::Result<()> {
+        let mut inner = self.inner.borrow_mut();
+        if inner.dirty_since.take().is_none() {
+            return Ok(());
+        }
+
+        match &inner.data {
+            Some(data) => save(data),
+            None => Ok(()),
+        }
+    }
+}
+
+fn restoration_file() -> eyre::Result<PathBuf> {
+    Ok(paths::local_app_data_dir()?.join("restoration.bin"))
+}
+
+fn load() -> eyre::Result<Option<Vec<u8>>> {
+    let path = restoration_file()?;
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    Ok(Some(fs::read(path)?))
+}
+
+/// Writes to a temporary file that then replaces the existing one, so that the data isn't lost
+/// if the app exits in the middle of writing.
+fn save(data: &[u8]) -> eyre::Result<()> {
+    let path = restoration_file()?;
+    let temp_path = path.with_extension("bin.tmp");
+
+    fs::write(&temp_path, data)?;
+    fs::rename(temp_path, path)?;
+
+    Ok(())
+}
+
+impl StandardMethodHandler for Restoration {
+    fn handle(&self, method: &str, args: EncodableValue, reply: StandardMethodReply) {
+        match method {
+            "get" => {
+                let inner = self.inner.borrow();
+                let mut res = BTreeMap::from_iter([(
+                    EncodableValue::Str("enabled"),
+                    EncodableValue::Bool(true),
+                )]);
+
+                if let Some(data) = &inner.data {
+                    res.insert(EncodableValue::Str("data"), EncodableValue::U8List(data));
+                }
+
+                reply.success(&EncodableValue::Map(res));
+            }
+            "put" => {
+                let Some(data) = args.as_u8_list() else {
+                    return reply.error("invalid_args", Some("expected restoration data"));
+                };
+
+                let mut inner = self.inner.borrow_mut();
+                inner.data = Some(data.to_vec());
+                inner.dirty_since.get_or_insert_with(Instant::now);
+
+                reply.success(&EncodableValue::Null);
+            }
+            _ => {
+                tracing::warn!(method, "unimplemented");
+                reply.not_implemented();
+            }
+        }
+    }
+}
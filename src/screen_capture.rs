@@ -0,0 +1,440 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::ffi::c_void;
+use std::sync::{Arc, Mutex};
+
+use color_eyre::eyre::{self, bail};
+use flutter_codec::EncodableValue;
+use flutter_embedder::FlutterOpenGLTexture;
+use windows::core::{factory, ComInterface, IInspectable};
+use windows::Foundation::TypedEventHandler;
+use windows::Graphics::Capture::{
+    Direct3D11CaptureFrame, Direct3D11CaptureFramePool, GraphicsCaptureItem, GraphicsCaptureSession,
+};
+use windows::Graphics::DirectX::Direct3D11::IDirect3DDevice;
+use windows::Graphics::DirectX::DirectXPixelFormat;
+use windows::Graphics::SizeInt32;
+use windows::Win32::Foundation::{BOOL, HWND, LPARAM, RECT};
+use windows::Win32::Graphics::Direct3D11::{ID3D11Device, ID3D11Texture2D};
+use windows::Win32::Graphics::Dxgi::IDXGIDevice;
+use windows::Win32::Graphics::Gdi::{
+    EnumDisplayMonitors, GetMonitorInfoW, HDC, HMONITOR, MONITORINFO, MONITORINFOEXW,
+};
+use windows::Win32::System::WinRT::Direct3D11::{
+    CreateDirect3D11DeviceFromDXGIDevice, IDirect3DDxgiInterfaceAccess,
+};
+use windows::Win32::System::WinRT::Graphics::Capture::IGraphicsCaptureItemInterop;
+use windows::Win32::UI::WindowsAndMessaging::{
+    EnumWindows, GetWindowTextLengthW, GetWindowTextW, IsWindowVisible,
+};
+
+use crate::egl_manager::EglManager;
+use crate::error_utils::ResultExt;
+use crate::standard_method_channel::{StandardMethodHandler, StandardMethodReply};
+use crate::texture_registry::{ExternalTexture, GlTexture, TextureRegistry};
+
+/// Number of frames in the capture frame pool. One is held by the engine while it is being drawn,
+/// and the other receives the next frame.
+const FRAME_POOL_SIZE: i32 = 2;
+
+/// Captures monitors and windows with Windows.Graphics.Capture. Captured frames are shown with a
+/// `Texture` widget, sampling directly from the textures that the capture API writes into.
+pub struct ScreenCaptureHandler {
+    device: IDirect3DDevice,
+    egl_manager: Arc<EglManager>,
+    texture_registry: Arc<TextureRegistry>,
+    captures: RefCell<BTreeMap<i64, Capture>>,
+}
+
+impl ScreenCaptureHandler {
+    pub fn new(
+        device: &ID3D11Device,
+        egl_manager: Arc<EglManager>,
+        texture_registry: Arc<TextureRegistry>,
+    ) -> eyre::Result<ScreenCaptureHandler> {
+        let device = unsafe {
+            CreateDirect3D11DeviceFromDXGIDevice(&device.cast::<IDXGIDevice>()?)?
+                .cast::<IDirect3DDevice>()?
+        };
+
+        Ok(ScreenCaptureHandler {
+            device,
+            egl_manager,
+            texture_registry,
+            captures: RefCell::new(BTreeMap::new()),
+        })
+    }
+
+    fn start(&self, item: GraphicsCaptureItem, cursor: bool) -> eyre::Result<i64> {
+        let capture = Capture::start(
+            &self.device,
+            self.egl_manager.clone(),
+            self.texture_registry.clone(),
+            item,
+            cursor,
+        )?;
+
+        let texture_id = capture.texture_id;
+        self.captures.borrow_mut().insert(texture_id, capture);
+
+        Ok(texture_id)
+    }
+}
+
+impl StandardMethodHandler for ScreenCaptureHandler {
+    fn handle(&self, method: &str, args: EncodableValue, reply: StandardMethodReply) {
+        match method {
+            "isSupported" => match GraphicsCaptureSession::IsSupported() {
+                Ok(supported) => reply.success(&EncodableValue::Bool(supported)),
+                Err(e) => reply.error("capture_error", Some(&e.to_string())),
+            },
+            "getSources" => {
+                let sources = list_sources();
+                let sources = sources
+                    .iter()
+                    .map(|source| {
+                        EncodableValue::Map(BTreeMap::from([
+                            (
+                                EncodableValue::Str("type"),
+                                EncodableValue::Str(source.source_type.name()),
+                            ),
+                            (
+                                EncodableValue::Str("handle"),
+                                EncodableValue::I64(source.handle as i64),
+                            ),
+                            (
+                                EncodableValue::Str("name"),
+                                EncodableValue::Str(&source.name),
+                            ),
+                        ]))
+                    })
+                    .collect();
+
+                reply.success(&EncodableValue::List(sources));
+            }
+            "start" => {
+                let source_type = args
+                    .get("type")
+                    .and_then(|v| v.as_string())
+                    .and_then(SourceType::from_name);
+
+                let (Some(source_type), Some(handle)) =
+                    (source_type, args.get("handle").and_then(|v| v.as_int()))
+                else {
+                    return reply.error("invalid_args", Some("expected a source type and handle"));
+                };
+
+                let cursor = args.get("cursor").and_then(|v| v.as_bool()).unwrap_or(true);
+
+                let res = create_item(source_type, handle as isize)
+                    .and_then(|item| self.start(item, cursor));
+
+                match res {
+                    Ok(texture_id) => reply.success(&EncodableValue::I64(texture_id)),
+                    Err(e) => reply.error("capture_error", Some(&format!("{e:?}"))),
+                }
+            }
+            "stop" => {
+                let Some(texture_id) = args.get("textureId").and_then(|v| v.as_int()) else {
+                    return reply.error("invalid_args", Some("expected a texture id"));
+                };
+
+                if self.captures.borrow_mut().remove(&texture_id).is_some() {
+                    reply.success(&EncodableValue::Null);
+                } else {
+                    reply.error("invalid_args", Some("unknown texture id"));
+                }
+            }
+            _ => {
+                tracing::warn!(method, "unimplemented");
+                reply.not_implemented();
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum SourceType {
+    Monitor,
+    Window,
+}
+
+impl SourceType {
+    fn from_name(name: &str) -> Option<SourceType> {
+        Some(match name {
+            "monitor" => SourceType::Monitor,
+            "window" => SourceType::Window,
+            _ => return None,
+        })
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            SourceType::Monitor => "monitor",
+            SourceType::Window => "window",
+        }
+    }
+}
+
+struct Source {
+    source_type: SourceType,
+    handle: isize,
+    name: String,
+}
+
+/// Lists the monitors, and the visible top level windows that have a title.
+fn list_sources() -> Vec<Source> {
+    unsafe extern "system" fn monitor_callback(
+        monitor: HMONITOR,
+        _: HDC,
+        _: *mut RECT,
+        sources: LPARAM,
+    ) -> BOOL {
+        let sources = &mut *(sources.0 as *mut Vec<Source>);
+
+        let mut info = MONITORINFOEXW {
+            monitorInfo: MONITORINFO {
+                cbSize: std::mem::size_of::<MONITORINFOEXW>() as u32,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        if GetMonitorInfoW(monitor, &mut info.monitorInfo).as_bool() {
+            let len = info.szDevice.iter().position(|&c| c == 0).unwrap_or(0);
+            sources.push(Source {
+                source_type: SourceType::Monitor,
+                handle: monitor.0,
+                name: String::from_utf16_lossy(&info.szDevice[..len]),
+            });
+        }
+
+        true.into()
+    }
+
+    unsafe extern "system" fn window_callback(hwnd: HWND, sources: LPARAM) -> BOOL {
+        let sources = &mut *(sources.0 as *mut Vec<Source>);
+
+        let len = GetWindowTextLengthW(hwnd);
+        if !IsWindowVisible(hwnd).as_bool() || len == 0 {
+            return true.into();
+        }
+
+        let mut title = vec![0u16; len as usize + 1];
+        let len = GetWindowTextW(hwnd, &mut title) as usize;
+
+        sources.push(Source {
+            source_type: SourceType::Window,
+            handle: hwnd.0,
+            name: String::from_utf16_lossy(&title[..len]),
+        });
+
+        true.into()
+    }
+
+    let mut sources = Vec::new();
+    let lparam = LPARAM(&mut sources as *mut Vec<Source> as isize);
+
+    unsafe {
+        EnumDisplayMonitors(None, None, Some(monitor_callback), lparam);
+        if let Err(e) = EnumWindows(Some(window_callback), lparam) {
+            tracing::error!("failed to enumerate windows: {e}");
+        }
+    }
+
+    sources
+}
+
+fn create_item(source_type: SourceType, handle: isize) -> eyre::Result<GraphicsCaptureItem> {
+    let interop = factory::<GraphicsCaptureItem, IGraphicsCaptureItemInterop>()?;
+
+    let item = unsafe {
+        match source_type {
+            SourceType::Monitor => interop.CreateForMonitor(HMONITOR(handle))?,
+            SourceType::Window => interop.CreateForWindow(HWND(handle))?,
+        }
+    };
+
+    Ok(item)
+}
+
+struct Capture {
+    texture_id: i64,
+    session: GraphicsCaptureSession,
+    frame_pool: Direct3D11CaptureFramePool,
+    texture_registry: Arc<TextureRegistry>,
+}
+
+impl Capture {
+    fn start(
+        device: &IDirect3DDevice,
+        egl_manager: Arc<EglManager>,
+        texture_registry: Arc<TextureRegistry>,
+        item: GraphicsCaptureItem,
+        cursor: bool,
+    ) -> eyre::Result<Capture> {
+        if !GraphicsCaptureSession::IsSupported()? {
+            bail!("screen capture is not supported on this system");
+        }
+
+        let size = item.Size()?;
+
+        // A free threaded pool delivers frames on a background thread, so frames don't have to go
+        // through the platform thread on their way to the raster thread.
+        let frame_pool = Direct3D11CaptureFramePool::CreateFreeThreaded(
+            device,
+            DirectXPixelFormat::B8G8R8A8UIntNormalized,
+            FRAME_POOL_SIZE,
+            size,
+        )?;
+
+        let session = frame_pool.CreateCaptureSession(&item)?;
+
+        if let Err(e) = session.SetIsCursorCaptureEnabled(cursor) {
+            // Only supported from Windows 10 2004.
+            tracing::warn!("failed to configure cursor capture: {e}");
+        }
+
+        let texture = Arc::new(CaptureTexture {
+            device: device.clone(),
+            egl_manager,
+            frame_pool: frame_pool.clone(),
+            pool_size: Mutex::new(size),
+            current: Mutex::new(None),
+        });
+
+        let texture_id = texture_registry.register(texture)?;
+
+        let res = frame_pool
+            .FrameArrived(
+                &TypedEventHandler::<Direct3D11CaptureFramePool, IInspectable>::new({
+                    let texture_registry = texture_registry.clone();
+                    move |_, _| {
+                        let _ = texture_registry
+                            .mark_frame_available(texture_id)
+                            .trace_err();
+                        Ok(())
+                    }
+                }),
+            )
+            .and_then(|_| session.StartCapture());
+
+        if let Err(e) = res {
+            let _ = texture_registry.unregister(texture_id);
+            let _ = session.Close();
+            let _ = frame_pool.Close();
+            return Err(e.into());
+        }
+
+        Ok(Capture {
+            texture_id,
+            session,
+            frame_pool,
+            texture_registry,
+        })
+    }
+}
+
+impl Drop for Capture {
+    fn drop(&mut self) {
+        let _ = self.session.Close().trace_err();
+        let _ = self.frame_pool.Close().trace_err();
+        let _ = self
+            .texture_registry
+            .unregister(self.texture_id)
+            .trace_err();
+    }
+}
+
+struct CaptureTexture {
+    device: IDirect3DDevice,
+    egl_manager: Arc<EglManager>,
+    frame_pool: Direct3D11CaptureFramePool,
+    pool_size: Mutex<SizeInt32>,
+    current: Mutex<Option<Arc<CapturedFrame>>>,
+}
+
+unsafe impl Send for CaptureTexture {}
+unsafe impl Sync for CaptureTexture {}
+
+impl ExternalTexture for CaptureTexture {
+    fn frame(&self, _width: usize, _height: usize) -> Option<FlutterOpenGLTexture> {
+        let mut current = self.current.lock().unwrap();
+
+        // There may not be a new frame, e.g. if the engine is redrawing for another reason, in
+        // which case the previous one is shown again.
+        if let Ok(frame) = self.frame_pool.TryGetNextFrame() {
+            match self.wrap_frame(frame) {
+                Ok(frame) => *current = Some(Arc::new(frame)),
+                Err(e) => tracing::error!("failed to get captured frame: {e:?}"),
+            }
+        }
+
+        let frame = current.clone()?;
+
+        Some(FlutterOpenGLTexture {
+            target: gl::TEXTURE_2D,
+            name: frame.gl_texture.name(),
+            format: gl::RGBA8,
+            width: frame.size.Width as usize,
+            height: frame.size.Height as usize,
+            // The engine may keep using the frame after the next one has arrived.
+            user_data: Arc::into_raw(frame) as *mut c_void,
+            destruction_callback: Some(release_frame),
+        })
+    }
+}
+
+unsafe extern "C" fn release_frame(user_data: *mut c_void) {
+    drop(Arc::from_raw(user_data.cast::<CapturedFrame>()));
+}
+
+impl CaptureTexture {
+    fn wrap_frame(&self, frame: Direct3D11CaptureFrame) -> eyre::Result<CapturedFrame> {
+        let size = frame.ContentSize()?;
+
+        // The pool needs to be recreated when the source is resized, otherwise frames are
+        // cropped or padded to the original size.
+        let mut pool_size = self.pool_size.lock().unwrap();
+        if size != *pool_size {
+            self.frame_pool.Recreate(
+                &self.device,
+                DirectXPixelFormat::B8G8R8A8UIntNormalized,
+                FRAME_POOL_SIZE,
+                size,
+            )?;
+            *pool_size = size;
+        }
+
+        let texture = unsafe {
+            frame
+                .Surface()?
+                .cast::<IDirect3DDxgiInterfaceAccess>()?
+                .GetInterface::<ID3D11Texture2D>()?
+        };
+
+        let gl_texture = GlTexture::new(self.egl_manager.clone(), &texture)?;
+
+        Ok(CapturedFrame {
+            frame,
+            size,
+            gl_texture,
+        })
+    }
+}
+
+struct CapturedFrame {
+    /// Returned to the pool when this is dropped.
+    frame: Direct3D11CaptureFrame,
+    size: SizeInt32,
+    gl_texture: GlTexture,
+}
+
+unsafe impl Send for CapturedFrame {}
+unsafe impl Sync for CapturedFrame {}
+
+impl Drop for CapturedFrame {
+    fn drop(&mut self) {
+        let _ = self.frame.Close();
+    }
+}
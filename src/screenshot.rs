@@ -0,0 +1,357 @@
+use std::collections::BTreeMap;
+use std::ffi::c_void;
+use std::path::Path;
+use std::sync::mpsc;
+use std::time::Duration;
+
+use color_eyre::eyre::{self, bail, OptionExt};
+use flutter_codec::EncodableValue;
+use windows::core::{ComInterface, IInspectable, HSTRING};
+use windows::Foundation::TypedEventHandler;
+use windows::Graphics::Capture::{
+    Direct3D11CaptureFramePool, GraphicsCaptureItem, GraphicsCaptureSession,
+};
+use windows::Graphics::DirectX::Direct3D11::IDirect3DDevice;
+use windows::Graphics::DirectX::DirectXPixelFormat;
+use windows::Win32::Foundation::GENERIC_READ;
+use windows::Win32::Graphics::Direct3D11::{
+    ID3D11Device, ID3D11Texture2D, D3D11_CPU_ACCESS_READ, D3D11_MAPPED_SUBRESOURCE, D3D11_MAP_READ,
+    D3D11_TEXTURE2D_DESC, D3D11_USAGE_STAGING,
+};
+use windows::Win32::Graphics::Dxgi::IDXGIDevice;
+use windows::Win32::Graphics::Imaging::{
+    CLSID_WICImagingFactory, GUID_ContainerFormatPng, GUID_WICPixelFormat32bppRGBA,
+    IWICBitmapDecoder, IWICImagingFactory, WICBitmapEncoderNoCache, WICConvertBitmapSource,
+    WICDecodeMetadataCacheOnDemand,
+};
+use windows::Win32::System::Com::{
+    CoCreateInstance, CLSCTX_INPROC_SERVER, STREAM_SEEK_END, STREAM_SEEK_SET,
+};
+use windows::Win32::System::WinRT::Direct3D11::{
+    CreateDirect3D11DeviceFromDXGIDevice, IDirect3DDxgiInterfaceAccess,
+};
+use windows::Win32::UI::Shell::SHCreateMemStream;
+use windows::UI::Composition::Visual;
+
+use crate::standard_method_channel::{StandardMethodHandler, StandardMethodReply};
+
+/// How long to wait for the compositor to deliver a frame.
+const CAPTURE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// A captured frame, as tightly packed, straight alpha RGBA rows from top to bottom.
+pub struct Frame {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl Frame {
+    /// Decodes an image file, e.g. a golden image written by [`Frame::encode_png`].
+    #[allow(dead_code)] // Only used by the test harness.
+    pub fn load(path: &Path) -> eyre::Result<Frame> {
+        unsafe {
+            let factory: IWICImagingFactory =
+                CoCreateInstance(&CLSID_WICImagingFactory, None, CLSCTX_INPROC_SERVER)?;
+
+            let decoder = factory.CreateDecoderFromFilename(
+                &HSTRING::from(path.as_os_str()),
+                None,
+                GENERIC_READ,
+                WICDecodeMetadataCacheOnDemand,
+            )?;
+
+            Frame::from_decoder(&decoder)
+        }
+    }
+
+    /// Decodes an encoded image (e.g. a png) from memory.
+    pub fn decode(data: &[u8]) -> eyre::Result<Frame> {
+        unsafe {
+            let factory: IWICImagingFactory =
+                CoCreateInstance(&CLSID_WICImagingFactory, None, CLSCTX_INPROC_SERVER)?;
+
+            let stream = factory.CreateStream()?;
+            stream.InitializeFromMemory(data)?;
+
+            let decoder =
+                factory.CreateDecoderFromStream(&stream, None, WICDecodeMetadataCacheOnDemand)?;
+
+            Frame::from_decoder(&decoder)
+        }
+    }
+
+    unsafe fn from_decoder(decoder: &IWICBitmapDecoder) -> eyre::Result<Frame> {
+        let frame = WICConvertBitmapSource(&GUID_WICPixelFormat32bppRGBA, &decoder.GetFrame(0)?)?;
+
+        let (mut width, mut height) = (0, 0);
+        frame.GetSize(&mut width, &mut height)?;
+
+        let mut pixels = vec![0u8; (width * height * 4) as usize];
+        frame.CopyPixels(std::ptr::null(), width * 4, &mut pixels)?;
+
+        Ok(Frame {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    pub fn encode_png(&self) -> eyre::Result<Vec<u8>> {
+        unsafe {
+            let factory: IWICImagingFactory =
+                CoCreateInstance(&CLSID_WICImagingFactory, None, CLSCTX_INPROC_SERVER)?;
+
+            let stream = SHCreateMemStream(None).ok_or_eyre("failed to create stream")?;
+
+            let encoder = factory.CreateEncoder(&GUID_ContainerFormatPng, None)?;
+            encoder.Initialize(&stream, WICBitmapEncoderNoCache)?;
+
+            let mut frame = None;
+            encoder.CreateNewFrame(&mut frame, std::ptr::null_mut())?;
+            let frame = frame.ok_or_eyre("failed to create png frame")?;
+
+            frame.Initialize(None)?;
+            frame.SetSize(self.width, self.height)?;
+
+            let mut format = GUID_WICPixelFormat32bppRGBA;
+            frame.SetPixelFormat(&mut format)?;
+            if format != GUID_WICPixelFormat32bppRGBA {
+                bail!("png encoder doesn't support rgba pixels");
+            }
+
+            frame.WritePixels(self.height, self.width * 4, &self.pixels)?;
+            frame.Commit()?;
+            encoder.Commit()?;
+
+            let mut len = 0;
+            stream.Seek(0, STREAM_SEEK_END, Some(&mut len))?;
+            stream.Seek(0, STREAM_SEEK_SET, None)?;
+
+            let mut png = vec![0u8; len as usize];
+            let mut read = 0;
+            stream
+                .Read(
+                    png.as_mut_ptr().cast::<c_void>(),
+                    png.len() as u32,
+                    Some(&mut read),
+                )
+                .ok()?;
+
+            png.truncate(read as usize);
+
+            Ok(png)
+        }
+    }
+}
+
+/// Copies the most recently composited contents of `visual` (including platform views and
+/// anything else in its subtree) out of the compositor, using Windows.Graphics.Capture. `visual`
+/// is expected to be a root visual, which flips its content vertically.
+///
+/// This blocks until the compositor has delivered a frame.
+pub fn capture_frame(device: &ID3D11Device, visual: &Visual) -> eyre::Result<Frame> {
+    if !GraphicsCaptureSession::IsSupported()? {
+        bail!("capturing frames is not supported on this system");
+    }
+
+    let item = GraphicsCaptureItem::CreateFromVisual(visual)?;
+    let size = item.Size()?;
+
+    if size.Width <= 0 || size.Height <= 0 {
+        bail!("nothing to capture");
+    }
+
+    let winrt_device = unsafe {
+        CreateDirect3D11DeviceFromDXGIDevice(&device.cast::<IDXGIDevice>()?)?
+            .cast::<IDirect3DDevice>()?
+    };
+
+    // Frames are delivered on a background thread, since the platform thread is blocked while
+    // waiting for one.
+    let frame_pool = Direct3D11CaptureFramePool::CreateFreeThreaded(
+        &winrt_device,
+        DirectXPixelFormat::B8G8R8A8UIntNormalized,
+        1,
+        size,
+    )?;
+
+    let session = frame_pool.CreateCaptureSession(&item)?;
+
+    if let Err(e) = session.SetIsCursorCaptureEnabled(false) {
+        // Only supported from Windows 10 2004.
+        tracing::warn!("failed to disable cursor capture: {e}");
+    }
+
+    let res = (|| {
+        let (tx, rx) = mpsc::channel();
+
+        frame_pool.FrameArrived(
+            &TypedEventHandler::<Direct3D11CaptureFramePool, IInspectable>::new(move |_, _| {
+                let _ = tx.send(());
+                Ok(())
+            }),
+        )?;
+
+        session.StartCapture()?;
+
+        if rx.recv_timeout(CAPTURE_TIMEOUT).is_err() {
+            bail!("timed out waiting for a frame");
+        }
+
+        let frame = frame_pool.TryGetNextFrame()?;
+        let content_size = frame.ContentSize()?;
+
+        let texture = unsafe {
+            frame
+                .Surface()?
+                .cast::<IDirect3DDxgiInterfaceAccess>()?
+                .GetInterface::<ID3D11Texture2D>()?
+        };
+
+        read_texture(
+            device,
+            &texture,
+            content_size.Width as u32,
+            content_size.Height as u32,
+        )
+    })();
+
+    let _ = session.Close();
+    let _ = frame_pool.Close();
+
+    res
+}
+
+/// Reads back the top left `width` x `height` pixels of a BGRA texture.
+fn read_texture(
+    device: &ID3D11Device,
+    texture: &ID3D11Texture2D,
+    width: u32,
+    height: u32,
+) -> eyre::Result<Frame> {
+    let mut desc = D3D11_TEXTURE2D_DESC::default();
+    unsafe { texture.GetDesc(&mut desc) };
+
+    let width = width.min(desc.Width);
+    let height = height.min(desc.Height);
+
+    let staging_desc = D3D11_TEXTURE2D_DESC {
+        Usage: D3D11_USAGE_STAGING,
+        BindFlags: 0,
+        CPUAccessFlags: D3D11_CPU_ACCESS_READ.0 as u32,
+        MiscFlags: 0,
+        ..desc
+    };
+
+    let mut staging = None;
+    unsafe { device.CreateTexture2D(&staging_desc, None, Some(&mut staging))? };
+    let staging = staging.ok_or_eyre("failed to create staging texture")?;
+
+    let mut context = None;
+    unsafe { device.GetImmediateContext(&mut context) };
+    let context = context.ok_or_eyre("failed to get device context")?;
+
+    let mut pixels = Vec::with_capacity((width * height * 4) as usize);
+
+    unsafe {
+        context.CopyResource(&staging, texture);
+
+        let mut mapped = D3D11_MAPPED_SUBRESOURCE::default();
+        context.Map(&staging, 0, D3D11_MAP_READ, 0, Some(&mut mapped))?;
+
+        // The root visual is flipped vertically to match GL's coordinate system, but the capture
+        // doesn't include the visual's own transform, so rows are read from the bottom up.
+        for y in (0..height).rev() {
+            let row = std::slice::from_raw_parts(
+                mapped
+                    .pData
+                    .cast::<u8>()
+                    .add((y * mapped.RowPitch) as usize),
+                (width * 4) as usize,
+            );
+
+            // Composition surfaces use premultiplied BGRA.
+            for pixel in row.chunks_exact(4) {
+                let [b, g, r, a] = [pixel[0], pixel[1], pixel[2], pixel[3]];
+                let unpremultiply = |c: u8| match a {
+                    0 => 0,
+                    a => ((c as u32 * 255 + a as u32 / 2) / a as u32).min(255) as u8,
+                };
+                pixels.extend_from_slice(&[
+                    unpremultiply(r),
+                    unpremultiply(g),
+                    unpremultiply(b),
+                    a,
+                ]);
+            }
+        }
+
+        context.Unmap(&staging, 0);
+    }
+
+    Ok(Frame {
+        width,
+        height,
+        pixels,
+    })
+}
+
+/// Handles `flion/screenshot`, which captures the window's contents for bug reports and visual
+/// tests.
+pub struct ScreenshotHandler {
+    device: ID3D11Device,
+    visual: Visual,
+}
+
+impl ScreenshotHandler {
+    pub fn new(device: ID3D11Device, visual: Visual) -> ScreenshotHandler {
+        ScreenshotHandler { device, visual }
+    }
+}
+
+impl StandardMethodHandler for ScreenshotHandler {
+    fn handle(&self, method: &str, args: EncodableValue, reply: StandardMethodReply) {
+        match method {
+            "capture" => {
+                let format = args
+                    .get("format")
+                    .and_then(|v| v.as_string())
+                    .unwrap_or("png");
+
+                if format != "png" && format != "rgba" {
+                    return reply.error("invalid_args", Some("format must be png or rgba"));
+                }
+
+                let frame = match capture_frame(&self.device, &self.visual) {
+                    Ok(frame) => frame,
+                    Err(e) => return reply.error("capture_failed", Some(&format!("{e:?}"))),
+                };
+
+                let data = if format == "png" {
+                    match frame.encode_png() {
+                        Ok(png) => png,
+                        Err(e) => return reply.error("encode_failed", Some(&format!("{e:?}"))),
+                    }
+                } else {
+                    frame.pixels
+                };
+
+                reply.success(&EncodableValue::Map(BTreeMap::from([
+                    (
+                        EncodableValue::Str("width"),
+                        EncodableValue::I64(frame.width.into()),
+                    ),
+                    (
+                        EncodableValue::Str("height"),
+                        EncodableValue::I64(frame.height.into()),
+                    ),
+                    (EncodableValue::Str("data"), EncodableValue::U8List(&data)),
+                ])));
+            }
+            _ => {
+                tracing::warn!(method, "unimplemented");
+                reply.not_implemented();
+            }
+        }
+    }
+}
@@ -0,0 +1,230 @@
+use std::cell::Cell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Cursor;
+
+use color_eyre::eyre;
+use flutter_codec::EncodableValue;
+use windows::Win32::Foundation::LPARAM;
+use windows::Win32::UI::Accessibility::UiaRootObjectId;
+
+use crate::engine::{FlutterEngine, SemanticsAction, SemanticsFlags, SemanticsNode};
+use crate::settings;
+
+/// Turns the framework's semantics tree on while a screen reader (or another UI Automation client,
+/// e.g. Accessibility Insights or a test driver) is in use. Building the tree has a cost on every
+/// frame, so it is off for everyone else.
+pub struct SemanticsActivation {
+    /// Set once a UI Automation client has asked for the window's root element. There is no
+    /// notification when clients go away, so this stays set.
+    uia_client_connected: Cell<bool>,
+}
+
+impl SemanticsActivation {
+    pub fn new() -> SemanticsActivation {
+        SemanticsActivation {
+            uia_client_connected: Cell::new(false),
+        }
+    }
+
+    /// Enables or disables semantics to match whether anything needs them. Should be called when
+    /// the screen reader setting changes.
+    pub fn refresh(&self, engine: &FlutterEngine) -> eyre::Result<()> {
+        let enabled = self.uia_client_connected.get() || settings::screen_reader_active()?;
+
+        if enabled != engine.semantics_enabled() {
+            tracing::info!(enabled, "updating semantics");
+            engine.update_semantics_enabled(enabled)?;
+        }
+
+        Ok(())
+    }
+
+    /// Handles `WM_GETOBJECT`, which UI Automation clients send to find the window's root element.
+    pub fn handle_get_object(&self, engine: &FlutterEngine, lparam: LPARAM) -> eyre::Result<()> {
+        if lparam.0 as i32 != UiaRootObjectId {
+            return Ok(());
+        }
+
+        if !self.uia_client_connected.replace(true) {
+            self.refresh(engine)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The framework's semantics tree, kept up to date from the engine's semantics updates.
+#[derive(Default)]
+pub struct SemanticsTree {
+    nodes: BTreeMap<i32, SemanticsNode>,
+    parents: BTreeMap<i32, i32>,
+}
+
+impl SemanticsTree {
+    pub const ROOT_ID: i32 = 0;
+
+    /// Applies an update, which contains the nodes that were added or changed. Nodes that are no
+    /// longer reachable from the root have been removed.
+    pub fn update(&mut self, nodes: Vec<SemanticsNode>) {
+        for node in nodes {
+            self.nodes.insert(node.id, node);
+        }
+
+        self.parents.clear();
+
+        let mut reachable = BTreeSet::new();
+        let mut stack = vec![SemanticsTree::ROOT_ID];
+        while let Some(id) = stack.pop() {
+            let Some(node) = self.nodes.get(&id) else {
+                continue;
+            };
+
+            reachable.insert(id);
+            for &child in &node.children {
+                self.parents.insert(child, id);
+                stack.push(child);
+            }
+        }
+
+        self.nodes.retain(|id, _| reachable.contains(id));
+    }
+
+    pub fn clear(&mut self) {
+        self.nodes.clear();
+        self.parents.clear();
+    }
+
+    pub fn node(&self, id: i32) -> Option<&SemanticsNode> {
+        self.nodes.get(&id)
+    }
+
+    pub fn parent(&self, id: i32) -> Option<i32> {
+        self.parents.get(&id).copied()
+    }
+
+    /// The node with input focus, if any.
+    pub fn focused(&self) -> Option<i32> {
+        self.nodes
+            .values()
+            .find(|node| node.flags.contains(SemanticsFlags::IS_FOCUSED))
+            .map(|node| node.id)
+    }
+
+    /// The transform from a node's coordinate space to physical pixels in the view.
+    fn global_transform(&self, id: i32) -> Option<[f64; 9]> {
+        let mut transform = self.node(id)?.transform;
+        let mut id = id;
+        while let Some(parent) = self.parent(id) {
+            transform = multiply(&self.node(parent)?.transform, &transform);
+            id = parent;
+        }
+
+        Some(transform)
+    }
+
+    /// A node's bounds as (left, top, right, bottom), in physical pixels relative to the view.
+    pub fn bounds(&self, id: i32) -> Option<[f64; 4]> {
+        let transform = self.global_transform(id)?;
+        Some(transform_rect(&transform, &self.node(id)?.rect))
+    }
+
+    /// Finds the deepest node under a point, in physical pixels relative to the view.
+    pub fn hit_test(&self, x: f64, y: f64) -> Option<i32> {
+        let root = self.node(SemanticsTree::ROOT_ID)?;
+        self.hit_test_node(root, &root.transform, x, y)
+    }
+
+    fn hit_test_node(
+        &self,
+        node: &SemanticsNode,
+        transform: &[f64; 9],
+        x: f64,
+        y: f64,
+    ) -> Option<i32> {
+        let [left, top, right, bottom] = transform_rect(transform, &node.rect);
+        if x < left || x >= right || y < top || y >= bottom {
+            return None;
+        }
+
+        for child in &node.hit_test_children {
+            let Some(child) = self.node(*child) else {
+                continue;
+            };
+
+            let transform = multiply(transform, &child.transform);
+            if let Some(id) = self.hit_test_node(child, &transform, x, y) {
+                return Some(id);
+            }
+        }
+
+        Some(node.id)
+    }
+}
+
+fn multiply(a: &[f64; 9], b: &[f64; 9]) -> [f64; 9] {
+    let mut m = [0.0; 9];
+    for row in 0..3 {
+        for col in 0..3 {
+            m[row * 3 + col] = (0..3).map(|i| a[row * 3 + i] * b[i * 3 + col]).sum();
+        }
+    }
+    m
+}
+
+/// Transforms the corners of `rect` and returns their bounding box.
+fn transform_rect(m: &[f64; 9], &[left, top, right, bottom]: &[f64; 4]) -> [f64; 4] {
+    let corners = [(left, top), (right, top), (left, bottom), (right, bottom)].map(|(x, y)| {
+        let w = m[6] * x + m[7] * y + m[8];
+        (
+            (m[0] * x + m[1] * y + m[2]) / w,
+            (m[3] * x + m[4] * y + m[5]) / w,
+        )
+    });
+
+    let xs = corners.map(|(x, _)| x);
+    let ys = corners.map(|(_, y)| y);
+    let min = |values: [f64; 4]| values.into_iter().fold(f64::INFINITY, f64::min);
+    let max = |values: [f64; 4]| values.into_iter().fold(f64::NEG_INFINITY, f64::max);
+
+    [min(xs), min(ys), max(xs), max(ys)]
+}
+
+/// A UI Automation control pattern method, called by assistive technology on the element for a
+/// semantics node.
+#[derive(Debug)]
+pub enum UiaAction {
+    /// `IInvokeProvider::Invoke`.
+    Invoke,
+    /// `IToggleProvider::Toggle`. Checkboxes and switches toggle when tapped.
+    Toggle,
+    /// `IScrollItemProvider::ScrollIntoView`.
+    ScrollIntoView,
+    /// `IValueProvider::SetValue`.
+    SetValue(String),
+    /// `IRangeValueProvider::SetValue`, which can only be approximated by stepping the value once
+    /// in the right direction.
+    Step { increase: bool },
+}
+
+/// Performs a UI Automation pattern method on the semantics node with the given id.
+pub fn dispatch_uia_action(
+    engine: &FlutterEngine,
+    node_id: u64,
+    action: &UiaAction,
+) -> eyre::Result<()> {
+    let (action, data) = match action {
+        UiaAction::Invoke | UiaAction::Toggle => (SemanticsAction::Tap, vec![]),
+        UiaAction::ScrollIntoView => (SemanticsAction::ShowOnScreen, vec![]),
+        UiaAction::SetValue(text) => {
+            let mut data = vec![];
+            flutter_codec::write_value(&mut Cursor::new(&mut data), &EncodableValue::Str(text))?;
+            (SemanticsAction::SetText, data)
+        }
+        UiaAction::Step { increase: true } => (SemanticsAction::Increase, vec![]),
+        UiaAction::Step { increase: false } => (SemanticsAction::Decrease, vec![]),
+    };
+
+    engine.dispatch_semantics_action(node_id, action, &data)?;
+
+    Ok(())
+}
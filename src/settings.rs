@@ -1,13 +1,94 @@
+use std::ffi::c_void;
 use std::mem;
 
 use color_eyre::eyre;
 use serde_json::json;
 use windows::core::w;
+use windows::Win32::Foundation::BOOL;
 use windows::Win32::System::Registry::{RegGetValueW, HKEY_CURRENT_USER, RRF_RT_REG_DWORD};
+use windows::Win32::UI::Accessibility::{HCF_HIGHCONTRASTON, HIGHCONTRASTW};
+use windows::Win32::UI::WindowsAndMessaging::{
+    SystemParametersInfoW, SPI_GETCLIENTAREAANIMATION, SPI_GETHIGHCONTRAST, SPI_GETSCREENREADER,
+    SYSTEM_PARAMETERS_INFO_UPDATE_FLAGS,
+};
 
-use crate::engine::FlutterEngine;
+use crate::engine::{AccessibilityFeatures, FlutterEngine};
 
 pub fn send_to_engine(engine: &FlutterEngine) -> eyre::Result<()> {
+    let message = json!({
+        "platformBrightness": if apps_use_light_theme()? { "light" } else { "dark" },
+        "alwaysUse24HourFormat": false,
+        "textScaleFactor": 1.0f32,
+    });
+
+    engine.send_platform_message(c"flutter/settings", &serde_json::to_vec(&message)?)?;
+    engine.update_accessibility_features(accessibility_features()?)?;
+
+    Ok(())
+}
+
+/// Reads the high contrast and animation settings. When animations are turned off in the
+/// settings app, the framework is asked to both disable them and reduce motion.
+pub fn accessibility_features() -> eyre::Result<AccessibilityFeatures> {
+    let mut features = AccessibilityFeatures::empty();
+
+    let mut high_contrast = HIGHCONTRASTW {
+        cbSize: mem::size_of::<HIGHCONTRASTW>() as u32,
+        ..Default::default()
+    };
+
+    let mut animations_enabled = BOOL::from(true);
+
+    unsafe {
+        SystemParametersInfoW(
+            SPI_GETHIGHCONTRAST,
+            high_contrast.cbSize,
+            Some(&mut high_contrast as *mut HIGHCONTRASTW as *mut c_void),
+            SYSTEM_PARAMETERS_INFO_UPDATE_FLAGS(0),
+        )?;
+
+        SystemParametersInfoW(
+            SPI_GETCLIENTAREAANIMATION,
+            0,
+            Some(&mut animations_enabled as *mut BOOL as *mut c_void),
+            SYSTEM_PARAMETERS_INFO_UPDATE_FLAGS(0),
+        )?;
+    }
+
+    if high_contrast.dwFlags.contains(HCF_HIGHCONTRASTON) {
+        features |= AccessibilityFeatures::HIGH_CONTRAST;
+    }
+
+    if !animations_enabled.as_bool() {
+        features |=
+            AccessibilityFeatures::DISABLE_ANIMATIONS | AccessibilityFeatures::REDUCE_MOTION;
+    }
+
+    if screen_reader_active()? {
+        features |= AccessibilityFeatures::ACCESSIBLE_NAVIGATION;
+    }
+
+    Ok(features)
+}
+
+/// Whether a screen reader is running. Screen readers set this when they start, and notify
+/// windows with `WM_SETTINGCHANGE`.
+pub fn screen_reader_active() -> eyre::Result<bool> {
+    let mut active = BOOL::from(false);
+
+    unsafe {
+        SystemParametersInfoW(
+            SPI_GETSCREENREADER,
+            0,
+            Some(&mut active as *mut BOOL as *mut c_void),
+            SYSTEM_PARAMETERS_INFO_UPDATE_FLAGS(0),
+        )?;
+    }
+
+    Ok(active.as_bool())
+}
+
+pub fn apps_use_light_theme() -> eyre::Result<bool> {
     let mut use_light_theme = 0u32;
     let mut use_light_theme_size = mem::size_of_val(&use_light_theme) as u32;
     unsafe {
@@ -22,13 +103,5 @@ pub fn send_to_engine(engine: &FlutterEngine) -> eyre::Result<()> {
         )?;
     }
 
-    let message = json!({
-        "platformBrightness": if use_light_theme == 0 { "dark" } else { "light" },
-        "alwaysUse24HourFormat": false,
-        "textScaleFactor": 1.0f32,
-    });
-
-    engine.send_platform_message(c"flutter/settings", &serde_json::to_vec(&message)?)?;
-
-    Ok(())
+    Ok(use_light_theme != 0)
 }
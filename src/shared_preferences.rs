@@ -0,0 +1,202 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+use color_eyre::eyre;
+use flutter_codec::EncodableValue;
+use serde_json::{Map, Value};
+
+use crate::paths;
+use crate::standard_method_channel::{StandardMethodHandler, StandardMethodReply};
+
+/// Handles `plugins.flutter.io/shared_preferences`, storing preferences as JSON in the app data
+/// directory.
+///
+/// Preferences are kept in memory and the whole file is rewritten on every change.
+pub struct SharedPreferencesHandler {
+    values: RefCell<Map<String, Value>>,
+}
+
+impl SharedPreferencesHandler {
+    pub fn new() -> SharedPreferencesHandler {
+        let values = match load() {
+            Ok(values) => values,
+            Err(e) => {
+                tracing::error!("failed to load shared preferences: {e:?}");
+                Map::new()
+            }
+        };
+
+        SharedPreferencesHandler {
+            values: RefCell::new(values),
+        }
+    }
+
+    fn set(&self, key: &str, value: Value) -> eyre::Result<()> {
+        let mut values = self.values.borrow_mut();
+        values.insert(key.to_owned(), value);
+        save(&values)
+    }
+
+    fn remove(&self, key: &str) -> eyre::Result<()> {
+        let mut values = self.values.borrow_mut();
+        if values.remove(key).is_some() {
+            save(&values)?;
+        }
+        Ok(())
+    }
+
+    fn clear(&self, filter: &Filter) -> eyre::Result<()> {
+        let mut values = self.values.borrow_mut();
+        values.retain(|key, _| !filter.matches(key));
+        save(&values)
+    }
+}
+
+fn preferences_file() -> eyre::Result<PathBuf> {
+    Ok(paths::app_data_dir()?.join("shared_preferences.json"))
+}
+
+fn load() -> eyre::Result<Map<String, Value>> {
+    let path = preferences_file()?;
+    if !path.exists() {
+        return Ok(Map::new());
+    }
+
+    Ok(serde_json::from_slice(&fs::read(path)?)?)
+}
+
+/// Writes to a temporary file that then replaces the existing one, so that the preferences
+/// aren't lost if the app exits in the middle of writing.
+fn save(values: &Map<String, Value>) -> eyre::Result<()> {
+    let path = preferences_file()?;
+    let temp_path = path.with_extension("json.tmp");
+
+    fs::write(&temp_path, serde_json::to_vec(values)?)?;
+    fs::rename(temp_path, path)?;
+
+    Ok(())
+}
+
+/// Selects the preferences affected by `getAll` and `clear`.
+struct Filter<'a> {
+    prefix: &'a str,
+    allow_list: Option<Vec<&'a str>>,
+}
+
+impl<'a> Filter<'a> {
+    fn from_args(args: &EncodableValue<'a>) -> Filter<'a> {
+        // The legacy methods without arguments only apply to the prefix used by the plugin.
+        let prefix = args
+            .get("prefix")
+            .and_then(|v| v.as_string())
+            .unwrap_or("flutter.");
+
+        let allow_list = args.get("allowList").and_then(|v| v.as_list()).map(|list| {
+            list.iter()
+                .filter_map(|v| v.as_string())
+                .collect::<Vec<_>>()
+        });
+
+        Filter { prefix, allow_list }
+    }
+
+    fn matches(&self, key: &str) -> bool {
+        key.starts_with(self.prefix)
+            && self
+                .allow_list
+                .as_ref()
+                .map_or(true, |list| list.contains(&key))
+    }
+}
+
+fn to_encodable(value: &Value) -> Option<EncodableValue> {
+    Some(match value {
+        Value::Bool(v) => EncodableValue::Bool(*v),
+        Value::Number(v) => match v.as_i64() {
+            Some(v) => EncodableValue::I64(v),
+            None => EncodableValue::F64(v.as_f64()?.into()),
+        },
+        Value::String(v) => EncodableValue::Str(v),
+        Value::Array(v) => EncodableValue::List(v.iter().filter_map(to_encodable).collect()),
+        Value::Null | Value::Object(_) => return None,
+    })
+}
+
+fn from_encodable(method: &str, value: &EncodableValue) -> Option<Value> {
+    Some(match method {
+        "setBool" => Value::Bool(value.as_bool()?),
+        "setInt" => Value::from(value.as_int()?),
+        "setDouble" => Value::from(value.as_f64()?),
+        "setString" => Value::from(value.as_string()?),
+        "setStringList" => Value::Array(
+            value
+                .as_list()?
+                .iter()
+                .map(|v| v.as_string().map(Value::from))
+                .collect::<Option<_>>()?,
+        ),
+        _ => return None,
+    })
+}
+
+impl StandardMethodHandler for SharedPreferencesHandler {
+    fn handle(&self, method: &str, args: EncodableValue, reply: StandardMethodReply) {
+        match method {
+            "getAll" | "getAllWithPrefix" | "getAllWithParameters" => {
+                let filter = Filter::from_args(&args);
+                let values = self.values.borrow();
+
+                let values = values
+                    .iter()
+                    .filter(|(key, _)| filter.matches(key))
+                    .filter_map(|(key, value)| {
+                        Some((EncodableValue::Str(key), to_encodable(value)?))
+                    })
+                    .collect::<BTreeMap<_, _>>();
+
+                reply.success(&EncodableValue::Map(values));
+            }
+            "setBool" | "setInt" | "setDouble" | "setString" | "setStringList" => {
+                let Some(key) = args.get("key").and_then(|v| v.as_string()) else {
+                    return reply.error("invalid_args", Some("expected a key"));
+                };
+
+                let Some(value) = args
+                    .get("value")
+                    .and_then(|value| from_encodable(method, value))
+                else {
+                    return reply.error("invalid_args", Some("invalid value"));
+                };
+
+                match self.set(key, value) {
+                    Ok(()) => reply.success(&EncodableValue::Bool(true)),
+                    Err(e) => reply.error("write_failed", Some(&format!("{e:?}"))),
+                }
+            }
+            "remove" => {
+                let Some(key) = args.get("key").and_then(|v| v.as_string()) else {
+                    return reply.error("invalid_args", Some("expected a key"));
+                };
+
+                match self.remove(key) {
+                    Ok(()) => reply.success(&EncodableValue::Bool(true)),
+                    Err(e) => reply.error("write_failed", Some(&format!("{e:?}"))),
+                }
+            }
+            "clear" | "clearWithPrefix" | "clearWithParameters" => {
+                match self.clear(&Filter::from_args(&args)) {
+                    Ok(()) => reply.success(&EncodableValue::Bool(true)),
+                    Err(e) => reply.error("write_failed", Some(&format!("{e:?}"))),
+                }
+            }
+            // Changes are written immediately.
+            "commit" => reply.success(&EncodableValue::Bool(true)),
+            _ => {
+                tracing::warn!(method, "unimplemented");
+                reply.not_implemented();
+            }
+        }
+    }
+}
@@ -0,0 +1,114 @@
+use std::str::FromStr;
+
+use windows::Win32::Foundation::{HWND, LPARAM, RECT, WPARAM};
+use windows::Win32::UI::WindowsAndMessaging::{
+    GetClientRect, GetWindowRect, MINMAXINFO, WMSZ_BOTTOM, WMSZ_BOTTOMLEFT, WMSZ_BOTTOMRIGHT,
+    WMSZ_LEFT, WMSZ_RIGHT, WMSZ_TOP, WMSZ_TOPLEFT, WMSZ_TOPRIGHT,
+};
+
+/// A size in logical pixels, parsed from `<width>x<height>`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Size {
+    pub width: f64,
+    pub height: f64,
+}
+
+impl FromStr for Size {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (width, height) = s
+            .split_once('x')
+            .ok_or_else(|| format!("expected <width>x<height>, found '{s}'"))?;
+
+        Ok(Size {
+            width: width.trim().parse().map_err(|e| format!("{e}"))?,
+            height: height.trim().parse().map_err(|e| format!("{e}"))?,
+        })
+    }
+}
+
+/// Constraints on the size of the window's client area, in logical pixels.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SizeConstraints {
+    pub min_size: Option<Size>,
+    pub max_size: Option<Size>,
+    /// Width divided by height.
+    pub aspect_ratio: Option<f64>,
+}
+
+/// Returns the size of the window frame, i.e. the difference between the window and client sizes.
+unsafe fn frame_size(hwnd: HWND) -> (i32, i32) {
+    let mut window_rect = RECT::default();
+    let mut client_rect = RECT::default();
+
+    if GetWindowRect(hwnd, &mut window_rect).is_err()
+        || GetClientRect(hwnd, &mut client_rect).is_err()
+    {
+        return (0, 0);
+    }
+
+    (
+        (window_rect.right - window_rect.left) - (client_rect.right - client_rect.left),
+        (window_rect.bottom - window_rect.top) - (client_rect.bottom - client_rect.top),
+    )
+}
+
+impl SizeConstraints {
+    /// Applies the min/max sizes to a `WM_GETMINMAXINFO` message.
+    pub unsafe fn handle_get_min_max_info(&self, hwnd: HWND, lparam: LPARAM, scale_factor: f64) {
+        let Some(info) = (lparam.0 as *mut MINMAXINFO).as_mut() else {
+            return;
+        };
+
+        let (frame_width, frame_height) = frame_size(hwnd);
+
+        if let Some(min) = self.min_size {
+            info.ptMinTrackSize.x = (min.width * scale_factor).round() as i32 + frame_width;
+            info.ptMinTrackSize.y = (min.height * scale_factor).round() as i32 + frame_height;
+        }
+
+        if let Some(max) = self.max_size {
+            info.ptMaxTrackSize.x = (max.width * scale_factor).round() as i32 + frame_width;
+            info.ptMaxTrackSize.y = (max.height * scale_factor).round() as i32 + frame_height;
+        }
+    }
+
+    /// Adjusts the drag rectangle of a `WM_SIZING` message to preserve the aspect ratio. Returns
+    /// `true` if the rectangle was modified.
+    pub unsafe fn handle_sizing(&self, hwnd: HWND, wparam: WPARAM, lparam: LPARAM) -> bool {
+        let Some(aspect_ratio) = self.aspect_ratio.filter(|ratio| *ratio > 0.0) else {
+            return false;
+        };
+
+        let Some(rect) = (lparam.0 as *mut RECT).as_mut() else {
+            return false;
+        };
+
+        let (frame_width, frame_height) = frame_size(hwnd);
+
+        let width = (rect.right - rect.left - frame_width) as f64;
+        let height = (rect.bottom - rect.top - frame_height) as f64;
+
+        let edge = wparam.0 as u32;
+        match edge {
+            // Dragging a vertical edge changes the width, so derive the height from it.
+            WMSZ_LEFT | WMSZ_RIGHT | WMSZ_BOTTOMLEFT | WMSZ_BOTTOMRIGHT => {
+                let height = (width / aspect_ratio).round() as i32;
+                rect.bottom = rect.top + height + frame_height;
+            }
+            WMSZ_TOPLEFT | WMSZ_TOPRIGHT => {
+                let height = (width / aspect_ratio).round() as i32;
+                rect.top = rect.bottom - height - frame_height;
+            }
+            // Dragging a horizontal edge changes the height, so derive the width from it.
+            WMSZ_TOP | WMSZ_BOTTOM => {
+                let width = (height * aspect_ratio).round() as i32;
+                rect.right = rect.left + width + frame_width;
+            }
+            _ => return false,
+        }
+
+        true
+    }
+}
@@ -0,0 +1,182 @@
+use std::ffi::c_void;
+use std::sync::Arc;
+use std::time::Duration;
+
+use color_eyre::eyre::{self, bail};
+use windows::core::{ComInterface, HSTRING};
+use windows::Foundation::Numerics::Vector2;
+use windows::Foundation::{Size, TimeSpan, TypedEventHandler};
+use windows::Graphics::DirectX::{DirectXAlphaMode, DirectXPixelFormat};
+use windows::Win32::Foundation::{GENERIC_READ, POINT};
+use windows::Win32::Graphics::Direct3D11::{ID3D11Device, ID3D11Texture2D, D3D11_BOX};
+use windows::Win32::Graphics::Imaging::{
+    CLSID_WICImagingFactory, GUID_WICPixelFormat32bppPBGRA, IWICImagingFactory,
+    WICConvertBitmapSource, WICDecodeMetadataCacheOnDemand,
+};
+use windows::Win32::System::Com::{CoCreateInstance, CLSCTX_INPROC_SERVER};
+use windows::Win32::System::WinRT::Composition::{
+    ICompositionDrawingSurfaceInterop, ICompositorInterop,
+};
+use windows::UI::Color;
+use windows::UI::Composition::{
+    CompositionBatchTypes, CompositionDrawingSurface, CompositionStretch, Compositor,
+    ContainerVisual, SpriteVisual,
+};
+
+use crate::commit_batcher::CommitBatcher;
+
+const FADE_DURATION: Duration = Duration::from_millis(200);
+
+/// A visual covering the window until the first Flutter frame is ready.
+pub struct Splash {
+    commits: Arc<CommitBatcher>,
+    parent: ContainerVisual,
+    visual: SpriteVisual,
+}
+
+impl Splash {
+    pub fn new(
+        commits: &Arc<CommitBatcher>,
+        device: &ID3D11Device,
+        parent: &ContainerVisual,
+        color: Color,
+        image: Option<&str>,
+    ) -> eyre::Result<Splash> {
+        let compositor = commits.compositor_controller().Compositor()?;
+        let visual = compositor.CreateSpriteVisual()?;
+        visual.SetRelativeSizeAdjustment(Vector2::new(1.0, 1.0))?;
+        visual.SetBrush(&compositor.CreateColorBrushWithColor(color)?)?;
+
+        if let Some(image) = image {
+            let image_visual = compositor.CreateSpriteVisual()?;
+            image_visual.SetRelativeSizeAdjustment(Vector2::new(1.0, 1.0))?;
+
+            let surface = unsafe { load_image(&compositor, device, image)? };
+            let brush = compositor.CreateSurfaceBrushWithSurface(&surface)?;
+            brush.SetStretch(CompositionStretch::Uniform)?;
+
+            image_visual.SetBrush(&brush)?;
+            visual.Children()?.InsertAtTop(&image_visual)?;
+        }
+
+        parent.Children()?.InsertAtTop(&visual)?;
+
+        // Nothing else will be committed until Flutter presents a frame, so this is committed by
+        // the event loop.
+        commits.request_commit();
+
+        Ok(Splash {
+            commits: commits.clone(),
+            parent: parent.clone(),
+            visual,
+        })
+    }
+
+    /// Fades out the splash visual and removes it once the animation has completed.
+    pub fn dismiss(self) -> eyre::Result<()> {
+        let compositor = self.commits.compositor_controller().Compositor()?;
+
+        let animation = compositor.CreateScalarKeyFrameAnimation()?;
+        animation.InsertKeyFrame(1.0, 0.0)?;
+        animation.SetDuration(TimeSpan::from(FADE_DURATION))?;
+
+        let batch = compositor.CreateScopedBatch(CompositionBatchTypes::Animation)?;
+
+        self.visual
+            .StartAnimation(&HSTRING::from("Opacity"), &animation)?;
+
+        batch.End()?;
+
+        self.commits.request_commit();
+
+        let Splash { parent, visual, .. } = self;
+        batch.Completed(&TypedEventHandler::new(move |_, _| {
+            parent.Children()?.Remove(&visual)?;
+            Ok(())
+        }))?;
+
+        Ok(())
+    }
+}
+
+/// Decodes an image file into a composition surface.
+unsafe fn load_image(
+    compositor: &Compositor,
+    device: &ID3D11Device,
+    path: &str,
+) -> eyre::Result<CompositionDrawingSurface> {
+    let factory: IWICImagingFactory =
+        CoCreateInstance(&CLSID_WICImagingFactory, None, CLSCTX_INPROC_SERVER)?;
+
+    let decoder = factory.CreateDecoderFromFilename(
+        &HSTRING::from(path),
+        None,
+        GENERIC_READ,
+        WICDecodeMetadataCacheOnDemand,
+    )?;
+
+    let frame = WICConvertBitmapSource(&GUID_WICPixelFormat32bppPBGRA, &decoder.GetFrame(0)?)?;
+
+    let (mut width, mut height) = (0, 0);
+    frame.GetSize(&mut width, &mut height)?;
+
+    if width == 0 || height == 0 {
+        bail!("splash image is empty");
+    }
+
+    let stride = width * 4;
+    let mut pixels = vec![0u8; (stride * height) as usize];
+    frame.CopyPixels(std::ptr::null(), stride, &mut pixels)?;
+
+    // The root visual is flipped vertically to match GL's coordinate system, so the rows need to
+    // be flipped to appear the right way up.
+    let pixels = pixels
+        .chunks_exact(stride as usize)
+        .rev()
+        .flatten()
+        .copied()
+        .collect::<Vec<u8>>();
+
+    let graphics_device = compositor
+        .cast::<ICompositorInterop>()?
+        .CreateGraphicsDevice(device)?;
+
+    let surface = graphics_device.CreateDrawingSurface(
+        Size {
+            Width: width as f32,
+            Height: height as f32,
+        },
+        DirectXPixelFormat::B8G8R8A8UIntNormalized,
+        DirectXAlphaMode::Premultiplied,
+    )?;
+
+    let interop = surface.cast::<ICompositionDrawingSurfaceInterop>()?;
+
+    let mut offset = POINT::default();
+    let texture: ID3D11Texture2D = interop.BeginDraw(None, &mut offset)?;
+
+    let mut context = None;
+    device.GetImmediateContext(&mut context);
+
+    if let Some(context) = context {
+        context.UpdateSubresource(
+            &texture,
+            0,
+            Some(&D3D11_BOX {
+                left: offset.x as u32,
+                top: offset.y as u32,
+                front: 0,
+                right: offset.x as u32 + width,
+                bottom: offset.y as u32 + height,
+                back: 1,
+            }),
+            pixels.as_ptr() as *const c_void,
+            stride,
+            0,
+        );
+    }
+
+    interop.EndDraw()?;
+
+    Ok(surface)
+}
@@ -1,13 +1,20 @@
 use std::io::{Cursor, Write};
+use std::rc::Rc;
 
 use flutter_codec::EncodableValue;
 
-use crate::engine::{BinaryMessageHandler, BinaryMessageReply};
+use crate::engine::{BinaryMessageHandler, BinaryMessageReply, BinaryMessenger};
 
 pub trait StandardMethodHandler {
     fn handle(&self, method: &str, args: EncodableValue, reply: StandardMethodReply);
 }
 
+impl<T: StandardMethodHandler> StandardMethodHandler for Rc<T> {
+    fn handle(&self, method: &str, args: EncodableValue, reply: StandardMethodReply) {
+        (**self).handle(method, args, reply);
+    }
+}
+
 impl<T: StandardMethodHandler> BinaryMessageHandler for T {
     fn handle(&self, message: &[u8], reply: BinaryMessageReply) {
         let reply = StandardMethodReply(reply);
@@ -29,12 +36,42 @@ impl<T: StandardMethodHandler> BinaryMessageHandler for T {
 
 pub struct StandardMethodReply(BinaryMessageReply);
 
+pub fn encode_method_call(method: &str, args: &EncodableValue) -> Vec<u8> {
+    let mut bytes = vec![];
+    let mut cursor = Cursor::new(&mut bytes);
+    flutter_codec::write_value(&mut cursor, &EncodableValue::Str(method)).unwrap();
+    flutter_codec::write_value(&mut cursor, args).unwrap();
+    bytes
+}
+
+pub fn encode_success_envelope(value: &EncodableValue) -> Vec<u8> {
+    let mut bytes = vec![];
+    let mut cursor = Cursor::new(&mut bytes);
+    cursor.write_all(&[0]).unwrap();
+    flutter_codec::write_value(&mut cursor, value).unwrap();
+    bytes
+}
+
 impl StandardMethodReply {
+    pub fn messenger(&self) -> BinaryMessenger {
+        self.0.messenger()
+    }
+
     pub fn success(self, value: &EncodableValue) {
+        self.0.send(&encode_success_envelope(value));
+    }
+
+    pub fn error(self, code: &str, message: Option<&str>) {
         let mut bytes = vec![];
         let mut cursor = Cursor::new(&mut bytes);
-        cursor.write_all(&[0]).unwrap();
-        flutter_codec::write_value(&mut cursor, value).unwrap();
+        cursor.write_all(&[1]).unwrap();
+        flutter_codec::write_value(&mut cursor, &EncodableValue::Str(code)).unwrap();
+        flutter_codec::write_value(
+            &mut cursor,
+            &message.map(EncodableValue::Str).unwrap_or(EncodableValue::Null),
+        )
+        .unwrap();
+        flutter_codec::write_value(&mut cursor, &EncodableValue::Null).unwrap();
         self.0.send(&bytes);
     }
 
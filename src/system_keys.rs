@@ -0,0 +1,205 @@
+use std::cell::Cell;
+use std::rc::Rc;
+
+use clap::ValueEnum;
+use windows::Win32::Foundation::{HWND, LPARAM, WPARAM};
+use windows::Win32::UI::WindowsAndMessaging::{SendMessageW, SC_CLOSE, SC_KEYMENU, WM_SYSCOMMAND};
+use winit::event::KeyEvent;
+use winit::keyboard::{Key, ModifiersState, NamedKey};
+
+/// Which of the framework and the system gets to act on a system shortcut.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum ShortcutPolicy {
+    /// The system always performs the shortcut. The framework still receives the key events, but
+    /// handling them doesn't stop the system.
+    System,
+    /// The framework always consumes the shortcut, and the system never performs it.
+    Framework,
+    /// The system performs the shortcut only if the framework doesn't handle the key event.
+    #[default]
+    Fallback,
+}
+
+/// The keyboard shortcuts that `DefWindowProc` performs for the window.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum SystemShortcut {
+    /// Alt+F4 closes the window.
+    Close,
+    /// Alt+Space opens the window menu.
+    WindowMenu,
+    /// F10 or Alt on its own activates the menu bar, and Alt+<letter> opens a menu by its
+    /// mnemonic (or beeps if the window has no menu bar).
+    MenuBar,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ShortcutPolicies {
+    pub close: ShortcutPolicy,
+    pub window_menu: ShortcutPolicy,
+    pub menu_bar: ShortcutPolicy,
+}
+
+impl ShortcutPolicies {
+    fn get(&self, shortcut: SystemShortcut) -> ShortcutPolicy {
+        match shortcut {
+            SystemShortcut::Close => self.close,
+            SystemShortcut::WindowMenu => self.window_menu,
+            SystemShortcut::MenuBar => self.menu_bar,
+        }
+    }
+}
+
+/// The most recent key event sent to the framework.
+#[derive(Clone, Copy, Debug)]
+struct LastKeyEvent {
+    id: u64,
+    shortcut: Option<SystemShortcut>,
+    handled: Option<bool>,
+}
+
+/// A system command that is waiting for the framework to respond to the key event that triggered
+/// it.
+#[derive(Clone, Copy, Debug)]
+struct HeldCommand {
+    key_event: u64,
+    wparam: WPARAM,
+    lparam: LPARAM,
+}
+
+/// Decides whether system shortcuts are performed by the system, based on the policy for each one
+/// and whether the framework handled the key event.
+///
+/// The system performs shortcuts by sending `WM_SYSCOMMAND` from `DefWindowProc`, which happens
+/// before the framework has responded to the key event. In [`ShortcutPolicy::Fallback`], the
+/// command is held back and sent again once it is known that the framework didn't handle the key.
+#[derive(Clone)]
+pub struct SystemKeys {
+    hwnd: HWND,
+    policies: ShortcutPolicies,
+    next_id: Rc<Cell<u64>>,
+    last_key_event: Rc<Cell<Option<LastKeyEvent>>>,
+    held: Rc<Cell<Option<HeldCommand>>>,
+    /// Set while a held command is being sent again, so that it isn't intercepted.
+    performing: Rc<Cell<bool>>,
+}
+
+impl SystemKeys {
+    pub fn new(hwnd: HWND, policies: ShortcutPolicies) -> SystemKeys {
+        SystemKeys {
+            hwnd,
+            policies,
+            next_id: Rc::new(Cell::new(0)),
+            last_key_event: Rc::new(Cell::new(None)),
+            held: Rc::new(Cell::new(None)),
+            performing: Rc::new(Cell::new(false)),
+        }
+    }
+
+    /// Should be called before a key event is sent to the framework. `on_handled` should be
+    /// called with the framework's response.
+    pub fn begin_key_event(
+        &self,
+        event: &KeyEvent,
+        modifiers: ModifiersState,
+    ) -> impl FnOnce(bool) + 'static {
+        let id = self.next_id.get();
+        self.next_id.set(id + 1);
+
+        self.last_key_event.set(Some(LastKeyEvent {
+            id,
+            shortcut: shortcut_for_key(event, modifiers),
+            handled: None,
+        }));
+
+        let system_keys = self.clone();
+        move |handled| system_keys.finish_key_event(id, handled)
+    }
+
+    fn finish_key_event(&self, id: u64, handled: bool) {
+        if let Some(mut last) = self.last_key_event.get().filter(|last| last.id == id) {
+            last.handled = Some(handled);
+            self.last_key_event.set(Some(last));
+        }
+
+        let Some(held) = self.held.get().filter(|held| held.key_event == id) else {
+            return;
+        };
+
+        self.held.set(None);
+
+        if !handled {
+            self.perform(held.wparam, held.lparam);
+        }
+    }
+
+    /// Handles `WM_SYSCOMMAND`, returning true if the command was consumed.
+    pub fn handle_sys_command(&self, wparam: WPARAM, lparam: LPARAM) -> bool {
+        if self.performing.get() {
+            return false;
+        }
+
+        // The low four bits are used internally by the system.
+        let command = (wparam.0 & 0xfff0) as u32;
+        let shortcut = match command {
+            SC_CLOSE => SystemShortcut::Close,
+            SC_KEYMENU if lparam.0 == ' ' as isize => SystemShortcut::WindowMenu,
+            SC_KEYMENU => SystemShortcut::MenuBar,
+            _ => return false,
+        };
+
+        // Commands that weren't triggered by the last key event (e.g. from clicking the close
+        // button) are left alone.
+        let Some(last) = self
+            .last_key_event
+            .get()
+            .filter(|last| last.shortcut == Some(shortcut))
+        else {
+            return false;
+        };
+
+        match self.policies.get(shortcut) {
+            ShortcutPolicy::System => false,
+            ShortcutPolicy::Framework => true,
+            ShortcutPolicy::Fallback => match last.handled {
+                Some(handled) => handled,
+                None => {
+                    self.held.set(Some(HeldCommand {
+                        key_event: last.id,
+                        wparam,
+                        lparam,
+                    }));
+                    true
+                }
+            },
+        }
+    }
+
+    fn perform(&self, wparam: WPARAM, lparam: LPARAM) {
+        tracing::debug!(command = wparam.0, "performing system shortcut");
+
+        // This doesn't return until e.g. the menu has been dismissed, since the menu runs a modal
+        // loop.
+        self.performing.set(true);
+        unsafe { SendMessageW(self.hwnd, WM_SYSCOMMAND, wparam, lparam) };
+        self.performing.set(false);
+    }
+}
+
+fn shortcut_for_key(event: &KeyEvent, modifiers: ModifiersState) -> Option<SystemShortcut> {
+    let alt = modifiers.alt_key() && !modifiers.control_key();
+
+    if event.state.is_pressed() {
+        match &event.logical_key {
+            Key::Named(NamedKey::F4) if alt => Some(SystemShortcut::Close),
+            Key::Named(NamedKey::Space) if alt => Some(SystemShortcut::WindowMenu),
+            Key::Character(_) if alt => Some(SystemShortcut::MenuBar),
+            _ => None,
+        }
+    } else {
+        // Menus are activated with F10 and Alt when they are released.
+        match &event.logical_key {
+            Key::Named(NamedKey::F10 | NamedKey::Alt) => Some(SystemShortcut::MenuBar),
+            _ => None,
+        }
+    }
+}
@@ -1,13 +1,23 @@
-use std::thread::{self, ThreadId};
-use std::time::{Duration, Instant};
+use std::cell::RefCell;
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use std::sync::OnceLock;
+use std::thread::{self, JoinHandle, ThreadId};
 
+use clap::ValueEnum;
+use color_eyre::eyre;
 use flutter_embedder::{
     FlutterEngineGetCurrentTime, FlutterTask, FlutterThreadPriority_kBackground,
     FlutterThreadPriority_kDisplay, FlutterThreadPriority_kRaster,
 };
+use windows::core::{w, PCWSTR};
+use windows::Win32::Foundation::{CloseHandle, HANDLE, WAIT_OBJECT_0};
 use windows::Win32::System::Threading::{
-    GetCurrentThread, SetThreadPriority, THREAD_PRIORITY_ABOVE_NORMAL,
-    THREAD_PRIORITY_BELOW_NORMAL, THREAD_PRIORITY_NORMAL,
+    AvSetMmThreadCharacteristicsW, CreateEventW, CreateWaitableTimerExW, GetCurrentThread,
+    SetEvent, SetThreadPriority, SetWaitableTimer, WaitForMultipleObjects,
+    CREATE_WAITABLE_TIMER_HIGH_RESOLUTION, INFINITE, THREAD_PRIORITY, THREAD_PRIORITY_ABOVE_NORMAL,
+    THREAD_PRIORITY_BELOW_NORMAL, THREAD_PRIORITY_HIGHEST, THREAD_PRIORITY_LOWEST,
+    THREAD_PRIORITY_NORMAL, TIMER_ALL_ACCESS,
 };
 
 use crate::engine::FlutterEngine;
@@ -15,6 +25,9 @@ use crate::engine::FlutterEngine;
 #[derive(Debug)]
 pub struct Task(u64, FlutterTask);
 
+// Tasks may be posted from any thread, but they are only ever run on the platform thread.
+unsafe impl Send for Task {}
+
 pub struct TaskRunner<F> {
     main_thread_id: ThreadId,
     handler: F,
@@ -42,53 +55,263 @@ where
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ThreadPriority {
+    Lowest,
+    BelowNormal,
+    Normal,
+    AboveNormal,
+    Highest,
+}
+
+impl ThreadPriority {
+    fn to_win32(self) -> THREAD_PRIORITY {
+        match self {
+            ThreadPriority::Lowest => THREAD_PRIORITY_LOWEST,
+            ThreadPriority::BelowNormal => THREAD_PRIORITY_BELOW_NORMAL,
+            ThreadPriority::Normal => THREAD_PRIORITY_NORMAL,
+            ThreadPriority::AboveNormal => THREAD_PRIORITY_ABOVE_NORMAL,
+            ThreadPriority::Highest => THREAD_PRIORITY_HIGHEST,
+        }
+    }
+}
+
+/// Scheduling options for the threads created by the engine. These are applied by the engine
+/// calling [`set_thread_priority`] on each thread when it starts.
+///
+/// The number of worker threads is chosen by the engine based on the number of cores, and can't
+/// be configured through the embedder API.
+#[derive(Clone, Copy, Debug)]
+pub struct ThreadConfig {
+    /// Priority of the engine's background worker threads.
+    pub worker_priority: ThreadPriority,
+    /// Priority of the UI thread.
+    pub ui_priority: ThreadPriority,
+    /// Priority of the raster thread.
+    pub raster_priority: ThreadPriority,
+    /// Register the raster thread with MMCSS (the multimedia class scheduler service), which
+    /// boosts it above other applications while frames are being produced.
+    pub raster_mmcss: bool,
+}
+
+impl Default for ThreadConfig {
+    fn default() -> ThreadConfig {
+        ThreadConfig {
+            worker_priority: ThreadPriority::BelowNormal,
+            ui_priority: ThreadPriority::AboveNormal,
+            raster_priority: ThreadPriority::AboveNormal,
+            raster_mmcss: false,
+        }
+    }
+}
+
+/// The thread priority setter doesn't receive any user data, so the config is global.
+static THREAD_CONFIG: OnceLock<ThreadConfig> = OnceLock::new();
+
+/// Sets the thread config. This must be called before the engine is started.
+pub fn configure_threads(config: ThreadConfig) {
+    if THREAD_CONFIG.set(config).is_err() {
+        tracing::warn!("thread config has already been set");
+    }
+}
+
 pub unsafe extern "C" fn set_thread_priority(thread_priority: i32) {
+    let config = THREAD_CONFIG.get().copied().unwrap_or_default();
+
     #[expect(non_upper_case_globals)]
     let priority = match thread_priority {
-        FlutterThreadPriority_kBackground => THREAD_PRIORITY_BELOW_NORMAL,
-        FlutterThreadPriority_kDisplay | FlutterThreadPriority_kRaster => {
-            THREAD_PRIORITY_ABOVE_NORMAL
-        }
-        _ => THREAD_PRIORITY_NORMAL,
+        FlutterThreadPriority_kBackground => config.worker_priority,
+        FlutterThreadPriority_kDisplay => config.ui_priority,
+        FlutterThreadPriority_kRaster => config.raster_priority,
+        _ => ThreadPriority::Normal,
     };
 
-    if let Err(e) = SetThreadPriority(GetCurrentThread(), priority) {
+    if let Err(e) = SetThreadPriority(GetCurrentThread(), priority.to_win32()) {
         tracing::error!("failed to set thread priority: {e}");
     }
+
+    if thread_priority == FlutterThreadPriority_kRaster && config.raster_mmcss {
+        let mut task_index = 0;
+        // The registration lasts for the lifetime of the thread, so the handle is never reverted.
+        if let Err(e) = AvSetMmThreadCharacteristicsW(w!("Games"), &mut task_index) {
+            tracing::error!("failed to register raster thread with mmcss: {e}");
+        }
+    }
+}
+
+/// A task waiting in a [`TaskRunnerExecutor`], ordered by target time and then by the order in
+/// which tasks were posted.
+struct QueuedTask {
+    target_time_nanos: u64,
+    seq: u64,
+    task: FlutterTask,
+}
+
+impl PartialEq for QueuedTask {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
 }
 
-#[derive(Default)]
+impl Eq for QueuedTask {}
+
+impl PartialOrd for QueuedTask {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for QueuedTask {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.target_time_nanos, self.seq).cmp(&(other.target_time_nanos, other.seq))
+    }
+}
+
+/// Runs engine tasks once their target time has been reached.
+///
+/// Pending tasks are kept in a priority queue, and a high resolution waitable timer is armed for
+/// the earliest one. When it fires `on_timer` is invoked (on a background thread), which should
+/// arrange for [`TaskRunnerExecutor::run_due_tasks`] to be called on the thread that owns the
+/// executor.
 pub struct TaskRunnerExecutor {
-    tasks: Vec<Task>,
+    tasks: BinaryHeap<Reverse<QueuedTask>>,
+    next_seq: u64,
+    timer: HANDLE,
+    exit_event: HANDLE,
+    timer_thread: Option<JoinHandle<()>>,
 }
 
 impl TaskRunnerExecutor {
-    pub fn enqueue(&mut self, task: Task) {
-        self.tasks.push(task);
+    pub fn new(on_timer: impl Fn() + Send + 'static) -> eyre::Result<TaskRunnerExecutor> {
+        let timer = unsafe {
+            CreateWaitableTimerExW(
+                None,
+                PCWSTR::null(),
+                CREATE_WAITABLE_TIMER_HIGH_RESOLUTION,
+                TIMER_ALL_ACCESS.0,
+            )?
+        };
+
+        let exit_event = unsafe { CreateEventW(None, true, false, PCWSTR::null())? };
+
+        let timer_thread =
+            thread::Builder::new()
+                .name("task-timer".to_owned())
+                .spawn(move || loop {
+                    match unsafe { WaitForMultipleObjects(&[timer, exit_event], false, INFINITE) } {
+                        WAIT_OBJECT_0 => on_timer(),
+                        result if result.0 == WAIT_OBJECT_0.0 + 1 => return,
+                        _ => {
+                            tracing::error!("failed to wait for task timer");
+                            return;
+                        }
+                    }
+                })?;
+
+        Ok(TaskRunnerExecutor {
+            tasks: BinaryHeap::new(),
+            next_seq: 0,
+            timer,
+            exit_event,
+            timer_thread: Some(timer_thread),
+        })
+    }
+
+    pub fn enqueue(&mut self, Task(target_time_nanos, task): Task) {
+        self.tasks.push(Reverse(QueuedTask {
+            target_time_nanos,
+            seq: self.next_seq,
+            task,
+        }));
+
+        self.next_seq += 1;
+    }
+
+    /// Discards all pending tasks, e.g. because the engine that posted them has been shut down.
+    pub fn clear(&mut self) {
+        self.tasks.clear();
+    }
+
+    /// Runs all tasks whose target time has passed, and arms the timer for the next one.
+    pub fn run_due_tasks(&mut self, engine: &FlutterEngine) {
+        while let Some(task) = self.pop_due_task() {
+            run_task(engine, &task);
+        }
+
+        self.schedule_next();
     }
 
-    pub fn process_all(&mut self, engine: &FlutterEngine) -> Option<Instant> {
+    /// Like [`TaskRunnerExecutor::run_due_tasks`], but doesn't borrow the executor while tasks
+    /// run, so that they can be run re-entrantly (e.g. by a modal loop that a task started).
+    pub fn run_due_tasks_reentrant(executor: &RefCell<TaskRunnerExecutor>, engine: &FlutterEngine) {
+        loop {
+            let Some(task) = executor.borrow_mut().pop_due_task() else {
+                break;
+            };
+
+            run_task(engine, &task);
+        }
+
+        executor.borrow_mut().schedule_next();
+    }
+
+    fn pop_due_task(&mut self) -> Option<FlutterTask> {
         let now = unsafe { FlutterEngineGetCurrentTime() };
-        let mut next_task_target_time = None;
 
-        self.tasks.retain(|Task(target_time_nanos, task)| {
-            if now >= *target_time_nanos {
-                engine.run_task(task).unwrap();
-                return false;
-            }
+        match self.tasks.peek() {
+            Some(Reverse(next)) if next.target_time_nanos <= now => {}
+            _ => return None,
+        }
+
+        let Reverse(QueuedTask { task, .. }) = self.tasks.pop().unwrap();
 
-            let delta = Duration::from_nanos(target_time_nanos - now);
-            let target_time = Instant::now() + delta;
+        Some(task)
+    }
+
+    /// Arms the timer for the next task, if there is one.
+    fn schedule_next(&mut self) {
+        if let Some(Reverse(next)) = self.tasks.peek() {
+            self.arm_timer(next.target_time_nanos);
+        }
+    }
+
+    fn arm_timer(&self, target_time_nanos: u64) {
+        let now = unsafe { FlutterEngineGetCurrentTime() };
+
+        // Negative due times are relative, in 100ns intervals. A due time of zero would be
+        // treated as absolute, so wait for at least one interval.
+        let delay = (target_time_nanos.saturating_sub(now) / 100).max(1) as i64;
+
+        if let Err(e) = unsafe { SetWaitableTimer(self.timer, &-delay, 0, None, None, false) } {
+            tracing::error!("failed to arm task timer: {e}");
+        }
+    }
+}
 
-            next_task_target_time = Some(if let Some(next) = next_task_target_time {
-                std::cmp::min(next, target_time)
-            } else {
-                target_time
-            });
+fn run_task(engine: &FlutterEngine, task: &FlutterTask) {
+    // This can fail for tasks that were posted by an engine that has since been restarted.
+    if let Err(e) = engine.run_task(task) {
+        tracing::error!("{e}");
+    }
+}
 
-            true
-        });
+impl Drop for TaskRunnerExecutor {
+    fn drop(&mut self) {
+        if let Err(e) = unsafe { SetEvent(self.exit_event) } {
+            // The thread may still be waiting on the handles, so they can't be closed.
+            tracing::error!("failed to stop task timer thread: {e}");
+            return;
+        }
 
-        next_task_target_time
+        if let Some(thread) = self.timer_thread.take() {
+            if thread.join().is_err() {
+                tracing::error!("task timer thread panicked");
+            }
+        }
+
+        unsafe {
+            let _ = CloseHandle(self.timer);
+            let _ = CloseHandle(self.exit_event);
+        }
     }
 }
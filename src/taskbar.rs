@@ -0,0 +1,165 @@
+use std::cell::RefCell;
+
+use color_eyre::eyre::{self, bail};
+use flutter_codec::EncodableValue;
+use windows::core::{HSTRING, PCWSTR};
+use windows::Win32::Foundation::HWND;
+use windows::Win32::System::Com::{CoCreateInstance, CLSCTX_INPROC_SERVER};
+use windows::Win32::UI::Shell::{
+    ITaskbarList3, TaskbarList, TBPFLAG, TBPF_ERROR, TBPF_INDETERMINATE, TBPF_NOPROGRESS,
+    TBPF_NORMAL, TBPF_PAUSED,
+};
+use windows::Win32::UI::WindowsAndMessaging::{
+    CreateIconFromResourceEx, DestroyIcon, GetSystemMetrics, LookupIconIdFromDirectoryEx, HICON,
+    LR_DEFAULTCOLOR, SM_CXSMICON, SM_CYSMICON,
+};
+
+use crate::standard_method_channel::{StandardMethodHandler, StandardMethodReply};
+
+/// Number of steps used to report fractional progress values to the taskbar.
+const PROGRESS_TOTAL: u64 = 10_000;
+
+pub struct TaskbarHandler {
+    hwnd: HWND,
+    taskbar: RefCell<Option<ITaskbarList3>>,
+    overlay_icon: RefCell<Option<HICON>>,
+}
+
+impl TaskbarHandler {
+    pub fn new(hwnd: HWND) -> TaskbarHandler {
+        TaskbarHandler {
+            hwnd,
+            taskbar: RefCell::new(None),
+            overlay_icon: RefCell::new(None),
+        }
+    }
+
+    /// The taskbar list is created lazily, since it can only be used once the taskbar button for
+    /// the window exists.
+    fn taskbar(&self) -> eyre::Result<ITaskbarList3> {
+        if let Some(taskbar) = self.taskbar.borrow().as_ref() {
+            return Ok(taskbar.clone());
+        }
+
+        let taskbar: ITaskbarList3 =
+            unsafe { CoCreateInstance(&TaskbarList, None, CLSCTX_INPROC_SERVER)? };
+
+        unsafe { taskbar.HrInit()? };
+
+        *self.taskbar.borrow_mut() = Some(taskbar.clone());
+
+        Ok(taskbar)
+    }
+
+    fn set_progress(&self, value: f64) -> eyre::Result<()> {
+        let completed = (value.clamp(0.0, 1.0) * PROGRESS_TOTAL as f64) as u64;
+        unsafe {
+            self.taskbar()?
+                .SetProgressValue(self.hwnd, completed, PROGRESS_TOTAL)?
+        };
+        Ok(())
+    }
+
+    fn set_progress_state(&self, state: &str) -> eyre::Result<()> {
+        let state: TBPFLAG = match state {
+            "none" => TBPF_NOPROGRESS,
+            "normal" => TBPF_NORMAL,
+            "paused" => TBPF_PAUSED,
+            "error" => TBPF_ERROR,
+            "indeterminate" => TBPF_INDETERMINATE,
+            state => bail!("invalid progress state: {state}"),
+        };
+
+        unsafe { self.taskbar()?.SetProgressState(self.hwnd, state)? };
+
+        Ok(())
+    }
+
+    /// Sets the overlay icon from encoded icon (.ico or .png) data, or clears it if `icon` is
+    /// `None`.
+    fn set_overlay_icon(&self, icon: Option<&[u8]>, description: Option<&str>) -> eyre::Result<()> {
+        let icon = match icon {
+            Some(bytes) => Some(create_overlay_icon(bytes)?),
+            None => None,
+        };
+
+        let description = description.map(HSTRING::from);
+
+        unsafe {
+            self.taskbar()?.SetOverlayIcon(
+                self.hwnd,
+                icon.unwrap_or_default(),
+                description
+                    .as_ref()
+                    .map(|d| PCWSTR(d.as_ptr()))
+                    .unwrap_or(PCWSTR::null()),
+            )?;
+        }
+
+        // The taskbar makes its own copy of the icon.
+        if let Some(prev) = self.overlay_icon.replace(icon) {
+            unsafe { DestroyIcon(prev)? };
+        }
+
+        Ok(())
+    }
+}
+
+/// Creates a small icon from `bytes`. An .ico file is a directory of images, so the entry that
+/// best fits the overlay size is picked out first; png data is used as is.
+fn create_overlay_icon(bytes: &[u8]) -> eyre::Result<HICON> {
+    let (cx, cy) = unsafe { (GetSystemMetrics(SM_CXSMICON), GetSystemMetrics(SM_CYSMICON)) };
+
+    let image = if bytes.starts_with(&[0, 0, 1, 0]) {
+        // The directory is a 6 byte header followed by a 16 byte entry per image, which must all
+        // be present before the system reads it.
+        let count = bytes
+            .get(4..6)
+            .map_or(0, |n| u16::from_le_bytes([n[0], n[1]]) as usize);
+        if count == 0 || bytes.len() < 6 + count * 16 {
+            bail!("invalid icon directory");
+        }
+
+        let offset =
+            unsafe { LookupIconIdFromDirectoryEx(bytes.as_ptr(), true, cx, cy, LR_DEFAULTCOLOR) };
+        match usize::try_from(offset) {
+            Ok(offset) if offset > 0 && offset < bytes.len() => &bytes[offset..],
+            _ => bail!("no suitable image in icon directory"),
+        }
+    } else {
+        bytes
+    };
+
+    let icon =
+        unsafe { CreateIconFromResourceEx(image, true, 0x00030000, cx, cy, LR_DEFAULTCOLOR)? };
+
+    Ok(icon)
+}
+
+impl StandardMethodHandler for TaskbarHandler {
+    fn handle(&self, method: &str, args: EncodableValue, reply: StandardMethodReply) {
+        let res = match method {
+            "setProgress" => match args.get("value").and_then(|v| v.as_f64()) {
+                Some(value) => self.set_progress(value),
+                None => Err(eyre::eyre!("expected a progress value")),
+            },
+            "setProgressState" => match args.get("state").and_then(|v| v.as_string()) {
+                Some(state) => self.set_progress_state(state),
+                None => Err(eyre::eyre!("expected a progress state")),
+            },
+            "setOverlayIcon" => self.set_overlay_icon(
+                args.get("icon").and_then(|v| v.as_u8_list()),
+                args.get("description").and_then(|v| v.as_string()),
+            ),
+            _ => {
+                tracing::warn!(method, "unimplemented");
+                return reply.not_implemented();
+            }
+        };
+
+        match res {
+            Ok(()) => reply.success(&EncodableValue::Null),
+            Err(e) => reply.error("taskbar_error", Some(&e.to_string())),
+        }
+    }
+}
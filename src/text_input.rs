@@ -1,4 +1,5 @@
 use std::cell::RefCell;
+use std::ops::Range;
 use std::rc::Rc;
 
 use color_eyre::eyre;
@@ -7,7 +8,10 @@ use serde_json::json;
 use winit::event::KeyEvent;
 use winit::keyboard::{Key, NamedKey};
 
-use crate::engine::{BinaryMessageHandler, BinaryMessageReply, FlutterEngine};
+use crate::engine::{BinaryMessageHandler, BinaryMessageReply, BinaryMessenger, FlutterEngine};
+use crate::error_utils::ResultExt;
+use crate::keyboard;
+use crate::touch_keyboard::TouchKeyboard;
 
 pub struct TextInputState {
     client: Option<u32>,
@@ -28,51 +32,72 @@ impl TextInputState {
         engine: &FlutterEngine,
     ) -> eyre::Result<()> {
         if event.state.is_pressed() {
-            match &event.logical_key {
-                Key::Named(NamedKey::Space) => self.insert_text(" "),
-                Key::Named(NamedKey::Enter) => {
-                    // TODO: Handle enter key
-                    return Ok(());
-                }
-                Key::Character(c) => self.insert_text(c.as_str()),
-                // Key::Unidentified(_) => todo!(),
-                // Key::Dead(_) => todo!(),
-                _ => return Ok(()),
+            if let Key::Named(NamedKey::Enter) = &event.logical_key {
+                // TODO: Handle enter key
+                return Ok(());
+            }
+
+            // Dead keys type nothing until the key that they are combined with is pressed.
+            match keyboard::typed_text(event) {
+                Some(text) => self.insert_text(text),
+                None => return Ok(()),
             }
         }
 
-        if let Some(client) = self.client {
-            let message = json!({
-                "method": "TextInputClient.updateEditingState",
-                "args": [
-                    client,
-                    &self.value,
-                ],
-            });
+        if let Some(message) = self.editing_state_message() {
+            engine.send_platform_message(c"flutter/textinput", &message)?;
+        }
 
-            let message = serde_json::to_vec(&message).unwrap();
+        Ok(())
+    }
+
+    pub fn has_client(&self) -> bool {
+        self.client.is_some()
+    }
 
-            engine
-                .send_platform_message(c"flutter/textinput", &message)
-                .unwrap();
+    /// Sends the current editing state to the framework, after it has been changed by the
+    /// platform (e.g. from the context menu).
+    pub fn send_editing_state(&self, messenger: &BinaryMessenger) -> eyre::Result<()> {
+        if let Some(message) = self.editing_state_message() {
+            messenger.send_platform_message(c"flutter/textinput", &message)?;
         }
 
         Ok(())
     }
 
-    fn insert_text(&mut self, text: &str) {
+    fn editing_state_message(&self) -> Option<Vec<u8>> {
+        let message = json!({
+            "method": "TextInputClient.updateEditingState",
+            "args": [
+                self.client?,
+                &self.value,
+            ],
+        });
+
+        Some(serde_json::to_vec(&message).unwrap())
+    }
+
+    pub fn selected_text(&self) -> Option<&str> {
+        self.value
+            .text
+            .get(self.selection_range())
+            .filter(|text| !text.is_empty())
+    }
+
+    pub fn select_all(&mut self) {
+        self.value.selection_base = 0;
+        self.value.selection_extent = self.value.text.len();
+    }
+
+    pub fn insert_text(&mut self, text: &str) {
         self.delete_selected();
         self.value.text.insert_str(self.value.selection_base, text);
         self.value.selection_base += text.len();
         self.value.selection_extent = self.value.selection_base;
     }
 
-    fn delete_selected(&mut self) {
-        let range = if self.value.selection_base < self.value.selection_extent {
-            self.value.selection_base..self.value.selection_extent
-        } else {
-            self.value.selection_extent..self.value.selection_base
-        };
+    pub fn delete_selected(&mut self) {
+        let range = self.selection_range();
 
         if range.is_empty() {
             return;
@@ -81,7 +106,15 @@ impl TextInputState {
         self.value.text.drain(range.clone());
 
         self.value.selection_base = range.start;
-        self.value.selection_extent = range.end;
+        self.value.selection_extent = range.start;
+    }
+
+    fn selection_range(&self) -> Range<usize> {
+        if self.value.selection_base < self.value.selection_extent {
+            self.value.selection_base..self.value.selection_extent
+        } else {
+            self.value.selection_extent..self.value.selection_base
+        }
     }
 }
 
@@ -114,11 +147,18 @@ pub struct TextEditingValue {
 
 pub struct TextInputHandler {
     state: Rc<RefCell<TextInputState>>,
+    touch_keyboard: TouchKeyboard,
 }
 
 impl TextInputHandler {
-    pub fn new(state: Rc<RefCell<TextInputState>>) -> TextInputHandler {
-        TextInputHandler { state }
+    pub fn new(
+        state: Rc<RefCell<TextInputState>>,
+        touch_keyboard: TouchKeyboard,
+    ) -> TextInputHandler {
+        TextInputHandler {
+            state,
+            touch_keyboard,
+        }
     }
 }
 
@@ -142,13 +182,16 @@ impl BinaryMessageHandler for TextInputHandler {
             }
             TextInputRequest::ClearClient => {
                 self.state.borrow_mut().client = None;
+                let _ = self.touch_keyboard.hide().trace_err();
                 reply.send(RES_SUCCESS);
             }
             TextInputRequest::Show => {
-                reply.not_implemented();
+                let _ = self.touch_keyboard.show().trace_err();
+                reply.send(RES_SUCCESS);
             }
             TextInputRequest::Hide => {
-                reply.not_implemented();
+                let _ = self.touch_keyboard.hide().trace_err();
+                reply.send(RES_SUCCESS);
             }
             TextInputRequest::SetEditingState(value) => {
                 self.state.borrow_mut().value = value;
@@ -0,0 +1,165 @@
+use std::collections::BTreeMap;
+use std::ptr;
+use std::sync::atomic::{AtomicI64, AtomicPtr, Ordering};
+use std::sync::{Arc, Mutex};
+
+use color_eyre::eyre;
+use flutter_embedder::{
+    FlutterEngineMarkExternalTextureFrameAvailable, FlutterEngineRegisterExternalTexture,
+    FlutterEngineUnregisterExternalTexture, FlutterOpenGLTexture,
+};
+
+use khronos_egl as egl;
+use windows::Win32::Graphics::Direct3D11::ID3D11Texture2D;
+
+use crate::egl_manager::EglManager;
+use crate::error::{self, check_engine_result};
+use crate::error_utils::ResultExt;
+
+/// A texture whose contents are produced by the embedder (e.g. video frames), which can be
+/// displayed by a `Texture` widget.
+pub trait ExternalTexture: Send + Sync {
+    /// Returns the latest frame. This is called on the raster thread, with the EGL context
+    /// current, after the texture has been marked as having a new frame available.
+    fn frame(&self, width: usize, height: usize) -> Option<FlutterOpenGLTexture>;
+}
+
+/// Keeps track of the external textures that have been registered with the engine.
+///
+/// Textures outlive individual engine instances, and are registered again with the new engine
+/// when it is restarted.
+pub struct TextureRegistry {
+    engine: AtomicPtr<flutter_embedder::_FlutterEngine>,
+    textures: Mutex<BTreeMap<i64, Arc<dyn ExternalTexture>>>,
+    next_id: AtomicI64,
+}
+
+impl TextureRegistry {
+    pub fn new() -> Arc<TextureRegistry> {
+        Arc::new(TextureRegistry {
+            engine: AtomicPtr::new(ptr::null_mut()),
+            textures: Mutex::new(BTreeMap::new()),
+            next_id: AtomicI64::new(1),
+        })
+    }
+
+    /// Registers all textures with a newly launched engine.
+    pub(crate) fn attach(&self, engine: flutter_embedder::FlutterEngine) -> error::Result<()> {
+        self.engine.store(engine, Ordering::Release);
+
+        for &id in self.textures.lock().unwrap().keys() {
+            let result = unsafe { FlutterEngineRegisterExternalTexture(engine, id) };
+            check_engine_result("register external texture", result)?;
+        }
+
+        Ok(())
+    }
+
+    /// Called before the engine is shut down.
+    pub(crate) fn detach(&self) {
+        self.engine.store(ptr::null_mut(), Ordering::Release);
+    }
+
+    pub fn register(&self, texture: Arc<dyn ExternalTexture>) -> error::Result<i64> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+
+        self.textures.lock().unwrap().insert(id, texture);
+
+        let engine = self.engine.load(Ordering::Acquire);
+        if !engine.is_null() {
+            let result = unsafe { FlutterEngineRegisterExternalTexture(engine, id) };
+            if let Err(e) = check_engine_result("register external texture", result) {
+                self.textures.lock().unwrap().remove(&id);
+                return Err(e);
+            }
+        }
+
+        Ok(id)
+    }
+
+    pub fn unregister(&self, id: i64) -> error::Result<()> {
+        self.textures.lock().unwrap().remove(&id);
+
+        let engine = self.engine.load(Ordering::Acquire);
+        if !engine.is_null() {
+            let result = unsafe { FlutterEngineUnregisterExternalTexture(engine, id) };
+            check_engine_result("unregister external texture", result)?;
+        }
+
+        Ok(())
+    }
+
+    /// Tells the engine that a texture has a new frame. This can be called from any thread.
+    pub fn mark_frame_available(&self, id: i64) -> error::Result<()> {
+        let engine = self.engine.load(Ordering::Acquire);
+        if engine.is_null() {
+            return Ok(());
+        }
+
+        let result = unsafe { FlutterEngineMarkExternalTextureFrameAvailable(engine, id) };
+        check_engine_result("mark external texture frame available", result)
+    }
+
+    pub(crate) fn frame(
+        &self,
+        id: i64,
+        width: usize,
+        height: usize,
+    ) -> Option<FlutterOpenGLTexture> {
+        let texture = self.textures.lock().unwrap().get(&id).cloned()?;
+        texture.frame(width, height)
+    }
+}
+
+/// A GL texture that samples from a D3D texture, through an ANGLE pbuffer surface. This is how
+/// external textures are given to the engine without copying.
+pub struct GlTexture {
+    egl_manager: Arc<EglManager>,
+    egl_surface: egl::Surface,
+    name: u32,
+}
+
+impl GlTexture {
+    /// This must be called on the raster thread.
+    pub fn new(egl_manager: Arc<EglManager>, texture: &ID3D11Texture2D) -> eyre::Result<GlTexture> {
+        let egl_surface = egl_manager.create_surface_from_d3d11_texture(texture, (0, 0))?;
+
+        let mut name = 0;
+        unsafe {
+            gl::GenTextures(1, &mut name);
+            gl::BindTexture(gl::TEXTURE_2D, name);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+        }
+
+        let texture = GlTexture {
+            egl_manager,
+            egl_surface,
+            name,
+        };
+
+        texture
+            .egl_manager
+            .bind_tex_image(egl_surface, egl::BACK_BUFFER)?;
+
+        Ok(texture)
+    }
+
+    pub fn name(&self) -> u32 {
+        self.name
+    }
+}
+
+impl Drop for GlTexture {
+    fn drop(&mut self) {
+        // This normally happens on the raster thread, once the engine has released the frame that
+        // uses the texture. Otherwise there is no current context and the GL texture is leaked.
+        unsafe { gl::DeleteTextures(1, &self.name) };
+        let _ = self
+            .egl_manager
+            .destroy_surface(self.egl_surface)
+            .trace_err();
+    }
+}
@@ -0,0 +1,129 @@
+use std::collections::BTreeSet;
+use std::ffi::CStr;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::thread;
+
+use color_eyre::eyre;
+use flutter_embedder::{
+    FlutterEngineGetCurrentTime, FlutterEngineTraceEventDurationBegin,
+    FlutterEngineTraceEventDurationEnd, FlutterEngineTraceEventInstant,
+};
+use serde_json::json;
+use windows::Win32::System::Threading::{GetCurrentProcessId, GetCurrentThreadId};
+
+/// Events are also written here, in the Chrome trace event format, if tracing to a file has been
+/// enabled.
+static TRACE_FILE: Mutex<Option<TraceFile>> = Mutex::new(None);
+
+struct TraceFile {
+    writer: BufWriter<File>,
+    /// Threads whose names have been written.
+    named_threads: BTreeSet<u32>,
+}
+
+/// Starts writing trace events to `path`, as JSON that can be loaded into chrome://tracing or
+/// Perfetto. The events are also still sent to the Dart timeline.
+pub fn trace_to_file(path: &Path) -> eyre::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    // The closing bracket is optional, so the trace can still be loaded if the app crashes.
+    writeln!(writer, "[")?;
+
+    *TRACE_FILE.lock().unwrap() = Some(TraceFile {
+        writer,
+        named_threads: BTreeSet::new(),
+    });
+
+    Ok(())
+}
+
+/// Finishes writing the trace file, if there is one.
+pub fn finish_trace_file() {
+    if let Some(mut file) = TRACE_FILE.lock().unwrap().take() {
+        if let Err(e) = writeln!(file.writer, "{{}}]").and_then(|_| file.writer.flush()) {
+            tracing::error!("failed to write trace file: {e}");
+        }
+    }
+}
+
+fn write_event(name: &CStr, phase: &str) {
+    let mut trace_file = TRACE_FILE.lock().unwrap();
+    let Some(file) = trace_file.as_mut() else {
+        return;
+    };
+
+    let pid = unsafe { GetCurrentProcessId() };
+    let tid = unsafe { GetCurrentThreadId() };
+    let ts = unsafe { FlutterEngineGetCurrentTime() } as f64 / 1000.0;
+
+    let mut events = vec![];
+
+    if file.named_threads.insert(tid) {
+        let thread_name = thread::current()
+            .name()
+            .map(str::to_owned)
+            .unwrap_or_else(|| format!("thread {tid}"));
+
+        events.push(json!({
+            "name": "thread_name",
+            "ph": "M",
+            "pid": pid,
+            "tid": tid,
+            "args": { "name": thread_name },
+        }));
+    }
+
+    let mut event = json!({
+        "name": name.to_string_lossy(),
+        "ph": phase,
+        "ts": ts,
+        "pid": pid,
+        "tid": tid,
+    });
+
+    if phase == "i" {
+        event["s"] = "t".into();
+    }
+
+    events.push(event);
+
+    let res = events
+        .iter()
+        .try_for_each(|event| writeln!(file.writer, "{event},"));
+
+    if let Err(e) = res {
+        tracing::error!("failed to write trace event, stopping trace: {e}");
+        *trace_file = None;
+    }
+}
+
+pub fn instant(name: &CStr) {
+    unsafe { FlutterEngineTraceEventInstant(name.as_ptr()) }
+    write_event(name, "i");
+}
+
+pub fn duration_begin(name: &CStr) {
+    unsafe { FlutterEngineTraceEventDurationBegin(name.as_ptr()) }
+    write_event(name, "B");
+}
+
+pub fn duration_end(name: &CStr) {
+    unsafe { FlutterEngineTraceEventDurationEnd(name.as_ptr()) }
+    write_event(name, "E");
+}
+
+/// Traces a duration that ends when the returned guard is dropped.
+pub fn span(name: &'static CStr) -> Span {
+    duration_begin(name);
+    Span(name)
+}
+
+pub struct Span(&'static CStr);
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        duration_end(self.0);
+    }
+}
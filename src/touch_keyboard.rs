@@ -0,0 +1,64 @@
+use std::cell::Cell;
+use std::rc::Rc;
+
+use color_eyre::eyre;
+use windows::Win32::Foundation::HWND;
+use windows::Win32::System::WinRT::IInputPaneInterop;
+use windows::Win32::UI::WindowsAndMessaging::GetMessageExtraInfo;
+use windows::UI::ViewManagement::InputPane;
+
+/// Mouse messages that were generated from touch or pen input have this signature in their extra
+/// info.
+const MI_WP_SIGNATURE: u32 = 0xff515700;
+const SIGNATURE_MASK: u32 = 0xffffff00;
+
+/// Shows the touch keyboard for text fields, if they were focused with touch (or pen) input.
+#[derive(Clone)]
+pub struct TouchKeyboard {
+    hwnd: HWND,
+    last_input_was_touch: Rc<Cell<bool>>,
+    shown: Rc<Cell<bool>>,
+}
+
+impl TouchKeyboard {
+    pub fn new(hwnd: HWND) -> TouchKeyboard {
+        TouchKeyboard {
+            hwnd,
+            last_input_was_touch: Rc::new(Cell::new(false)),
+            shown: Rc::new(Cell::new(false)),
+        }
+    }
+
+    /// Records whether a mouse button message came from touch, which Windows also reports as
+    /// mouse input. Must be called from the window procedure while handling the message.
+    pub fn handle_mouse_button_message(&self) {
+        let info = unsafe { GetMessageExtraInfo() }.0 as u32;
+        self.last_input_was_touch
+            .set(info & SIGNATURE_MASK == MI_WP_SIGNATURE);
+    }
+
+    pub fn show(&self) -> eyre::Result<()> {
+        if !self.last_input_was_touch.get() {
+            return Ok(());
+        }
+
+        self.input_pane()?.TryShow()?;
+        self.shown.set(true);
+
+        Ok(())
+    }
+
+    /// Hides the touch keyboard, if it was shown by [`TouchKeyboard::show`].
+    pub fn hide(&self) -> eyre::Result<()> {
+        if self.shown.replace(false) {
+            self.input_pane()?.TryHide()?;
+        }
+
+        Ok(())
+    }
+
+    fn input_pane(&self) -> eyre::Result<InputPane> {
+        let interop = windows::core::factory::<InputPane, IInputPaneInterop>()?;
+        Ok(unsafe { interop.GetForWindow(self.hwnd)? })
+    }
+}
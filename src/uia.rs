@@ -0,0 +1,590 @@
+//! Exposes the framework's semantics tree to UI Automation, so that screen readers and other
+//! assistive technology can read and operate the app.
+//!
+//! The window's root element is a fragment root, whose children are the children of the root
+//! semantics node. Each semantics node is an element with the control patterns that match its
+//! flags and actions, which are performed with [`semantics::dispatch_uia_action`].
+//!
+//! Providers are created on demand and only refer to their node by id, so they stay valid (but
+//! report that the element isn't available) after the node has been removed.
+
+use std::cell::RefCell;
+use std::mem::ManuallyDrop;
+use std::rc::{Rc, Weak};
+
+use windows::core::{implement, ComInterface, Error, IUnknown, Result, BSTR, HRESULT, PCWSTR};
+use windows::Win32::Foundation::{BOOL, HWND, LPARAM, LRESULT, POINT, VARIANT_BOOL, WPARAM};
+use windows::Win32::Graphics::Gdi::{ClientToScreen, ScreenToClient};
+use windows::Win32::System::Com::SAFEARRAY;
+use windows::Win32::System::Ole::{SafeArrayCreateVector, SafeArrayPutElement};
+use windows::Win32::System::Variant::{VARIANT, VT_BOOL, VT_BSTR, VT_I4};
+use windows::Win32::UI::Accessibility::*;
+
+use crate::engine::{FlutterEngine, SemanticsActions, SemanticsFlags, SemanticsNode};
+use crate::error_utils::ResultExt;
+use crate::semantics::{self, SemanticsTree, UiaAction};
+
+/// Returned for elements whose semantics node has been removed.
+const UIA_E_ELEMENTNOTAVAILABLE: HRESULT = HRESULT(0x8004_0201_u32 as i32);
+
+/// Flutter only reports the value of a slider as text, which is a percentage for the material
+/// sliders, so range values are assumed to be in this range.
+const RANGE_MAXIMUM: f64 = 100.0;
+
+/// The semantics tree of a window, as UI Automation elements.
+#[derive(Clone)]
+pub struct UiaTree {
+    shared: Rc<Shared>,
+}
+
+struct Shared {
+    hwnd: HWND,
+    /// Providers can be kept alive by clients after the engine has gone.
+    engine: Weak<FlutterEngine>,
+    tree: RefCell<SemanticsTree>,
+}
+
+impl UiaTree {
+    /// Starts tracking semantics updates from `engine`.
+    pub fn new(hwnd: HWND, engine: &Rc<FlutterEngine>) -> UiaTree {
+        let shared = Rc::new(Shared {
+            hwnd,
+            engine: Rc::downgrade(engine),
+            tree: RefCell::new(SemanticsTree::default()),
+        });
+
+        engine.set_semantics_update_handler({
+            let shared = Rc::downgrade(&shared);
+            move |nodes| {
+                if let Some(shared) = shared.upgrade() {
+                    Shared::update(&shared, nodes);
+                }
+            }
+        });
+
+        UiaTree { shared }
+    }
+
+    /// Forgets the current tree, e.g. when semantics are disabled or the engine is restarted.
+    /// The framework sends the whole tree again once semantics are enabled.
+    pub fn clear(&self) {
+        self.shared.tree.borrow_mut().clear();
+    }
+
+    /// Handles `WM_GETOBJECT`, returning the root element when UI Automation asks for it.
+    pub fn handle_get_object(&self, wparam: WPARAM, lparam: LPARAM) -> Option<LRESULT> {
+        if lparam.0 as i32 != UiaRootObjectId {
+            return None;
+        }
+
+        let root: IRawElementProviderSimple = RootProvider {
+            shared: self.shared.clone(),
+        }
+        .into();
+        Some(unsafe { UiaReturnRawElementProvider(self.shared.hwnd, wparam, lparam, &root) })
+    }
+}
+
+impl Shared {
+    fn update(self: &Rc<Self>, nodes: Vec<SemanticsNode>) {
+        let (previous_focus, focus) = {
+            let mut tree = self.tree.borrow_mut();
+            let previous_focus = tree.focused();
+            tree.update(nodes);
+            (previous_focus, tree.focused())
+        };
+
+        if focus != previous_focus && unsafe { UiaClientsAreListening() }.as_bool() {
+            if let Some(id) = focus {
+                let provider: IRawElementProviderSimple = self.node(id).into();
+                let _ = unsafe {
+                    UiaRaiseAutomationEvent(&provider, UIA_AutomationFocusChangedEventId)
+                }
+                .trace_err();
+            }
+        }
+    }
+
+    fn node(self: &Rc<Self>, id: i32) -> NodeProvider {
+        NodeProvider {
+            shared: self.clone(),
+            id,
+        }
+    }
+
+    /// The element for a node, which is the root element for the root node.
+    fn fragment(self: &Rc<Self>, id: i32) -> IRawElementProviderFragment {
+        if id == SemanticsTree::ROOT_ID {
+            RootProvider {
+                shared: self.clone(),
+            }
+            .into()
+        } else {
+            self.node(id).into()
+        }
+    }
+
+    /// Converts a point or rect from physical pixels in the view to screen coordinates.
+    fn client_to_screen(&self, x: f64, y: f64) -> (f64, f64) {
+        let mut origin = POINT::default();
+        unsafe { ClientToScreen(self.hwnd, &mut origin) };
+        (x + origin.x as f64, y + origin.y as f64)
+    }
+
+    fn screen_to_client(&self, x: f64, y: f64) -> (f64, f64) {
+        let mut origin = POINT::default();
+        unsafe { ScreenToClient(self.hwnd, &mut origin) };
+        (x + origin.x as f64, y + origin.y as f64)
+    }
+}
+
+/// The window's root element, which is hosted by the window's own element.
+#[implement(
+    IRawElementProviderSimple,
+    IRawElementProviderFragment,
+    IRawElementProviderFragmentRoot
+)]
+struct RootProvider {
+    shared: Rc<Shared>,
+}
+
+impl IRawElementProviderSimple_Impl for RootProvider {
+    fn ProviderOptions(&self) -> Result<ProviderOptions> {
+        Ok(provider_options())
+    }
+
+    fn GetPatternProvider(&self, _pattern_id: UIA_PATTERN_ID) -> Result<IUnknown> {
+        Err(Error::OK)
+    }
+
+    fn GetPropertyValue(&self, _property_id: UIA_PROPERTY_ID) -> Result<VARIANT> {
+        // The window's element provides the name, control type and so on.
+        Ok(VARIANT::default())
+    }
+
+    fn HostRawElementProvider(&self) -> Result<IRawElementProviderSimple> {
+        unsafe { UiaHostProviderFromHwnd(self.shared.hwnd) }
+    }
+}
+
+impl IRawElementProviderFragment_Impl for RootProvider {
+    fn Navigate(&self, direction: NavigateDirection) -> Result<IRawElementProviderFragment> {
+        let tree = self.shared.tree.borrow();
+        let children = tree
+            .node(SemanticsTree::ROOT_ID)
+            .map_or(&[][..], |root| &root.children[..]);
+
+        let child = match direction {
+            NavigateDirection_FirstChild => children.first(),
+            NavigateDirection_LastChild => children.last(),
+            _ => None,
+        };
+
+        match child {
+            Some(&id) => Ok(self.shared.node(id).into()),
+            None => Err(Error::OK),
+        }
+    }
+
+    fn GetRuntimeId(&self) -> Result<*mut SAFEARRAY> {
+        // Roots that are hosted by a window get their id from it.
+        Ok(std::ptr::null_mut())
+    }
+
+    fn BoundingRectangle(&self) -> Result<UiaRect> {
+        // Likewise, the bounds are the window's.
+        Ok(UiaRect::default())
+    }
+
+    fn GetEmbeddedFragmentRoots(&self) -> Result<*mut SAFEARRAY> {
+        Ok(std::ptr::null_mut())
+    }
+
+    fn SetFocus(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn FragmentRoot(&self) -> Result<IRawElementProviderFragmentRoot> {
+        Ok(RootProvider {
+            shared: self.shared.clone(),
+        }
+        .into())
+    }
+}
+
+impl IRawElementProviderFragmentRoot_Impl for RootProvider {
+    fn ElementProviderFromPoint(&self, x: f64, y: f64) -> Result<IRawElementProviderFragment> {
+        let (x, y) = self.shared.screen_to_client(x, y);
+        match self.shared.tree.borrow().hit_test(x, y) {
+            Some(id) => Ok(self.shared.fragment(id)),
+            None => Err(Error::OK),
+        }
+    }
+
+    fn GetFocus(&self) -> Result<IRawElementProviderFragment> {
+        match self.shared.tree.borrow().focused() {
+            Some(id) => Ok(self.shared.fragment(id)),
+            None => Err(Error::OK),
+        }
+    }
+}
+
+/// The element for a semantics node other than the root.
+#[implement(
+    IRawElementProviderSimple,
+    IRawElementProviderFragment,
+    IInvokeProvider,
+    IToggleProvider,
+    IValueProvider,
+    IRangeValueProvider,
+    IScrollItemProvider
+)]
+struct NodeProvider {
+    shared: Rc<Shared>,
+    id: i32,
+}
+
+impl NodeProvider {
+    fn with_node<R>(&self, f: impl FnOnce(&SemanticsNode) -> R) -> Result<R> {
+        match self.shared.tree.borrow().node(self.id) {
+            Some(node) => Ok(f(node)),
+            None => Err(UIA_E_ELEMENTNOTAVAILABLE.into()),
+        }
+    }
+
+    fn dispatch(&self, action: UiaAction) -> Result<()> {
+        self.with_node(|_| ())?;
+
+        let Some(engine) = self.shared.engine.upgrade() else {
+            return Err(UIA_E_ELEMENTNOTAVAILABLE.into());
+        };
+
+        semantics::dispatch_uia_action(&engine, self.id as u64, &action)
+            .trace_err()
+            .map_err(|_| Error::from(UIA_E_ELEMENTNOTAVAILABLE))
+    }
+
+    fn sibling(&self, offset: isize) -> Option<i32> {
+        let tree = self.shared.tree.borrow();
+        let children = &tree.node(tree.parent(self.id)?)?.children;
+        let index = children.iter().position(|&id| id == self.id)?;
+        children.get(index.checked_add_signed(offset)?).copied()
+    }
+}
+
+/// Which patterns a node's element supports.
+fn supports_pattern(node: &SemanticsNode, pattern_id: UIA_PATTERN_ID) -> bool {
+    let flags = node.flags;
+    let actions = node.actions;
+    let is_toggle =
+        flags.intersects(SemanticsFlags::HAS_CHECKED_STATE | SemanticsFlags::HAS_TOGGLED_STATE);
+    let is_range = flags.contains(SemanticsFlags::IS_SLIDER)
+        || actions.intersects(SemanticsActions::INCREASE | SemanticsActions::DECREASE);
+
+    match pattern_id {
+        UIA_InvokePatternId => {
+            actions.contains(SemanticsActions::TAP)
+                && !is_toggle
+                && !flags.contains(SemanticsFlags::IS_TEXT_FIELD)
+        }
+        UIA_TogglePatternId => is_toggle,
+        UIA_ValuePatternId => flags.contains(SemanticsFlags::IS_TEXT_FIELD),
+        UIA_RangeValuePatternId => is_range,
+        UIA_ScrollItemPatternId => actions.contains(SemanticsActions::SHOW_ON_SCREEN),
+        _ => false,
+    }
+}
+
+fn control_type(node: &SemanticsNode) -> UIA_CONTROLTYPE_ID {
+    let flags = node.flags;
+    if flags.contains(SemanticsFlags::IS_TEXT_FIELD) {
+        UIA_EditControlTypeId
+    } else if flags.contains(SemanticsFlags::IS_SLIDER) {
+        UIA_SliderControlTypeId
+    } else if flags.contains(SemanticsFlags::HAS_CHECKED_STATE) {
+        UIA_CheckBoxControlTypeId
+    } else if flags.intersects(SemanticsFlags::IS_BUTTON | SemanticsFlags::HAS_TOGGLED_STATE) {
+        UIA_ButtonControlTypeId
+    } else if flags.contains(SemanticsFlags::IS_LINK) {
+        UIA_HyperlinkControlTypeId
+    } else if flags.contains(SemanticsFlags::IS_IMAGE) {
+        UIA_ImageControlTypeId
+    } else if !node.label.is_empty() && node.actions.is_empty() {
+        UIA_TextControlTypeId
+    } else {
+        UIA_GroupControlTypeId
+    }
+}
+
+impl IRawElementProviderSimple_Impl for NodeProvider {
+    fn ProviderOptions(&self) -> Result<ProviderOptions> {
+        Ok(provider_options())
+    }
+
+    fn GetPatternProvider(&self, pattern_id: UIA_PATTERN_ID) -> Result<IUnknown> {
+        if !self.with_node(|node| supports_pattern(node, pattern_id))? {
+            return Err(Error::OK);
+        }
+
+        let provider: IRawElementProviderSimple = self.shared.node(self.id).into();
+        provider.cast()
+    }
+
+    fn GetPropertyValue(&self, property_id: UIA_PROPERTY_ID) -> Result<VARIANT> {
+        self.with_node(|node| {
+            let flags = node.flags;
+            match property_id {
+                UIA_ControlTypePropertyId => variant_i32(control_type(node).0),
+                UIA_NamePropertyId if !node.label.is_empty() => variant_str(&node.label),
+                UIA_NamePropertyId if !node.tooltip.is_empty() => variant_str(&node.tooltip),
+                UIA_HelpTextPropertyId if !node.hint.is_empty() => variant_str(&node.hint),
+                UIA_IsEnabledPropertyId => variant_bool(
+                    !flags.contains(SemanticsFlags::HAS_ENABLED_STATE)
+                        || flags.contains(SemanticsFlags::IS_ENABLED),
+                ),
+                UIA_IsKeyboardFocusablePropertyId => variant_bool(
+                    flags.intersects(SemanticsFlags::IS_FOCUSABLE | SemanticsFlags::IS_TEXT_FIELD),
+                ),
+                UIA_HasKeyboardFocusPropertyId => {
+                    variant_bool(flags.contains(SemanticsFlags::IS_FOCUSED))
+                }
+                UIA_IsOffscreenPropertyId => {
+                    variant_bool(flags.contains(SemanticsFlags::IS_HIDDEN))
+                }
+                UIA_IsPasswordPropertyId => {
+                    variant_bool(flags.contains(SemanticsFlags::IS_OBSCURED))
+                }
+                _ => VARIANT::default(),
+            }
+        })
+    }
+
+    fn HostRawElementProvider(&self) -> Result<IRawElementProviderSimple> {
+        Err(Error::OK)
+    }
+}
+
+impl IRawElementProviderFragment_Impl for NodeProvider {
+    fn Navigate(&self, direction: NavigateDirection) -> Result<IRawElementProviderFragment> {
+        let id = match direction {
+            NavigateDirection_Parent => {
+                let parent = self.shared.tree.borrow().parent(self.id);
+                parent.ok_or_else(|| Error::from(UIA_E_ELEMENTNOTAVAILABLE))?
+            }
+            NavigateDirection_NextSibling => match self.sibling(1) {
+                Some(id) => id,
+                None => return Err(Error::OK),
+            },
+            NavigateDirection_PreviousSibling => match self.sibling(-1) {
+                Some(id) => id,
+                None => return Err(Error::OK),
+            },
+            NavigateDirection_FirstChild | NavigateDirection_LastChild => {
+                let child = self.with_node(|node| match direction {
+                    NavigateDirection_FirstChild => node.children.first().copied(),
+                    _ => node.children.last().copied(),
+                })?;
+
+                match child {
+                    Some(id) => id,
+                    None => return Err(Error::OK),
+                }
+            }
+            _ => return Err(Error::OK),
+        };
+
+        Ok(self.shared.fragment(id))
+    }
+
+    fn GetRuntimeId(&self) -> Result<*mut SAFEARRAY> {
+        // Ids are appended to the window's runtime id, which makes them unique.
+        let ids = [UiaAppendRuntimeId as i32, self.id];
+        unsafe {
+            let array = SafeArrayCreateVector(VT_I4, 0, ids.len() as u32);
+            if array.is_null() {
+                return Err(Error::from_win32());
+            }
+
+            for (i, id) in ids.iter().enumerate() {
+                SafeArrayPutElement(array, &(i as i32), (id as *const i32).cast())?;
+            }
+
+            Ok(array)
+        }
+    }
+
+    fn BoundingRectangle(&self) -> Result<UiaRect> {
+        let bounds = self.shared.tree.borrow().bounds(self.id);
+        let Some([left, top, right, bottom]) = bounds else {
+            return Err(UIA_E_ELEMENTNOTAVAILABLE.into());
+        };
+
+        let (screen_left, screen_top) = self.shared.client_to_screen(left, top);
+        Ok(UiaRect {
+            left: screen_left,
+            top: screen_top,
+            width: right - left,
+            height: bottom - top,
+        })
+    }
+
+    fn GetEmbeddedFragmentRoots(&self) -> Result<*mut SAFEARRAY> {
+        Ok(std::ptr::null_mut())
+    }
+
+    fn SetFocus(&self) -> Result<()> {
+        // The framework has no action for moving input focus, only for showing the node.
+        self.dispatch(UiaAction::ScrollIntoView)
+    }
+
+    fn FragmentRoot(&self) -> Result<IRawElementProviderFragmentRoot> {
+        Ok(RootProvider {
+            shared: self.shared.clone(),
+        }
+        .into())
+    }
+}
+
+impl IInvokeProvider_Impl for NodeProvider {
+    fn Invoke(&self) -> Result<()> {
+        self.dispatch(UiaAction::Invoke)
+    }
+}
+
+impl IToggleProvider_Impl for NodeProvider {
+    fn Toggle(&self) -> Result<()> {
+        self.dispatch(UiaAction::Toggle)
+    }
+
+    fn ToggleState(&self) -> Result<ToggleState> {
+        self.with_node(|node| {
+            let flags = node.flags;
+            if flags.contains(SemanticsFlags::IS_CHECK_STATE_MIXED) {
+                ToggleState_Indeterminate
+            } else if flags.intersects(SemanticsFlags::IS_CHECKED | SemanticsFlags::IS_TOGGLED) {
+                ToggleState_On
+            } else {
+                ToggleState_Off
+            }
+        })
+    }
+}
+
+impl IValueProvider_Impl for NodeProvider {
+    fn SetValue(&self, value: &PCWSTR) -> Result<()> {
+        let value = unsafe { value.to_string()? };
+        self.dispatch(UiaAction::SetValue(value))
+    }
+
+    fn Value(&self) -> Result<BSTR> {
+        self.with_node(|node| {
+            if node.flags.contains(SemanticsFlags::IS_OBSCURED) {
+                BSTR::new()
+            } else {
+                BSTR::from(node.value.as_str())
+            }
+        })
+    }
+
+    fn IsReadOnly(&self) -> Result<BOOL> {
+        self.with_node(|node| {
+            BOOL::from(
+                node.flags.contains(SemanticsFlags::IS_READ_ONLY)
+                    || !node.actions.contains(SemanticsActions::SET_TEXT),
+            )
+        })
+    }
+}
+
+impl IRangeValueProvider_Impl for NodeProvider {
+    fn SetValue(&self, value: f64) -> Result<()> {
+        let current = IRangeValueProvider_Impl::Value(self)?;
+        if value == current {
+            return Ok(());
+        }
+
+        self.dispatch(UiaAction::Step {
+            increase: value > current,
+        })
+    }
+
+    fn Value(&self) -> Result<f64> {
+        self.with_node(|node| parse_range_value(&node.value))
+    }
+
+    fn IsReadOnly(&self) -> Result<BOOL> {
+        self.with_node(|node| {
+            BOOL::from(
+                !node
+                    .actions
+                    .intersects(SemanticsActions::INCREASE | SemanticsActions::DECREASE),
+            )
+        })
+    }
+
+    fn Maximum(&self) -> Result<f64> {
+        Ok(RANGE_MAXIMUM)
+    }
+
+    fn Minimum(&self) -> Result<f64> {
+        Ok(0.0)
+    }
+
+    fn LargeChange(&self) -> Result<f64> {
+        Ok(RANGE_MAXIMUM / 10.0)
+    }
+
+    fn SmallChange(&self) -> Result<f64> {
+        Ok(RANGE_MAXIMUM / 100.0)
+    }
+}
+
+impl IScrollItemProvider_Impl for NodeProvider {
+    fn ScrollIntoView(&self) -> Result<()> {
+        self.dispatch(UiaAction::ScrollIntoView)
+    }
+}
+
+fn provider_options() -> ProviderOptions {
+    // Calls are made on the window's thread, which is where the tree is updated.
+    ProviderOptions(ProviderOptions_ServerSideProvider.0 | ProviderOptions_UseComThreading.0)
+}
+
+/// Reads the number at the start of a value such as `50%`, or 0 if there isn't one.
+fn parse_range_value(value: &str) -> f64 {
+    let end = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '-'))
+        .unwrap_or(value.len());
+
+    value[..end].parse().unwrap_or(0.0)
+}
+
+fn variant_i32(value: i32) -> VARIANT {
+    let mut variant = VARIANT::default();
+    unsafe {
+        let inner = &mut *variant.Anonymous.Anonymous;
+        inner.vt = VT_I4;
+        inner.Anonymous.lVal = value;
+    }
+    variant
+}
+
+fn variant_bool(value: bool) -> VARIANT {
+    let mut variant = VARIANT::default();
+    unsafe {
+        let inner = &mut *variant.Anonymous.Anonymous;
+        inner.vt = VT_BOOL;
+        inner.Anonymous.boolVal = VARIANT_BOOL(if value { -1 } else { 0 });
+    }
+    variant
+}
+
+fn variant_str(value: &str) -> VARIANT {
+    let mut variant = VARIANT::default();
+    unsafe {
+        let inner = &mut *variant.Anonymous.Anonymous;
+        inner.vt = VT_BSTR;
+        inner.Anonymous.bstrVal = ManuallyDrop::new(BSTR::from(value));
+    }
+    variant
+}
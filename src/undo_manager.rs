@@ -0,0 +1,128 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use serde::Deserialize;
+use serde_json::json;
+use winit::keyboard::Key;
+
+use crate::engine::{BinaryMessageHandler, BinaryMessageReply, BinaryMessenger};
+use crate::error;
+
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UndoState {
+    pub can_undo: bool,
+    pub can_redo: bool,
+}
+
+/// Keeps track of whether the framework's `UndoHistory` can undo or redo, and asks it to when the
+/// platform requests an undo (e.g. from an unhandled Ctrl+Z).
+#[derive(Clone, Default)]
+pub struct UndoManager {
+    inner: Rc<RefCell<Inner>>,
+}
+
+#[derive(Default)]
+struct Inner {
+    state: UndoState,
+    /// Set once the framework has sent its undo state, since the messenger isn't available
+    /// before then. The state is stale once this is disconnected by a restart.
+    messenger: Option<BinaryMessenger>,
+}
+
+impl UndoManager {
+    pub fn new() -> UndoManager {
+        UndoManager::default()
+    }
+
+    pub fn state(&self) -> UndoState {
+        let inner = self.inner.borrow();
+        match &inner.messenger {
+            Some(messenger) if messenger.is_connected() => inner.state,
+            _ => UndoState::default(),
+        }
+    }
+
+    /// Asks the framework to undo. Returns false if there is nothing to undo.
+    pub fn undo(&self) -> error::Result<bool> {
+        self.send("undo", self.state().can_undo)
+    }
+
+    /// Asks the framework to redo. Returns false if there is nothing to redo.
+    pub fn redo(&self) -> error::Result<bool> {
+        self.send("redo", self.state().can_redo)
+    }
+
+    /// Handles the standard undo and redo shortcuts (Ctrl+Z, and Ctrl+Y or Ctrl+Shift+Z), for key
+    /// presses that the framework didn't handle. Returns whether the key was a shortcut.
+    pub fn handle_shortcut(&self, key: &Key, ctrl: bool, shift: bool) -> error::Result<bool> {
+        let Key::Character(c) = key else {
+            return Ok(false);
+        };
+
+        if !ctrl {
+            return Ok(false);
+        }
+
+        match c.to_lowercase().as_str() {
+            "z" if shift => self.redo(),
+            "z" => self.undo(),
+            "y" if !shift => self.redo(),
+            _ => Ok(false),
+        }
+    }
+
+    fn send(&self, direction: &str, enabled: bool) -> error::Result<bool> {
+        let Some(messenger) = self
+            .inner
+            .borrow()
+            .messenger
+            .clone()
+            .filter(|messenger| messenger.is_connected())
+        else {
+            return Ok(false);
+        };
+
+        if !enabled {
+            return Ok(false);
+        }
+
+        let message = json!({
+            "method": "UndoManagerClient.handlePlatformUndo",
+            "args": [direction],
+        });
+
+        messenger.send_platform_message(c"flutter/undomanager", &serde_json::to_vec(&message)?)?;
+
+        Ok(true)
+    }
+}
+
+impl BinaryMessageHandler for UndoManager {
+    fn handle(&self, message: &[u8], reply: BinaryMessageReply) {
+        #[derive(Debug, Deserialize)]
+        #[serde(tag = "method", content = "args")]
+        enum Request {
+            #[serde(rename = "UndoManager.setUndoState")]
+            SetUndoState(UndoState),
+        }
+
+        let Ok(req) = serde_json::from_slice::<Request>(message) else {
+            let message = String::from_utf8_lossy(message);
+            tracing::warn!("unimplemented: {message}");
+            reply.not_implemented();
+            return;
+        };
+
+        match req {
+            Request::SetUndoState(state) => {
+                let mut inner = self.inner.borrow_mut();
+                inner.state = state;
+                inner.messenger = Some(reply.messenger());
+                drop(inner);
+
+                reply.send(c"[null]".to_bytes());
+            }
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use std::path::PathBuf;
+
+use color_eyre::eyre::{self, bail};
+use flutter_codec::EncodableValue;
+use windows::core::{HSTRING, PCWSTR, PWSTR};
+use windows::Win32::Foundation::{HWND, MAX_PATH};
+use windows::Win32::UI::Shell::{
+    AssocQueryStringW, PathCreateFromUrlW, ShellExecuteW, ASSOCF_IS_PROTOCOL, ASSOCSTR_COMMAND,
+};
+use windows::Win32::UI::WindowsAndMessaging::SW_SHOWNORMAL;
+
+use crate::standard_method_channel::{StandardMethodHandler, StandardMethodReply};
+
+/// Schemes that can be launched. Other schemes could start arbitrary registered handlers, so
+/// they are rejected.
+const SCHEMES: &[&str] = &["http", "https", "mailto", "file"];
+
+/// Handles `plugins.flutter.io/url_launcher`, opening urls with their default handler.
+pub struct UrlLauncherHandler {
+    hwnd: HWND,
+}
+
+impl UrlLauncherHandler {
+    pub fn new(hwnd: HWND) -> UrlLauncherHandler {
+        UrlLauncherHandler { hwnd }
+    }
+
+    fn can_launch(&self, url: &str) -> bool {
+        let Some((scheme, _)) = url.split_once(':') else {
+            return false;
+        };
+
+        let scheme = scheme.to_ascii_lowercase();
+        if !SCHEMES.contains(&scheme.as_str()) {
+            return false;
+        }
+
+        if scheme == "file" {
+            return file_path(url).is_ok_and(|path| path.exists());
+        }
+
+        // Querying the size of the command succeeds if there is a handler for the scheme.
+        let mut len = 0;
+        unsafe {
+            AssocQueryStringW(
+                ASSOCF_IS_PROTOCOL,
+                ASSOCSTR_COMMAND,
+                &HSTRING::from(scheme),
+                PCWSTR::null(),
+                PWSTR::null(),
+                &mut len,
+            )
+            .is_ok()
+        }
+    }
+
+    fn launch(&self, url: &str) -> eyre::Result<()> {
+        if !self.can_launch(url) {
+            bail!("no handler for {url}");
+        }
+
+        let result = unsafe {
+            ShellExecuteW(
+                self.hwnd,
+                &HSTRING::from("open"),
+                &HSTRING::from(url),
+                PCWSTR::null(),
+                PCWSTR::null(),
+                SW_SHOWNORMAL,
+            )
+        };
+
+        // Values greater than 32 indicate success.
+        if result.0 <= 32 {
+            bail!("failed to launch {url} (error {})", result.0);
+        }
+
+        Ok(())
+    }
+}
+
+fn file_path(url: &str) -> eyre::Result<PathBuf> {
+    let mut path = [0u16; MAX_PATH as usize];
+    let mut len = path.len() as u32;
+
+    unsafe {
+        PathCreateFromUrlW(&HSTRING::from(url), PWSTR(path.as_mut_ptr()), &mut len, 0)?;
+    }
+
+    Ok(PathBuf::from(String::from_utf16(&path[..len as usize])?))
+}
+
+impl StandardMethodHandler for UrlLauncherHandler {
+    fn handle(&self, method: &str, args: EncodableValue, reply: StandardMethodReply) {
+        match method {
+            "canLaunch" => {
+                let Some(url) = args.get("url").and_then(|v| v.as_string()) else {
+                    return reply.error("invalid_args", Some("expected a url"));
+                };
+
+                reply.success(&EncodableValue::Bool(self.can_launch(url)));
+            }
+            "launch" => {
+                let Some(url) = args.get("url").and_then(|v| v.as_string()) else {
+                    return reply.error("invalid_args", Some("expected a url"));
+                };
+
+                // In-app web views and other options aren't supported, so urls are always
+                // opened externally.
+                match self.launch(url) {
+                    Ok(()) => reply.success(&EncodableValue::Bool(true)),
+                    Err(e) => {
+                        tracing::error!("{e:?}");
+                        reply.success(&EncodableValue::Bool(false));
+                    }
+                }
+            }
+            "closeWebView" => reply.success(&EncodableValue::Null),
+            _ => {
+                tracing::warn!(method, "unimplemented");
+                reply.not_implemented();
+            }
+        }
+    }
+}
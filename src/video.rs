@@ -0,0 +1,485 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::ffi::c_void;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+
+use color_eyre::eyre::{self, OptionExt};
+use flutter_codec::EncodableValue;
+use flutter_embedder::FlutterOpenGLTexture;
+use windows::core::{implement, Result as WinResult, BSTR};
+use windows::Win32::Foundation::RECT;
+use windows::Win32::Graphics::Direct3D11::{
+    ID3D11Device, ID3D11Texture2D, D3D11_BIND_RENDER_TARGET, D3D11_BIND_SHADER_RESOURCE,
+    D3D11_TEXTURE2D_DESC, D3D11_USAGE_DEFAULT,
+};
+use windows::Win32::Graphics::Dwm::DwmFlush;
+use windows::Win32::Graphics::Dxgi::Common::{DXGI_FORMAT_B8G8R8A8_UNORM, DXGI_SAMPLE_DESC};
+use windows::Win32::Media::MediaFoundation::{
+    CLSID_MFMediaEngineClassFactory, IMFAttributes, IMFDXGIDeviceManager, IMFMediaEngine,
+    IMFMediaEngineClassFactory, IMFMediaEngineNotify, IMFMediaEngineNotify_Impl,
+    MFCreateAttributes, MFCreateDXGIDeviceManager, MFShutdown, MFStartup, MFSTARTUP_FULL,
+    MF_MEDIA_ENGINE_CALLBACK, MF_MEDIA_ENGINE_DXGI_MANAGER, MF_MEDIA_ENGINE_EVENT,
+    MF_MEDIA_ENGINE_EVENT_ENDED, MF_MEDIA_ENGINE_EVENT_ERROR,
+    MF_MEDIA_ENGINE_EVENT_FIRSTFRAMEREADY, MF_MEDIA_ENGINE_EVENT_PAUSE,
+    MF_MEDIA_ENGINE_EVENT_PLAYING, MF_MEDIA_ENGINE_EVENT_SEEKED,
+    MF_MEDIA_ENGINE_VIDEO_OUTPUT_FORMAT, MF_VERSION,
+};
+use windows::Win32::System::Com::{CoCreateInstance, CLSCTX_ALL};
+
+use crate::egl_manager::EglManager;
+use crate::error_utils::ResultExt;
+use crate::standard_method_channel::{StandardMethodHandler, StandardMethodReply};
+use crate::texture_registry::{ExternalTexture, GlTexture, TextureRegistry};
+
+/// Plays videos with the Media Foundation media engine, which decodes frames into D3D textures
+/// that are shown with a `Texture` widget.
+///
+/// The media engine shares the D3D device with ANGLE, so the device must be created with video
+/// support and multithread protection.
+pub struct VideoHandler {
+    device: ID3D11Device,
+    egl_manager: Arc<EglManager>,
+    texture_registry: Arc<TextureRegistry>,
+    players: RefCell<BTreeMap<i64, VideoPlayer>>,
+}
+
+impl VideoHandler {
+    pub fn new(
+        device: ID3D11Device,
+        egl_manager: Arc<EglManager>,
+        texture_registry: Arc<TextureRegistry>,
+    ) -> eyre::Result<VideoHandler> {
+        unsafe { MFStartup(MF_VERSION, MFSTARTUP_FULL)? };
+
+        Ok(VideoHandler {
+            device,
+            egl_manager,
+            texture_registry,
+            players: RefCell::new(BTreeMap::new()),
+        })
+    }
+
+    fn create(&self, uri: &str) -> eyre::Result<i64> {
+        let player = VideoPlayer::new(
+            &self.device,
+            self.egl_manager.clone(),
+            self.texture_registry.clone(),
+            uri,
+        )?;
+
+        let texture_id = player.texture_id;
+        self.players.borrow_mut().insert(texture_id, player);
+
+        Ok(texture_id)
+    }
+}
+
+impl Drop for VideoHandler {
+    fn drop(&mut self) {
+        self.players.get_mut().clear();
+        let _ = unsafe { MFShutdown() }.trace_err();
+    }
+}
+
+impl StandardMethodHandler for VideoHandler {
+    fn handle(&self, method: &str, args: EncodableValue, reply: StandardMethodReply) {
+        if method == "create" {
+            let Some(uri) = args.get("uri").and_then(|v| v.as_string()) else {
+                return reply.error("invalid_args", Some("expected a uri"));
+            };
+
+            return match self.create(uri) {
+                Ok(texture_id) => reply.success(&EncodableValue::I64(texture_id)),
+                Err(e) => reply.error("video_error", Some(&format!("{e:?}"))),
+            };
+        }
+
+        let Some(texture_id) = args.get("textureId").and_then(|v| v.as_int()) else {
+            return reply.error("invalid_args", Some("expected a texture id"));
+        };
+
+        if method == "dispose" {
+            self.players.borrow_mut().remove(&texture_id);
+            return reply.success(&EncodableValue::Null);
+        }
+
+        let players = self.players.borrow();
+        let Some(player) = players.get(&texture_id) else {
+            return reply.error("invalid_args", Some("unknown texture id"));
+        };
+
+        let media_engine = &player.media_engine;
+
+        let res = match method {
+            "play" => unsafe { media_engine.Play() }.map(|_| EncodableValue::Null),
+            "pause" => unsafe { media_engine.Pause() }.map(|_| EncodableValue::Null),
+            "seekTo" => {
+                let Some(position) = args.get("position").and_then(|v| v.as_int()) else {
+                    return reply.error("invalid_args", Some("expected a position"));
+                };
+
+                unsafe { media_engine.SetCurrentTime(position as f64 / 1000.0) }
+                    .map(|_| EncodableValue::Null)
+            }
+            "getPosition" => Ok(millis(unsafe { media_engine.GetCurrentTime() })),
+            "getDuration" => Ok(millis(unsafe { media_engine.GetDuration() })),
+            _ => {
+                tracing::warn!(method, "unimplemented");
+                return reply.not_implemented();
+            }
+        };
+
+        match res {
+            Ok(value) => reply.success(&value),
+            Err(e) => reply.error("video_error", Some(&e.to_string())),
+        }
+    }
+}
+
+/// Converts a media engine time in seconds to milliseconds. Unknown times (e.g. the duration of
+/// a live stream) are reported as null.
+fn millis(seconds: f64) -> EncodableValue<'static> {
+    if seconds.is_finite() {
+        EncodableValue::I64((seconds * 1000.0) as i64)
+    } else {
+        EncodableValue::Null
+    }
+}
+
+struct VideoPlayer {
+    texture_id: i64,
+    media_engine: IMFMediaEngine,
+    texture_registry: Arc<TextureRegistry>,
+    pump: Arc<FramePump>,
+    pump_thread: Option<JoinHandle<()>>,
+}
+
+impl VideoPlayer {
+    fn new(
+        device: &ID3D11Device,
+        egl_manager: Arc<EglManager>,
+        texture_registry: Arc<TextureRegistry>,
+        uri: &str,
+    ) -> eyre::Result<VideoPlayer> {
+        let pump = Arc::new(FramePump {
+            state: Mutex::new(PumpState::default()),
+            condvar: Condvar::new(),
+        });
+
+        let media_engine = unsafe { create_media_engine(device, pump.clone())? };
+
+        unsafe { media_engine.SetSource(&BSTR::from(uri))? };
+
+        let texture = Arc::new(VideoTexture {
+            media_engine: media_engine.clone(),
+            device: device.clone(),
+            egl_manager,
+            frame: Mutex::new(None),
+        });
+
+        let texture_id = match texture_registry.register(texture) {
+            Ok(texture_id) => texture_id,
+            Err(e) => {
+                let _ = unsafe { media_engine.Shutdown() };
+                return Err(e.into());
+            }
+        };
+
+        let pump_thread = thread::Builder::new()
+            .name("video-frames".to_owned())
+            .spawn({
+                let pump = pump.clone();
+                let texture_registry = texture_registry.clone();
+                move || pump.run(texture_id, &texture_registry)
+            });
+
+        let pump_thread = match pump_thread {
+            Ok(pump_thread) => pump_thread,
+            Err(e) => {
+                let _ = texture_registry.unregister(texture_id);
+                let _ = unsafe { media_engine.Shutdown() };
+                return Err(e.into());
+            }
+        };
+
+        Ok(VideoPlayer {
+            texture_id,
+            media_engine,
+            texture_registry,
+            pump,
+            pump_thread: Some(pump_thread),
+        })
+    }
+}
+
+impl Drop for VideoPlayer {
+    fn drop(&mut self) {
+        self.pump.stop();
+
+        if let Some(thread) = self.pump_thread.take() {
+            if thread.join().is_err() {
+                tracing::error!("video frame thread panicked");
+            }
+        }
+
+        let _ = self
+            .texture_registry
+            .unregister(self.texture_id)
+            .trace_err();
+        let _ = unsafe { self.media_engine.Shutdown() }.trace_err();
+    }
+}
+
+unsafe fn create_media_engine(
+    device: &ID3D11Device,
+    pump: Arc<FramePump>,
+) -> eyre::Result<IMFMediaEngine> {
+    let mut reset_token = 0;
+    let mut device_manager: Option<IMFDXGIDeviceManager> = None;
+    MFCreateDXGIDeviceManager(&mut reset_token, &mut device_manager)?;
+    let device_manager = device_manager.ok_or_eyre("failed to create dxgi device manager")?;
+    device_manager.ResetDevice(device, reset_token)?;
+
+    let mut attributes: Option<IMFAttributes> = None;
+    MFCreateAttributes(&mut attributes, 3)?;
+    let attributes = attributes.ok_or_eyre("failed to create media engine attributes")?;
+
+    let notify: IMFMediaEngineNotify = MediaEngineNotify { pump }.into();
+
+    attributes.SetUnknown(&MF_MEDIA_ENGINE_DXGI_MANAGER, &device_manager)?;
+    attributes.SetUnknown(&MF_MEDIA_ENGINE_CALLBACK, &notify)?;
+    attributes.SetUINT32(
+        &MF_MEDIA_ENGINE_VIDEO_OUTPUT_FORMAT,
+        DXGI_FORMAT_B8G8R8A8_UNORM.0 as u32,
+    )?;
+
+    let factory: IMFMediaEngineClassFactory =
+        CoCreateInstance(&CLSID_MFMediaEngineClassFactory, None, CLSCTX_ALL)?;
+
+    Ok(factory.CreateInstance(0, &attributes)?)
+}
+
+/// Tells the engine when new frames may be available.
+///
+/// The media engine doesn't notify us when a frame has been decoded, so while a video is playing
+/// the texture is marked as available on every vsync, and the raster thread checks whether there
+/// is a new frame when the engine asks for it.
+struct FramePump {
+    state: Mutex<PumpState>,
+    condvar: Condvar,
+}
+
+#[derive(Default)]
+struct PumpState {
+    playing: bool,
+    /// Set when a single new frame should be shown while paused, e.g. after seeking.
+    frame_requested: bool,
+    stopped: bool,
+}
+
+impl FramePump {
+    fn update(&self, f: impl FnOnce(&mut PumpState)) {
+        f(&mut self.state.lock().unwrap());
+        self.condvar.notify_all();
+    }
+
+    fn stop(&self) {
+        self.update(|state| state.stopped = true);
+    }
+
+    fn run(&self, texture_id: i64, texture_registry: &TextureRegistry) {
+        loop {
+            {
+                let mut state = self.state.lock().unwrap();
+
+                while !state.stopped && !state.playing && !state.frame_requested {
+                    state = self.condvar.wait(state).unwrap();
+                }
+
+                if state.stopped {
+                    return;
+                }
+
+                state.frame_requested = false;
+            }
+
+            if let Err(e) = unsafe { DwmFlush() } {
+                tracing::error!("failed to wait for vsync: {e}");
+                return;
+            }
+
+            let _ = texture_registry
+                .mark_frame_available(texture_id)
+                .trace_err();
+        }
+    }
+}
+
+#[implement(IMFMediaEngineNotify)]
+struct MediaEngineNotify {
+    pump: Arc<FramePump>,
+}
+
+impl IMFMediaEngineNotify_Impl for MediaEngineNotify {
+    fn EventNotify(&self, event: u32, param1: usize, param2: u32) -> WinResult<()> {
+        match MF_MEDIA_ENGINE_EVENT(event as i32) {
+            MF_MEDIA_ENGINE_EVENT_PLAYING => self.pump.update(|state| state.playing = true),
+            MF_MEDIA_ENGINE_EVENT_PAUSE | MF_MEDIA_ENGINE_EVENT_ENDED => {
+                self.pump.update(|state| state.playing = false)
+            }
+            MF_MEDIA_ENGINE_EVENT_FIRSTFRAMEREADY | MF_MEDIA_ENGINE_EVENT_SEEKED => {
+                self.pump.update(|state| state.frame_requested = true)
+            }
+            MF_MEDIA_ENGINE_EVENT_ERROR => {
+                tracing::error!(code = param1, hresult = param2, "video playback failed");
+                self.pump.update(|state| state.playing = false);
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
+
+/// The latest video frame, which is copied into a D3D texture and exposed to GL through an ANGLE
+/// pbuffer.
+struct VideoTexture {
+    media_engine: IMFMediaEngine,
+    device: ID3D11Device,
+    egl_manager: Arc<EglManager>,
+    frame: Mutex<Option<Arc<Frame>>>,
+}
+
+// The media engine is free-threaded, and the D3D device is multithread protected.
+unsafe impl Send for VideoTexture {}
+unsafe impl Sync for VideoTexture {}
+
+impl ExternalTexture for VideoTexture {
+    fn frame(&self, _width: usize, _height: usize) -> Option<FlutterOpenGLTexture> {
+        let frame = self.update_frame().trace_err().ok()??;
+
+        let texture = FlutterOpenGLTexture {
+            target: gl::TEXTURE_2D,
+            name: frame.gl_texture.name(),
+            format: gl::RGBA8,
+            width: frame.width as usize,
+            height: frame.height as usize,
+            // The engine may keep using the texture after the frame has been replaced.
+            user_data: Arc::into_raw(frame) as *mut c_void,
+            destruction_callback: Some(release_frame),
+        };
+
+        Some(texture)
+    }
+}
+
+unsafe extern "C" fn release_frame(user_data: *mut c_void) {
+    drop(Arc::from_raw(user_data.cast::<Frame>()));
+}
+
+impl VideoTexture {
+    fn update_frame(&self) -> eyre::Result<Option<Arc<Frame>>> {
+        let mut width = 0;
+        let mut height = 0;
+        unsafe {
+            self.media_engine
+                .GetNativeVideoSize(Some(&mut width as *mut u32), Some(&mut height as *mut u32))?
+        };
+
+        // There is nothing to show until the video's metadata has been loaded.
+        if width == 0 || height == 0 {
+            return Ok(None);
+        }
+
+        let mut current = self.frame.lock().unwrap();
+
+        let frame = match &*current {
+            Some(frame) if frame.width == width && frame.height == height => frame.clone(),
+            _ => {
+                let frame = Arc::new(Frame::new(
+                    &self.device,
+                    self.egl_manager.clone(),
+                    width,
+                    height,
+                )?);
+                *current = Some(frame.clone());
+                frame
+            }
+        };
+
+        if let Ok(pts) = unsafe { self.media_engine.OnVideoStreamTick() } {
+            let mut last_pts = frame.last_pts.lock().unwrap();
+            if *last_pts != Some(pts) {
+                unsafe {
+                    self.media_engine.TransferVideoFrame(
+                        &frame.d3d_texture,
+                        None,
+                        &RECT {
+                            left: 0,
+                            top: 0,
+                            right: width as i32,
+                            bottom: height as i32,
+                        },
+                        None,
+                    )?;
+                }
+
+                *last_pts = Some(pts);
+            }
+        }
+
+        Ok(Some(frame))
+    }
+}
+
+struct Frame {
+    width: u32,
+    height: u32,
+    d3d_texture: ID3D11Texture2D,
+    gl_texture: GlTexture,
+    last_pts: Mutex<Option<i64>>,
+}
+
+unsafe impl Send for Frame {}
+unsafe impl Sync for Frame {}
+
+impl Frame {
+    /// Creates the frame's textures. This must be called on the raster thread.
+    fn new(
+        device: &ID3D11Device,
+        egl_manager: Arc<EglManager>,
+        width: u32,
+        height: u32,
+    ) -> eyre::Result<Frame> {
+        let desc = D3D11_TEXTURE2D_DESC {
+            Width: width,
+            Height: height,
+            MipLevels: 1,
+            ArraySize: 1,
+            Format: DXGI_FORMAT_B8G8R8A8_UNORM,
+            SampleDesc: DXGI_SAMPLE_DESC {
+                Count: 1,
+                Quality: 0,
+            },
+            Usage: D3D11_USAGE_DEFAULT,
+            BindFlags: (D3D11_BIND_RENDER_TARGET.0 | D3D11_BIND_SHADER_RESOURCE.0) as u32,
+            CPUAccessFlags: 0,
+            MiscFlags: 0,
+        };
+
+        let mut d3d_texture = None;
+        unsafe { device.CreateTexture2D(&desc, None, Some(&mut d3d_texture))? };
+        let d3d_texture = d3d_texture.ok_or_eyre("failed to create video texture")?;
+
+        let gl_texture = GlTexture::new(egl_manager, &d3d_texture)?;
+
+        Ok(Frame {
+            width,
+            height,
+            d3d_texture,
+            gl_texture,
+            last_pts: Mutex::new(None),
+        })
+    }
+}
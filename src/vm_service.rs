@@ -0,0 +1,76 @@
+use std::sync::OnceLock;
+
+use flutter_codec::EncodableValue;
+
+use crate::standard_method_channel::{StandardMethodHandler, StandardMethodReply};
+
+/// The URI of the Dart VM service, once the engine has reported it.
+static SERVICE_URI: OnceLock<String> = OnceLock::new();
+
+const LISTENING_PREFIX: &str = "The Dart VM service is listening on ";
+
+#[derive(Clone, Debug, Default)]
+pub struct VmServiceConfig {
+    pub enabled: bool,
+    pub host: Option<String>,
+    pub port: Option<u16>,
+}
+
+impl VmServiceConfig {
+    /// Returns the engine switches that apply this configuration.
+    pub fn engine_switches(&self) -> Vec<String> {
+        if !self.enabled {
+            return vec!["--disable-vm-service".to_owned()];
+        }
+
+        let mut switches = vec![];
+
+        if let Some(host) = &self.host {
+            switches.push(format!("--vm-service-host={host}"));
+        }
+
+        if let Some(port) = self.port {
+            switches.push(format!("--vm-service-port={port}"));
+        }
+
+        switches
+    }
+}
+
+/// Checks an engine log message for the VM service URI, recording it if found.
+pub fn capture_uri(message: &str) {
+    let Some(uri) = message
+        .lines()
+        .find_map(|line| line.trim().strip_prefix(LISTENING_PREFIX))
+    else {
+        return;
+    };
+
+    let uri = uri.trim();
+    if SERVICE_URI.set(uri.to_owned()).is_ok() {
+        tracing::info!(uri, "dart vm service available");
+    }
+}
+
+pub fn service_uri() -> Option<&'static str> {
+    SERVICE_URI.get().map(|uri| uri.as_str())
+}
+
+/// Lets tooling running inside the app discover the VM service URI so that it can attach
+/// DevTools without scraping logs.
+pub struct DevToolsHandler;
+
+impl StandardMethodHandler for DevToolsHandler {
+    fn handle(&self, method: &str, _args: EncodableValue, reply: StandardMethodReply) {
+        match method {
+            "getVmServiceUri" => match service_uri() {
+                Some(uri) => reply.success(&EncodableValue::Str(uri)),
+                None => reply.success(&EncodableValue::Null),
+            },
+            _ => {
+                tracing::warn!(method, "unimplemented");
+                reply.not_implemented();
+            }
+        }
+    }
+}
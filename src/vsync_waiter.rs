@@ -0,0 +1,361 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use color_eyre::eyre;
+use flutter_embedder::{FlutterEngineGetCurrentTime, FlutterEngineOnVsync};
+use windows::Win32::Foundation::{BOOL, HWND};
+use windows::Win32::Graphics::Dwm::{
+    DwmFlush, DwmGetCompositionTimingInfo, DwmGetWindowAttribute, DWMWA_CLOAKED, DWM_TIMING_INFO,
+};
+use windows::Win32::UI::WindowsAndMessaging::IsIconic;
+
+use crate::frame_stats;
+use crate::resize_controller::ResizeController;
+use crate::timeline;
+
+const DEFAULT_FRAME_INTERVAL: Duration = Duration::from_nanos(1_000_000_000 / 60);
+
+/// How often to check whether a hidden window has become visible again, in case nothing wakes
+/// the waiter up (e.g. when the window is uncloaked).
+const HIDDEN_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How the waiter decides when to start frames.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FramePacing {
+    /// Frames start at each display refresh.
+    #[default]
+    Vsync,
+    /// Frames start at display refreshes, at most this many times a second. Refreshes are skipped
+    /// until enough time has passed, so e.g. a cap of 30 renders every other frame at 60Hz. A cap
+    /// above the refresh rate has no effect.
+    Capped(u32),
+    /// Frames start as soon as the engine asks for them, for measuring the maximum rate that they
+    /// can be produced at. Frames that are presented faster than the display refreshes are never
+    /// seen.
+    Uncapped,
+}
+
+/// Answers vsync requests from the engine on a dedicated thread, synchronised to DWM composition.
+///
+/// Requests are held while the window is minimized or cloaked, or while the waiter is suspended
+/// (e.g. when the session is locked), which stops the engine from producing frames that would
+/// never be seen.
+pub struct VsyncWaiter {
+    hwnd: HWND,
+    resize_controller: Arc<ResizeController>,
+    pacing: FramePacing,
+    /// The refresh rate of the display that the window is on, if it isn't the one that DWM reports
+    /// timing info for.
+    refresh_rate: Mutex<Option<f64>>,
+    /// A lower cap than the pacing's, while power is being saved.
+    power_saving_max_fps: Mutex<Option<u32>>,
+    suspended: AtomicBool,
+    state: Mutex<State>,
+    condvar: Condvar,
+    thread: Mutex<Option<JoinHandle<()>>>,
+    /// The current time in engine nanoseconds, for waiters that are driven by
+    /// [`VsyncWaiter::advance`] rather than DWM.
+    virtual_clock: Option<Mutex<u64>>,
+}
+
+#[derive(Default)]
+struct State {
+    pending: Option<VsyncRequest>,
+    /// Incremented when pending requests are cancelled, so that a request that is already being
+    /// waited on can be dropped.
+    generation: u64,
+    stopped: bool,
+}
+
+struct VsyncRequest {
+    engine: flutter_embedder::FlutterEngine,
+    baton: isize,
+}
+
+unsafe impl Send for VsyncWaiter {}
+unsafe impl Sync for VsyncWaiter {}
+
+impl VsyncWaiter {
+    pub fn new(
+        hwnd: HWND,
+        resize_controller: Arc<ResizeController>,
+        pacing: FramePacing,
+    ) -> eyre::Result<Arc<VsyncWaiter>> {
+        let waiter = Arc::new(VsyncWaiter {
+            hwnd,
+            resize_controller,
+            pacing,
+            refresh_rate: Mutex::new(None),
+            power_saving_max_fps: Mutex::new(None),
+            suspended: AtomicBool::new(false),
+            state: Mutex::new(State::default()),
+            condvar: Condvar::new(),
+            thread: Mutex::new(None),
+            virtual_clock: None,
+        });
+
+        let thread = thread::Builder::new().name("vsync".to_owned()).spawn({
+            let waiter = waiter.clone();
+            move || waiter.run()
+        })?;
+
+        *waiter.thread.lock().unwrap() = Some(thread);
+
+        Ok(waiter)
+    }
+
+    /// Creates a waiter that only answers requests when [`VsyncWaiter::advance`] is called, with
+    /// timestamps from a virtual clock, so that animations progress deterministically.
+    #[allow(dead_code)] // Only used by the test harness.
+    pub fn with_virtual_clock(
+        hwnd: HWND,
+        resize_controller: Arc<ResizeController>,
+    ) -> Arc<VsyncWaiter> {
+        Arc::new(VsyncWaiter {
+            hwnd,
+            resize_controller,
+            pacing: FramePacing::Vsync,
+            refresh_rate: Mutex::new(None),
+            power_saving_max_fps: Mutex::new(None),
+            suspended: AtomicBool::new(false),
+            state: Mutex::new(State::default()),
+            condvar: Condvar::new(),
+            thread: Mutex::new(None),
+            virtual_clock: Some(Mutex::new(unsafe { FlutterEngineGetCurrentTime() })),
+        })
+    }
+
+    /// Answers the outstanding request, if there is one, with a frame that starts at the current
+    /// virtual time and lasts for `interval`, and advances the clock to the end of the frame.
+    ///
+    /// Returns false if the engine hasn't requested a frame.
+    #[allow(dead_code)] // Only used by the test harness.
+    pub fn advance(&self, interval: Duration) -> bool {
+        let Some(clock) = &self.virtual_clock else {
+            return false;
+        };
+
+        let mut state = self.state.lock().unwrap();
+        let Some(request) = state.pending.take() else {
+            return false;
+        };
+
+        let mut clock = clock.lock().unwrap();
+        let frame_start = *clock;
+        let frame_target = frame_start + interval.as_nanos() as u64;
+        *clock = frame_target;
+
+        frame_stats::record_vsync(frame_start, frame_target);
+
+        // The state lock is still held, for the same reason as in `run`.
+        unsafe {
+            FlutterEngineOnVsync(request.engine, request.baton, frame_start, frame_target);
+        }
+
+        true
+    }
+
+    /// Returns whether the engine is waiting for a vsync.
+    #[allow(dead_code)] // Only used by the test harness.
+    pub fn has_pending_request(&self) -> bool {
+        self.state.lock().unwrap().pending.is_some()
+    }
+
+    /// Queues a vsync request from the engine, to be answered at the next vblank.
+    pub fn request(&self, engine: flutter_embedder::FlutterEngine, baton: isize) {
+        self.state.lock().unwrap().pending = Some(VsyncRequest { engine, baton });
+        self.condvar.notify_all();
+    }
+
+    /// Drops any outstanding request. Once this returns the engine that made the request won't be
+    /// called into, so it can safely be shut down.
+    pub fn cancel(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.pending = None;
+        state.generation += 1;
+    }
+
+    /// Cancels any outstanding request and waits for the vsync thread to exit.
+    pub fn stop(&self) {
+        {
+            let mut state = self.state.lock().unwrap();
+            state.pending = None;
+            state.stopped = true;
+        }
+
+        self.condvar.notify_all();
+
+        if let Some(thread) = self.thread.lock().unwrap().take() {
+            if thread.join().is_err() {
+                tracing::error!("vsync thread panicked");
+            }
+        }
+    }
+
+    /// Sets the refresh rate used for frame intervals, e.g. when the window moves to another
+    /// display. If `None`, the rate that DWM composes at is used, which follows dynamic refresh
+    /// rate changes but is only accurate for the primary display.
+    pub fn set_refresh_rate(&self, refresh_rate: Option<f64>) {
+        *self.refresh_rate.lock().unwrap() = refresh_rate;
+    }
+
+    /// Caps the frame rate below the pacing's while set, e.g. when running on battery. Has no
+    /// effect on [`FramePacing::Uncapped`], which is only used for measurement.
+    pub fn set_power_saving_max_fps(&self, max_fps: Option<u32>) {
+        *self.power_saving_max_fps.lock().unwrap() = max_fps;
+    }
+
+    /// Holds requests until unsuspended, regardless of whether the window is visible.
+    pub fn set_suspended(&self, suspended: bool) {
+        self.suspended.store(suspended, Ordering::Relaxed);
+        self.wake();
+    }
+
+    fn frame_interval(&self) -> Duration {
+        match *self.refresh_rate.lock().unwrap() {
+            Some(rate) if rate > 0.0 => Duration::from_secs_f64(1.0 / rate),
+            _ => dwm_frame_interval(),
+        }
+    }
+
+    /// Wakes the waiter so that it re-checks the window's visibility, e.g. after it has been
+    /// restored.
+    pub fn wake(&self) {
+        self.condvar.notify_all();
+    }
+
+    fn run(&self) {
+        let mut last_frame_start = None;
+
+        loop {
+            let (request, generation) = {
+                let mut state = self.state.lock().unwrap();
+                loop {
+                    if state.stopped {
+                        return;
+                    }
+
+                    if state.pending.is_some() && !self.should_pause() {
+                        break (state.pending.take().unwrap(), state.generation);
+                    }
+
+                    state = if state.pending.is_some() {
+                        self.condvar
+                            .wait_timeout(state, HIDDEN_POLL_INTERVAL)
+                            .unwrap()
+                            .0
+                    } else {
+                        self.condvar.wait(state).unwrap()
+                    };
+                }
+            };
+
+            let interval = self.frame_interval();
+
+            self.wait_for_frame(interval, last_frame_start);
+            last_frame_start = Some(Instant::now());
+
+            let frame_start = unsafe { FlutterEngineGetCurrentTime() };
+            let frame_target = frame_start + interval.as_nanos() as u64;
+
+            frame_stats::record_vsync(frame_start, frame_target);
+
+            // The lock is held while calling into the engine so that `cancel` can't return while
+            // the request is being answered.
+            let state = self.state.lock().unwrap();
+            if state.stopped || state.generation != generation {
+                continue;
+            }
+
+            unsafe {
+                FlutterEngineOnVsync(request.engine, request.baton, frame_start, frame_target);
+            }
+
+            drop(state);
+        }
+    }
+
+    /// Blocks until the next frame should start, given the display's refresh interval and when
+    /// the previous frame started.
+    fn wait_for_frame(&self, interval: Duration, last_frame_start: Option<Instant>) {
+        let power_saving_max_fps = *self.power_saving_max_fps.lock().unwrap();
+        let max_fps = match self.pacing {
+            FramePacing::Vsync => power_saving_max_fps,
+            FramePacing::Capped(max_fps) => {
+                Some(power_saving_max_fps.map_or(max_fps, |cap| cap.min(max_fps)))
+            }
+            FramePacing::Uncapped => return,
+        };
+
+        let min_interval = match max_fps {
+            Some(max_fps) => Duration::from_secs(1) / max_fps.max(1),
+            None => Duration::ZERO,
+        };
+
+        let _span = timeline::span(c"WaitForVsync");
+
+        loop {
+            // Blocks until the next composition pass.
+            if let Err(e) = unsafe { DwmFlush() } {
+                tracing::warn!("DwmFlush failed: {e}");
+                return;
+            }
+
+            // Vblanks don't arrive at exactly the refresh interval, so a refresh is only skipped
+            // if the next one would still be close enough to the cap.
+            let Some(last_frame_start) = last_frame_start else {
+                return;
+            };
+
+            if last_frame_start.elapsed() + interval / 2 >= min_interval {
+                return;
+            }
+        }
+    }
+
+    fn should_pause(&self) -> bool {
+        if self.suspended.load(Ordering::Relaxed) {
+            return true;
+        }
+
+        // Frames must keep being produced while a resize is waiting for one, or the window would
+        // hang.
+        if self.resize_controller.current_resize().is_some() {
+            return false;
+        }
+
+        unsafe { IsIconic(self.hwnd).as_bool() || is_cloaked(self.hwnd) }
+    }
+}
+
+unsafe fn is_cloaked(hwnd: HWND) -> bool {
+    let mut cloaked = BOOL(0);
+    DwmGetWindowAttribute(
+        hwnd,
+        DWMWA_CLOAKED,
+        &mut cloaked as *mut BOOL as _,
+        std::mem::size_of::<BOOL>() as u32,
+    )
+    .is_ok()
+        && cloaked.0 != 0
+}
+
+fn dwm_frame_interval() -> Duration {
+    let mut info = DWM_TIMING_INFO {
+        cbSize: std::mem::size_of::<DWM_TIMING_INFO>() as u32,
+        ..Default::default()
+    };
+
+    if unsafe { DwmGetCompositionTimingInfo(HWND(0), &mut info) }.is_err() {
+        return DEFAULT_FRAME_INTERVAL;
+    }
+
+    let rate = info.rateRefresh;
+    if rate.uiNumerator == 0 {
+        return DEFAULT_FRAME_INTERVAL;
+    }
+
+    Duration::from_nanos(1_000_000_000 * rate.uiDenominator as u64 / rate.uiNumerator as u64)
+}
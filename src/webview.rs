@@ -0,0 +1,505 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use color_eyre::eyre::{self, OptionExt};
+use flutter_codec::EncodableValue;
+use webview2_com::Microsoft::Web::WebView2::Win32::{
+    CreateCoreWebView2EnvironmentWithOptions, ICoreWebView2, ICoreWebView2CompositionController,
+    ICoreWebView2Controller, ICoreWebView2Environment, ICoreWebView2Environment3,
+    COREWEBVIEW2_MOUSE_EVENT_KIND, COREWEBVIEW2_MOUSE_EVENT_KIND_LEAVE,
+    COREWEBVIEW2_MOUSE_EVENT_KIND_LEFT_BUTTON_DOWN, COREWEBVIEW2_MOUSE_EVENT_KIND_LEFT_BUTTON_UP,
+    COREWEBVIEW2_MOUSE_EVENT_KIND_MIDDLE_BUTTON_DOWN,
+    COREWEBVIEW2_MOUSE_EVENT_KIND_MIDDLE_BUTTON_UP, COREWEBVIEW2_MOUSE_EVENT_KIND_MOVE,
+    COREWEBVIEW2_MOUSE_EVENT_KIND_RIGHT_BUTTON_DOWN, COREWEBVIEW2_MOUSE_EVENT_KIND_RIGHT_BUTTON_UP,
+    COREWEBVIEW2_MOUSE_EVENT_KIND_WHEEL, COREWEBVIEW2_MOUSE_EVENT_VIRTUAL_KEYS_NONE,
+    COREWEBVIEW2_MOVE_FOCUS_REASON_PROGRAMMATIC,
+};
+use webview2_com::{
+    CreateCoreWebView2CompositionControllerCompletedHandler,
+    CreateCoreWebView2EnvironmentCompletedHandler, ExecuteScriptCompletedHandler,
+    NavigationCompletedEventHandler, WebMessageReceivedEventHandler,
+};
+use windows::core::{ComInterface, HSTRING, PCWSTR, PWSTR};
+use windows::Win32::Foundation::{BOOL, E_INVALIDARG, HWND, POINT, RECT};
+use windows::Win32::System::WinRT::EventRegistrationToken;
+use windows::UI::Composition::ContainerVisual;
+
+use crate::error_utils::ResultExt;
+use crate::event_channel::EventChannel;
+use crate::paths;
+use crate::platform_views::PlatformViewFactory;
+use crate::standard_method_channel::{StandardMethodHandler, StandardMethodReply};
+
+/// The platform view type used for web views.
+pub const VIEW_TYPE: &str = "flion/webview";
+
+/// Hosts WebView2 browsers in platform views. Each browser renders directly into its platform
+/// view's visual through a composition controller, so input has to be forwarded explicitly over
+/// the `flion/webview` channel.
+pub struct WebViews {
+    hwnd: HWND,
+    environment: RefCell<EnvironmentState>,
+    views: RefCell<BTreeMap<i64, Rc<WebView>>>,
+    events: Rc<EventChannel>,
+}
+
+enum EnvironmentState {
+    NotCreated,
+    Creating,
+    Ready(ICoreWebView2Environment3),
+    Failed,
+}
+
+struct WebView {
+    id: i64,
+    visual: ContainerVisual,
+    state: RefCell<WebViewState>,
+}
+
+#[derive(Default)]
+struct WebViewState {
+    browser: Option<Browser>,
+    /// Set while the browser is being created.
+    creating_browser: bool,
+    /// Applied once the browser has been created.
+    pending_url: Option<String>,
+    size: (i32, i32),
+    disposed: bool,
+}
+
+struct Browser {
+    composition_controller: ICoreWebView2CompositionController,
+    controller: ICoreWebView2Controller,
+    webview: ICoreWebView2,
+}
+
+impl WebViews {
+    pub fn new(hwnd: HWND, events: Rc<EventChannel>) -> Rc<WebViews> {
+        Rc::new(WebViews {
+            hwnd,
+            environment: RefCell::new(EnvironmentState::NotCreated),
+            views: RefCell::new(BTreeMap::new()),
+            events,
+        })
+    }
+
+    fn ensure_environment(self: &Rc<Self>) -> eyre::Result<()> {
+        match &*self.environment.borrow() {
+            EnvironmentState::NotCreated => {}
+            EnvironmentState::Creating => return Ok(()),
+            EnvironmentState::Ready(environment) => {
+                for view in self.views.borrow().values() {
+                    self.create_browser(environment, view)?;
+                }
+                return Ok(());
+            }
+            EnvironmentState::Failed => eyre::bail!("webview2 is unavailable"),
+        }
+
+        *self.environment.borrow_mut() = EnvironmentState::Creating;
+
+        let user_data_dir = HSTRING::from(paths::app_data_dir()?.join("WebView2").as_os_str());
+
+        let handler = CreateCoreWebView2EnvironmentCompletedHandler::create(Box::new({
+            let this = self.clone();
+            move |result, environment: Option<ICoreWebView2Environment>| {
+                let environment = result.map_err(eyre::Report::from).and_then(|_| {
+                    Ok(environment
+                        .ok_or_eyre("no environment was created")?
+                        .cast::<ICoreWebView2Environment3>()?)
+                });
+
+                match environment {
+                    Ok(environment) => {
+                        *this.environment.borrow_mut() = EnvironmentState::Ready(environment);
+                        let _ = this.ensure_environment().trace_err();
+                    }
+                    Err(e) => {
+                        tracing::error!("failed to create webview2 environment: {e:?}");
+                        *this.environment.borrow_mut() = EnvironmentState::Failed;
+                    }
+                }
+
+                Ok(())
+            }
+        }));
+
+        unsafe {
+            CreateCoreWebView2EnvironmentWithOptions(
+                PCWSTR::null(),
+                &user_data_dir,
+                None,
+                &handler,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn create_browser(
+        self: &Rc<Self>,
+        environment: &ICoreWebView2Environment3,
+        view: &Rc<WebView>,
+    ) -> eyre::Result<()> {
+        let mut state = view.state.borrow_mut();
+        if state.browser.is_some() || state.creating_browser || state.disposed {
+            return Ok(());
+        }
+
+        let handler = CreateCoreWebView2CompositionControllerCompletedHandler::create(Box::new({
+            let this = self.clone();
+            let view = view.clone();
+            move |result, composition_controller: Option<ICoreWebView2CompositionController>| {
+                let res = result.map_err(eyre::Report::from).and_then(|_| {
+                    let composition_controller =
+                        composition_controller.ok_or_eyre("no controller was created")?;
+                    this.attach_browser(&view, composition_controller)
+                });
+
+                if let Err(e) = res {
+                    tracing::error!(id = view.id, "failed to create webview: {e:?}");
+                }
+
+                Ok(())
+            }
+        }));
+
+        unsafe { environment.CreateCoreWebView2CompositionController(self.hwnd, &handler)? };
+
+        state.creating_browser = true;
+
+        Ok(())
+    }
+
+    fn attach_browser(
+        &self,
+        view: &WebView,
+        composition_controller: ICoreWebView2CompositionController,
+    ) -> eyre::Result<()> {
+        let controller = composition_controller.cast::<ICoreWebView2Controller>()?;
+
+        let mut state = view.state.borrow_mut();
+        state.creating_browser = false;
+
+        if state.disposed {
+            unsafe { controller.Close()? };
+            return Ok(());
+        }
+
+        let webview = unsafe {
+            composition_controller.SetRootVisualTarget(&view.visual)?;
+            controller.SetBounds(bounds(state.size))?;
+            controller.SetIsVisible(true)?;
+            controller.CoreWebView2()?
+        };
+
+        self.add_event_handlers(view.id, &webview)?;
+
+        if let Some(url) = state.pending_url.take() {
+            unsafe { webview.Navigate(&HSTRING::from(url))? };
+        }
+
+        state.browser = Some(Browser {
+            composition_controller,
+            controller,
+            webview,
+        });
+
+        Ok(())
+    }
+
+    fn add_event_handlers(&self, id: i64, webview: &ICoreWebView2) -> eyre::Result<()> {
+        let mut token = EventRegistrationToken::default();
+
+        let handler = WebMessageReceivedEventHandler::create(Box::new({
+            let events = self.events.clone();
+            move |_, args| {
+                let Some(args) = args else {
+                    return Ok(());
+                };
+
+                let mut message = PWSTR::null();
+                unsafe { args.TryGetWebMessageAsString(&mut message)? };
+                let message = webview2_com::take_pwstr(message);
+
+                let _ = events
+                    .send(&event(
+                        "message",
+                        id,
+                        [("message", EncodableValue::Str(&message))],
+                    ))
+                    .trace_err();
+
+                Ok(())
+            }
+        }));
+
+        unsafe { webview.add_WebMessageReceived(&handler, &mut token)? };
+
+        let handler = NavigationCompletedEventHandler::create(Box::new({
+            let events = self.events.clone();
+            move |webview: Option<ICoreWebView2>, args| {
+                let (Some(webview), Some(args)) = (webview, args) else {
+                    return Ok(());
+                };
+
+                let mut success = BOOL::default();
+                let mut url = PWSTR::null();
+                unsafe {
+                    args.IsSuccess(&mut success)?;
+                    webview.Source(&mut url)?;
+                }
+                let url = webview2_com::take_pwstr(url);
+
+                let _ = events
+                    .send(&event(
+                        "navigationCompleted",
+                        id,
+                        [
+                            ("url", EncodableValue::Str(&url)),
+                            ("success", EncodableValue::Bool(success.as_bool())),
+                        ],
+                    ))
+                    .trace_err();
+
+                Ok(())
+            }
+        }));
+
+        unsafe { webview.add_NavigationCompleted(&handler, &mut token)? };
+
+        Ok(())
+    }
+
+    fn view(&self, id: i64) -> Option<Rc<WebView>> {
+        self.views.borrow().get(&id).cloned()
+    }
+}
+
+fn bounds((width, height): (i32, i32)) -> RECT {
+    RECT {
+        left: 0,
+        top: 0,
+        right: width,
+        bottom: height,
+    }
+}
+
+fn event<'a, const N: usize>(
+    event_type: &'a str,
+    id: i64,
+    fields: [(&'a str, EncodableValue<'a>); N],
+) -> EncodableValue<'a> {
+    let mut event = BTreeMap::new();
+    event.insert(EncodableValue::Str("type"), EncodableValue::Str(event_type));
+    event.insert(EncodableValue::Str("id"), EncodableValue::I64(id));
+    for (key, value) in fields {
+        event.insert(EncodableValue::Str(key), value);
+    }
+    EncodableValue::Map(event)
+}
+
+/// Creates web views for `flutter/platform_views`.
+pub struct WebViewFactory(pub Rc<WebViews>);
+
+impl PlatformViewFactory for WebViewFactory {
+    fn create(
+        &self,
+        id: i64,
+        visual: &ContainerVisual,
+        params: &EncodableValue,
+    ) -> eyre::Result<()> {
+        let view = Rc::new(WebView {
+            id,
+            visual: visual.clone(),
+            state: RefCell::new(WebViewState {
+                pending_url: params
+                    .get("url")
+                    .and_then(|v| v.as_string())
+                    .map(str::to_owned),
+                ..Default::default()
+            }),
+        });
+
+        self.0.views.borrow_mut().insert(id, view);
+        self.0.ensure_environment()
+    }
+
+    fn dispose(&self, id: i64) {
+        let Some(view) = self.0.views.borrow_mut().remove(&id) else {
+            return;
+        };
+
+        let mut state = view.state.borrow_mut();
+        state.disposed = true;
+
+        if let Some(browser) = state.browser.take() {
+            let _ = unsafe { browser.controller.Close() }.trace_err();
+        }
+    }
+}
+
+/// Handles `flion/webview`, which controls navigation, scripting and input for web views.
+pub struct WebViewHandler(pub Rc<WebViews>);
+
+impl StandardMethodHandler for WebViewHandler {
+    fn handle(&self, method: &str, args: EncodableValue, reply: StandardMethodReply) {
+        let Some(id) = args.get("id").and_then(|v| v.as_int()) else {
+            return reply.error("invalid_args", Some("expected an id"));
+        };
+
+        let Some(view) = self.0.view(id) else {
+            return reply.error("invalid_args", Some("unknown web view"));
+        };
+
+        let mut state = view.state.borrow_mut();
+
+        // Resizing and navigating can be done before the browser has been created.
+        match method {
+            "resize" => {
+                let (Some(width), Some(height)) = (
+                    args.get("width").and_then(|v| v.as_f64()),
+                    args.get("height").and_then(|v| v.as_f64()),
+                ) else {
+                    return reply.error("invalid_args", Some("expected a size"));
+                };
+
+                state.size = (width as i32, height as i32);
+
+                let res = match &state.browser {
+                    Some(browser) => unsafe { browser.controller.SetBounds(bounds(state.size)) },
+                    None => Ok(()),
+                };
+
+                return match res {
+                    Ok(()) => reply.success(&EncodableValue::Null),
+                    Err(e) => reply.error("webview_error", Some(&e.to_string())),
+                };
+            }
+            "navigate" if state.browser.is_none() => {
+                let Some(url) = args.get("url").and_then(|v| v.as_string()) else {
+                    return reply.error("invalid_args", Some("expected a url"));
+                };
+
+                state.pending_url = Some(url.to_owned());
+                return reply.success(&EncodableValue::Null);
+            }
+            _ => {}
+        }
+
+        let Some(browser) = &state.browser else {
+            return reply.error("not_ready", Some("the web view has not been created yet"));
+        };
+
+        let webview = &browser.webview;
+
+        let res = unsafe {
+            match method {
+                "navigate" => {
+                    let Some(url) = args.get("url").and_then(|v| v.as_string()) else {
+                        return reply.error("invalid_args", Some("expected a url"));
+                    };
+
+                    webview.Navigate(&HSTRING::from(url))
+                }
+                "reload" => webview.Reload(),
+                "goBack" => webview.GoBack(),
+                "goForward" => webview.GoForward(),
+                "postMessage" => {
+                    let Some(message) = args.get("message").and_then(|v| v.as_string()) else {
+                        return reply.error("invalid_args", Some("expected a message"));
+                    };
+
+                    webview.PostWebMessageAsString(&HSTRING::from(message))
+                }
+                "executeScript" => {
+                    let Some(script) = args.get("script").and_then(|v| v.as_string()) else {
+                        return reply.error("invalid_args", Some("expected a script"));
+                    };
+
+                    // The reply is normally sent by the completion handler, which isn't called if
+                    // the script couldn't be started.
+                    let reply = Rc::new(RefCell::new(Some(reply)));
+
+                    let handler = ExecuteScriptCompletedHandler::create(Box::new({
+                        let reply = reply.clone();
+                        move |result, json: String| {
+                            if let Some(reply) = reply.borrow_mut().take() {
+                                match result {
+                                    Ok(()) => reply.success(&EncodableValue::Str(&json)),
+                                    Err(e) => reply.error("script_error", Some(&e.to_string())),
+                                }
+                            }
+                            Ok(())
+                        }
+                    }));
+
+                    if let Err(e) = webview.ExecuteScript(&HSTRING::from(script), &handler) {
+                        tracing::error!("failed to execute script: {e}");
+                        if let Some(reply) = reply.borrow_mut().take() {
+                            reply.error("script_error", Some(&e.to_string()));
+                        }
+                    }
+
+                    return;
+                }
+                "sendPointerEvent" => send_pointer_event(browser, &args),
+                "focus" => browser
+                    .controller
+                    .MoveFocus(COREWEBVIEW2_MOVE_FOCUS_REASON_PROGRAMMATIC),
+                _ => {
+                    tracing::warn!(method, "unimplemented");
+                    return reply.not_implemented();
+                }
+            }
+        };
+
+        match res {
+            Ok(()) => reply.success(&EncodableValue::Null),
+            Err(e) => reply.error("webview_error", Some(&e.to_string())),
+        }
+    }
+}
+
+/// Forwards a pointer event from the framework. Coordinates are in physical pixels, relative to
+/// the view.
+unsafe fn send_pointer_event(
+    browser: &Browser,
+    args: &EncodableValue,
+) -> windows::core::Result<()> {
+    let x = args.get("x").and_then(|v| v.as_f64()).unwrap_or(0.0);
+    let y = args.get("y").and_then(|v| v.as_f64()).unwrap_or(0.0);
+    let button = args.get("button").and_then(|v| v.as_string());
+
+    let kind: COREWEBVIEW2_MOUSE_EVENT_KIND =
+        match (args.get("kind").and_then(|v| v.as_string()), button) {
+            (Some("move"), _) => COREWEBVIEW2_MOUSE_EVENT_KIND_MOVE,
+            (Some("down"), Some("right")) => COREWEBVIEW2_MOUSE_EVENT_KIND_RIGHT_BUTTON_DOWN,
+            (Some("down"), Some("middle")) => COREWEBVIEW2_MOUSE_EVENT_KIND_MIDDLE_BUTTON_DOWN,
+            (Some("down"), _) => COREWEBVIEW2_MOUSE_EVENT_KIND_LEFT_BUTTON_DOWN,
+            (Some("up"), Some("right")) => COREWEBVIEW2_MOUSE_EVENT_KIND_RIGHT_BUTTON_UP,
+            (Some("up"), Some("middle")) => COREWEBVIEW2_MOUSE_EVENT_KIND_MIDDLE_BUTTON_UP,
+            (Some("up"), _) => COREWEBVIEW2_MOUSE_EVENT_KIND_LEFT_BUTTON_UP,
+            (Some("wheel"), _) => COREWEBVIEW2_MOUSE_EVENT_KIND_WHEEL,
+            (Some("leave"), _) => COREWEBVIEW2_MOUSE_EVENT_KIND_LEAVE,
+            _ => return Err(E_INVALIDARG.into()),
+        };
+
+    // For wheel events this is the scroll amount, in multiples of WHEEL_DELTA.
+    let mouse_data = args
+        .get("delta")
+        .and_then(|v| v.as_f64())
+        .map(|delta| delta as i32 as u32)
+        .unwrap_or(0);
+
+    browser.composition_controller.SendMouseInput(
+        kind,
+        COREWEBVIEW2_MOUSE_EVENT_VIRTUAL_KEYS_NONE,
+        mouse_data,
+        POINT {
+            x: x as i32,
+            y: y as i32,
+        },
+    )
+}
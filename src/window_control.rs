@@ -0,0 +1,295 @@
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use color_eyre::eyre;
+use flutter_codec::EncodableValue;
+use windows::Win32::Foundation::HWND;
+use windows::Win32::UI::WindowsAndMessaging::{
+    SetWindowDisplayAffinity, WDA_EXCLUDEFROMCAPTURE, WDA_NONE,
+};
+use winit::dpi::{LogicalPosition, LogicalSize};
+use winit::window::{Fullscreen, Window, WindowLevel};
+
+use crate::size_constraints::{Size, SizeConstraints};
+use crate::standard_method_channel::{StandardMethodHandler, StandardMethodReply};
+use crate::window_effects::{self, BorderColor, CornerPreference};
+
+#[derive(Clone, Copy, Debug)]
+pub struct WindowBounds {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+/// Programmatic control over the window. All coordinates are in logical pixels.
+#[derive(Clone)]
+pub struct WindowController {
+    window: Rc<Window>,
+    hwnd: HWND,
+    size_constraints: Rc<Cell<SizeConstraints>>,
+}
+
+impl WindowController {
+    pub fn new(
+        window: Rc<Window>,
+        hwnd: HWND,
+        size_constraints: SizeConstraints,
+    ) -> WindowController {
+        WindowController {
+            window,
+            hwnd,
+            size_constraints: Rc::new(Cell::new(size_constraints)),
+        }
+    }
+
+    pub fn show(&self) {
+        self.window.set_visible(true);
+        self.window.focus_window();
+    }
+
+    pub fn hide(&self) {
+        self.window.set_visible(false);
+    }
+
+    pub fn minimize(&self) {
+        self.window.set_minimized(true);
+    }
+
+    pub fn maximize(&self) {
+        self.window.set_maximized(true);
+    }
+
+    pub fn restore(&self) {
+        self.window.set_minimized(false);
+        self.window.set_maximized(false);
+    }
+
+    pub fn is_minimized(&self) -> bool {
+        self.window.is_minimized().unwrap_or(false)
+    }
+
+    pub fn is_maximized(&self) -> bool {
+        self.window.is_maximized()
+    }
+
+    pub fn set_fullscreen(&self, fullscreen: bool) {
+        self.window
+            .set_fullscreen(fullscreen.then_some(Fullscreen::Borderless(None)));
+    }
+
+    pub fn is_fullscreen(&self) -> bool {
+        self.window.fullscreen().is_some()
+    }
+
+    pub fn set_always_on_top(&self, always_on_top: bool) {
+        self.window.set_window_level(if always_on_top {
+            WindowLevel::AlwaysOnTop
+        } else {
+            WindowLevel::Normal
+        });
+    }
+
+    pub fn set_corner_preference(&self, preference: CornerPreference) -> eyre::Result<()> {
+        window_effects::set_corner_preference(self.hwnd, preference)
+    }
+
+    pub fn set_border_color(&self, color: BorderColor) -> eyre::Result<()> {
+        window_effects::set_border_color(self.hwnd, color)
+    }
+
+    /// Hides the window's content from screenshots, screen recordings and screen sharing. The
+    /// window is left out of captures entirely on Windows 10 2004 and later, and is shown as black
+    /// on earlier versions.
+    pub fn set_content_protection(&self, protected: bool) -> eyre::Result<()> {
+        let affinity = if protected {
+            WDA_EXCLUDEFROMCAPTURE
+        } else {
+            WDA_NONE
+        };
+        unsafe { SetWindowDisplayAffinity(self.hwnd, affinity)? };
+        Ok(())
+    }
+
+    pub fn bounds(&self) -> WindowBounds {
+        let scale_factor = self.window.scale_factor();
+        let position = self
+            .window
+            .outer_position()
+            .unwrap_or_default()
+            .to_logical::<f64>(scale_factor);
+        let size = self.window.outer_size().to_logical::<f64>(scale_factor);
+        WindowBounds {
+            x: position.x,
+            y: position.y,
+            width: size.width,
+            height: size.height,
+        }
+    }
+
+    pub fn set_position(&self, x: f64, y: f64) {
+        self.window.set_outer_position(LogicalPosition::new(x, y));
+    }
+
+    pub fn set_size(&self, width: f64, height: f64) {
+        let _ = self
+            .window
+            .request_inner_size(LogicalSize::new(width, height));
+    }
+
+    pub fn size_constraints(&self) -> SizeConstraints {
+        self.size_constraints.get()
+    }
+
+    pub fn set_size_constraints(&self, size_constraints: SizeConstraints) {
+        self.size_constraints.set(size_constraints);
+
+        // Nudge the window so that the new constraints are applied to the current size.
+        let size = self.window.inner_size();
+        let _ = self.window.request_inner_size(size);
+    }
+}
+
+pub struct WindowControlHandler {
+    controller: WindowController,
+}
+
+impl WindowControlHandler {
+    pub fn new(controller: WindowController) -> WindowControlHandler {
+        WindowControlHandler { controller }
+    }
+}
+
+impl StandardMethodHandler for WindowControlHandler {
+    fn handle(&self, method: &str, args: EncodableValue, reply: StandardMethodReply) {
+        let controller = &self.controller;
+        let bool_arg = || args.get("value").and_then(|v| v.as_bool());
+
+        match method {
+            "show" => controller.show(),
+            "hide" => controller.hide(),
+            "minimize" => controller.minimize(),
+            "maximize" => controller.maximize(),
+            "restore" => controller.restore(),
+            "isMinimized" => {
+                return reply.success(&EncodableValue::Bool(controller.is_minimized()));
+            }
+            "isMaximized" => {
+                return reply.success(&EncodableValue::Bool(controller.is_maximized()));
+            }
+            "isFullScreen" => {
+                return reply.success(&EncodableValue::Bool(controller.is_fullscreen()));
+            }
+            "setFullScreen" => {
+                let Some(value) = bool_arg() else {
+                    return reply.error("invalid_args", Some("expected a bool value"));
+                };
+                controller.set_fullscreen(value);
+            }
+            "setAlwaysOnTop" => {
+                let Some(value) = bool_arg() else {
+                    return reply.error("invalid_args", Some("expected a bool value"));
+                };
+                controller.set_always_on_top(value);
+            }
+            "setContentProtection" => {
+                let Some(value) = bool_arg() else {
+                    return reply.error("invalid_args", Some("expected a bool value"));
+                };
+
+                if let Err(e) = controller.set_content_protection(value) {
+                    return reply.error("window_error", Some(&e.to_string()));
+                }
+            }
+            "setCornerPreference" => {
+                let Some(preference) = args
+                    .get("value")
+                    .and_then(|v| v.as_string())
+                    .and_then(CornerPreference::from_name)
+                else {
+                    return reply.error("invalid_args", Some("unknown corner preference"));
+                };
+
+                if let Err(e) = controller.set_corner_preference(preference) {
+                    return reply.error("dwm_error", Some(&e.to_string()));
+                }
+            }
+            "setBorderColor" => {
+                // A color as 0xAARRGGBB (e.g. `Color.value` in Dart), whose alpha is ignored,
+                // "none" to remove the border, or null for the default.
+                let color = match args.get("value") {
+                    None | Some(EncodableValue::Null) => BorderColor::Default,
+                    Some(EncodableValue::Str("none")) => BorderColor::None,
+                    Some(value) => match value.as_int() {
+                        Some(argb) => BorderColor::Rgb(argb as u32 & 0xffffff),
+                        None => return reply.error("invalid_args", Some("expected a color")),
+                    },
+                };
+
+                if let Err(e) = controller.set_border_color(color) {
+                    return reply.error("dwm_error", Some(&e.to_string()));
+                }
+            }
+            "getBounds" => {
+                let bounds = controller.bounds();
+                return reply.success(&EncodableValue::Map(BTreeMap::from_iter([
+                    (
+                        EncodableValue::Str("x"),
+                        EncodableValue::F64(bounds.x.into()),
+                    ),
+                    (
+                        EncodableValue::Str("y"),
+                        EncodableValue::F64(bounds.y.into()),
+                    ),
+                    (
+                        EncodableValue::Str("width"),
+                        EncodableValue::F64(bounds.width.into()),
+                    ),
+                    (
+                        EncodableValue::Str("height"),
+                        EncodableValue::F64(bounds.height.into()),
+                    ),
+                ])));
+            }
+            "setMinSize" | "setMaxSize" => {
+                let number = |key| args.get(key).and_then(|v| v.as_f64());
+                let size = match (number("width"), number("height")) {
+                    (Some(width), Some(height)) => Some(Size { width, height }),
+                    _ => None,
+                };
+
+                let mut constraints = controller.size_constraints();
+                if method == "setMinSize" {
+                    constraints.min_size = size;
+                } else {
+                    constraints.max_size = size;
+                }
+
+                controller.set_size_constraints(constraints);
+            }
+            "setAspectRatio" => {
+                let mut constraints = controller.size_constraints();
+                constraints.aspect_ratio = args.get("value").and_then(|v| v.as_f64());
+                controller.set_size_constraints(constraints);
+            }
+            "setBounds" => {
+                let number = |key| args.get(key).and_then(|v| v.as_f64());
+
+                if let (Some(x), Some(y)) = (number("x"), number("y")) {
+                    controller.set_position(x, y);
+                }
+
+                if let (Some(width), Some(height)) = (number("width"), number("height")) {
+                    controller.set_size(width, height);
+                }
+            }
+            _ => {
+                tracing::warn!(method, "unimplemented");
+                return reply.not_implemented();
+            }
+        }
+
+        reply.success(&EncodableValue::Null);
+    }
+}
@@ -0,0 +1,303 @@
+use std::cell::Cell;
+use std::ffi::c_void;
+use std::mem;
+use std::rc::Rc;
+use std::str::FromStr;
+
+use clap::ValueEnum;
+use color_eyre::eyre;
+use flutter_codec::EncodableValue;
+use windows::Win32::Foundation::{BOOL, COLORREF, HWND};
+use windows::Win32::Graphics::Dwm::{
+    DwmSetWindowAttribute, DWMSBT_MAINWINDOW, DWMSBT_NONE, DWMSBT_TABBEDWINDOW,
+    DWMSBT_TRANSIENTWINDOW, DWMWA_BORDER_COLOR, DWMWA_SYSTEMBACKDROP_TYPE,
+    DWMWA_USE_IMMERSIVE_DARK_MODE, DWMWA_WINDOW_CORNER_PREFERENCE, DWMWCP_DEFAULT,
+    DWMWCP_DONOTROUND, DWMWCP_ROUND, DWMWCP_ROUNDSMALL, DWM_SYSTEMBACKDROP_TYPE,
+    DWM_WINDOW_CORNER_PREFERENCE,
+};
+
+use crate::settings;
+use crate::standard_method_channel::{StandardMethodHandler, StandardMethodReply};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Backdrop {
+    None,
+    #[default]
+    Mica,
+    MicaAlt,
+    Acrylic,
+}
+
+impl Backdrop {
+    fn from_name(name: &str) -> Option<Backdrop> {
+        Some(match name {
+            "none" => Backdrop::None,
+            "mica" => Backdrop::Mica,
+            "micaAlt" => Backdrop::MicaAlt,
+            "acrylic" => Backdrop::Acrylic,
+            _ => return None,
+        })
+    }
+
+    fn to_dwm(self) -> DWM_SYSTEMBACKDROP_TYPE {
+        match self {
+            Backdrop::None => DWMSBT_NONE,
+            Backdrop::Mica => DWMSBT_MAINWINDOW,
+            Backdrop::MicaAlt => DWMSBT_TABBEDWINDOW,
+            Backdrop::Acrylic => DWMSBT_TRANSIENTWINDOW,
+        }
+    }
+}
+
+pub fn set_backdrop(hwnd: HWND, backdrop: Backdrop) -> eyre::Result<()> {
+    let backdrop = backdrop.to_dwm();
+    unsafe {
+        DwmSetWindowAttribute(
+            hwnd,
+            DWMWA_SYSTEMBACKDROP_TYPE,
+            &backdrop as *const DWM_SYSTEMBACKDROP_TYPE as *const c_void,
+            mem::size_of::<DWM_SYSTEMBACKDROP_TYPE>() as u32,
+        )?;
+    }
+    Ok(())
+}
+
+/// Switches the window frame (and with it the backdrop material) between the dark and light
+/// variants.
+pub fn set_dark_mode(hwnd: HWND, dark: bool) -> eyre::Result<()> {
+    let value = BOOL::from(dark);
+    unsafe {
+        DwmSetWindowAttribute(
+            hwnd,
+            DWMWA_USE_IMMERSIVE_DARK_MODE,
+            &value as *const BOOL as *const c_void,
+            mem::size_of::<BOOL>() as u32,
+        )?;
+    }
+    Ok(())
+}
+
+/// How the corners of the window are rounded on Windows 11. Earlier versions always have square
+/// corners.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum CornerPreference {
+    /// Let the system decide, which rounds the corners of most windows.
+    #[default]
+    Default,
+    Round,
+    RoundSmall,
+    Square,
+}
+
+impl CornerPreference {
+    pub fn from_name(name: &str) -> Option<CornerPreference> {
+        Some(match name {
+            "default" => CornerPreference::Default,
+            "round" => CornerPreference::Round,
+            "roundSmall" => CornerPreference::RoundSmall,
+            "square" => CornerPreference::Square,
+            _ => return None,
+        })
+    }
+
+    fn to_dwm(self) -> DWM_WINDOW_CORNER_PREFERENCE {
+        match self {
+            CornerPreference::Default => DWMWCP_DEFAULT,
+            CornerPreference::Round => DWMWCP_ROUND,
+            CornerPreference::RoundSmall => DWMWCP_ROUNDSMALL,
+            CornerPreference::Square => DWMWCP_DONOTROUND,
+        }
+    }
+}
+
+pub fn set_corner_preference(hwnd: HWND, preference: CornerPreference) -> eyre::Result<()> {
+    let preference = preference.to_dwm();
+    unsafe {
+        DwmSetWindowAttribute(
+            hwnd,
+            DWMWA_WINDOW_CORNER_PREFERENCE,
+            &preference as *const DWM_WINDOW_CORNER_PREFERENCE as *const c_void,
+            mem::size_of::<DWM_WINDOW_CORNER_PREFERENCE>() as u32,
+        )?;
+    }
+    Ok(())
+}
+
+const DWMWA_COLOR_DEFAULT: u32 = 0xFFFFFFFF;
+const DWMWA_COLOR_NONE: u32 = 0xFFFFFFFE;
+
+/// The color of the window's border on Windows 11.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BorderColor {
+    /// The system's border color, which follows the accent color setting.
+    #[default]
+    Default,
+    /// No border is drawn.
+    None,
+    /// An opaque color, as `0xRRGGBB`.
+    Rgb(u32),
+}
+
+impl BorderColor {
+    fn to_colorref(self) -> COLORREF {
+        COLORREF(match self {
+            BorderColor::Default => DWMWA_COLOR_DEFAULT,
+            BorderColor::None => DWMWA_COLOR_NONE,
+            // COLORREF is 0x00BBGGRR.
+            BorderColor::Rgb(rgb) => {
+                let [b, g, r, _] = rgb.to_le_bytes();
+                u32::from_le_bytes([r, g, b, 0])
+            }
+        })
+    }
+}
+
+impl FromStr for BorderColor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "default" => return Ok(BorderColor::Default),
+            "none" => return Ok(BorderColor::None),
+            _ => {}
+        }
+
+        let hex = s.trim_start_matches('#');
+        if hex.len() != 6 {
+            return Err(format!(
+                "expected #RRGGBB, 'default' or 'none', found '{s}'"
+            ));
+        }
+
+        u32::from_str_radix(hex, 16)
+            .map(BorderColor::Rgb)
+            .map_err(|e| e.to_string())
+    }
+}
+
+pub fn set_border_color(hwnd: HWND, color: BorderColor) -> eyre::Result<()> {
+    let color = color.to_colorref();
+    unsafe {
+        DwmSetWindowAttribute(
+            hwnd,
+            DWMWA_BORDER_COLOR,
+            &color as *const COLORREF as *const c_void,
+            mem::size_of::<COLORREF>() as u32,
+        )?;
+    }
+    Ok(())
+}
+
+/// The window's backdrop and frame theme, which follow the system theme unless the app has chosen
+/// a dark or light variant.
+#[derive(Clone)]
+pub struct WindowEffects {
+    hwnd: HWND,
+    backdrop: Rc<Cell<Backdrop>>,
+    /// The variant chosen by the app, or `None` to follow the system theme.
+    dark: Rc<Cell<Option<bool>>>,
+    /// Set while power is being saved, when the backdrop is turned off since DWM has to keep
+    /// blurring what is behind the window.
+    power_saving: Rc<Cell<bool>>,
+}
+
+impl WindowEffects {
+    pub fn new(hwnd: HWND, backdrop: Backdrop) -> WindowEffects {
+        WindowEffects {
+            hwnd,
+            backdrop: Rc::new(Cell::new(backdrop)),
+            dark: Rc::new(Cell::new(None)),
+            power_saving: Rc::new(Cell::new(false)),
+        }
+    }
+
+    /// Applies the backdrop, matching the dark/light variant to the system theme unless the app
+    /// has chosen one.
+    pub fn apply(&self) -> eyre::Result<()> {
+        let dark = match self.dark.get() {
+            Some(dark) => dark,
+            None => !settings::apps_use_light_theme()?,
+        };
+
+        let backdrop = if self.power_saving.get() {
+            Backdrop::None
+        } else {
+            self.backdrop.get()
+        };
+
+        set_dark_mode(self.hwnd, dark)?;
+        set_backdrop(self.hwnd, backdrop)
+    }
+
+    pub fn set_effect(&self, backdrop: Backdrop, dark: Option<bool>) -> eyre::Result<()> {
+        self.backdrop.set(backdrop);
+        self.dark.set(dark);
+        self.apply()
+    }
+
+    /// Turns the backdrop off while power is being saved. The app's choice is kept, and restored
+    /// afterwards.
+    pub fn set_power_saving(&self, power_saving: bool) -> eyre::Result<()> {
+        if self.power_saving.replace(power_saving) == power_saving {
+            return Ok(());
+        }
+
+        self.apply()
+    }
+
+    /// Handles the system theme changing (`WM_SETTINGCHANGE` with "ImmersiveColorSet"). The
+    /// backdrop is reapplied along with the frame, since the material doesn't always pick up the
+    /// new variant otherwise.
+    pub fn handle_theme_changed(&self) -> eyre::Result<()> {
+        if self.dark.get().is_some() {
+            return Ok(());
+        }
+
+        self.apply()
+    }
+}
+
+pub struct WindowEffectsHandler {
+    effects: WindowEffects,
+}
+
+impl WindowEffectsHandler {
+    pub fn new(effects: WindowEffects) -> WindowEffectsHandler {
+        WindowEffectsHandler { effects }
+    }
+}
+
+impl StandardMethodHandler for WindowEffectsHandler {
+    fn handle(&self, method: &str, args: EncodableValue, reply: StandardMethodReply) {
+        match method {
+            "setEffect" => {
+                let Some(args) = args.as_map() else {
+                    return reply.error("invalid_args", Some("expected a map"));
+                };
+
+                let Some(backdrop) = args
+                    .get(&EncodableValue::Str("effect"))
+                    .and_then(|v| v.as_string())
+                    .and_then(Backdrop::from_name)
+                else {
+                    return reply.error("invalid_args", Some("unknown effect"));
+                };
+
+                // If no explicit mode is given, follow the system theme.
+                let dark = match args.get(&EncodableValue::Str("dark")) {
+                    Some(EncodableValue::Bool(dark)) => Some(*dark),
+                    _ => None,
+                };
+
+                match self.effects.set_effect(backdrop, dark) {
+                    Ok(()) => reply.success(&EncodableValue::Null),
+                    Err(e) => reply.error("dwm_error", Some(&e.to_string())),
+                }
+            }
+            _ => {
+                tracing::warn!(method, "unimplemented");
+                reply.not_implemented();
+            }
+        }
+    }
+}
@@ -0,0 +1,169 @@
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::mem;
+use std::rc::Rc;
+
+use color_eyre::eyre;
+use flutter_codec::EncodableValue;
+use windows::Win32::Foundation::{HWND, LPARAM, RECT, WPARAM};
+use windows::Win32::Graphics::Gdi::{
+    GetMonitorInfoW, MonitorFromWindow, MONITORINFO, MONITOR_DEFAULTTONEAREST,
+};
+use windows::Win32::UI::WindowsAndMessaging::{
+    GetWindowPlacement, GetWindowRect, IsIconic, IsZoomed, SWP_HIDEWINDOW, SWP_NOMOVE,
+    SWP_SHOWWINDOW, WA_INACTIVE, WINDOWPLACEMENT, WINDOWPOS,
+};
+
+use crate::error_utils::ResultExt;
+use crate::event_channel::EventChannel;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum WindowState {
+    Normal,
+    Minimized,
+    Maximized,
+    /// Docked to a side or corner of the screen with Snap.
+    Snapped,
+}
+
+impl WindowState {
+    fn query(hwnd: HWND) -> eyre::Result<WindowState> {
+        if unsafe { IsIconic(hwnd) }.as_bool() {
+            return Ok(WindowState::Minimized);
+        }
+
+        if unsafe { IsZoomed(hwnd) }.as_bool() {
+            return Ok(WindowState::Maximized);
+        }
+
+        // Snapped windows are shown normally but somewhere other than their normal position, which
+        // they go back to when they are dragged away.
+        let mut placement = WINDOWPLACEMENT {
+            length: mem::size_of::<WINDOWPLACEMENT>() as u32,
+            ..Default::default()
+        };
+
+        let mut rect = RECT::default();
+        unsafe {
+            GetWindowPlacement(hwnd, &mut placement)?;
+            GetWindowRect(hwnd, &mut rect)?;
+        }
+
+        // The normal position is in workspace coordinates, which are relative to the monitor's
+        // work area rather than the screen.
+        let monitor = unsafe { MonitorFromWindow(hwnd, MONITOR_DEFAULTTONEAREST) };
+        let mut monitor_info = MONITORINFO {
+            cbSize: mem::size_of::<MONITORINFO>() as u32,
+            ..Default::default()
+        };
+
+        if unsafe { GetMonitorInfoW(monitor, &mut monitor_info) }.as_bool() {
+            let dx = monitor_info.rcWork.left - monitor_info.rcMonitor.left;
+            let dy = monitor_info.rcWork.top - monitor_info.rcMonitor.top;
+            rect.left -= dx;
+            rect.right -= dx;
+            rect.top -= dy;
+            rect.bottom -= dy;
+        }
+
+        if rect == placement.rcNormalPosition {
+            Ok(WindowState::Normal)
+        } else {
+            Ok(WindowState::Snapped)
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            WindowState::Normal => "normal",
+            WindowState::Minimized => "minimized",
+            WindowState::Maximized => "maximized",
+            WindowState::Snapped => "snapped",
+        }
+    }
+}
+
+/// Reports changes to the window over `flion/window_events`, so that the app can react to losing
+/// focus or being hidden. Each event is a map with a `type` of:
+///
+/// - `activated` or `deactivated`, when the window gains or loses activation.
+/// - `shown` or `hidden`.
+/// - `moved`, with the new `x` and `y` of the window's top left corner in physical pixels.
+/// - `stateChanged`, with the new `state`: `normal`, `minimized`, `maximized` or `snapped`.
+pub struct WindowEvents {
+    hwnd: HWND,
+    events: Rc<EventChannel>,
+    state: Cell<Option<WindowState>>,
+}
+
+impl WindowEvents {
+    pub fn new(hwnd: HWND, events: Rc<EventChannel>) -> WindowEvents {
+        WindowEvents {
+            hwnd,
+            events,
+            state: Cell::new(None),
+        }
+    }
+
+    /// Handles `WM_ACTIVATE`.
+    pub fn handle_activate(&self, wparam: WPARAM) {
+        let event_type = match (wparam.0 & 0xffff) as u32 {
+            WA_INACTIVE => "deactivated",
+            _ => "activated",
+        };
+
+        self.send(event_type, []);
+    }
+
+    /// Handles `WM_WINDOWPOSCHANGED`.
+    pub fn handle_window_pos_changed(&self, lparam: LPARAM) {
+        // Nothing is computed while no one is listening, since windows are moved often.
+        if !self.events.is_listening() {
+            self.state.set(None);
+            return;
+        }
+
+        let pos = unsafe { &*(lparam.0 as *const WINDOWPOS) };
+
+        if pos.flags.contains(SWP_SHOWWINDOW) {
+            self.send("shown", []);
+        } else if pos.flags.contains(SWP_HIDEWINDOW) {
+            self.send("hidden", []);
+        }
+
+        if !pos.flags.contains(SWP_NOMOVE) {
+            self.send(
+                "moved",
+                [
+                    (EncodableValue::Str("x"), EncodableValue::I32(pos.x)),
+                    (EncodableValue::Str("y"), EncodableValue::I32(pos.y)),
+                ],
+            );
+        }
+
+        let Ok(state) = WindowState::query(self.hwnd).trace_err() else {
+            return;
+        };
+
+        if self.state.replace(Some(state)) != Some(state) {
+            self.send(
+                "stateChanged",
+                [(
+                    EncodableValue::Str("state"),
+                    EncodableValue::Str(state.name()),
+                )],
+            );
+        }
+    }
+
+    fn send<const N: usize>(
+        &self,
+        event_type: &'static str,
+        fields: [(EncodableValue<'static>, EncodableValue<'static>); N],
+    ) {
+        let mut event = BTreeMap::from_iter(fields);
+        event.insert(EncodableValue::Str("type"), EncodableValue::Str(event_type));
+
+        let _ = self.events.send(&EncodableValue::Map(event)).trace_err();
+    }
+}
@@ -0,0 +1,204 @@
+use std::fs;
+use std::mem;
+use std::path::PathBuf;
+
+use color_eyre::eyre;
+use serde::{Deserialize, Serialize};
+use windows::Win32::Foundation::{HWND, RECT};
+use windows::Win32::Graphics::Gdi::{
+    GetMonitorInfoW, MonitorFromRect, MonitorFromWindow, HMONITOR, MONITORINFO, MONITORINFOEXW,
+    MONITOR_DEFAULTTONEAREST, MONITOR_DEFAULTTONULL,
+};
+use windows::Win32::UI::WindowsAndMessaging::{
+    GetWindowPlacement, GetWindowRect, IsIconic, IsWindowVisible, IsZoomed, SetWindowPlacement,
+    SetWindowPos, SWP_NOACTIVATE, SWP_NOZORDER, SW_HIDE, SW_SHOWMAXIMIZED, SW_SHOWNORMAL,
+    WINDOWPLACEMENT,
+};
+
+use crate::paths;
+
+/// Window placement saved between runs. Coordinates are in physical pixels.
+#[derive(Debug, Serialize, Deserialize)]
+struct SavedPlacement {
+    left: i32,
+    top: i32,
+    right: i32,
+    bottom: i32,
+    maximized: bool,
+    monitor: String,
+}
+
+fn placement_file() -> eyre::Result<PathBuf> {
+    Ok(paths::app_data_dir()?.join("window_placement.json"))
+}
+
+pub fn save(hwnd: HWND) -> eyre::Result<()> {
+    let mut placement = WINDOWPLACEMENT {
+        length: mem::size_of::<WINDOWPLACEMENT>() as u32,
+        ..Default::default()
+    };
+
+    unsafe { GetWindowPlacement(hwnd, &mut placement)? };
+
+    let rect = placement.rcNormalPosition;
+    let monitor = unsafe { MonitorFromWindow(hwnd, MONITOR_DEFAULTTONEAREST) };
+
+    let saved = SavedPlacement {
+        left: rect.left,
+        top: rect.top,
+        right: rect.right,
+        bottom: rect.bottom,
+        maximized: placement.showCmd == SW_SHOWMAXIMIZED.0 as u32,
+        monitor: monitor_name(monitor).unwrap_or_default(),
+    };
+
+    fs::write(placement_file()?, serde_json::to_vec_pretty(&saved)?)?;
+
+    Ok(())
+}
+
+/// Restores the placement saved by [`save`], if any.
+///
+/// If the monitor that the window was on is no longer connected (or the saved position is no
+/// longer visible), only the size and maximized state are restored and the window is left on
+/// its current monitor.
+///
+/// A hidden window is kept hidden, in which case this returns `true` if the window should be
+/// maximized when it is shown.
+pub fn restore(hwnd: HWND) -> eyre::Result<bool> {
+    let path = placement_file()?;
+    if !path.exists() {
+        return Ok(false);
+    }
+
+    let saved: SavedPlacement = serde_json::from_slice(&fs::read(path)?)?;
+
+    let mut placement = WINDOWPLACEMENT {
+        length: mem::size_of::<WINDOWPLACEMENT>() as u32,
+        ..Default::default()
+    };
+
+    unsafe { GetWindowPlacement(hwnd, &mut placement)? };
+
+    let saved_rect = RECT {
+        left: saved.left,
+        top: saved.top,
+        right: saved.right,
+        bottom: saved.bottom,
+    };
+
+    if saved_rect.right <= saved_rect.left || saved_rect.bottom <= saved_rect.top {
+        tracing::warn!(?saved, "ignoring invalid window placement");
+        return Ok(false);
+    }
+
+    let monitor = unsafe { MonitorFromRect(&saved_rect, MONITOR_DEFAULTTONULL) };
+    let is_on_saved_monitor =
+        !monitor.is_invalid() && monitor_name(monitor).is_some_and(|name| name == saved.monitor);
+
+    if is_on_saved_monitor {
+        placement.rcNormalPosition = saved_rect;
+    } else {
+        tracing::info!(
+            monitor = saved.monitor,
+            "saved monitor is not available, restoring size only"
+        );
+        let current = placement.rcNormalPosition;
+        placement.rcNormalPosition = RECT {
+            left: current.left,
+            top: current.top,
+            right: current.left + (saved_rect.right - saved_rect.left),
+            bottom: current.top + (saved_rect.bottom - saved_rect.top),
+        };
+    }
+
+    let is_visible = unsafe { IsWindowVisible(hwnd) }.as_bool();
+
+    placement.flags = Default::default();
+    placement.showCmd = if !is_visible {
+        SW_HIDE.0 as u32
+    } else if saved.maximized {
+        SW_SHOWMAXIMIZED.0 as u32
+    } else {
+        SW_SHOWNORMAL.0 as u32
+    };
+
+    unsafe { SetWindowPlacement(hwnd, &placement)? };
+
+    Ok(saved.maximized && !is_visible)
+}
+
+/// Moves the window onto the nearest monitor if it isn't on any, e.g. because the monitor that it
+/// was on has been disconnected or its resolution has been lowered. The window is shrunk to fit
+/// the monitor's work area if necessary, and centered in it.
+///
+/// Minimized and maximized windows are left alone, since the system places them when they are
+/// restored.
+pub fn ensure_on_screen(hwnd: HWND) -> eyre::Result<()> {
+    if unsafe { IsIconic(hwnd).as_bool() || IsZoomed(hwnd).as_bool() } {
+        return Ok(());
+    }
+
+    let mut rect = RECT::default();
+    unsafe { GetWindowRect(hwnd, &mut rect)? };
+
+    if !unsafe { MonitorFromRect(&rect, MONITOR_DEFAULTTONULL) }.is_invalid() {
+        return Ok(());
+    }
+
+    let monitor = unsafe { MonitorFromRect(&rect, MONITOR_DEFAULTTONEAREST) };
+
+    let mut info = MONITORINFO {
+        cbSize: mem::size_of::<MONITORINFO>() as u32,
+        ..Default::default()
+    };
+
+    if !unsafe { GetMonitorInfoW(monitor, &mut info) }.as_bool() {
+        eyre::bail!("failed to get monitor info");
+    }
+
+    let work = info.rcWork;
+    let width = (rect.right - rect.left).min(work.right - work.left);
+    let height = (rect.bottom - rect.top).min(work.bottom - work.top);
+    let x = work.left + (work.right - work.left - width) / 2;
+    let y = work.top + (work.bottom - work.top - height) / 2;
+
+    tracing::info!(?rect, x, y, width, height, "moving window back on screen");
+
+    unsafe {
+        SetWindowPos(
+            hwnd,
+            None,
+            x,
+            y,
+            width,
+            height,
+            SWP_NOZORDER | SWP_NOACTIVATE,
+        )?
+    };
+
+    Ok(())
+}
+
+fn monitor_name(monitor: HMONITOR) -> Option<String> {
+    let mut info = MONITORINFOEXW {
+        monitorInfo: MONITORINFO {
+            cbSize: mem::size_of::<MONITORINFOEXW>() as u32,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    let res = unsafe { GetMonitorInfoW(monitor, &mut info as *mut MONITORINFOEXW as *mut _) };
+    if !res.as_bool() {
+        return None;
+    }
+
+    let len = info
+        .szDevice
+        .iter()
+        .position(|&c| c == 0)
+        .unwrap_or(info.szDevice.len());
+
+    Some(String::from_utf16_lossy(&info.szDevice[..len]))
+}